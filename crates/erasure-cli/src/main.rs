@@ -0,0 +1,479 @@
+use std::{
+    collections::BTreeMap,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
+
+use erasure_client::Client;
+use erasure_node::{
+    file::{self, EncodeConfig, File, Metadata, Shard},
+    shardfile,
+    tcp::TcpNetwork,
+};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+const USAGE: &str = "usage:
+  erasure encode <input> [dir] [--data n] [--parity n] [--shard-size n] [--block-size n]
+                                              split <input> into shard files in [dir]
+  erasure verify <shard>...                   check shards and report which files can be rebuilt
+  erasure decode <output> <shard>...          rebuild a file from any data-shards of its shards
+
+  erasure upload <input> [name] <remote>      store a file on the cluster, as [name] if given
+  erasure download <name> [output] <remote>   fetch a file from the cluster into [output]
+  erasure stat <name> <remote>                show a file's metadata
+  erasure list <remote>                       list the files the cluster holds
+  erasure delete <name> <remote>              delete a file from every node
+  erasure cluster-status <remote>             show which nodes answer, how fast, and their file counts
+
+<remote> is --node <addr>, once per node of the cluster, and optionally
+  --listen <addr>   where nodes send their replies, 127.0.0.1:0 by default; use an address the
+                    nodes can reach when they run on other machines
+  --timeout <secs>  how long to wait for the nodes, 5 by default";
+
+// Transfers at least this large get a progress bar.
+const LARGE: u64 = 1 << 20;
+
+fn encode(args: &[String]) -> Result<bool, String> {
+    let (mut data, mut parity, mut shard_size, mut block_size) = (None, None, None, None);
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--data" {
+            let value = args.next().ok_or("--data needs a value")?;
+            data = Some(
+                value
+                    .parse()
+                    .map_err(|_| format!("invalid data shards: {value}"))?,
+            );
+        } else if arg == "--shard-size" {
+            let value = args.next().ok_or("--shard-size needs a value")?;
+            shard_size = Some(
+                value
+                    .parse()
+                    .map_err(|_| format!("invalid shard size: {value}"))?,
+            );
+        } else if arg == "--block-size" {
+            let value = args.next().ok_or("--block-size needs a value")?;
+            block_size = Some(
+                value
+                    .parse()
+                    .map_err(|_| format!("invalid block size: {value}"))?,
+            );
+        } else if arg == "--parity" {
+            let value = args.next().ok_or("--parity needs a value")?;
+            parity = Some(
+                value
+                    .parse()
+                    .map_err(|_| format!("invalid parity: {value}"))?,
+            );
+        } else {
+            paths.push(arg);
+        }
+    }
+
+    let (input, dir) = match paths.as_slice() {
+        [input] => (Path::new(input.as_str()), Path::new(".")),
+        [input, dir] => (Path::new(input.as_str()), Path::new(dir.as_str())),
+        _ => return Err(USAGE.to_string()),
+    };
+
+    let name = input
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| format!("{}: not a file name", input.display()))?;
+    let content = std::fs::read(input).map_err(|err| format!("{}: {err}", input.display()))?;
+
+    let size = shard_size.unwrap_or(file::DEFAULT_SHARD_SIZE).max(1);
+    let data = data.unwrap_or_else(|| file::data_shards(block_size.unwrap_or(content.len()), size));
+    let mut config = EncodeConfig::new(data, parity.unwrap_or(data));
+    if let Some(shard_size) = shard_size {
+        config = config.with_shard_size(shard_size);
+    }
+    if let Some(block_size) = block_size {
+        config = config.with_block_size(block_size);
+    }
+    let file =
+        File::encode_with(content, config).map_err(|err| format!("{}: {err}", input.display()))?;
+
+    std::fs::create_dir_all(dir).map_err(|err| format!("{}: {err}", dir.display()))?;
+    let written =
+        shardfile::write(dir, name, &file).map_err(|err| format!("{}: {err}", dir.display()))?;
+
+    let meta = file.metadata();
+    for path in &written {
+        println!("{}", path.display());
+    }
+    match meta.block_count() {
+        1 => println!(
+            "{name}: {} shards, any {} of them rebuild the file",
+            meta.shard_count(),
+            meta.data_shards()
+        ),
+        blocks => println!(
+            "{name}: {} shards in {blocks} blocks, any {} of each block's {} rebuild it",
+            meta.shard_count(),
+            meta.data_shards(),
+            meta.shard_count() / blocks
+        ),
+    }
+
+    Ok(true)
+}
+
+fn open(paths: &[String]) -> (Vec<(String, Metadata, Shard)>, usize) {
+    let mut shards = Vec::with_capacity(paths.len());
+    let mut failed = 0;
+    for path in paths {
+        match shardfile::open(path) {
+            Ok(shard) => shards.push(shard),
+            Err(err) => {
+                eprintln!("{path}: {err}");
+                failed += 1;
+            }
+        }
+    }
+
+    (shards, failed)
+}
+
+fn verify(args: &[String]) -> Result<bool, String> {
+    if args.is_empty() {
+        return Err(USAGE.to_string());
+    }
+
+    let (shards, failed) = open(args);
+    let mut files = BTreeMap::<String, Vec<(String, Metadata, Shard)>>::new();
+    for shard in shards {
+        files.entry(shard.0.clone()).or_default().push(shard);
+    }
+
+    let mut recoverable = true;
+    for (name, shards) in files {
+        let metas = shards.iter().fold(Vec::new(), |mut metas, (_, meta, _)| {
+            if !metas.contains(meta) {
+                metas.push(meta.clone());
+            }
+            metas
+        });
+        if metas.len() > 1 {
+            println!("{name}: shards of {} different encodings", metas.len());
+            recoverable = false;
+            continue;
+        }
+
+        let file = shardfile::assemble(shards).ok_or("no shards")?;
+        let meta = file.metadata();
+        let present = file.shards().present();
+        let status = if file.can_decode() {
+            "recoverable"
+        } else {
+            recoverable = false;
+            "not recoverable"
+        };
+        let per_block = match meta.block_count() {
+            1 => String::new(),
+            _ => " per block".to_string(),
+        };
+        println!(
+            "{name}: {present}/{} shards, {} needed{per_block}, {status}",
+            meta.shard_count(),
+            meta.data_shards()
+        );
+
+        let inconsistent = file.verify();
+        if !inconsistent.is_empty() {
+            println!("{name}: inconsistent shards {inconsistent:?}");
+            recoverable = false;
+        }
+    }
+
+    if failed > 0 {
+        println!("{failed} unreadable shard files");
+    }
+
+    Ok(recoverable && failed == 0)
+}
+
+fn decode(args: &[String]) -> Result<bool, String> {
+    let [output, paths @ ..] = args else {
+        return Err(USAGE.to_string());
+    };
+    if paths.is_empty() {
+        return Err(USAGE.to_string());
+    }
+
+    let (shards, _) = open(paths);
+    let file = shardfile::assemble(shards).ok_or("no readable shards")?;
+    let output = PathBuf::from(output);
+    let mut writer = std::fs::File::create(&output)
+        .map(BufWriter::new)
+        .map_err(|err| format!("{}: {err}", output.display()))?;
+    let written = file
+        .decode_to_writer(&mut writer)
+        .and_then(|_| Ok(writer.flush()?));
+    if let Err(err) = written {
+        let _ = std::fs::remove_file(&output);
+        return Err(format!("{}: {err}", output.display()));
+    }
+    println!(
+        "{}: rebuilt from {} shards",
+        output.display(),
+        file.shards().present()
+    );
+
+    Ok(true)
+}
+
+struct Remote {
+    nodes: Vec<String>,
+    listen: String,
+    timeout: Option<Duration>,
+    args: Vec<String>,
+}
+
+impl Remote {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut remote = Remote {
+            nodes: Vec::new(),
+            listen: "127.0.0.1:0".to_string(),
+            timeout: None,
+            args: Vec::new(),
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--node" {
+                let value = args.next().ok_or("--node needs a value")?;
+                remote.nodes.push(value.clone());
+            } else if arg == "--listen" {
+                remote.listen = args.next().ok_or("--listen needs a value")?.clone();
+            } else if arg == "--timeout" {
+                let value = args.next().ok_or("--timeout needs a value")?;
+                let secs = value
+                    .parse::<f64>()
+                    .ok()
+                    .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                    .ok_or_else(|| format!("invalid timeout: {value}"))?;
+                remote.timeout = Some(secs);
+            } else {
+                remote.args.push(arg.clone());
+            }
+        }
+
+        if remote.nodes.is_empty() {
+            return Err(USAGE.to_string());
+        }
+        Ok(remote)
+    }
+
+    // The nodes reply by dialing the address this listens on, so the client is a peer of
+    // theirs for the length of the command.
+    async fn connect(&self) -> Result<Client<TcpNetwork>, String> {
+        let network = TcpNetwork::bind(self.listen.as_str(), self.nodes.clone())
+            .await
+            .map_err(|err| format!("{}: {err}", self.listen))?;
+        let client = Client::new(network);
+        Ok(match self.timeout {
+            Some(timeout) => client.with_timeout(timeout),
+            None => client,
+        })
+    }
+}
+
+// A bar on stderr that only shows once a transfer turns out to be large; the returned
+// callback feeds it the client's progress.
+fn progress_bar() -> (ProgressBar, impl FnMut(u64, u64) + Send + 'static) {
+    let bar = ProgressBar::hidden();
+    let updated = bar.clone();
+    let update = move |done, total| {
+        if total < LARGE {
+            return;
+        }
+        if updated.is_hidden() {
+            let style = ProgressStyle::with_template(
+                "{bar:40} {bytes}/{total_bytes} {bytes_per_sec} eta {eta}",
+            )
+            .expect("valid template");
+            updated.set_style(style);
+            updated.set_draw_target(ProgressDrawTarget::stderr());
+        }
+        updated.set_length(total);
+        updated.set_position(done);
+    };
+
+    (bar, update)
+}
+
+async fn upload(remote: Remote) -> Result<bool, String> {
+    let (input, name) = match remote.args.as_slice() {
+        [input] => (Path::new(input.as_str()), None),
+        [input, name] => (Path::new(input.as_str()), Some(name.clone())),
+        _ => return Err(USAGE.to_string()),
+    };
+    let name = match name {
+        Some(name) => name,
+        None => input
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| format!("{}: not a file name", input.display()))?
+            .to_string(),
+    };
+    let content = std::fs::read(input).map_err(|err| format!("{}: {err}", input.display()))?;
+
+    let (bar, progress) = progress_bar();
+    let mut client = remote.connect().await?.with_progress(progress);
+    let uploaded = client.upload(name.clone(), &content).await;
+    bar.finish_and_clear();
+    uploaded.map_err(|err| format!("{name}: {err}"))?;
+
+    println!(
+        "{name}: {} bytes sent to {} nodes",
+        content.len(),
+        remote.nodes.len()
+    );
+    Ok(true)
+}
+
+async fn download(remote: Remote) -> Result<bool, String> {
+    let (name, output) = match remote.args.as_slice() {
+        [name] => (name, name.rsplit('/').next().unwrap_or(name)),
+        [name, output] => (name, output.as_str()),
+        _ => return Err(USAGE.to_string()),
+    };
+
+    let (bar, progress) = progress_bar();
+    let mut client = remote.connect().await?.with_progress(progress);
+    let downloaded = client.download(name).await;
+    bar.finish_and_clear();
+    let content = downloaded.map_err(|err| format!("{name}: {err}"))?;
+
+    std::fs::write(output, &content).map_err(|err| format!("{output}: {err}"))?;
+    println!("{name}: {} bytes written to {output}", content.len());
+    Ok(true)
+}
+
+async fn stat(remote: Remote) -> Result<bool, String> {
+    let [name] = remote.args.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+
+    let mut client = remote.connect().await?;
+    let meta = client
+        .stat(name)
+        .await
+        .map_err(|err| format!("{name}: {err}"))?;
+
+    println!("name:     {name}");
+    println!("size:     {}", meta.len());
+    println!(
+        "shards:   {} data + {} parity in {} blocks",
+        meta.data_shards(),
+        meta.parity_shards(),
+        meta.block_count()
+    );
+    let stamp = meta.stamp();
+    println!("version:  {} by writer {:x}", stamp.time, stamp.writer);
+    if meta.created_at() > 0 {
+        println!("created:  {}", meta.created_at());
+    }
+    if let Some(content_type) = meta.content_type() {
+        println!("type:     {content_type}");
+    }
+    for (key, value) in meta.attributes() {
+        println!("{key}: {value}");
+    }
+    Ok(true)
+}
+
+async fn list(remote: Remote) -> Result<bool, String> {
+    if !remote.args.is_empty() {
+        return Err(USAGE.to_string());
+    }
+
+    let mut client = remote.connect().await?;
+    let files = client.list().await.map_err(|err| err.to_string())?;
+    for (name, meta) in files {
+        println!("{:>12}  {name}", meta.len());
+    }
+    Ok(true)
+}
+
+async fn delete(remote: Remote) -> Result<bool, String> {
+    let [name] = remote.args.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+
+    let mut client = remote.connect().await?;
+    client
+        .delete(name)
+        .await
+        .map_err(|err| format!("{name}: {err}"))?;
+    println!("{name}: deleted");
+    Ok(true)
+}
+
+async fn cluster_status(remote: Remote) -> Result<bool, String> {
+    if !remote.args.is_empty() {
+        return Err(USAGE.to_string());
+    }
+
+    let mut client = remote.connect().await?;
+    let peers = client.status().await.map_err(|err| err.to_string())?;
+    let mut up = 0;
+    for peer in &peers {
+        match peer.latency {
+            Some(latency) => {
+                up += 1;
+                println!(
+                    "{}  up    {:>8.1}ms  {} files",
+                    peer.peer,
+                    latency.as_secs_f64() * 1000.0,
+                    peer.files
+                );
+            }
+            None => println!("{}  down", peer.peer),
+        }
+    }
+    println!("{up}/{} nodes up", peers.len());
+
+    Ok(up == peers.len())
+}
+
+// Only the commands that talk to a cluster need a runtime.
+fn remote<F: Future<Output = Result<bool, String>>>(
+    args: &[String],
+    command: impl FnOnce(Remote) -> F,
+) -> Result<bool, String> {
+    let remote = Remote::parse(args)?;
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|err| err.to_string())?
+        .block_on(command(remote))
+}
+
+fn main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let result = match args.first().map(String::as_str) {
+        Some("encode") => encode(&args[1..]),
+        Some("verify") => verify(&args[1..]),
+        Some("decode") => decode(&args[1..]),
+        Some("upload") => remote(&args[1..], upload),
+        Some("download") => remote(&args[1..], download),
+        Some("stat") => remote(&args[1..], stat),
+        Some("list") => remote(&args[1..], list),
+        Some("delete") => remote(&args[1..], delete),
+        Some("cluster-status") => remote(&args[1..], cluster_status),
+        _ => Err(USAGE.to_string()),
+    };
+
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::from(2)
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    future,
+    pin::pin,
+    task::Poll,
+    time::Duration,
+};
+
+use erasure_node::{
+    Error,
+    file::{self, DecodeError, EncodeConfig, File, Metadata, Stamp, VectorClock},
+    network::{Command, Network, NetworkExt},
+    placement,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ClientError {
+    NoPeers,
+    NotFound,
+    Unreachable,
+    Node(Error),
+}
+
+impl std::fmt::Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoPeers => write!(f, "no peers to talk to"),
+            Self::NotFound => write!(f, "no peer has the file"),
+            Self::Unreachable => write!(f, "no peer answered"),
+            Self::Node(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<Error> for ClientError {
+    fn from(err: Error) -> Self {
+        Self::Node(err)
+    }
+}
+
+impl From<DecodeError> for ClientError {
+    fn from(err: DecodeError) -> Self {
+        Self::Node(err.into())
+    }
+}
+
+// Bounds on the layout `upload` picks; see `encoding`.
+const MAX_DATA_SHARDS: usize = 64;
+const MAX_SHARD_SIZE: usize = 1 << 20;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerStatus {
+    pub peer: String,
+    /// Round trip of the manifest request; None when the peer did not answer in time.
+    pub latency: Option<Duration>,
+    pub files: usize,
+}
+
+// Bytes done and bytes in total.
+type Progress = Box<dyn FnMut(u64, u64) + Send>;
+
+// How long a peer took to answer, and its manifest.
+type Answer = (Duration, Vec<(String, Metadata)>);
+
+pub struct Client<N> {
+    network: N,
+    timeout: Duration,
+    encoding: Option<EncodeConfig>,
+    progress: Option<Progress>,
+}
+
+impl<N: Network> Client<N> {
+    pub fn new(network: N) -> Self {
+        Self {
+            network,
+            timeout: Duration::from_secs(5),
+            encoding: None,
+            progress: None,
+        }
+    }
+
+    /// How long `stat` and `download` wait for peers before giving up. Uploads stat
+    /// the file first, so a name no peer knows yet also waits this long.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Encodes uploads with `config` instead of picking a layout from the content length.
+    pub fn with_encoding(mut self, config: EncodeConfig) -> Self {
+        self.encoding = Some(config);
+        self
+    }
+
+    /// Called with the bytes done and the total as `upload` sends shards and `download`
+    /// collects enough of them to decode.
+    pub fn with_progress(mut self, progress: impl FnMut(u64, u64) + Send + 'static) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    pub fn network(&self) -> &N {
+        &self.network
+    }
+
+    fn progress(&mut self, done: u64, total: u64) {
+        if let Some(progress) = &mut self.progress {
+            progress(done, total);
+        }
+    }
+
+    async fn peers(&self) -> Result<Vec<String>, ClientError> {
+        let peers = self.network.discover().await;
+        if peers.is_empty() {
+            return Err(ClientError::NoPeers);
+        }
+
+        Ok(peers)
+    }
+
+    pub async fn upload(
+        &mut self,
+        name: String,
+        content: impl AsRef<[u8]>,
+    ) -> Result<(), ClientError> {
+        let peers = self.peers().await?;
+
+        // Writing on top of the version peers hold keeps this upload from conflicting with it.
+        let mut clock = match self.stat(&name).await {
+            Ok(meta) => meta.clock().clone(),
+            Err(ClientError::NotFound) => VectorClock::default(),
+            Err(err) => return Err(err),
+        };
+
+        let id = self.network.id();
+        let stamp = Stamp::new(self.network.clock(), &id);
+        clock.tick(file::writer(&id));
+        let content = content.as_ref();
+        let config = self.encoding.unwrap_or_else(|| encoding(content.len()));
+        let file = File::encode_with(content, config)?
+            .with_stamp(stamp)
+            .with_clock(clock);
+
+        for peer in &peers {
+            self.network
+                .create(peer.clone(), name.clone(), file.metadata().clone())
+                .await;
+        }
+
+        let order = placement::order(&name, &peers);
+        let total = file.shards().size() as u64;
+        let mut sent = 0;
+        for shard in file.shards().present_iter() {
+            let peer = order[shard.index() % order.len()].clone();
+            sent += shard.size() as u64;
+            self.network
+                .replicate(peer, name.clone(), stamp, shard)
+                .await;
+            self.progress(sent, total);
+        }
+
+        Ok(())
+    }
+
+    // Replies to an earlier request that arrived after it finished would otherwise be
+    // mistaken for answers to the next one.
+    async fn drain(&self) {
+        loop {
+            let mut recv = pin!(self.network.recv());
+            let polled = future::poll_fn(|cx| Poll::Ready(recv.as_mut().poll(cx))).await;
+            if !matches!(polled, Poll::Ready(Some(_))) {
+                break;
+            }
+        }
+    }
+
+    pub async fn stat(&mut self, name: &str) -> Result<Metadata, ClientError> {
+        let peers = self.peers().await?;
+        self.drain().await;
+        for peer in peers {
+            self.network.request_meta(peer, name.to_string()).await;
+        }
+
+        let deadline = self.network.now() + self.timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(self.network.now());
+            match self.network.recv_timeout(remaining).await {
+                Ok((
+                    _,
+                    Command::Meta {
+                        name: other, meta, ..
+                    },
+                )) if other == name => {
+                    return Ok(meta);
+                }
+                Ok(_) => {}
+                Err(Error::Timeout) => return Err(ClientError::NotFound),
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    pub async fn download_string(&mut self, name: &str) -> Result<String, ClientError> {
+        let content = self.download(name).await?;
+        String::from_utf8(content).map_err(|_| DecodeError::InvalidContent.into())
+    }
+
+    pub async fn download(&mut self, name: &str) -> Result<Vec<u8>, ClientError> {
+        let mut file = File::empty(self.stat(name).await?);
+
+        let peers = self.peers().await?;
+        self.drain().await;
+        for peer in &peers {
+            self.network
+                .request(peer.clone(), name.to_string(), Vec::new())
+                .await;
+        }
+
+        let meta = file.metadata().clone();
+        let needed = (meta.data_shards() * meta.block_count()).max(1);
+        let deadline = self.network.now() + self.timeout;
+        while !file.can_decode() {
+            let remaining = deadline.saturating_duration_since(self.network.now());
+            match self.network.recv_timeout(remaining).await {
+                Ok((
+                    _,
+                    Command::Replicate {
+                        name: other,
+                        stamp,
+                        shard,
+                        checksum,
+                    },
+                )) if other == name
+                    && stamp == file.metadata().stamp()
+                    && shard.crc() == checksum
+                    && file.verify_shard(&shard) =>
+                {
+                    let _ = file.shards_mut().merge(shard);
+                    let present = file.shards().present().min(needed);
+                    self.progress(meta.len() * present as u64 / needed as u64, meta.len());
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    for peer in peers {
+                        self.network.cancel(peer, name.to_string()).await;
+                    }
+                    return Err(err.into());
+                }
+            }
+        }
+
+        for peer in peers {
+            self.network.cancel(peer, name.to_string()).await;
+        }
+
+        Ok(file.decode()?)
+    }
+
+    // Only the peers are told, as in `Node::delete`; each drops the version `stat` found and
+    // any older one.
+    pub async fn delete(&mut self, name: &str) -> Result<(), ClientError> {
+        let stamp = self.stat(name).await?.stamp();
+        for peer in self.peers().await? {
+            self.network.delete(peer, name.to_string(), stamp).await;
+        }
+
+        Ok(())
+    }
+
+    /// The newest version of every file any peer holds, by name.
+    pub async fn list(&mut self) -> Result<Vec<(String, Metadata)>, ClientError> {
+        let mut files = BTreeMap::<String, Metadata>::new();
+        let mut answered = false;
+        for (_, answer) in self.manifests().await? {
+            let Some((_, manifest)) = answer else {
+                continue;
+            };
+            answered = true;
+            for (name, meta) in manifest {
+                if files
+                    .get(&name)
+                    .is_none_or(|known| known.stamp() < meta.stamp())
+                {
+                    files.insert(name, meta);
+                }
+            }
+        }
+
+        if !answered {
+            return Err(ClientError::Unreachable);
+        }
+        Ok(files.into_iter().collect())
+    }
+
+    pub async fn status(&mut self) -> Result<Vec<PeerStatus>, ClientError> {
+        let manifests = self.manifests().await?;
+        Ok(manifests
+            .into_iter()
+            .map(|(peer, answer)| PeerStatus {
+                peer,
+                latency: answer.as_ref().map(|(latency, _)| *latency),
+                files: answer.map_or(0, |(_, manifest)| manifest.len()),
+            })
+            .collect())
+    }
+
+    // An announce asks a peer for its manifest; peers that stay silent until the timeout
+    // are listed without one.
+    async fn manifests(&self) -> Result<Vec<(String, Option<Answer>)>, ClientError> {
+        let peers = self.peers().await?;
+        self.drain().await;
+        let start = self.network.now();
+        for peer in &peers {
+            self.network.announce(peer.clone()).await;
+        }
+
+        let mut answers = HashMap::new();
+        let deadline = start + self.timeout;
+        while answers.len() < peers.len() {
+            let remaining = deadline.saturating_duration_since(self.network.now());
+            match self.network.recv_timeout(remaining).await {
+                Ok((peer, Command::Manifest { files })) if peers.contains(&peer) => {
+                    let latency = self.network.now().saturating_duration_since(start);
+                    answers.entry(peer).or_insert((latency, files));
+                }
+                Ok(_) => {}
+                Err(Error::Timeout) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(peers
+            .into_iter()
+            .map(|peer| {
+                let answer = answers.remove(&peer);
+                (peer, answer)
+            })
+            .collect())
+    }
+}
+
+// As many 64 byte shards as the content needs and as many parity shards, like
+// `File::encode`, until the shard count would make encoding slow; then larger shards, and
+// for content too large for those, blocks of shards.
+fn encoding(len: usize) -> EncodeConfig {
+    let data_shards = file::data_shards(len, file::DEFAULT_SHARD_SIZE).min(MAX_DATA_SHARDS);
+    let shard_size = len
+        .div_ceil(data_shards)
+        .next_multiple_of(file::DEFAULT_SHARD_SIZE)
+        .clamp(file::DEFAULT_SHARD_SIZE, MAX_SHARD_SIZE);
+    let config = EncodeConfig::new(data_shards, data_shards).with_shard_size(shard_size);
+    match data_shards * shard_size {
+        block_size if len > block_size => config.with_block_size(block_size),
+        _ => config,
+    }
+}
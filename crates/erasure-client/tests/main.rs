@@ -0,0 +1,315 @@
+mod client {
+    use std::{
+        collections::{HashMap, HashSet},
+        pin::pin,
+        sync::{
+            Arc, Mutex,
+            mpsc::{Receiver, Sender, channel},
+        },
+        task::{Context, Poll, Waker},
+        time::{Duration, Instant},
+    };
+
+    use erasure_client::{Client, ClientError};
+    use erasure_node::{
+        Error,
+        config::NodeConfig,
+        network::{Command, Network},
+        node::Node,
+    };
+
+    #[derive(Default)]
+    struct Inner {
+        id: usize,
+        senders: HashMap<usize, Sender<(usize, Command)>>,
+        receivers: HashMap<usize, Receiver<(usize, Command)>>,
+        members: HashSet<usize>,
+    }
+
+    #[derive(Clone, Default)]
+    struct TestNetworkBuilder {
+        inner: Arc<Mutex<Inner>>,
+    }
+
+    impl TestNetworkBuilder {
+        fn spawn(&self, member: bool) -> TestNetwork {
+            let mut inner = self.inner.lock().unwrap();
+            let id = inner.id;
+            inner.id += 1;
+
+            let (sender, receiver) = channel();
+            inner.senders.insert(id, sender);
+            inner.receivers.insert(id, receiver);
+            if member {
+                inner.members.insert(id);
+            }
+
+            TestNetwork {
+                id,
+                builder: self.inner.clone(),
+            }
+        }
+
+        fn node(&self) -> Arc<Node<TestNetwork>> {
+            let node = Arc::new(Node::new(self.spawn(true), NodeConfig::default()));
+            let node_clone = Arc::clone(&node);
+            std::thread::spawn(move || aw(node_clone.run()));
+            node
+        }
+
+        fn client(&self) -> Client<TestNetwork> {
+            Client::new(self.spawn(false))
+        }
+
+        fn leave(&self, id: usize) {
+            self.inner.lock().unwrap().members.remove(&id);
+        }
+    }
+
+    struct TestNetwork {
+        id: usize,
+        builder: Arc<Mutex<Inner>>,
+    }
+
+    impl Network for TestNetwork {
+        fn id(&self) -> String {
+            format!("{}", self.id)
+        }
+
+        async fn sleep(&self, duration: Duration) {
+            let deadline = Instant::now() + duration;
+            std::future::poll_fn(|cx| {
+                if Instant::now() >= deadline {
+                    return Poll::Ready(());
+                }
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            })
+            .await
+        }
+
+        async fn discover(&self) -> Vec<String> {
+            let inner = self.builder.lock().unwrap();
+            inner
+                .members
+                .iter()
+                .filter(|id| **id != self.id)
+                .map(|id| format!("{id}"))
+                .collect()
+        }
+
+        async fn send(&self, peer: String, cmd: Command) {
+            let id = peer.parse().unwrap();
+            let _ = self.builder.lock().unwrap().senders[&id].send((self.id, cmd));
+        }
+
+        async fn recv(&self) -> Option<(String, Command)> {
+            std::future::poll_fn(|cx| {
+                match self.builder.lock().unwrap().receivers[&self.id].try_recv() {
+                    Ok((id, cmd)) => Poll::Ready(Some((format!("{id}"), cmd))),
+                    Err(_) => {
+                        cx.waker().wake_by_ref();
+                        Poll::Pending
+                    }
+                }
+            })
+            .await
+        }
+    }
+
+    fn aw<F: Future>(fut: F) -> F::Output {
+        let mut fut = pin!(fut);
+        loop {
+            if let Poll::Ready(res) = fut.as_mut().poll(&mut Context::from_waker(Waker::noop())) {
+                return res;
+            }
+        }
+    }
+
+    fn eventually(condition: impl Fn() -> bool) {
+        for _ in 0..100 {
+            if condition() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+
+        panic!("condition never held");
+    }
+
+    #[test]
+    fn roundtrip() {
+        let builder = TestNetworkBuilder::default();
+        let nodes = (0..4).map(|_| builder.node()).collect::<Vec<_>>();
+        let mut client = builder.client().with_timeout(Duration::from_secs(1));
+
+        // Not valid UTF-8, so only the byte API can carry it.
+        let content = (0..1200).map(|i| (i * 7 % 256) as u8).collect::<Vec<_>>();
+        let name = "hello".to_string();
+
+        aw(client.upload(name.clone(), &content)).unwrap();
+        eventually(|| nodes.iter().all(|node| !node.shards(&name).is_empty()));
+
+        let meta = aw(client.stat(&name)).unwrap();
+        assert_eq!(Some(meta), nodes[0].metadata(&name));
+
+        let mut reader = builder.client();
+        assert_eq!(aw(reader.download(&name)).unwrap(), content);
+    }
+
+    #[test]
+    fn node_upload() {
+        let builder = TestNetworkBuilder::default();
+        let nodes = (0..4).map(|_| builder.node()).collect::<Vec<_>>();
+        let mut client = builder.client();
+
+        let content = "hello world!".repeat(100);
+        let name = "hello".to_string();
+
+        aw(nodes[0].upload(name.clone(), content.clone())).unwrap();
+        eventually(|| nodes.iter().all(|node| !node.shards(&name).is_empty()));
+
+        assert_eq!(aw(client.download_string(&name)).unwrap(), content);
+    }
+
+    #[test]
+    fn no_peers() {
+        let builder = TestNetworkBuilder::default();
+        let mut client = builder.client();
+
+        assert_eq!(
+            aw(client.upload("hello".into(), "hello world!")),
+            Err(ClientError::NoPeers)
+        );
+        assert_eq!(aw(client.stat("hello")), Err(ClientError::NoPeers));
+    }
+
+    #[test]
+    fn stat_not_found() {
+        let builder = TestNetworkBuilder::default();
+        let _nodes = (0..2).map(|_| builder.node()).collect::<Vec<_>>();
+        let mut client = builder.client().with_timeout(Duration::from_millis(200));
+
+        assert_eq!(aw(client.stat("missing")), Err(ClientError::NotFound));
+        assert_eq!(aw(client.download("missing")), Err(ClientError::NotFound));
+    }
+
+    #[test]
+    fn download_timeout() {
+        let builder = TestNetworkBuilder::default();
+        let nodes = (0..4).map(|_| builder.node()).collect::<Vec<_>>();
+        let mut client = builder.client().with_timeout(Duration::from_millis(200));
+
+        let content = "hello world!".repeat(100);
+        let name = "hello".to_string();
+
+        aw(nodes[0].upload(name.clone(), content)).unwrap();
+        eventually(|| nodes.iter().all(|node| !node.shards(&name).is_empty()));
+
+        // The only reachable peer knows the file but holds too few shards to decode it.
+        for id in 0..3 {
+            builder.leave(id);
+        }
+        assert!(nodes[3].shards(&name).len() < nodes[3].metadata(&name).unwrap().data_shards());
+
+        assert_eq!(
+            aw(client.download(&name)),
+            Err(ClientError::Node(Error::Timeout))
+        );
+    }
+
+    #[test]
+    fn overwrite() {
+        let builder = TestNetworkBuilder::default();
+        let nodes = (0..4).map(|_| builder.node()).collect::<Vec<_>>();
+        let mut client = builder.client().with_timeout(Duration::from_millis(500));
+
+        let name = "hello".to_string();
+        aw(client.upload(name.clone(), "first")).unwrap();
+        eventually(|| nodes.iter().all(|node| node.metadata(&name).is_some()));
+        let first = nodes[0].metadata(&name).unwrap();
+
+        aw(client.upload(name.clone(), "second")).unwrap();
+        eventually(|| {
+            nodes.iter().all(|node| {
+                node.metadata(&name)
+                    .is_some_and(|meta| meta.stamp() != first.stamp())
+            })
+        });
+
+        for node in &nodes {
+            assert!(node.conflicts().is_empty());
+            assert!(node.siblings(&name).is_empty());
+        }
+        assert_eq!(aw(client.download_string(&name)).unwrap(), "second");
+    }
+
+    #[test]
+    fn list_and_delete() {
+        let builder = TestNetworkBuilder::default();
+        let nodes = (0..3).map(|_| builder.node()).collect::<Vec<_>>();
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&progress);
+        let mut client = builder
+            .client()
+            .with_timeout(Duration::from_millis(500))
+            .with_progress(move |done, total| seen.lock().unwrap().push((done, total)));
+
+        for name in ["b", "a"] {
+            aw(client.upload(name.to_string(), "hello world!".repeat(10))).unwrap();
+        }
+        eventually(|| nodes.iter().all(|node| node.metadata("b").is_some()));
+        let (done, total) = *progress.lock().unwrap().last().unwrap();
+        assert_eq!(done, total);
+
+        let listed = aw(client.list()).unwrap();
+        let names = listed
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a", "b"]);
+        assert_eq!(Some(&listed[1].1), nodes[0].metadata("b").as_ref());
+
+        let status = aw(client.status()).unwrap();
+        assert_eq!(status.len(), 3);
+        assert!(
+            status
+                .iter()
+                .all(|peer| peer.latency.is_some() && peer.files == 2)
+        );
+
+        aw(client.delete("a")).unwrap();
+        eventually(|| nodes.iter().all(|node| node.metadata("a").is_none()));
+        assert_eq!(aw(client.stat("a")), Err(ClientError::NotFound));
+        assert_eq!(aw(client.delete("a")), Err(ClientError::NotFound));
+    }
+
+    #[test]
+    fn status_unreachable() {
+        let builder = TestNetworkBuilder::default();
+        // A member that never runs, so it never answers.
+        let _silent = builder.spawn(true);
+        let mut client = builder.client().with_timeout(Duration::from_millis(100));
+
+        let status = aw(client.status()).unwrap();
+        assert_eq!(status.len(), 1);
+        assert_eq!(status[0].latency, None);
+        assert_eq!(aw(client.list()), Err(ClientError::Unreachable));
+    }
+
+    #[test]
+    fn large_layout() {
+        let builder = TestNetworkBuilder::default();
+        let nodes = (0..3).map(|_| builder.node()).collect::<Vec<_>>();
+        let mut client = builder.client().with_timeout(Duration::from_millis(200));
+
+        // Large content gets larger shards rather than thousands of small ones.
+        let content = (0..300_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        aw(client.upload("large".to_string(), &content)).unwrap();
+        eventually(|| nodes.iter().all(|node| node.metadata("large").is_some()));
+
+        let meta = aw(client.stat("large")).unwrap();
+        assert_eq!((meta.data_shards(), meta.block_count()), (64, 1));
+        assert_eq!(aw(client.download("large")).unwrap(), content);
+    }
+}
@@ -0,0 +1,102 @@
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+use erasure_node::{
+    codec,
+    file::{self, EncodeConfig, File},
+    network::Command,
+};
+
+fn content() -> String {
+    "hello world! ".repeat(300)
+}
+
+fn encode(c: &mut Criterion) {
+    let content = content();
+    c.bench_function("encode", |b| {
+        b.iter(|| File::encode(black_box(&content)).unwrap())
+    });
+}
+
+// Large enough for the `parallel` feature to split every shard across the rayon pool.
+fn encode_large(c: &mut Criterion) {
+    let content = (0..4 << 20).map(|i| i as u8).collect::<Vec<_>>();
+    let config = EncodeConfig::new(10, 4);
+    c.bench_function("encode_large", |b| {
+        b.iter(|| File::encode_with(black_box(&content), config).unwrap())
+    });
+}
+
+fn serve(c: &mut Criterion) {
+    let file = File::encode(content()).unwrap();
+    let stamp = file.metadata().stamp();
+    c.bench_function("serve", |b| {
+        b.iter(|| {
+            file.shards()
+                .present_iter()
+                .map(|shard| {
+                    codec::encode(&Command::Replicate {
+                        name: "hello".into(),
+                        stamp,
+                        checksum: shard.crc(),
+                        shard,
+                    })
+                })
+                .map(|frame| frame.unwrap().len())
+                .sum::<usize>()
+        })
+    });
+}
+
+fn merge(c: &mut Criterion) {
+    let file = File::encode(content()).unwrap();
+    let stamp = file.metadata().stamp();
+    let frames = file
+        .shards()
+        .present_iter()
+        .map(|shard| {
+            codec::encode(&Command::Replicate {
+                name: "hello".into(),
+                stamp,
+                checksum: shard.crc(),
+                shard,
+            })
+            .unwrap()
+        })
+        .collect::<Vec<_>>();
+
+    c.bench_function("merge", |b| {
+        b.iter(|| {
+            let mut merged = File::empty(file.metadata().clone());
+            for frame in &frames {
+                if let Some(Command::Replicate { shard, .. }) = codec::decode(frame.clone()) {
+                    merged.shards_mut().merge(shard).unwrap();
+                }
+            }
+            merged
+        })
+    });
+}
+
+fn decode(c: &mut Criterion) {
+    let file = File::encode(content()).unwrap();
+    let mut degraded = File::empty(file.metadata().clone());
+    for shard in file
+        .shards()
+        .present_iter()
+        .skip(file.metadata().parity_shards())
+    {
+        degraded.shards_mut().merge(shard).unwrap();
+    }
+
+    c.bench_function("decode", |b| b.iter(|| degraded.decode().unwrap()));
+}
+
+fn request(c: &mut Criterion) {
+    let file = File::encode(content()).unwrap();
+    let bitmap = file.shards().bitmap();
+    c.bench_function("request", |b| {
+        b.iter(|| file::indices(black_box(&bitmap)).len())
+    });
+}
+
+criterion_group!(hot, encode, encode_large, serve, merge, decode, request);
+criterion_main!(hot);
@@ -0,0 +1,39 @@
+fn main() {
+    #[cfg(feature = "proto")]
+    proto();
+}
+
+// protoc is not required: the schema is parsed in Rust and handed to prost-build as a
+// descriptor set.
+#[cfg(feature = "proto")]
+fn proto() {
+    use prost::Message;
+    use prost_build::Config;
+    use prost_types::FileDescriptorSet;
+
+    let schema = "proto/erasure.proto";
+    println!("cargo:rerun-if-changed={schema}");
+
+    let parsed = protobuf_parse::Parser::new()
+        .pure()
+        .include("proto")
+        .input(schema)
+        .parse_and_typecheck()
+        .expect("malformed proto schema");
+
+    let mut set = protobuf::descriptor::FileDescriptorSet::new();
+    set.file = parsed.file_descriptors;
+    let bytes = protobuf::Message::write_to_bytes(&set).expect("unencodable descriptor set");
+    let set = FileDescriptorSet::decode(bytes.as_slice()).expect("undecodable descriptor set");
+
+    Config::new()
+        .bytes([
+            ".erasure.v1.Shard.data",
+            ".erasure.v1.Signature.signature",
+            ".erasure.v1.WrappedKey.sealed",
+            ".erasure.v1.Ingest.content",
+        ])
+        .btree_map([".erasure.v1.Metadata.attributes"])
+        .compile_fds(set)
+        .expect("failed to generate proto types");
+}
@@ -0,0 +1,190 @@
+use std::{
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::{
+    codec,
+    file::{Metadata, Shard},
+    network::Command,
+};
+
+const MAGIC: &[u8] = b"ERARCHV1";
+const TRAILER: usize = 8 + MAGIC.len();
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub name: String,
+    pub offset: usize,
+    pub len: usize,
+    pub shards: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArchivedFile {
+    pub name: String,
+    pub meta: Metadata,
+    pub shards: Vec<Shard>,
+    pub corrupt: Vec<usize>,
+}
+
+pub struct Archive {
+    data: Bytes,
+    index: Vec<IndexEntry>,
+}
+
+impl Archive {
+    // None if an entry is too large to frame.
+    pub fn encode(files: &[(String, Metadata, Vec<Shard>)]) -> Option<Bytes> {
+        let mut buf = BytesMut::new();
+        buf.put_slice(MAGIC);
+
+        let mut index = Vec::with_capacity(files.len());
+        for (name, meta, shards) in files {
+            let offset = buf.len();
+            let create = Command::Create {
+                name: name.clone(),
+                meta: meta.clone(),
+            };
+            buf.put_slice(&codec::encode(&create)?);
+
+            for shard in shards {
+                let replicate = Command::Replicate {
+                    name: name.clone(),
+                    stamp: meta.stamp(),
+                    checksum: shard.crc(),
+                    shard: shard.clone(),
+                };
+                buf.put_slice(&codec::encode(&replicate)?);
+            }
+
+            index.push(IndexEntry {
+                name: name.clone(),
+                offset,
+                len: buf.len() - offset,
+                shards: shards.len(),
+            });
+        }
+
+        let start = buf.len();
+        buf.put_u32(u32::try_from(index.len()).ok()?);
+        for entry in &index {
+            buf.put_u32(u32::try_from(entry.name.len()).ok()?);
+            buf.put_slice(entry.name.as_bytes());
+            buf.put_u64(entry.offset as u64);
+            buf.put_u64(entry.len as u64);
+            buf.put_u32(u32::try_from(entry.shards).ok()?);
+        }
+
+        buf.put_u64(start as u64);
+        buf.put_slice(MAGIC);
+        Some(buf.freeze())
+    }
+
+    pub fn write(
+        path: impl AsRef<Path>,
+        files: &[(String, Metadata, Vec<Shard>)],
+    ) -> io::Result<()> {
+        let data = Self::encode(files).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "archive entry too large")
+        })?;
+        std::fs::write(path, data)
+    }
+
+    pub fn decode(data: Bytes) -> Option<Self> {
+        if data.len() < MAGIC.len() + TRAILER || !data.starts_with(MAGIC) || !data.ends_with(MAGIC)
+        {
+            return None;
+        }
+
+        let mut trailer = data.slice(data.len() - TRAILER..);
+        let start = trailer.get_u64() as usize;
+        if start < MAGIC.len() || start > data.len() - TRAILER {
+            return None;
+        }
+
+        let mut buf = data.slice(start..data.len() - TRAILER);
+        let count = buf.try_get_u32().ok()?;
+        let index = (0..count)
+            .map(|_| {
+                let len = buf.try_get_u32().ok()? as usize;
+                if buf.len() < len {
+                    return None;
+                }
+                let name = String::from_utf8(buf.split_to(len).to_vec()).ok()?;
+                let entry = IndexEntry {
+                    name,
+                    offset: buf.try_get_u64().ok()? as usize,
+                    len: buf.try_get_u64().ok()? as usize,
+                    shards: buf.try_get_u32().ok()? as usize,
+                };
+
+                let end = entry.offset.checked_add(entry.len)?;
+                (entry.offset >= MAGIC.len() && end <= start).then_some(entry)
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        buf.is_empty().then_some(Self { data, index })
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let data = Bytes::from(std::fs::read(path)?);
+        Self::decode(data)
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "malformed archive"))
+    }
+
+    pub fn index(&self) -> &[IndexEntry] {
+        &self.index
+    }
+
+    pub fn get(&self, name: &str) -> Option<ArchivedFile> {
+        let entry = self.index.iter().find(|entry| entry.name == name)?;
+        self.read(entry)
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = Option<ArchivedFile>> + '_ {
+        self.index.iter().map(|entry| self.read(entry))
+    }
+
+    fn read(&self, entry: &IndexEntry) -> Option<ArchivedFile> {
+        let mut buf = BytesMut::from(&self.data[entry.offset..entry.offset + entry.len]);
+
+        let Command::Create { name, meta } = codec::decode(codec::split(&mut buf)?)? else {
+            return None;
+        };
+        if name != entry.name {
+            return None;
+        }
+
+        let (mut shards, mut corrupt) = (Vec::new(), Vec::new());
+        for _ in 0..entry.shards {
+            let Command::Replicate {
+                name,
+                stamp,
+                shard,
+                checksum,
+            } = codec::decode(codec::split(&mut buf)?)?
+            else {
+                return None;
+            };
+            if name != entry.name || stamp != meta.stamp() {
+                return None;
+            }
+
+            if shard.crc() == checksum {
+                shards.push(shard);
+            } else {
+                corrupt.push(shard.index());
+            }
+        }
+
+        buf.is_empty().then_some(ArchivedFile {
+            name,
+            meta,
+            shards,
+            corrupt,
+        })
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, Hasher, RandomState},
+    time::{Duration, Instant},
+};
+
+use siphasher::sip::SipHasher24;
+
+use crate::file::Shard;
+
+pub fn nonce() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+// SipHash-2-4 keyed by the nonce and shard index, so the holder and the
+// challenger compute the same proof whatever build or process they run.
+pub fn prove(shard: &Shard, nonce: u64) -> u64 {
+    let mut hasher = SipHasher24::new_with_keys(nonce, shard.index() as u64);
+    hasher.write(shard.data());
+    hasher.finish()
+}
+
+type Challenge = (u64, u64, Instant);
+
+pub(crate) struct Audits {
+    timeout: Duration,
+    placed: HashMap<String, HashMap<usize, String>>,
+    pending: HashMap<(String, String, usize), Challenge>,
+    passed: usize,
+    failed: usize,
+}
+
+impl Audits {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            placed: HashMap::new(),
+            pending: HashMap::new(),
+            passed: 0,
+            failed: 0,
+        }
+    }
+
+    pub fn placed(&mut self, name: String, index: usize, peer: String) {
+        self.placed.entry(name).or_default().insert(index, peer);
+    }
+
+    pub fn released(&mut self, name: &str, index: usize) {
+        if let Some(holders) = self.placed.get_mut(name) {
+            holders.remove(&index);
+        }
+    }
+
+    pub fn placements(&self) -> Vec<(String, usize, String)> {
+        self.placed
+            .iter()
+            .flat_map(|(name, holders)| {
+                holders
+                    .iter()
+                    .map(|(index, peer)| (name.clone(), *index, peer.clone()))
+            })
+            .collect()
+    }
+
+    pub fn holders(&self, name: &str) -> HashMap<usize, String> {
+        self.placed.get(name).cloned().unwrap_or_default()
+    }
+
+    pub fn challenge(&mut self, peer: String, name: String, shard: &Shard, now: Instant) -> u64 {
+        let nonce = nonce();
+        let expected = prove(shard, nonce);
+        self.pending
+            .insert((peer, name, shard.index()), (nonce, expected, now));
+        nonce
+    }
+
+    // Challenges left unanswered for longer than the timeout count as failed audits;
+    // returns the peers that let one lapse.
+    pub fn expire(&mut self, now: Instant) -> Vec<String> {
+        let timeout = self.timeout;
+        let mut expired = Vec::new();
+        self.pending.retain(|(peer, _, _), (_, _, sent)| {
+            let alive = now.saturating_duration_since(*sent) < timeout;
+            if !alive {
+                expired.push(peer.clone());
+            }
+            alive
+        });
+
+        self.failed += expired.len();
+        expired
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn verify(
+        &mut self,
+        peer: String,
+        name: String,
+        index: usize,
+        nonce: u64,
+        proof: Option<u64>,
+    ) -> Option<bool> {
+        let key = (peer, name, index);
+        if self.pending.get(&key)?.0 != nonce {
+            return None;
+        }
+
+        let (_, expected, _) = self.pending.remove(&key)?;
+        let passed = proof == Some(expected);
+        if passed {
+            self.passed += 1;
+        } else {
+            self.failed += 1;
+        }
+
+        Some(passed)
+    }
+
+    pub fn passed(&self) -> usize {
+        self.passed
+    }
+
+    pub fn failed(&self) -> usize {
+        self.failed
+    }
+}
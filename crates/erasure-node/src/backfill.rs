@@ -0,0 +1,88 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+type Key = (String, usize);
+
+#[derive(Default)]
+pub(crate) struct Backfill {
+    queued: VecDeque<Key>,
+    holders: HashMap<Key, VecDeque<String>>,
+    inflight: HashMap<Key, Instant>,
+    completed: usize,
+    abandoned: usize,
+}
+
+impl Backfill {
+    pub fn enqueue(&mut self, name: &str, index: usize, holders: Vec<String>) {
+        let key = (name.to_string(), index);
+        if holders.is_empty() || self.holders.contains_key(&key) {
+            return;
+        }
+
+        self.holders.insert(key.clone(), holders.into());
+        self.queued.push_back(key);
+    }
+
+    pub fn arrived(&mut self, name: &str, index: usize) -> bool {
+        let key = (name.to_string(), index);
+        if self.holders.remove(&key).is_none() {
+            return false;
+        }
+
+        self.queued.retain(|queued| *queued != key);
+        self.inflight.remove(&key);
+        self.completed += 1;
+        true
+    }
+
+    pub fn expire(&mut self, now: Instant, timeout: Duration) {
+        let expired = self
+            .inflight
+            .iter()
+            .filter(|(_, sent)| now.saturating_duration_since(**sent) >= timeout)
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+
+        for key in expired {
+            self.inflight.remove(&key);
+            let holders = self.holders.get_mut(&key).unwrap();
+            holders.pop_front();
+
+            if holders.is_empty() {
+                self.holders.remove(&key);
+                self.abandoned += 1;
+            } else {
+                self.queued.push_front(key);
+            }
+        }
+    }
+
+    pub fn next(&mut self, window: usize, now: Instant) -> Vec<(String, String, usize)> {
+        let mut fetches = Vec::new();
+        while self.inflight.len() < window {
+            let Some(key) = self.queued.pop_front() else {
+                break;
+            };
+
+            let holder = self.holders[&key][0].clone();
+            fetches.push((holder, key.0.clone(), key.1));
+            self.inflight.insert(key, now);
+        }
+
+        fetches
+    }
+
+    pub fn pending(&self) -> usize {
+        self.holders.len()
+    }
+
+    pub fn completed(&self) -> usize {
+        self.completed
+    }
+
+    pub fn abandoned(&self) -> usize {
+        self.abandoned
+    }
+}
@@ -0,0 +1,113 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use smallvec::SmallVec;
+
+struct Entry {
+    indices: SmallVec<[usize; 8]>,
+    inserted: Instant,
+    used: Instant,
+}
+
+pub(crate) struct ShardCache {
+    capacity: usize,
+    ttl: Duration,
+    entries: HashMap<String, Entry>,
+    hits: usize,
+    misses: usize,
+}
+
+impl ShardCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            entries: HashMap::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.contains_key(name)
+    }
+
+    pub fn insert(&mut self, name: &str, index: usize, now: Instant) -> Vec<(String, Vec<usize>)> {
+        let indices = &mut self
+            .entries
+            .entry(name.to_string())
+            .or_insert_with(|| Entry {
+                indices: SmallVec::new(),
+                inserted: now,
+                used: now,
+            })
+            .indices;
+        if !indices.contains(&index) {
+            indices.push(index);
+        }
+
+        let mut evicted = Vec::new();
+        while self.entries.len() > self.capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .filter(|(other, _)| *other != name)
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(name, _)| name.clone());
+
+            let Some(oldest) = oldest else {
+                break;
+            };
+
+            evicted.extend(self.remove(&oldest));
+        }
+
+        evicted
+    }
+
+    pub fn expire(&mut self, now: Instant) -> Vec<(String, Vec<usize>)> {
+        let expired = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| now.saturating_duration_since(entry.inserted) >= self.ttl)
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+
+        expired
+            .into_iter()
+            .filter_map(|name| self.remove(&name))
+            .collect()
+    }
+
+    pub fn keep(&mut self, name: &str, index: usize) {
+        if let Some(entry) = self.entries.get_mut(name) {
+            entry.indices.retain(|kept| *kept != index);
+        }
+    }
+
+    pub fn hit(&mut self, name: &str, now: Instant) {
+        if let Some(entry) = self.entries.get_mut(name) {
+            entry.used = now;
+            self.hits += 1;
+        }
+    }
+
+    pub fn miss(&mut self) {
+        self.misses += 1;
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    fn remove(&mut self, name: &str) -> Option<(String, Vec<usize>)> {
+        let entry = self.entries.remove(name)?;
+        Some((name.to_string(), entry.indices.into_vec()))
+    }
+}
@@ -0,0 +1,703 @@
+use std::cell::RefCell;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::{
+    compress::Compression,
+    file::{CodecId, Encryption, Metadata, Shard, Signature, Stamp, VectorClock, WrappedKey},
+    membership::{State, Update},
+    network::Command,
+    quota::Rejection,
+};
+
+const HEADER: usize = 4;
+
+// Bumped whenever fields are appended to a command or to the metadata. Decoders read the
+// fields their own version knows and skip whatever a newer writer appended after them.
+pub const VERSION: u8 = 1;
+const SCRATCH: usize = 64 * 1024;
+
+thread_local! {
+    static BUFFER: RefCell<BytesMut> = RefCell::new(BytesMut::new());
+}
+
+// None if a field is too long for its length prefix.
+pub fn encode(command: &Command) -> Option<Bytes> {
+    BUFFER.with_borrow_mut(|buf| {
+        if buf.capacity() < SCRATCH / 4 {
+            buf.reserve(SCRATCH);
+        }
+        match encode_into(command, buf) {
+            Some(()) => Some(buf.split().freeze()),
+            None => {
+                buf.clear();
+                None
+            }
+        }
+    })
+}
+
+fn encode_into(command: &Command, buf: &mut BytesMut) -> Option<()> {
+    buf.put_u32(0);
+    buf.put_u8(VERSION);
+
+    match command {
+        Command::Create { name, meta } => {
+            buf.put_u8(0);
+            put_str(buf, name)?;
+            put_meta(buf, meta)?;
+        }
+        Command::Replicate {
+            name,
+            stamp,
+            shard,
+            checksum,
+        } => {
+            buf.put_u8(1);
+            put_str(buf, name)?;
+            put_stamp(buf, stamp);
+            put_len(buf, shard.index())?;
+            put_len(buf, shard.size())?;
+            buf.put_slice(shard.data());
+            buf.put_u32(*checksum);
+        }
+        Command::Request { name, bitmap } => {
+            buf.put_u8(2);
+            put_str(buf, name)?;
+            put_len(buf, bitmap.len())?;
+            buf.put_slice(bitmap);
+        }
+        Command::Fetch { name, index } => {
+            buf.put_u8(3);
+            put_str(buf, name)?;
+            put_len(buf, *index)?;
+        }
+        Command::Cancel { name } => {
+            buf.put_u8(4);
+            put_str(buf, name)?;
+        }
+        Command::Announce => buf.put_u8(5),
+        Command::Manifest { files } => {
+            buf.put_u8(6);
+            put_len(buf, files.len())?;
+            for (name, meta) in files {
+                put_str(buf, name)?;
+                put_meta(buf, meta)?;
+            }
+        }
+        Command::Leave => buf.put_u8(7),
+        Command::Challenge { name, index, nonce } => {
+            buf.put_u8(8);
+            put_str(buf, name)?;
+            put_len(buf, *index)?;
+            buf.put_u64(*nonce);
+        }
+        Command::Proof {
+            name,
+            index,
+            nonce,
+            proof,
+        } => {
+            buf.put_u8(9);
+            put_str(buf, name)?;
+            put_len(buf, *index)?;
+            buf.put_u64(*nonce);
+            match proof {
+                Some(proof) => {
+                    buf.put_u8(1);
+                    buf.put_u64(*proof);
+                }
+                None => buf.put_u8(0),
+            }
+        }
+        Command::Reject { name, reason } => {
+            buf.put_u8(10);
+            put_str(buf, name)?;
+            buf.put_u8(match reason {
+                Rejection::Storage => 0,
+                Rejection::Bandwidth => 1,
+                Rejection::Decommissioning => 2,
+            });
+        }
+        Command::Drop { name, indices } => {
+            buf.put_u8(11);
+            put_str(buf, name)?;
+            put_len(buf, indices.len())?;
+            for index in indices {
+                put_len(buf, *index)?;
+            }
+        }
+        Command::RequestMeta { name } => {
+            buf.put_u8(12);
+            put_str(buf, name)?;
+        }
+        Command::Meta { name, meta, bitmap } => {
+            buf.put_u8(13);
+            put_str(buf, name)?;
+            put_meta(buf, meta)?;
+            put_len(buf, bitmap.len())?;
+            buf.put_slice(bitmap);
+        }
+        Command::Ingest { name, content } => {
+            buf.put_u8(14);
+            put_str(buf, name)?;
+            put_len(buf, content.len())?;
+            buf.put_slice(content);
+        }
+        Command::Ping { seq, updates } => {
+            buf.put_u8(15);
+            buf.put_u64(*seq);
+            put_updates(buf, updates)?;
+        }
+        Command::PingReq {
+            seq,
+            target,
+            updates,
+        } => {
+            buf.put_u8(16);
+            buf.put_u64(*seq);
+            put_str(buf, target)?;
+            put_updates(buf, updates)?;
+        }
+        Command::Ack { seq, updates } => {
+            buf.put_u8(17);
+            buf.put_u64(*seq);
+            put_updates(buf, updates)?;
+        }
+        Command::Nack { name, index } => {
+            buf.put_u8(18);
+            put_str(buf, name)?;
+            put_len(buf, *index)?;
+        }
+        Command::Credit { messages, bytes } => {
+            buf.put_u8(19);
+            buf.put_u64(*messages);
+            buf.put_u64(*bytes);
+        }
+        Command::Fragment {
+            id,
+            index,
+            count,
+            data,
+        } => {
+            buf.put_u8(20);
+            buf.put_u64(*id);
+            put_len(buf, *index)?;
+            put_len(buf, *count)?;
+            put_len(buf, data.len())?;
+            buf.put_slice(data);
+        }
+        Command::Offer { name, meta, layout } => {
+            buf.put_u8(21);
+            put_str(buf, name)?;
+            put_meta(buf, meta)?;
+            put_len(buf, layout.len())?;
+            for member in layout {
+                put_str(buf, member)?;
+            }
+        }
+        Command::Delete { name, stamp } => {
+            buf.put_u8(22);
+            put_str(buf, name)?;
+            put_stamp(buf, stamp);
+        }
+    }
+
+    let len = u32::try_from(buf.len() - HEADER).ok()?;
+    buf[..HEADER].copy_from_slice(&len.to_be_bytes());
+    Some(())
+}
+
+// The length `encode` would produce, worked out from the fields without building the frame.
+pub fn encoded_len(command: &Command) -> usize {
+    let body = match command {
+        Command::Create { name, meta } => str_len(name) + meta_len(meta),
+        Command::Replicate { name, shard, .. } => str_len(name) + STAMP + 4 + 4 + shard.size() + 4,
+        Command::Request { name, bitmap } => str_len(name) + 4 + bitmap.len(),
+        Command::Fetch { name, .. } => str_len(name) + 4,
+        Command::Cancel { name } => str_len(name),
+        Command::Announce | Command::Leave => 0,
+        Command::Manifest { files } => {
+            4 + files
+                .iter()
+                .map(|(name, meta)| str_len(name) + meta_len(meta))
+                .sum::<usize>()
+        }
+        Command::Challenge { name, .. } => str_len(name) + 4 + 8,
+        Command::Proof { name, proof, .. } => str_len(name) + 4 + 8 + 1 + proof.map_or(0, |_| 8),
+        Command::Reject { name, .. } => str_len(name) + 1,
+        Command::Drop { name, indices } => str_len(name) + 4 + 4 * indices.len(),
+        Command::RequestMeta { name } => str_len(name),
+        Command::Meta { name, meta, bitmap } => str_len(name) + meta_len(meta) + 4 + bitmap.len(),
+        Command::Ingest { name, content } => str_len(name) + 4 + content.len(),
+        Command::Ping { updates, .. } | Command::Ack { updates, .. } => 8 + updates_len(updates),
+        Command::PingReq {
+            target, updates, ..
+        } => 8 + str_len(target) + updates_len(updates),
+        Command::Nack { name, .. } => str_len(name) + 4,
+        Command::Credit { .. } => 8 + 8,
+        Command::Fragment { data, .. } => 8 + 4 + 4 + 4 + data.len(),
+        Command::Offer { name, meta, layout } => {
+            str_len(name)
+                + meta_len(meta)
+                + 4
+                + layout.iter().map(|member| str_len(member)).sum::<usize>()
+        }
+        Command::Delete { name, .. } => str_len(name) + STAMP,
+    };
+
+    HEADER + 2 + body
+}
+
+pub fn split(buf: &mut BytesMut) -> Option<Bytes> {
+    if buf.len() < HEADER {
+        return None;
+    }
+
+    let len = u32::from_be_bytes(buf[..HEADER].try_into().unwrap()) as usize;
+    if buf.len() < HEADER + len {
+        return None;
+    }
+
+    Some(buf.split_to(HEADER + len).freeze())
+}
+
+pub fn decode(mut frame: Bytes) -> Option<Command> {
+    let len = frame.try_get_u32().ok()? as usize;
+    if frame.len() != len {
+        return None;
+    }
+    let version = get_version(&mut frame)?;
+
+    let command = match frame.try_get_u8().ok()? {
+        0 => Command::Create {
+            name: get_str(&mut frame)?,
+            meta: get_meta(&mut frame)?,
+        },
+        1 => {
+            let name = get_str(&mut frame)?;
+            let stamp = get_stamp(&mut frame)?;
+            let index = frame.try_get_u32().ok()? as usize;
+            let data = get_slice(&mut frame)?;
+            Command::Replicate {
+                name,
+                stamp,
+                shard: Shard::new(index, data),
+                checksum: frame.try_get_u32().ok()?,
+            }
+        }
+        2 => Command::Request {
+            name: get_str(&mut frame)?,
+            bitmap: get_slice(&mut frame)?.to_vec(),
+        },
+        3 => Command::Fetch {
+            name: get_str(&mut frame)?,
+            index: frame.try_get_u32().ok()? as usize,
+        },
+        4 => Command::Cancel {
+            name: get_str(&mut frame)?,
+        },
+        5 => Command::Announce,
+        6 => {
+            let count = frame.try_get_u32().ok()?;
+            let files = (0..count)
+                .map(|_| Some((get_str(&mut frame)?, get_meta(&mut frame)?)))
+                .collect::<Option<Vec<_>>>()?;
+            Command::Manifest { files }
+        }
+        7 => Command::Leave,
+        8 => Command::Challenge {
+            name: get_str(&mut frame)?,
+            index: frame.try_get_u32().ok()? as usize,
+            nonce: frame.try_get_u64().ok()?,
+        },
+        9 => Command::Proof {
+            name: get_str(&mut frame)?,
+            index: frame.try_get_u32().ok()? as usize,
+            nonce: frame.try_get_u64().ok()?,
+            proof: match frame.try_get_u8().ok()? {
+                0 => None,
+                1 => Some(frame.try_get_u64().ok()?),
+                _ => return None,
+            },
+        },
+        10 => Command::Reject {
+            name: get_str(&mut frame)?,
+            reason: match frame.try_get_u8().ok()? {
+                0 => Rejection::Storage,
+                1 => Rejection::Bandwidth,
+                2 => Rejection::Decommissioning,
+                _ => return None,
+            },
+        },
+        11 => {
+            let name = get_str(&mut frame)?;
+            let count = frame.try_get_u32().ok()?;
+            let indices = (0..count)
+                .map(|_| Some(frame.try_get_u32().ok()? as usize))
+                .collect::<Option<Vec<_>>>()?;
+            Command::Drop { name, indices }
+        }
+        12 => Command::RequestMeta {
+            name: get_str(&mut frame)?,
+        },
+        13 => Command::Meta {
+            name: get_str(&mut frame)?,
+            meta: get_meta(&mut frame)?,
+            bitmap: get_slice(&mut frame)?.to_vec(),
+        },
+        14 => Command::Ingest {
+            name: get_str(&mut frame)?,
+            content: get_slice(&mut frame)?,
+        },
+        15 => Command::Ping {
+            seq: frame.try_get_u64().ok()?,
+            updates: get_updates(&mut frame)?,
+        },
+        16 => Command::PingReq {
+            seq: frame.try_get_u64().ok()?,
+            target: get_str(&mut frame)?,
+            updates: get_updates(&mut frame)?,
+        },
+        17 => Command::Ack {
+            seq: frame.try_get_u64().ok()?,
+            updates: get_updates(&mut frame)?,
+        },
+        18 => Command::Nack {
+            name: get_str(&mut frame)?,
+            index: frame.try_get_u32().ok()? as usize,
+        },
+        19 => Command::Credit {
+            messages: frame.try_get_u64().ok()?,
+            bytes: frame.try_get_u64().ok()?,
+        },
+        20 => Command::Fragment {
+            id: frame.try_get_u64().ok()?,
+            index: frame.try_get_u32().ok()? as usize,
+            count: frame.try_get_u32().ok()? as usize,
+            data: get_slice(&mut frame)?.to_vec(),
+        },
+        21 => {
+            let name = get_str(&mut frame)?;
+            let meta = get_meta(&mut frame)?;
+            let count = frame.try_get_u32().ok()?;
+            let layout = (0..count)
+                .map(|_| get_str(&mut frame))
+                .collect::<Option<Vec<_>>>()?;
+            Command::Offer { name, meta, layout }
+        }
+        22 => Command::Delete {
+            name: get_str(&mut frame)?,
+            stamp: get_stamp(&mut frame)?,
+        },
+        _ => return None,
+    };
+
+    known(&frame, version).then_some(command)
+}
+
+fn get_version(frame: &mut Bytes) -> Option<u8> {
+    frame.try_get_u8().ok().filter(|version| *version > 0)
+}
+
+// Leftover bytes are only expected from a newer writer.
+fn known(rest: &Bytes, version: u8) -> bool {
+    rest.is_empty() || version > VERSION
+}
+
+const STAMP: usize = 16;
+
+fn str_len(value: &str) -> usize {
+    4 + value.len()
+}
+
+fn meta_len(meta: &Metadata) -> usize {
+    let encryption = meta.encryption().map_or(0, |encryption| {
+        8 + encryption.nonce.len()
+            + 4
+            + encryption
+                .recipients
+                .iter()
+                .map(|wrapped| 32 + 32 + 4 + wrapped.sealed.len())
+                .sum::<usize>()
+    });
+    let signature = meta.signature().map_or(0, |signature| {
+        signature.signer.len() + 4 + signature.signature.len()
+    });
+    let attributes = meta
+        .attributes()
+        .iter()
+        .map(|(key, value)| str_len(key) + str_len(value))
+        .sum::<usize>();
+
+    HEADER
+        + 1
+        + 8
+        + 4 * 4
+        + 1
+        + STAMP
+        + 4
+        + 16 * meta.clock().entries().count()
+        + 4
+        + 4 * meta.checksums().len()
+        + 4
+        + 8 * meta.blocks().len()
+        + 1
+        + 1
+        + encryption
+        + 4
+        + 32 * meta.digests().len()
+        + 1
+        + signature
+        + 8
+        + str_len(meta.content_type().unwrap_or_default())
+        + 4
+        + attributes
+}
+
+fn updates_len(updates: &[Update]) -> usize {
+    4 + updates
+        .iter()
+        .map(|update| str_len(&update.member) + 1 + 8)
+        .sum::<usize>()
+}
+
+// Lengths, counts and indices go out as u32; anything wider cannot be framed.
+fn put_len(buf: &mut BytesMut, value: usize) -> Option<()> {
+    buf.put_u32(u32::try_from(value).ok()?);
+    Some(())
+}
+
+fn put_str(buf: &mut BytesMut, value: &str) -> Option<()> {
+    put_len(buf, value.len())?;
+    buf.put_slice(value.as_bytes());
+    Some(())
+}
+
+// Length-prefixed, so metadata from a newer writer can be skipped past inside a manifest.
+fn put_meta(buf: &mut BytesMut, meta: &Metadata) -> Option<()> {
+    let start = buf.len();
+    buf.put_u32(0);
+    buf.put_u8(VERSION);
+    buf.put_u64(meta.len());
+    put_len(buf, meta.shard_size())?;
+    put_len(buf, meta.stripes())?;
+    put_len(buf, meta.data_shards())?;
+    put_len(buf, meta.parity_shards())?;
+    buf.put_u8(meta.codec().id());
+    put_stamp(buf, &meta.stamp());
+    put_clock(buf, meta.clock())?;
+    put_len(buf, meta.checksums().len())?;
+    for checksum in meta.checksums() {
+        buf.put_u32(*checksum);
+    }
+    put_len(buf, meta.blocks().len())?;
+    for block in meta.blocks() {
+        buf.put_u64(*block);
+    }
+    buf.put_u8(meta.compression().id());
+    match meta.encryption() {
+        Some(encryption) => {
+            buf.put_u8(1);
+            buf.put_u64(encryption.key_id);
+            buf.put_slice(&encryption.nonce);
+            put_len(buf, encryption.recipients.len())?;
+            for wrapped in &encryption.recipients {
+                buf.put_slice(&wrapped.recipient);
+                buf.put_slice(&wrapped.ephemeral);
+                put_len(buf, wrapped.sealed.len())?;
+                buf.put_slice(&wrapped.sealed);
+            }
+        }
+        None => buf.put_u8(0),
+    }
+    put_len(buf, meta.digests().len())?;
+    for digest in meta.digests() {
+        buf.put_slice(digest);
+    }
+    match meta.signature() {
+        Some(signature) => {
+            buf.put_u8(1);
+            buf.put_slice(&signature.signer);
+            put_len(buf, signature.signature.len())?;
+            buf.put_slice(&signature.signature);
+        }
+        None => buf.put_u8(0),
+    }
+    buf.put_u64(meta.created_at());
+    put_str(buf, meta.content_type().unwrap_or_default())?;
+    put_len(buf, meta.attributes().len())?;
+    for (key, value) in meta.attributes() {
+        put_str(buf, key)?;
+        put_str(buf, value)?;
+    }
+
+    let len = u32::try_from(buf.len() - start - HEADER).ok()?;
+    buf[start..start + HEADER].copy_from_slice(&len.to_be_bytes());
+    Some(())
+}
+
+// The bytes a metadata signature covers: the metadata framed as usual, minus the signature.
+#[cfg(feature = "signing")]
+pub(crate) fn signable(meta: &Metadata) -> Option<Bytes> {
+    let mut buf = BytesMut::new();
+    put_meta(&mut buf, &meta.unsigned())?;
+    Some(buf.freeze())
+}
+
+fn put_stamp(buf: &mut BytesMut, stamp: &Stamp) {
+    buf.put_u64(stamp.time);
+    buf.put_u64(stamp.writer);
+}
+
+fn put_clock(buf: &mut BytesMut, clock: &VectorClock) -> Option<()> {
+    put_len(buf, clock.entries().count())?;
+    for (writer, counter) in clock.entries() {
+        buf.put_u64(writer);
+        buf.put_u64(counter);
+    }
+    Some(())
+}
+
+fn put_updates(buf: &mut BytesMut, updates: &[Update]) -> Option<()> {
+    put_len(buf, updates.len())?;
+    for update in updates {
+        put_str(buf, &update.member)?;
+        buf.put_u8(match update.state {
+            State::Alive => 0,
+            State::Suspect => 1,
+            State::Dead => 2,
+        });
+        buf.put_u64(update.incarnation);
+    }
+    Some(())
+}
+
+fn get_slice(frame: &mut Bytes) -> Option<Bytes> {
+    let len = frame.try_get_u32().ok()? as usize;
+    (frame.len() >= len).then(|| frame.split_to(len))
+}
+
+fn get_str(frame: &mut Bytes) -> Option<String> {
+    String::from_utf8(get_slice(frame)?.to_vec()).ok()
+}
+
+// Metadata whose layout does not hold together is rejected like a malformed frame.
+fn get_meta(frame: &mut Bytes) -> Option<Metadata> {
+    let mut frame = get_slice(frame)?;
+    let version = get_version(&mut frame)?;
+    let len = frame.try_get_u64().ok()?;
+    let shard_size = frame.try_get_u32().ok()?;
+    let stripes = frame.try_get_u32().ok()?;
+    let data_shards = frame.try_get_u32().ok()?;
+    let parity_shards = frame.try_get_u32().ok()?;
+    let codec = CodecId::from_id(frame.try_get_u8().ok()?)?;
+    let stamp = get_stamp(&mut frame)?;
+    let clock = get_clock(&mut frame)?;
+    let count = frame.try_get_u32().ok()?;
+    let checksums = (0..count)
+        .map(|_| frame.try_get_u32().ok())
+        .collect::<Option<_>>()?;
+    let count = frame.try_get_u32().ok()?;
+    let blocks = (0..count)
+        .map(|_| frame.try_get_u64().ok())
+        .collect::<Option<_>>()?;
+    let compression = Compression::from_id(frame.try_get_u8().ok()?)?;
+    let meta = Metadata::new(len, shard_size, stripes, data_shards, parity_shards, codec)
+        .with_stamp(stamp)
+        .with_clock(clock)
+        .with_checksums(checksums)
+        .with_blocks(blocks)
+        .with_compression(compression);
+    let meta = match frame.try_get_u8().ok()? {
+        0 => meta,
+        1 => {
+            let key_id = frame.try_get_u64().ok()?;
+            let mut nonce = [0; 24];
+            frame.try_copy_to_slice(&mut nonce).ok()?;
+            let count = frame.try_get_u32().ok()?;
+            let recipients = (0..count)
+                .map(|_| {
+                    let mut recipient = [0; 32];
+                    frame.try_copy_to_slice(&mut recipient).ok()?;
+                    let mut ephemeral = [0; 32];
+                    frame.try_copy_to_slice(&mut ephemeral).ok()?;
+                    let sealed = get_slice(&mut frame)?;
+                    Some(WrappedKey {
+                        recipient,
+                        ephemeral,
+                        sealed,
+                    })
+                })
+                .collect::<Option<_>>()?;
+            meta.with_encryption(Encryption {
+                key_id,
+                nonce,
+                recipients,
+            })
+        }
+        _ => return None,
+    };
+    let count = frame.try_get_u32().ok()?;
+    let digests = (0..count)
+        .map(|_| {
+            let mut digest = [0; 32];
+            frame.try_copy_to_slice(&mut digest).ok()?;
+            Some(digest)
+        })
+        .collect::<Option<_>>()?;
+    let meta = meta.with_digests(digests);
+    let meta = match frame.try_get_u8().ok()? {
+        0 => meta,
+        1 => {
+            let mut signer = [0; 32];
+            frame.try_copy_to_slice(&mut signer).ok()?;
+            let signature = get_slice(&mut frame)?;
+            meta.with_signature(Signature { signer, signature })
+        }
+        _ => return None,
+    };
+    let mut meta = meta.with_created_at(frame.try_get_u64().ok()?);
+    let content_type = get_str(&mut frame)?;
+    if !content_type.is_empty() {
+        meta = meta.with_content_type(content_type);
+    }
+    let count = frame.try_get_u32().ok()?;
+    let attributes = (0..count)
+        .map(|_| Some((get_str(&mut frame)?, get_str(&mut frame)?)))
+        .collect::<Option<_>>()?;
+    let meta = meta.with_attributes(attributes);
+    (known(&frame, version) && meta.is_consistent()).then_some(meta)
+}
+
+fn get_stamp(frame: &mut Bytes) -> Option<Stamp> {
+    Some(Stamp {
+        time: frame.try_get_u64().ok()?,
+        writer: frame.try_get_u64().ok()?,
+    })
+}
+
+fn get_clock(frame: &mut Bytes) -> Option<VectorClock> {
+    let count = frame.try_get_u32().ok()?;
+    (0..count)
+        .map(|_| Some((frame.try_get_u64().ok()?, frame.try_get_u64().ok()?)))
+        .collect()
+}
+
+fn get_updates(frame: &mut Bytes) -> Option<Vec<Update>> {
+    let count = frame.try_get_u32().ok()?;
+    (0..count)
+        .map(|_| {
+            Some(Update {
+                member: get_str(frame)?,
+                state: match frame.try_get_u8().ok()? {
+                    0 => State::Alive,
+                    1 => State::Suspect,
+                    2 => State::Dead,
+                    _ => return None,
+                },
+                incarnation: frame.try_get_u64().ok()?,
+            })
+        })
+        .collect()
+}
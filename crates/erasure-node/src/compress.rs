@@ -0,0 +1,71 @@
+use bytes::Bytes;
+
+#[cfg(feature = "zstd")]
+const ZSTD_LEVEL: i32 = 3;
+
+// Applied to shards as they leave a file and undone as they are merged back, so
+// reconstruction and checksums always see the shards as they were encoded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Compression {
+    #[default]
+    None,
+    #[cfg(feature = "lz4")]
+    Lz4,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Compression {
+    pub fn id(&self) -> u8 {
+        match self {
+            Self::None => 0,
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => 1,
+            #[cfg(feature = "zstd")]
+            Self::Zstd => 2,
+        }
+    }
+
+    // None for unknown ids and for algorithms compiled out of this build.
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Self::None),
+            #[cfg(feature = "lz4")]
+            1 => Some(Self::Lz4),
+            #[cfg(feature = "zstd")]
+            2 => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+
+    // Deterministic, so every holder of a shard serves the same bytes.
+    pub fn compress(&self, data: &Bytes) -> Bytes {
+        match self {
+            Self::None => data.clone(),
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => lz4_flex::block::compress(data).into(),
+            #[cfg(feature = "zstd")]
+            Self::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL)
+                .expect("zstd compression failed")
+                .into(),
+        }
+    }
+
+    // Fails unless the data expands to exactly `size` bytes, which also bounds the allocation.
+    pub fn decompress(&self, data: &Bytes, size: usize) -> Option<Bytes> {
+        let decompressed = match self {
+            Self::None => data.clone(),
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => {
+                let mut out = vec![0; size];
+                let len = lz4_flex::block::decompress_into(data, &mut out).ok()?;
+                out.truncate(len);
+                out.into()
+            }
+            #[cfg(feature = "zstd")]
+            Self::Zstd => zstd::bulk::decompress(data, size).ok()?.into(),
+        };
+        (decompressed.len() == size).then_some(decompressed)
+    }
+}
@@ -0,0 +1,238 @@
+use std::time::Duration;
+
+#[cfg(feature = "signing")]
+use crate::sign::{SigningKey, VerifyingKey};
+use crate::{
+    compress::Compression,
+    file::{DEFAULT_SHARD_SIZE, EncodeConfig},
+    quota::Quota,
+    redundancy::AdaptiveRedundancy,
+    store::StoreConfig,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Replication {
+    #[default]
+    Push,
+    Pull,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct NodeConfig {
+    pub adaptive_redundancy: Option<AdaptiveRedundancy>,
+    pub encoding: Option<EncodeConfig>,
+    pub shard_size: usize,
+    pub compression: Option<Compression>,
+    pub quota: Quota,
+    pub cache_capacity: usize,
+    pub cache_ttl: Duration,
+    pub decoded_capacity: usize,
+    pub decoded_ttl: Duration,
+    pub repair_interval: Option<Duration>,
+    // Members every file's metadata is kept on; None for a majority of the live members.
+    pub manifest_replicas: Option<usize>,
+    pub race_factor: usize,
+    pub request_window: Duration,
+    pub negative_ttl: Duration,
+    pub join_sample: usize,
+    pub backfill_window: usize,
+    pub backfill_timeout: Duration,
+    pub backfill_attempts: usize,
+    pub nack_retries: usize,
+    pub nack_ttl: Duration,
+    pub audit_timeout: Duration,
+    pub failure_sample: Duration,
+    pub peer_ttl: Duration,
+    pub orphan_capacity: usize,
+    pub orphan_ttl: Duration,
+    pub stream_timeout: Duration,
+    pub replication: Replication,
+    pub store: StoreConfig,
+    // Kept out of serialized configs; set it with `with_signing_key`.
+    #[cfg(feature = "signing")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub signing_key: Option<SigningKey>,
+    #[cfg(feature = "signing")]
+    pub trusted_signers: Vec<VerifyingKey>,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            adaptive_redundancy: None,
+            encoding: None,
+            shard_size: DEFAULT_SHARD_SIZE,
+            compression: None,
+            quota: Quota::default(),
+            cache_capacity: 64,
+            cache_ttl: Duration::from_secs(300),
+            decoded_capacity: 64,
+            decoded_ttl: Duration::from_secs(60),
+            repair_interval: None,
+            manifest_replicas: None,
+            race_factor: 2,
+            request_window: Duration::from_millis(50),
+            negative_ttl: Duration::from_millis(500),
+            join_sample: 3,
+            backfill_window: 4,
+            backfill_timeout: Duration::from_secs(2),
+            backfill_attempts: 3,
+            nack_retries: 3,
+            nack_ttl: Duration::from_secs(5),
+            audit_timeout: Duration::from_secs(2),
+            failure_sample: Duration::from_secs(1),
+            peer_ttl: Duration::from_secs(600),
+            orphan_capacity: 256,
+            orphan_ttl: Duration::from_secs(2),
+            stream_timeout: Duration::from_secs(2),
+            replication: Replication::Push,
+            store: StoreConfig::Memory,
+            #[cfg(feature = "signing")]
+            signing_key: None,
+            #[cfg(feature = "signing")]
+            trusted_signers: Vec::new(),
+        }
+    }
+}
+
+impl NodeConfig {
+    pub fn with_adaptive_redundancy(mut self, policy: AdaptiveRedundancy) -> Self {
+        self.adaptive_redundancy = Some(policy);
+        self
+    }
+
+    pub fn with_encoding(mut self, encoding: EncodeConfig) -> Self {
+        self.encoding = Some(encoding);
+        self
+    }
+
+    pub fn with_shard_size(mut self, shard_size: usize) -> Self {
+        self.shard_size = shard_size.max(1);
+        self
+    }
+
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    pub fn with_quota(mut self, quota: Quota) -> Self {
+        self.quota = quota;
+        self
+    }
+
+    pub fn with_cache(mut self, capacity: usize, ttl: Duration) -> Self {
+        self.cache_capacity = capacity;
+        self.cache_ttl = ttl;
+        self
+    }
+
+    pub fn with_decoded_cache(mut self, capacity: usize, ttl: Duration) -> Self {
+        self.decoded_capacity = capacity;
+        self.decoded_ttl = ttl;
+        self
+    }
+
+    pub fn with_repair_interval(mut self, interval: Duration) -> Self {
+        self.repair_interval = Some(interval);
+        self
+    }
+
+    pub fn with_manifest_replicas(mut self, replicas: usize) -> Self {
+        self.manifest_replicas = Some(replicas.max(1));
+        self
+    }
+
+    pub fn with_race_factor(mut self, factor: usize) -> Self {
+        self.race_factor = factor.max(1);
+        self
+    }
+
+    pub fn with_request_dedup(mut self, window: Duration, negative_ttl: Duration) -> Self {
+        self.request_window = window;
+        self.negative_ttl = negative_ttl;
+        self
+    }
+
+    pub fn with_join_sample(mut self, sample: usize) -> Self {
+        self.join_sample = sample;
+        self
+    }
+
+    pub fn with_backfill(mut self, window: usize, timeout: Duration, attempts: usize) -> Self {
+        self.backfill_window = window.max(1);
+        self.backfill_timeout = timeout;
+        self.backfill_attempts = attempts.max(1);
+        self
+    }
+
+    pub fn with_nack_retries(mut self, retries: usize) -> Self {
+        self.nack_retries = retries;
+        self
+    }
+
+    pub fn with_nack_ttl(mut self, ttl: Duration) -> Self {
+        self.nack_ttl = ttl;
+        self
+    }
+
+    // Samples the share of known peers missing from discovery at most once per `sample`,
+    // and forgets peers that have not been discovered for `ttl`.
+    pub fn with_failure_tracking(mut self, sample: Duration, ttl: Duration) -> Self {
+        self.failure_sample = sample;
+        self.peer_ttl = ttl;
+        self
+    }
+
+    // A challenge not answered within this counts as a failed audit.
+    pub fn with_audit_timeout(mut self, timeout: Duration) -> Self {
+        self.audit_timeout = timeout;
+        self
+    }
+
+    pub fn with_orphans(mut self, capacity: usize, ttl: Duration) -> Self {
+        self.orphan_capacity = capacity;
+        self.orphan_ttl = ttl;
+        self
+    }
+
+    // A download stream ends once no shard has arrived for this long.
+    pub fn with_stream_timeout(mut self, timeout: Duration) -> Self {
+        self.stream_timeout = timeout;
+        self
+    }
+
+    pub fn with_replication(mut self, replication: Replication) -> Self {
+        self.replication = replication;
+        self
+    }
+
+    // Only `Node::open` opens the store; `Node::new` keeps everything in memory.
+    pub fn with_store(mut self, store: StoreConfig) -> Self {
+        self.store = store;
+        self
+    }
+
+    #[cfg(feature = "signing")]
+    pub fn with_signing_key(mut self, key: SigningKey) -> Self {
+        self.signing_key = Some(key);
+        self
+    }
+
+    // Once any signer is trusted, metadata that is unsigned or signed by anyone else is refused.
+    #[cfg(feature = "signing")]
+    pub fn with_trusted_signer(mut self, key: VerifyingKey) -> Self {
+        self.trusted_signers.push(key);
+        self
+    }
+}
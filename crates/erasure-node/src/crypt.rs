@@ -0,0 +1,193 @@
+use bytes::Bytes;
+use chacha20poly1305::{
+    AeadCore, KeyInit, XChaCha20Poly1305, XNonce,
+    aead::{Aead, OsRng, Payload},
+};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+
+use crate::file::{Encryption, Metadata, WrappedKey};
+
+const WRAP_INFO: &[u8] = b"erasure-node key wrap v1";
+
+// A per-file secret; `id` is recorded in the metadata so the owner can tell which key to use,
+// while the secret itself never leaves the caller.
+#[derive(Clone)]
+pub struct Key {
+    id: u64,
+    secret: [u8; 32],
+    recipients: Vec<[u8; 32]>,
+}
+
+impl std::fmt::Debug for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Key").field("id", &self.id).finish()
+    }
+}
+
+impl Key {
+    pub fn new(id: u64, secret: [u8; 32]) -> Self {
+        Self {
+            id,
+            secret,
+            recipients: Vec::new(),
+        }
+    }
+
+    // Peers (by `Identity::public`) that get the key wrapped into the metadata of every file it
+    // seals, so they can open the file without being handed the key.
+    pub fn with_recipients(mut self, recipients: impl IntoIterator<Item = [u8; 32]>) -> Self {
+        self.recipients = recipients.into_iter().collect();
+        self
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn recipients(&self) -> &[[u8; 32]] {
+        &self.recipients
+    }
+
+    // A fresh ephemeral key per wrap means the wrapping key is never reused, so the nonce can be fixed.
+    fn wrap(&self, recipient: [u8; 32]) -> WrappedKey {
+        let ephemeral = EphemeralSecret::random_from_rng(OsRng);
+        let public = PublicKey::from(&ephemeral).to_bytes();
+        let shared = ephemeral.diffie_hellman(&PublicKey::from(recipient));
+        let sealed = wrapping(shared.as_bytes(), &public, &recipient)
+            .encrypt(
+                &XNonce::default(),
+                Payload {
+                    msg: &self.secret,
+                    aad: &self.id.to_be_bytes(),
+                },
+            )
+            .expect("encryption failed");
+
+        WrappedKey {
+            recipient,
+            ephemeral: public,
+            sealed: Bytes::from(sealed),
+        }
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(&self.secret.into())
+    }
+}
+
+// The key id is authenticated along with the content, so a ciphertext cannot be
+// passed off under another key's id.
+pub(crate) fn seal(key: &Key, content: &[u8]) -> (Encryption, Vec<u8>) {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let aad = key.id.to_be_bytes();
+    let sealed = key
+        .cipher()
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: content,
+                aad: &aad,
+            },
+        )
+        .expect("encryption failed");
+
+    let encryption = Encryption {
+        key_id: key.id,
+        nonce: nonce.into(),
+        recipients: key
+            .recipients
+            .iter()
+            .map(|recipient| key.wrap(*recipient))
+            .collect(),
+    };
+    (encryption, sealed)
+}
+
+pub(crate) fn open(key: &Key, encryption: &Encryption, sealed: &[u8]) -> Option<Vec<u8>> {
+    if encryption.key_id != key.id {
+        return None;
+    }
+
+    let aad = key.id.to_be_bytes();
+    key.cipher()
+        .decrypt(
+            XNonce::from_slice(&encryption.nonce),
+            Payload {
+                msg: sealed,
+                aad: &aad,
+            },
+        )
+        .ok()
+}
+
+// Both public keys go into the derivation, binding the wrapping key to this exchange.
+fn wrapping(shared: &[u8; 32], ephemeral: &[u8; 32], recipient: &[u8; 32]) -> XChaCha20Poly1305 {
+    let salt = [ephemeral.as_slice(), recipient.as_slice()].concat();
+    let mut key = [0; 32];
+    Hkdf::<Sha256>::new(Some(&salt), shared)
+        .expand(WRAP_INFO, &mut key)
+        .expect("valid output length");
+    XChaCha20Poly1305::new(&key.into())
+}
+
+// A peer's long-term X25519 key pair; the public half is what a `Key` is wrapped for.
+#[derive(Clone)]
+pub struct Identity {
+    secret: StaticSecret,
+}
+
+impl std::fmt::Debug for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Identity")
+            .field("public", &self.public())
+            .finish()
+    }
+}
+
+impl Identity {
+    pub fn generate() -> Self {
+        Self {
+            secret: StaticSecret::random_from_rng(OsRng),
+        }
+    }
+
+    pub fn from_bytes(secret: [u8; 32]) -> Self {
+        Self {
+            secret: StaticSecret::from(secret),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.secret.to_bytes()
+    }
+
+    pub fn public(&self) -> [u8; 32] {
+        PublicKey::from(&self.secret).to_bytes()
+    }
+
+    // The file key wrapped for this identity, if the metadata carries one that opens.
+    pub fn unwrap(&self, meta: &Metadata) -> Option<Key> {
+        let encryption = meta.encryption()?;
+        let public = self.public();
+        let wrapped = encryption
+            .recipients
+            .iter()
+            .find(|wrapped| wrapped.recipient == public)?;
+
+        let shared = self
+            .secret
+            .diffie_hellman(&PublicKey::from(wrapped.ephemeral));
+        let secret = wrapping(shared.as_bytes(), &wrapped.ephemeral, &public)
+            .decrypt(
+                &XNonce::default(),
+                Payload {
+                    msg: &wrapped.sealed,
+                    aad: &encryption.key_id.to_be_bytes(),
+                },
+            )
+            .ok()?;
+        Some(Key::new(encryption.key_id, secret.try_into().ok()?))
+    }
+}
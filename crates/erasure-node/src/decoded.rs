@@ -0,0 +1,85 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+
+struct Entry {
+    version: u64,
+    content: Bytes,
+    inserted: Instant,
+}
+
+pub(crate) struct Decoded {
+    capacity: usize,
+    ttl: Duration,
+    entries: HashMap<String, Entry>,
+    hits: usize,
+    decodes: usize,
+}
+
+impl Decoded {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            entries: HashMap::new(),
+            hits: 0,
+            decodes: 0,
+        }
+    }
+
+    pub fn get(&mut self, name: &str, version: u64, now: Instant) -> Option<Bytes> {
+        let entry = self.entries.get(name)?;
+        if entry.version != version || now.saturating_duration_since(entry.inserted) >= self.ttl {
+            self.entries.remove(name);
+            return None;
+        }
+
+        self.hits += 1;
+        Some(entry.content.clone())
+    }
+
+    pub fn insert(&mut self, name: &str, version: u64, content: &Bytes, now: Instant) {
+        self.decodes += 1;
+        if self.capacity == 0 {
+            return;
+        }
+
+        let ttl = self.ttl;
+        self.entries
+            .retain(|_, entry| now.saturating_duration_since(entry.inserted) < ttl);
+
+        while self.entries.len() >= self.capacity && !self.entries.contains_key(name) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.inserted)
+                .map(|(name, _)| name.clone());
+
+            let Some(oldest) = oldest else {
+                break;
+            };
+
+            self.entries.remove(&oldest);
+        }
+
+        self.entries.insert(
+            name.to_string(),
+            Entry {
+                version,
+                content: content.clone(),
+                inserted: now,
+            },
+        );
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    pub fn decodes(&self) -> usize {
+        self.decodes
+    }
+}
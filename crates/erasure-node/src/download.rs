@@ -0,0 +1,96 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use tracing::Span;
+
+pub(crate) struct Download {
+    requested: HashMap<String, Instant>,
+    contacted: HashSet<String>,
+    tried: HashMap<usize, HashSet<String>>,
+    senders: HashMap<usize, String>,
+    failed: bool,
+    span: Span,
+}
+
+impl Download {
+    pub fn new(span: Span) -> Self {
+        Self {
+            requested: HashMap::new(),
+            contacted: HashSet::new(),
+            tried: HashMap::new(),
+            senders: HashMap::new(),
+            failed: false,
+            span,
+        }
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn schedule(&mut self, ranked: Vec<String>, count: usize, now: Instant) -> Vec<String> {
+        let mut next = ranked
+            .iter()
+            .filter(|peer| !self.contacted.contains(*peer))
+            .take(count)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if next.is_empty() {
+            self.contacted.clear();
+            self.failed = false;
+            next = ranked.into_iter().take(count).collect();
+        }
+
+        for peer in &next {
+            self.contacted.insert(peer.clone());
+            self.requested.insert(peer.clone(), now);
+        }
+
+        next
+    }
+
+    pub fn responded(&mut self, peer: &str, now: Instant) -> Option<Duration> {
+        self.requested
+            .remove(peer)
+            .map(|requested| now.saturating_duration_since(requested))
+    }
+
+    pub fn received(&mut self, index: usize, peer: String) {
+        self.senders.insert(index, peer);
+    }
+
+    pub fn sender(&self, index: usize) -> Option<String> {
+        self.senders.get(&index).cloned()
+    }
+
+    pub fn retry(&mut self, index: usize, peer: String, candidates: &[String]) -> Option<String> {
+        let tried = self.tried.entry(index).or_default();
+        tried.insert(peer);
+
+        let next = candidates
+            .iter()
+            .find(|candidate| !tried.contains(*candidate))
+            .cloned();
+
+        match &next {
+            Some(next) => {
+                tried.insert(next.clone());
+            }
+            None => self.failed = true,
+        }
+
+        next
+    }
+
+    pub fn finish(&mut self) -> Vec<String> {
+        self.requested.clear();
+        self.contacted.drain().collect()
+    }
+
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+}
@@ -0,0 +1,43 @@
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hasher},
+    time::Duration,
+};
+
+use crate::file::EncodeConfig;
+
+pub fn digest(content: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(content);
+    hasher.finish()
+}
+
+#[derive(Default)]
+pub(crate) struct Encodings {
+    known: HashMap<(u64, EncodeConfig), (String, Duration)>,
+    reused: usize,
+    saved: Duration,
+}
+
+impl Encodings {
+    pub fn lookup(&self, digest: u64, config: EncodeConfig) -> Option<(String, Duration)> {
+        self.known.get(&(digest, config)).cloned()
+    }
+
+    pub fn encoded(&mut self, digest: u64, config: EncodeConfig, name: String, cost: Duration) {
+        self.known.insert((digest, config), (name, cost));
+    }
+
+    pub fn reused(&mut self, cost: Duration) {
+        self.reused += 1;
+        self.saved += cost;
+    }
+
+    pub fn reuses(&self) -> usize {
+        self.reused
+    }
+
+    pub fn saved(&self) -> Duration {
+        self.saved
+    }
+}
@@ -0,0 +1,518 @@
+#[cfg(feature = "raptorq")]
+use raptorq::{
+    EncodingPacket, ObjectTransmissionInformation, PayloadId, SourceBlockDecoder,
+    SourceBlockEncoder, SourceBlockEncodingPlan, extended_source_block_symbols,
+};
+use reed_solomon_erasure::{self as rs, galois_8, galois_16};
+
+use crate::file::CodecId;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum CodecError {
+    #[error("unsupported shard counts")]
+    InvalidLayout,
+    #[error("shards have mismatched or unsupported sizes")]
+    InvalidShardSize,
+    #[error("too few shards to reconstruct")]
+    TooFewShards,
+}
+
+pub trait Codec: Send + Sync {
+    fn data_shards(&self) -> usize;
+
+    fn parity_shards(&self) -> usize;
+
+    // Shard sizes must be a multiple of the element the codec works on.
+    fn element(&self) -> usize {
+        1
+    }
+
+    // Fills the parity shards from the data shards.
+    fn encode(&self, shards: &mut [&mut [u8]]) -> Result<(), CodecError>;
+
+    // Rebuilds every shard not marked present, or only the data shards when `data_only`.
+    fn reconstruct(
+        &self,
+        shards: &mut [(&mut [u8], bool)],
+        data_only: bool,
+    ) -> Result<(), CodecError>;
+
+    fn verify(&self, shards: &[&[u8]]) -> Result<bool, CodecError>;
+
+    // Stored bytes per byte of content.
+    fn overhead(&self) -> f64 {
+        (self.data_shards() + self.parity_shards()) as f64 / self.data_shards().max(1) as f64
+    }
+}
+
+pub fn codec(
+    id: CodecId,
+    data_shards: usize,
+    parity_shards: usize,
+) -> Result<Box<dyn Codec>, CodecError> {
+    Ok(match id {
+        CodecId::ReedSolomon8 | CodecId::ReedSolomon16 => {
+            Box::new(ReedSolomon::new(id, data_shards, parity_shards)?)
+        }
+        CodecId::Xor => Box::new(Xor::new(data_shards, parity_shards)?),
+        CodecId::Replication => Box::new(Replicas::new(data_shards, parity_shards)?),
+        #[cfg(feature = "raptorq")]
+        CodecId::RaptorQ => Box::new(RaptorQ::new(data_shards, parity_shards)?),
+    })
+}
+
+// The layouts `codec` accepts, checked without building the codec. Groups other codecs form are
+// held to what galois_16 can encode.
+pub(crate) fn supports(id: CodecId, data_shards: usize, parity_shards: usize) -> bool {
+    let group = data_shards.saturating_add(parity_shards);
+    match id {
+        CodecId::ReedSolomon8 => data_shards > 0 && parity_shards > 0 && group <= 256,
+        CodecId::ReedSolomon16 => data_shards > 0 && parity_shards > 0 && group <= 65536,
+        CodecId::Xor => data_shards > 0 && parity_shards == 1 && group <= 65536,
+        CodecId::Replication => data_shards == 1 && group <= 65536,
+        #[cfg(feature = "raptorq")]
+        CodecId::RaptorQ => {
+            data_shards > 0
+                && data_shards <= RAPTORQ_MAX_SOURCE
+                && parity_shards
+                    <= RAPTORQ_MAX_SYMBOLS
+                        - extended_source_block_symbols(data_shards as u32) as usize
+        }
+    }
+}
+
+fn layout(shards: &[(&mut [u8], bool)], count: usize) -> Result<(), CodecError> {
+    let mut sizes = shards.iter().map(|(shard, _)| shard.len());
+    let size = sizes.next().unwrap_or(0);
+    if shards.len() != count || sizes.any(|other| other != size) {
+        return Err(CodecError::InvalidShardSize);
+    }
+    Ok(())
+}
+
+fn rs_error(err: rs::Error) -> CodecError {
+    match err {
+        rs::Error::TooFewShardsPresent => CodecError::TooFewShards,
+        rs::Error::TooFewDataShards | rs::Error::TooFewParityShards | rs::Error::TooManyShards => {
+            CodecError::InvalidLayout
+        }
+        _ => CodecError::InvalidShardSize,
+    }
+}
+
+// galois_8 for up to 256 shards, galois_16 (2-byte elements) beyond.
+pub enum ReedSolomon {
+    Narrow(Box<galois_8::ReedSolomon>),
+    Wide(Box<galois_16::ReedSolomon>),
+}
+
+fn wide(shard: &mut [u8]) -> Result<&mut [[u8; 2]], CodecError> {
+    match shard.as_chunks_mut() {
+        (elements, []) => Ok(elements),
+        _ => Err(CodecError::InvalidShardSize),
+    }
+}
+
+impl ReedSolomon {
+    pub fn new(id: CodecId, data_shards: usize, parity_shards: usize) -> Result<Self, CodecError> {
+        match id {
+            CodecId::ReedSolomon8 => galois_8::ReedSolomon::new(data_shards, parity_shards)
+                .map(|r| Self::Narrow(Box::new(r))),
+            CodecId::ReedSolomon16 => galois_16::ReedSolomon::new(data_shards, parity_shards)
+                .map(|r| Self::Wide(Box::new(r))),
+            _ => return Err(CodecError::InvalidLayout),
+        }
+        .map_err(rs_error)
+    }
+}
+
+impl Codec for ReedSolomon {
+    fn data_shards(&self) -> usize {
+        match self {
+            Self::Narrow(r) => r.data_shard_count(),
+            Self::Wide(r) => r.data_shard_count(),
+        }
+    }
+
+    fn parity_shards(&self) -> usize {
+        match self {
+            Self::Narrow(r) => r.parity_shard_count(),
+            Self::Wide(r) => r.parity_shard_count(),
+        }
+    }
+
+    fn element(&self) -> usize {
+        match self {
+            Self::Narrow(_) => 1,
+            Self::Wide(_) => 2,
+        }
+    }
+
+    fn encode(&self, shards: &mut [&mut [u8]]) -> Result<(), CodecError> {
+        match self {
+            Self::Narrow(r) => r.encode(shards).map_err(rs_error),
+            Self::Wide(r) => r
+                .encode(
+                    shards
+                        .iter_mut()
+                        .map(|shard| wide(shard))
+                        .collect::<Result<Vec<_>, _>>()?,
+                )
+                .map_err(rs_error),
+        }
+    }
+
+    fn reconstruct(
+        &self,
+        shards: &mut [(&mut [u8], bool)],
+        data_only: bool,
+    ) -> Result<(), CodecError> {
+        match self {
+            Self::Narrow(r) if data_only => r.reconstruct_data(shards).map_err(rs_error),
+            Self::Narrow(r) => r.reconstruct(shards).map_err(rs_error),
+            Self::Wide(r) => {
+                let mut shards = shards
+                    .iter_mut()
+                    .map(|(shard, present)| Ok((wide(shard)?, *present)))
+                    .collect::<Result<Vec<_>, CodecError>>()?;
+                if data_only {
+                    r.reconstruct_data(&mut shards).map_err(rs_error)
+                } else {
+                    r.reconstruct(&mut shards).map_err(rs_error)
+                }
+            }
+        }
+    }
+
+    fn verify(&self, shards: &[&[u8]]) -> Result<bool, CodecError> {
+        match self {
+            Self::Narrow(r) => r.verify(shards).map_err(rs_error),
+            Self::Wide(r) => r
+                .verify(
+                    &shards
+                        .iter()
+                        .map(|shard| match shard.as_chunks() {
+                            (elements, []) => Ok(elements),
+                            _ => Err(CodecError::InvalidShardSize),
+                        })
+                        .collect::<Result<Vec<_>, _>>()?,
+                )
+                .map_err(rs_error),
+        }
+    }
+}
+
+// A single parity shard holding the XOR of the data shards; survives the loss of any one shard.
+pub struct Xor {
+    data_shards: usize,
+}
+
+impl Xor {
+    pub fn new(data_shards: usize, parity_shards: usize) -> Result<Self, CodecError> {
+        if data_shards == 0 || parity_shards != 1 {
+            return Err(CodecError::InvalidLayout);
+        }
+        Ok(Self { data_shards })
+    }
+}
+
+fn xor_into(target: &mut [u8], source: &[u8]) {
+    for (target, source) in target.iter_mut().zip(source) {
+        *target ^= source;
+    }
+}
+
+impl Codec for Xor {
+    fn data_shards(&self) -> usize {
+        self.data_shards
+    }
+
+    fn parity_shards(&self) -> usize {
+        1
+    }
+
+    fn encode(&self, shards: &mut [&mut [u8]]) -> Result<(), CodecError> {
+        let Some((parity, data)) = shards.split_last_mut() else {
+            return Err(CodecError::InvalidShardSize);
+        };
+        if data.len() != self.data_shards || data.iter().any(|shard| shard.len() != parity.len()) {
+            return Err(CodecError::InvalidShardSize);
+        }
+
+        parity.fill(0);
+        for shard in data {
+            xor_into(parity, shard);
+        }
+        Ok(())
+    }
+
+    fn reconstruct(
+        &self,
+        shards: &mut [(&mut [u8], bool)],
+        data_only: bool,
+    ) -> Result<(), CodecError> {
+        layout(shards, self.data_shards + 1)?;
+        let mut missing = shards
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, present))| !present);
+        let Some((index, _)) = missing.next() else {
+            return Ok(());
+        };
+        if missing.next().is_some() {
+            return Err(CodecError::TooFewShards);
+        }
+        if data_only && index == self.data_shards {
+            return Ok(());
+        }
+
+        let (before, rest) = shards.split_at_mut(index);
+        let (target, after) = rest.split_first_mut().unwrap();
+        target.0.fill(0);
+        for (shard, _) in before.iter().chain(after.iter()) {
+            xor_into(target.0, shard);
+        }
+        target.1 = true;
+        Ok(())
+    }
+
+    fn verify(&self, shards: &[&[u8]]) -> Result<bool, CodecError> {
+        let Some((parity, data)) = shards.split_last() else {
+            return Err(CodecError::InvalidShardSize);
+        };
+        if data.len() != self.data_shards || data.iter().any(|shard| shard.len() != parity.len()) {
+            return Err(CodecError::InvalidShardSize);
+        }
+
+        let mut expected = vec![0; parity.len()];
+        for shard in data {
+            xor_into(&mut expected, shard);
+        }
+        Ok(expected == *parity)
+    }
+}
+
+// N-way replication: one data shard and `copies` identical parity shards.
+pub struct Replicas {
+    copies: usize,
+}
+
+impl Replicas {
+    pub fn new(data_shards: usize, parity_shards: usize) -> Result<Self, CodecError> {
+        if data_shards != 1 {
+            return Err(CodecError::InvalidLayout);
+        }
+        Ok(Self {
+            copies: parity_shards,
+        })
+    }
+}
+
+impl Codec for Replicas {
+    fn data_shards(&self) -> usize {
+        1
+    }
+
+    fn parity_shards(&self) -> usize {
+        self.copies
+    }
+
+    fn encode(&self, shards: &mut [&mut [u8]]) -> Result<(), CodecError> {
+        let Some((data, copies)) = shards.split_first_mut() else {
+            return Err(CodecError::InvalidShardSize);
+        };
+        if copies.len() != self.copies || copies.iter().any(|copy| copy.len() != data.len()) {
+            return Err(CodecError::InvalidShardSize);
+        }
+
+        for copy in copies {
+            copy.copy_from_slice(data);
+        }
+        Ok(())
+    }
+
+    fn reconstruct(
+        &self,
+        shards: &mut [(&mut [u8], bool)],
+        data_only: bool,
+    ) -> Result<(), CodecError> {
+        layout(shards, self.copies + 1)?;
+        let Some(source) = shards.iter().position(|(_, present)| *present) else {
+            return Err(CodecError::TooFewShards);
+        };
+
+        let source = shards[source].0.to_vec();
+        let targets = if data_only {
+            &mut shards[..1]
+        } else {
+            &mut shards[..]
+        };
+        for (target, present) in targets.iter_mut().filter(|(_, present)| !present) {
+            target.copy_from_slice(&source);
+            *present = true;
+        }
+        Ok(())
+    }
+
+    fn verify(&self, shards: &[&[u8]]) -> Result<bool, CodecError> {
+        let Some((data, copies)) = shards.split_first() else {
+            return Err(CodecError::InvalidShardSize);
+        };
+        if copies.len() != self.copies || copies.iter().any(|copy| copy.len() != data.len()) {
+            return Err(CodecError::InvalidShardSize);
+        }
+        Ok(copies.iter().all(|copy| copy == data))
+    }
+}
+
+// RFC 6330 caps a source block at 56403 symbols and encoding symbol ids at 24 bits.
+#[cfg(feature = "raptorq")]
+const RAPTORQ_MAX_SOURCE: usize = 56403;
+#[cfg(feature = "raptorq")]
+const RAPTORQ_MAX_SYMBOLS: usize = 1 << 24;
+
+// A systematic RaptorQ fountain code: data shards are the source symbols and parity shard `i` is
+// repair symbol `i`, of which there are as many as the id space allows. Any `data_shards` shards
+// decode with roughly 99% probability, and each extra shard cuts the failure rate a hundredfold.
+#[cfg(feature = "raptorq")]
+pub struct RaptorQ {
+    data_shards: usize,
+    parity_shards: usize,
+    plan: SourceBlockEncodingPlan,
+}
+
+#[cfg(feature = "raptorq")]
+impl RaptorQ {
+    pub fn new(data_shards: usize, parity_shards: usize) -> Result<Self, CodecError> {
+        if data_shards == 0
+            || data_shards > RAPTORQ_MAX_SOURCE
+            || parity_shards
+                > RAPTORQ_MAX_SYMBOLS - extended_source_block_symbols(data_shards as u32) as usize
+        {
+            return Err(CodecError::InvalidLayout);
+        }
+        Ok(Self {
+            data_shards,
+            parity_shards,
+            plan: SourceBlockEncodingPlan::generate(data_shards as u16),
+        })
+    }
+
+    // Symbols, and so the stripes a file is coded in, are at most 64KiB.
+    fn config(&self, size: usize) -> Result<ObjectTransmissionInformation, CodecError> {
+        match u16::try_from(size) {
+            Ok(size) if size > 0 => Ok(ObjectTransmissionInformation::new(0, size, 0, 1, 1)),
+            _ => Err(CodecError::InvalidShardSize),
+        }
+    }
+
+    // Repair symbol ids start after the padding symbols RaptorQ extends the block with.
+    fn symbol(&self, index: usize) -> u32 {
+        match index.checked_sub(self.data_shards) {
+            Some(repair) => extended_source_block_symbols(self.data_shards as u32) + repair as u32,
+            None => index as u32,
+        }
+    }
+
+    fn encoder(&self, data: &[u8], size: usize) -> Result<SourceBlockEncoder, CodecError> {
+        let config = self.config(size)?;
+        Ok(SourceBlockEncoder::with_encoding_plan2(
+            0, &config, data, &self.plan,
+        ))
+    }
+
+    fn repair(&self, encoder: &SourceBlockEncoder, index: usize) -> Vec<u8> {
+        let repair = (index - self.data_shards) as u32;
+        let mut packets = encoder.repair_packets(repair, 1);
+        packets.pop().unwrap().split().1
+    }
+}
+
+#[cfg(feature = "raptorq")]
+impl Codec for RaptorQ {
+    fn data_shards(&self) -> usize {
+        self.data_shards
+    }
+
+    fn parity_shards(&self) -> usize {
+        self.parity_shards
+    }
+
+    fn encode(&self, shards: &mut [&mut [u8]]) -> Result<(), CodecError> {
+        let size = shards.first().map_or(0, |shard| shard.len());
+        if shards.len() != self.data_shards + self.parity_shards
+            || shards.iter().any(|shard| shard.len() != size)
+        {
+            return Err(CodecError::InvalidShardSize);
+        }
+
+        let (data, parity) = shards.split_at_mut(self.data_shards);
+        let encoder = self.encoder(&data.concat(), size)?;
+        for (offset, shard) in parity.iter_mut().enumerate() {
+            shard.copy_from_slice(&self.repair(&encoder, self.data_shards + offset));
+        }
+        Ok(())
+    }
+
+    fn reconstruct(
+        &self,
+        shards: &mut [(&mut [u8], bool)],
+        data_only: bool,
+    ) -> Result<(), CodecError> {
+        layout(shards, self.data_shards + self.parity_shards)?;
+        let size = shards[0].0.len();
+        let config = self.config(size)?;
+        let limit = if data_only {
+            self.data_shards
+        } else {
+            shards.len()
+        };
+        if shards[..limit].iter().all(|(_, present)| *present) {
+            return Ok(());
+        }
+
+        let packets = shards
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, present))| *present)
+            .map(|(index, (shard, _))| {
+                EncodingPacket::new(PayloadId::new(0, self.symbol(index)), shard.to_vec())
+            });
+        let mut decoder = SourceBlockDecoder::new2(0, &config, (self.data_shards * size) as u64);
+        let data = decoder.decode(packets).ok_or(CodecError::TooFewShards)?;
+
+        let (source, repair) = shards[..limit].split_at_mut(self.data_shards);
+        for ((shard, present), symbol) in source.iter_mut().zip(data.chunks(size)) {
+            if !*present {
+                shard.copy_from_slice(symbol);
+                *present = true;
+            }
+        }
+        if repair.iter().any(|(_, present)| !present) {
+            let encoder = self.encoder(&data, size)?;
+            for (offset, (shard, present)) in repair.iter_mut().enumerate() {
+                if !*present {
+                    shard.copy_from_slice(&self.repair(&encoder, self.data_shards + offset));
+                    *present = true;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn verify(&self, shards: &[&[u8]]) -> Result<bool, CodecError> {
+        let size = shards.first().map_or(0, |shard| shard.len());
+        if shards.len() != self.data_shards + self.parity_shards
+            || shards.iter().any(|shard| shard.len() != size)
+        {
+            return Err(CodecError::InvalidShardSize);
+        }
+
+        let (data, parity) = shards.split_at(self.data_shards);
+        let encoder = self.encoder(&data.concat(), size)?;
+        Ok(parity
+            .iter()
+            .enumerate()
+            .all(|(offset, shard)| self.repair(&encoder, self.data_shards + offset) == *shard))
+    }
+}
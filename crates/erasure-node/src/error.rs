@@ -0,0 +1,40 @@
+use std::{io, string::FromUtf8Error};
+
+use crate::file::{DecodeError, ShardError};
+
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum Error {
+    #[error(
+        "cannot encode {len} bytes as {data_shards}+{parity_shards} shards of {shard_size} bytes"
+    )]
+    Encode {
+        len: usize,
+        data_shards: usize,
+        parity_shards: usize,
+        shard_size: usize,
+    },
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+    #[error(transparent)]
+    Shard(#[from] ShardError),
+    #[error("decoded content is not valid utf-8")]
+    Utf8(#[from] FromUtf8Error),
+    #[error("i/o error: {0}")]
+    Io(io::ErrorKind),
+    #[error("malformed frame")]
+    Malformed,
+    #[error("field too large to frame")]
+    TooLarge,
+    #[error("content cannot be decrypted with this key")]
+    Decrypt,
+    #[error("network closed")]
+    Disconnected,
+    #[error("timed out waiting for the network")]
+    Timeout,
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err.kind())
+    }
+}
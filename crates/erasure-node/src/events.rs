@@ -0,0 +1,186 @@
+use std::{
+    fmt::Write as _,
+    io::Write,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use bytes::Bytes;
+
+use crate::{file::DecodeError, network::Command, node::Conflict, observer::NodeObserver};
+
+enum Field<'a> {
+    Str(&'a str),
+    Num(u64),
+}
+
+pub struct EventLog<W> {
+    node: String,
+    sink: Mutex<W>,
+    clock: Box<dyn Fn() -> Duration + Send + Sync>,
+}
+
+impl<W: Write + Send> EventLog<W> {
+    pub fn new(node: impl Into<String>, sink: W) -> Self {
+        Self {
+            node: node.into(),
+            sink: Mutex::new(sink),
+            clock: Box::new(|| {
+                SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+            }),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: impl Fn() -> Duration + Send + Sync + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    fn emit(&self, event: &str, fields: &[(&str, Option<Field>)]) {
+        let mut line = format!("{{\"at_us\":{}", (self.clock)().as_micros());
+        line.push_str(",\"node\":");
+        escape(&mut line, &self.node);
+        line.push_str(",\"event\":");
+        escape(&mut line, event);
+
+        for (key, value) in fields {
+            let Some(value) = value else {
+                continue;
+            };
+
+            let _ = write!(line, ",\"{key}\":");
+            match value {
+                Field::Str(value) => escape(&mut line, value),
+                Field::Num(value) => {
+                    let _ = write!(line, "{value}");
+                }
+            }
+        }
+        line.push_str("}\n");
+
+        let _ = self.sink.lock().unwrap().write_all(line.as_bytes());
+    }
+
+    fn command(&self, event: &str, peer: &str, command: &Command) {
+        let (kind, name, index) = describe(command);
+        self.emit(
+            event,
+            &[
+                ("peer", Some(Field::Str(peer))),
+                ("command", Some(Field::Str(kind))),
+                ("file", name.map(Field::Str)),
+                ("index", index.map(|index| Field::Num(index as u64))),
+                ("size", Some(Field::Num(command.size() as u64))),
+            ],
+        );
+    }
+}
+
+impl<W: Write + Send> NodeObserver for EventLog<W> {
+    fn on_command(&self, peer: &str, command: &Command) {
+        self.command("received", peer, command);
+    }
+
+    fn on_send(&self, peer: &str, command: &Command) {
+        self.command("sent", peer, command);
+    }
+
+    fn on_shard_stored(&self, name: &str, index: usize) {
+        self.emit(
+            "stored",
+            &[
+                ("file", Some(Field::Str(name))),
+                ("index", Some(Field::Num(index as u64))),
+            ],
+        );
+    }
+
+    fn on_decode(&self, name: &str, result: &Result<Bytes, DecodeError>) {
+        let (outcome, counts) = match result {
+            Ok(_) => ("ok", None),
+            Err(DecodeError::Missing) => ("missing", None),
+            Err(DecodeError::Insufficient { present, needed }) => {
+                ("insufficient", Some((*present, *needed)))
+            }
+            Err(DecodeError::Inconsistent) => ("inconsistent", None),
+            Err(DecodeError::InvalidLayout) => ("layout", None),
+            Err(DecodeError::InvalidContent) => ("invalid", None),
+            Err(DecodeError::OutOfRange) => ("range", None),
+        };
+
+        self.emit(
+            "decoded",
+            &[
+                ("file", Some(Field::Str(name))),
+                ("outcome", Some(Field::Str(outcome))),
+                (
+                    "present",
+                    counts.map(|(present, _)| Field::Num(present as u64)),
+                ),
+                (
+                    "needed",
+                    counts.map(|(_, needed)| Field::Num(needed as u64)),
+                ),
+            ],
+        );
+    }
+
+    fn on_conflict(&self, conflict: &Conflict) {
+        self.emit(
+            "conflict",
+            &[
+                ("file", Some(Field::Str(&conflict.name))),
+                ("kept_us", Some(Field::Num(conflict.kept.time))),
+                ("discarded_us", Some(Field::Num(conflict.discarded.time))),
+            ],
+        );
+    }
+}
+
+fn describe(command: &Command) -> (&'static str, Option<&str>, Option<usize>) {
+    match command {
+        Command::Create { name, .. } => ("create", Some(name), None),
+        Command::Replicate { name, shard, .. } => ("replicate", Some(name), Some(shard.index())),
+        Command::Request { name, .. } => ("request", Some(name), None),
+        Command::Fetch { name, index } => ("fetch", Some(name), Some(*index)),
+        Command::Cancel { name } => ("cancel", Some(name), None),
+        Command::Announce => ("announce", None, None),
+        Command::Manifest { .. } => ("manifest", None, None),
+        Command::Leave => ("leave", None, None),
+        Command::Challenge { name, index, .. } => ("challenge", Some(name), Some(*index)),
+        Command::Proof { name, index, .. } => ("proof", Some(name), Some(*index)),
+        Command::Reject { name, .. } => ("reject", Some(name), None),
+        Command::Drop { name, .. } => ("drop", Some(name), None),
+        Command::RequestMeta { name } => ("request_meta", Some(name), None),
+        Command::Meta { name, .. } => ("meta", Some(name), None),
+        Command::Ingest { name, .. } => ("ingest", Some(name), None),
+        Command::Ping { .. } => ("ping", None, None),
+        Command::PingReq { .. } => ("ping_req", None, None),
+        Command::Ack { .. } => ("ack", None, None),
+        Command::Nack { name, index } => ("nack", Some(name), Some(*index)),
+        Command::Credit { .. } => ("credit", None, None),
+        Command::Fragment { .. } => ("fragment", None, None),
+        Command::Offer { name, .. } => ("offer", Some(name), None),
+        Command::Delete { name, .. } => ("delete", Some(name), None),
+    }
+}
+
+fn escape(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
@@ -1,12 +1,185 @@
 pub use std::io::Write;
 
-use reed_solomon_erasure::galois_8::ReedSolomon;
+use std::{
+    cmp,
+    collections::{BTreeMap, VecDeque},
+    io::{self, Read},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
 
-const SHARD_SIZE: usize = 64;
+use bytes::{Bytes, BytesMut};
+use smallvec::SmallVec;
+
+#[cfg(feature = "encryption")]
+use crate::crypt::{self, Identity, Key};
+use crate::{
+    Error,
+    compress::Compression,
+    erasure::{self, Codec, CodecError},
+    pool,
+};
+#[cfg(feature = "signing")]
+use crate::{
+    codec,
+    sign::{self, SigningKey},
+};
+
+pub const DEFAULT_SHARD_SIZE: usize = 64;
+pub const DEFAULT_STRIPE_SIZE: usize = 4096;
+// The most shards a file may span, so metadata off the wire cannot size a huge `File`.
+pub const MAX_SHARDS: usize = 1 << 24;
+#[cfg(feature = "parallel")]
+const PARALLEL_WIDTH: usize = 32 * 1024;
+
+static VERSIONS: AtomicU64 = AtomicU64::new(0);
+
+fn version() -> u64 {
+    VERSIONS.fetch_add(1, Ordering::Relaxed)
+}
+
+// Empty content still gets a single (all padding) data shard, so every file has a layout.
+pub fn data_shards(len: usize, shard_size: usize) -> usize {
+    len.div_ceil(shard_size).max(1)
+}
+
+fn fitted_shard_size(len: usize, data_shards: usize) -> usize {
+    len.div_ceil(data_shards)
+        .next_multiple_of(DEFAULT_SHARD_SIZE)
+        .max(DEFAULT_SHARD_SIZE)
+}
+
+// Like `read_exact`, but a short read at the end of the stream is not an error.
+fn fill(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+// Fills the parity shards of `arena`, laid out as consecutive shards of `shard_size` bytes.
+#[cfg(not(feature = "parallel"))]
+fn encode_arena(codec: &dyn Codec, arena: &mut [u8], shard_size: usize) -> Result<(), CodecError> {
+    codec.encode(&mut arena.chunks_mut(shard_size).collect::<Vec<_>>())
+}
+
+// Every column of bytes across the shards is coded on its own, so the shards can be cut
+// into narrower stripes and each stripe encoded on the rayon pool.
+#[cfg(feature = "parallel")]
+fn encode_arena(codec: &dyn Codec, arena: &mut [u8], shard_size: usize) -> Result<(), CodecError> {
+    use rayon::prelude::*;
+
+    let width = PARALLEL_WIDTH.next_multiple_of(codec.element());
+    let mut stripes = Vec::<Vec<&mut [u8]>>::new();
+    for shard in arena.chunks_mut(shard_size) {
+        for (stripe, chunk) in shard.chunks_mut(width).enumerate() {
+            match stripes.get_mut(stripe) {
+                Some(stripe) => stripe.push(chunk),
+                None => stripes.push(vec![chunk]),
+            }
+        }
+    }
+    stripes
+        .into_par_iter()
+        .try_for_each(|mut stripe| codec.encode(&mut stripe))
+}
+
+pub type Indices = SmallVec<[usize; 16]>;
+
+pub fn indices(bitmap: &[u8]) -> Indices {
+    (0..bitmap.len() * 8)
+        .filter(|index| bitmap[index / 8] & (1 << (index % 8)) != 0)
+        .collect()
+}
+
+// A shard's position in its file: the block times the group width, plus its place in the block.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct ShardIndex(usize);
+
+impl ShardIndex {
+    pub fn new(index: usize) -> Self {
+        Self(index)
+    }
+
+    pub fn get(self) -> usize {
+        self.0
+    }
+}
+
+impl From<usize> for ShardIndex {
+    fn from(index: usize) -> Self {
+        Self(index)
+    }
+}
+
+impl From<ShardIndex> for usize {
+    fn from(index: ShardIndex) -> Self {
+        index.0
+    }
+}
+
+impl std::fmt::Display for ShardIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShardError {
+    OutOfRange {
+        index: ShardIndex,
+        count: usize,
+    },
+    WrongSize {
+        index: ShardIndex,
+        size: usize,
+        expected: usize,
+    },
+    Present(ShardIndex),
+    Corrupt(ShardIndex),
+}
+
+impl std::fmt::Display for ShardError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::OutOfRange { index, count } => {
+                write!(f, "shard {index} is out of range for {count} shards")
+            }
+            Self::WrongSize {
+                index,
+                size,
+                expected,
+            } => write!(f, "shard {index} is {size} bytes, expected {expected}"),
+            Self::Present(index) => write!(f, "shard {index} is already present"),
+            Self::Corrupt(index) => write!(f, "shard {index} does not match its checksum"),
+        }
+    }
+}
+
+impl std::error::Error for ShardError {}
 
 #[derive(Clone, Debug)]
 pub struct Shards {
-    inner: Vec<Option<Vec<u8>>>,
+    inner: Vec<Option<Bytes>>,
+    checksums: Arc<[u32]>,
+    #[cfg(feature = "signing")]
+    digests: Arc<[[u8; 32]]>,
+    compression: Compression,
+    shard_size: usize,
+    version: u64,
 }
 
 pub struct ShardsIter<'a> {
@@ -24,17 +197,18 @@ impl Iterator for ShardsIter<'_> {
         match self.inner.inner.get(index)?.as_ref() {
             None => self.next(),
             Some(data) => Some(Shard {
-                data: data.clone(),
-                index,
+                data: self.inner.compression.compress(data),
+                index: index.into(),
             }),
         }
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Shard {
-    index: usize,
-    data: Vec<u8>,
+    index: ShardIndex,
+    data: Bytes,
 }
 
 impl std::fmt::Debug for Shard {
@@ -44,31 +218,157 @@ impl std::fmt::Debug for Shard {
 }
 
 impl Shard {
+    pub fn new(index: impl Into<ShardIndex>, data: impl Into<Bytes>) -> Self {
+        Self {
+            index: index.into(),
+            data: data.into(),
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
     pub fn size(&self) -> usize {
         self.data.len()
     }
 
     pub fn index(&self) -> usize {
-        self.index
+        self.index.get()
+    }
+
+    pub fn crc(&self) -> u32 {
+        crc32c::crc32c(&self.data)
     }
 }
 
 impl Shards {
-    pub fn insert(&mut self, shard: Vec<u8>, index: usize) {
-        self.inner[index] = Some(shard);
+    // Stores a shard as is, without checking its checksum, as long as it fits the layout.
+    pub fn insert(
+        &mut self,
+        shard: impl Into<Bytes>,
+        index: impl Into<ShardIndex>,
+    ) -> Result<(), ShardError> {
+        let (index, data) = (index.into(), shard.into());
+        let slot = self.slot(index)?;
+        if data.len() != self.shard_size {
+            return Err(ShardError::WrongSize {
+                index,
+                size: data.len(),
+                expected: self.shard_size,
+            });
+        }
+
+        self.inner[slot] = Some(data);
+        self.version = version();
+        Ok(())
     }
 
     pub fn delete(&mut self, index: usize) {
-        self.inner[index] = None;
+        if self.inner.get_mut(index).and_then(Option::take).is_some() {
+            self.version = version();
+        }
+    }
+
+    pub fn merge(&mut self, shard: Shard) -> Result<(), ShardError> {
+        let index = shard.index;
+        let slot = self.slot(index)?;
+        if self.inner[slot].is_some() {
+            return Err(ShardError::Present(index));
+        }
+        if self.compression == Compression::None && shard.data.len() != self.shard_size {
+            return Err(ShardError::WrongSize {
+                index,
+                size: shard.data.len(),
+                expected: self.shard_size,
+            });
+        }
+        let data = self
+            .open(&shard.data)
+            .filter(|data| self.intact(slot, data))
+            .ok_or(ShardError::Corrupt(index))?;
+
+        // Copy out of the frame so a stored shard does not pin the receive buffer.
+        let data = match self.compression == Compression::None {
+            true => Bytes::copy_from_slice(&data),
+            false => data,
+        };
+        self.inner[slot] = Some(data);
+        self.version = version();
+        Ok(())
+    }
+
+    fn slot(&self, index: ShardIndex) -> Result<usize, ShardError> {
+        match index.get() < self.inner.len() {
+            true => Ok(index.get()),
+            false => Err(ShardError::OutOfRange {
+                index,
+                count: self.inner.len(),
+            }),
+        }
+    }
+
+    // Undoes the file's compression on a shard received from elsewhere.
+    fn open(&self, data: &Bytes) -> Option<Bytes> {
+        self.compression.decompress(data, self.shard_size)
+    }
+
+    // Metadata without checksums (older writers) trusts every shard.
+    fn intact(&self, index: usize, data: &[u8]) -> bool {
+        let intact = self
+            .checksums
+            .get(index)
+            .is_none_or(|checksum| crc32c::crc32c(data) == *checksum);
+        #[cfg(feature = "signing")]
+        let intact = intact
+            && self
+                .digests
+                .get(index)
+                .is_none_or(|digest| sign::digest(data) == *digest);
+        intact
+    }
+
+    // Whether every shard has a checksum (or digest) to be checked against as it arrives;
+    // otherwise only the parity can tell a corrupt shard from a good one.
+    pub(crate) fn checked(&self) -> bool {
+        let checked = self.checksums.len() >= self.inner.len();
+        #[cfg(feature = "signing")]
+        let checked = checked || self.digests.len() >= self.inner.len();
+        checked
+    }
+
+    pub fn corrupt(&self) -> Vec<usize> {
+        self.inner
+            .iter()
+            .enumerate()
+            .filter(|(index, data)| data.as_ref().is_some_and(|data| !self.intact(*index, data)))
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        self.inner.get(index).is_some_and(|data| data.is_some())
+    }
+
+    pub fn get(&self, index: usize) -> Option<Shard> {
+        let data = self.inner.get(index)?.as_ref()?;
+        Some(Shard {
+            index: index.into(),
+            data: self.compression.compress(data),
+        })
     }
 
-    pub fn merge(&mut self, shard: Shard) {
-        if self.inner[shard.index].is_none() {
-            self.inner[shard.index] = Some(shard.data);
+    pub fn bitmap(&self) -> Vec<u8> {
+        let mut bitmap = vec![0; self.inner.len().div_ceil(8)];
+        for (index, data) in self.inner.iter().enumerate() {
+            if data.is_some() {
+                bitmap[index / 8] |= 1 << (index % 8);
+            }
         }
+        bitmap
     }
 
-    fn present(&self) -> usize {
+    pub fn present(&self) -> usize {
         self.inner.iter().filter(|data| data.is_some()).count()
     }
 
@@ -79,6 +379,10 @@ impl Shards {
         }
     }
 
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
     pub fn size(&self) -> usize {
         self.inner
             .iter()
@@ -87,107 +391,1722 @@ impl Shards {
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct Metadata {
-    len: usize,
-    data_shards: usize,
-    parity_shards: usize,
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CodecId {
+    ReedSolomon8,
+    ReedSolomon16,
+    Xor,
+    Replication,
+    #[cfg(feature = "raptorq")]
+    RaptorQ,
 }
 
-#[derive(Clone, Debug)]
-pub struct File {
-    meta: Metadata,
-    shards: Shards,
+impl CodecId {
+    // galois_8 caps a group at 256 shards; wider groups fall back to galois_16.
+    pub fn for_shards(shard_count: usize) -> Self {
+        if shard_count > 256 {
+            Self::ReedSolomon16
+        } else {
+            Self::ReedSolomon8
+        }
+    }
+
+    pub fn id(&self) -> u8 {
+        match self {
+            Self::ReedSolomon8 => 0,
+            Self::ReedSolomon16 => 1,
+            Self::Xor => 2,
+            Self::Replication => 3,
+            #[cfg(feature = "raptorq")]
+            Self::RaptorQ => 4,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Self::ReedSolomon8),
+            1 => Some(Self::ReedSolomon16),
+            2 => Some(Self::Xor),
+            3 => Some(Self::Replication),
+            #[cfg(feature = "raptorq")]
+            4 => Some(Self::RaptorQ),
+            _ => None,
+        }
+    }
 }
 
-impl File {
-    pub fn empty(meta: Metadata) -> Self {
-        let shards = Shards {
-            inner: vec![None; meta.data_shards + meta.parity_shards],
-        };
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Stamp {
+    pub time: u64,
+    pub writer: u64,
+}
 
-        Self { meta, shards }
+impl Stamp {
+    pub fn new(time: u64, writer: &str) -> Self {
+        Self {
+            time,
+            writer: self::writer(writer),
+        }
     }
+}
 
-    pub fn encode<S: AsRef<str>>(content: S) -> Option<Self> {
-        let bytes = content.as_ref().as_bytes();
-        let data_shards = bytes.chunks(SHARD_SIZE).count();
-        let parity_shards = data_shards;
+// 64-bit FNV-1a: unlike the std hashers it is fixed, so every node and release agrees on it.
+pub(crate) fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes.into_iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
 
-        let mut shards = (0..data_shards + parity_shards)
-            .map(|_| Some(vec![0; SHARD_SIZE]))
-            .collect::<Vec<_>>();
+// Maps a writer id to the same clock entry on every node.
+pub fn writer(id: &str) -> u64 {
+    fnv1a(id.bytes())
+}
 
-        bytes
-            .chunks(SHARD_SIZE)
-            .zip(shards.iter_mut())
-            .for_each(|(chunk, shard)| {
-                shard
-                    .as_mut()
-                    .unwrap()
-                    .as_mut_slice()
-                    .write_all(chunk)
-                    .unwrap();
-            });
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VectorClock {
+    entries: BTreeMap<u64, u64>,
+}
 
-        let r = ReedSolomon::new(data_shards, parity_shards).ok()?;
+impl VectorClock {
+    pub fn get(&self, writer: u64) -> u64 {
+        self.entries.get(&writer).copied().unwrap_or(0)
+    }
 
-        let mut shard_refs = shards
-            .iter_mut()
-            .map(|shard| shard.as_mut().unwrap())
-            .collect::<Vec<_>>();
+    pub fn tick(&mut self, writer: u64) {
+        *self.entries.entry(writer).or_default() += 1;
+    }
 
-        r.encode(&mut shard_refs).ok()?;
+    pub fn merge(&mut self, other: &Self) {
+        for (writer, counter) in other.entries() {
+            let entry = self.entries.entry(writer).or_default();
+            *entry = (*entry).max(counter);
+        }
+    }
 
-        let meta = Metadata {
-            len: bytes.len(),
+    pub fn concurrent(&self, other: &Self) -> bool {
+        self.partial_cmp(other).is_none()
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.entries
+            .iter()
+            .map(|(writer, counter)| (*writer, *counter))
+    }
+}
+
+impl FromIterator<(u64, u64)> for VectorClock {
+    fn from_iter<I: IntoIterator<Item = (u64, u64)>>(iter: I) -> Self {
+        Self {
+            entries: iter
+                .into_iter()
+                .filter(|(_, counter)| *counter > 0)
+                .collect(),
+        }
+    }
+}
+
+impl PartialOrd for VectorClock {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        let (mut less, mut greater) = (false, false);
+        for writer in self.entries.keys().chain(other.entries.keys()) {
+            match self.get(*writer).cmp(&other.get(*writer)) {
+                cmp::Ordering::Less => less = true,
+                cmp::Ordering::Greater => greater = true,
+                cmp::Ordering::Equal => {}
+            }
+        }
+
+        match (less, greater) {
+            (false, false) => Some(cmp::Ordering::Equal),
+            (true, false) => Some(cmp::Ordering::Less),
+            (false, true) => Some(cmp::Ordering::Greater),
+            (true, true) => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Metadata {
+    len: u64,
+    shard_size: u32,
+    stripes: u32,
+    data_shards: u32,
+    parity_shards: u32,
+    codec: CodecId,
+    stamp: Stamp,
+    clock: VectorClock,
+    checksums: Arc<[u32]>,
+    blocks: Arc<[u64]>,
+    compression: Compression,
+    encryption: Option<Arc<Encryption>>,
+    digests: Arc<[[u8; 32]]>,
+    signature: Option<Arc<Signature>>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    created_at: u64,
+    #[cfg_attr(feature = "serde", serde(default))]
+    content_type: Option<Arc<str>>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    attributes: Arc<BTreeMap<String, String>>,
+}
+
+// Content encrypted before encoding: which key sealed it, the nonce it used and the key
+// wrapped for every peer allowed to open it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Encryption {
+    pub key_id: u64,
+    pub nonce: [u8; 24],
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub recipients: Vec<WrappedKey>,
+}
+
+// The file key sealed to one peer's X25519 public key under an ephemeral key of its own.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WrappedKey {
+    pub recipient: [u8; 32],
+    pub ephemeral: [u8; 32],
+    pub sealed: Bytes,
+}
+
+// An Ed25519 signature over the rest of the metadata, including the shard digests.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Signature {
+    pub signer: [u8; 32],
+    pub signature: Bytes,
+}
+
+impl Metadata {
+    pub(crate) fn new(
+        len: u64,
+        shard_size: u32,
+        stripes: u32,
+        data_shards: u32,
+        parity_shards: u32,
+        codec: CodecId,
+    ) -> Self {
+        Self {
+            len,
+            shard_size,
+            stripes,
             data_shards,
             parity_shards,
-        };
+            codec,
+            stamp: Stamp::default(),
+            clock: VectorClock::default(),
+            checksums: Arc::from([]),
+            blocks: Arc::from([]),
+            compression: Compression::None,
+            encryption: None,
+            digests: Arc::from([]),
+            signature: None,
+            created_at: 0,
+            content_type: None,
+            attributes: Arc::default(),
+        }
+    }
 
-        let shards = Shards { inner: shards };
+    pub fn with_stamp(mut self, stamp: Stamp) -> Self {
+        self.stamp = stamp;
+        self
+    }
 
-        Some(Self { meta, shards })
+    pub fn with_clock(mut self, clock: VectorClock) -> Self {
+        self.clock = clock;
+        self
     }
 
-    pub fn decode(&self) -> Option<String> {
-        let meta = self.metadata();
-        if !self.can_decode() {
-            return None;
+    // In the same microseconds as `Stamp::time`; kept across overwrites, unlike the stamp.
+    pub fn with_created_at(mut self, created_at: u64) -> Self {
+        self.created_at = created_at;
+        self
+    }
+
+    pub fn with_content_type(mut self, content_type: impl Into<String>) -> Self {
+        self.content_type = Some(content_type.into().into());
+        self
+    }
+
+    pub fn with_attribute(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        Arc::make_mut(&mut self.attributes).insert(key.into(), value.into());
+        self
+    }
+
+    pub fn with_attributes(mut self, attributes: BTreeMap<String, String>) -> Self {
+        self.attributes = Arc::new(attributes);
+        self
+    }
+
+    pub fn with_checksums(mut self, checksums: Vec<u32>) -> Self {
+        self.checksums = checksums.into();
+        self
+    }
+
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn with_encryption(mut self, encryption: Encryption) -> Self {
+        self.encryption = Some(Arc::new(encryption));
+        self
+    }
+
+    pub(crate) fn with_digests(mut self, digests: Vec<[u8; 32]>) -> Self {
+        self.digests = digests.into();
+        self
+    }
+
+    pub(crate) fn with_signature(mut self, signature: Signature) -> Self {
+        self.signature = Some(Arc::new(signature));
+        self
+    }
+
+    // Signs the metadata as it stands, digests included; changing it afterwards breaks the signature.
+    // Metadata too large to frame stays unsigned.
+    #[cfg(feature = "signing")]
+    pub(crate) fn signed(self, key: &SigningKey) -> Self {
+        let meta = self.unsigned();
+        match codec::signable(&meta) {
+            Some(message) => {
+                let signature = sign::sign(key, &message);
+                meta.with_signature(signature)
+            }
+            None => meta,
         }
+    }
 
-        let mut data = self.shards().clone();
+    // The metadata as it was signed.
+    #[cfg(feature = "signing")]
+    pub(crate) fn unsigned(&self) -> Self {
+        Self {
+            signature: None,
+            ..self.clone()
+        }
+    }
 
-        let r = ReedSolomon::new(meta.data_shards, meta.parity_shards).ok()?;
+    pub fn with_blocks(mut self, blocks: Vec<u64>) -> Self {
+        self.blocks = blocks.into();
+        self
+    }
 
-        r.reconstruct(&mut data.inner).ok()?;
+    pub fn len(&self) -> u64 {
+        self.len
+    }
 
-        let mut content = data
-            .inner
-            .into_iter()
-            .take(meta.data_shards)
-            .flatten()
-            .flatten()
-            .collect::<Vec<_>>();
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
 
-        content.truncate(meta.len);
+    pub fn shard_size(&self) -> usize {
+        self.shard_size as usize
+    }
 
-        String::from_utf8(content).ok()
+    pub fn stripes(&self) -> usize {
+        self.stripes as usize
     }
 
-    pub fn can_decode(&self) -> bool {
-        self.shards().present() >= self.metadata().data_shards
+    pub fn codec(&self) -> CodecId {
+        self.codec
     }
 
-    pub fn metadata(&self) -> &Metadata {
-        &self.meta
+    pub fn compression(&self) -> Compression {
+        self.compression
     }
 
-    pub fn shards(&self) -> &Shards {
-        &self.shards
+    pub fn encryption(&self) -> Option<Encryption> {
+        self.encryption.as_deref().cloned()
     }
 
-    pub fn shards_mut(&mut self) -> &mut Shards {
-        &mut self.shards
+    pub fn created_at(&self) -> u64 {
+        self.created_at
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    pub fn attributes(&self) -> &BTreeMap<String, String> {
+        &self.attributes
+    }
+
+    pub fn attribute(&self, key: &str) -> Option<&str> {
+        self.attributes.get(key).map(String::as_str)
+    }
+
+    pub fn digests(&self) -> &[[u8; 32]] {
+        &self.digests
+    }
+
+    pub fn signature(&self) -> Option<&Signature> {
+        self.signature.as_deref()
+    }
+
+    pub fn signer(&self) -> Option<[u8; 32]> {
+        self.signature().map(|signature| signature.signer)
+    }
+
+    #[cfg(feature = "signing")]
+    pub fn verify_signature(&self) -> bool {
+        self.signature().is_some_and(|signature| {
+            codec::signable(self).is_some_and(|message| sign::verify(signature, &message))
+        })
+    }
+
+    pub fn data_shards(&self) -> usize {
+        self.data_shards as usize
+    }
+
+    pub fn parity_shards(&self) -> usize {
+        self.parity_shards as usize
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.block_count() * (self.data_shards() + self.parity_shards())
+    }
+
+    // Whether the layout holds together: a group the codec can code, blocks that add up to the
+    // content and fit their data shards, and a checksum and digest per shard if any are given.
+    // Metadata from the wire is checked before anything is sized from it.
+    pub fn is_consistent(&self) -> bool {
+        let capacity = self.data_shards as u64 * self.shard_size as u64;
+        let blocks = match self.blocks.is_empty() {
+            true => self.len <= capacity,
+            false => {
+                self.blocks.iter().all(|len| *len <= capacity)
+                    && self
+                        .blocks
+                        .iter()
+                        .try_fold(0u64, |sum, len| sum.checked_add(*len))
+                        == Some(self.len)
+            }
+        };
+        let count = (self.data_shards() + self.parity_shards()).checked_mul(self.block_count());
+        let sized = |len: usize| len == 0 || Some(len) == count;
+
+        erasure::supports(self.codec, self.data_shards(), self.parity_shards())
+            && self.shard_size > 0
+            && self.shard_size.is_multiple_of(self.stripes.max(1))
+            && blocks
+            && count.is_some_and(|count| count <= MAX_SHARDS)
+            && sized(self.checksums.len())
+            && sized(self.digests.len())
+    }
+
+    pub fn encode_config(&self) -> EncodeConfig {
+        let mut config = EncodeConfig::new(self.data_shards(), self.parity_shards());
+        if self.codec != config.codec() {
+            config = config.with_codec(self.codec);
+        }
+        if self.compression != Compression::None {
+            config = config.with_compression(self.compression);
+        }
+        let config = match self.stripes() {
+            0 | 1 => config.with_shard_size(self.shard_size()),
+            stripes => config.with_stripe_size(self.shard_size() / stripes),
+        };
+        match self.blocks.first() {
+            Some(block) => config.with_block_size(*block as usize),
+            None => config,
+        }
+    }
+
+    // Content length of every block; files without a block map are a single block.
+    pub fn blocks(&self) -> &[u64] {
+        &self.blocks
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.blocks.len().max(1)
+    }
+
+    // Stripes across every block, in content order.
+    pub fn stripe_count(&self) -> usize {
+        self.block_count() * self.stripes().max(1)
+    }
+
+    fn block_lens(&self) -> Vec<u64> {
+        if self.blocks.is_empty() {
+            vec![self.len]
+        } else {
+            self.blocks.to_vec()
+        }
+    }
+
+    pub fn stamp(&self) -> Stamp {
+        self.stamp
+    }
+
+    pub fn clock(&self) -> &VectorClock {
+        &self.clock
+    }
+
+    pub fn checksums(&self) -> &[u32] {
+        &self.checksums
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    Missing,
+    Insufficient { present: usize, needed: usize },
+    Inconsistent,
+    InvalidLayout,
+    InvalidContent,
+    OutOfRange,
+}
+
+impl DecodeError {
+    pub fn is_pending(&self) -> bool {
+        matches!(self, Self::Missing | Self::Insufficient { .. })
+    }
+
+    pub fn is_corrupt(&self) -> bool {
+        matches!(
+            self,
+            Self::Inconsistent | Self::InvalidLayout | Self::InvalidContent
+        )
+    }
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Missing => write!(f, "unknown file"),
+            Self::Insufficient { present, needed } => {
+                write!(f, "insufficient shards: {present} of {needed}")
+            }
+            Self::Inconsistent => write!(f, "shards are inconsistent with parity"),
+            Self::InvalidLayout => write!(f, "shards do not match the file's layout"),
+            Self::InvalidContent => write!(f, "decoded content is not valid utf-8"),
+            Self::OutOfRange => write!(f, "byte range is outside the file"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// How many data and parity shards a file is split into. Without a `shard_size`
+/// shards are sized to fit the content in `data_shards`, rounded up to a multiple of 64 bytes.
+/// With a `stripe_size` the content is encoded `data_shards * stripe_size` bytes at a time
+/// and every shard grows by `stripe_size` bytes per stripe; `shard_size` is then ignored.
+/// With a `block_size` the content is split into blocks of that many bytes, each encoded into
+/// its own `data_shards + parity_shards` shards; `stripe_size` is then ignored.
+/// Without a `codec` groups use Reed-Solomon over the narrowest field that fits them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EncodeConfig {
+    pub data_shards: usize,
+    pub parity_shards: usize,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub shard_size: Option<usize>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub stripe_size: Option<usize>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub block_size: Option<usize>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub codec: Option<CodecId>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub compression: Option<Compression>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub max_memory: Option<usize>,
+}
+
+impl EncodeConfig {
+    pub fn new(data_shards: usize, parity_shards: usize) -> Self {
+        Self {
+            data_shards,
+            parity_shards,
+            shard_size: None,
+            stripe_size: None,
+            block_size: None,
+            codec: None,
+            compression: None,
+            max_memory: None,
+        }
+    }
+
+    pub fn with_shard_size(mut self, shard_size: usize) -> Self {
+        self.shard_size = Some(shard_size);
+        self
+    }
+
+    pub fn with_stripe_size(mut self, stripe_size: usize) -> Self {
+        self.stripe_size = Some(stripe_size);
+        self
+    }
+
+    pub fn with_block_size(mut self, block_size: usize) -> Self {
+        self.block_size = Some(block_size);
+        self
+    }
+
+    pub fn with_codec(mut self, codec: CodecId) -> Self {
+        self.codec = Some(codec);
+        self
+    }
+
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    pub fn with_max_memory(mut self, max_memory: usize) -> Self {
+        self.max_memory = Some(max_memory);
+        self
+    }
+
+    fn codec(&self) -> CodecId {
+        self.codec
+            .unwrap_or_else(|| CodecId::for_shards(self.data_shards + self.parity_shards))
+    }
+}
+
+/// Encodes content as it is written, one block at a time, so content larger than memory can
+/// be handed off block by block through `next_block`. Without a `block_size` blocks span
+/// `DEFAULT_STRIPE_SIZE` bytes per data shard. With `max_memory` the block being filled and
+/// the encoded blocks waiting to be taken (counted uncompressed) never hold more than that:
+/// writes fail with `WouldBlock` while there is no room to encode another block.
+pub struct Encoder {
+    config: EncodeConfig,
+    block: EncodeConfig,
+    block_size: usize,
+    encoded_size: usize,
+    buffer: Vec<u8>,
+    ready: VecDeque<Vec<Shard>>,
+    pending: usize,
+    blocks: Vec<u64>,
+    checksums: Vec<u32>,
+    #[cfg(feature = "signing")]
+    digests: Vec<[u8; 32]>,
+}
+
+impl Encoder {
+    pub fn new(config: EncodeConfig) -> Result<Self, Error> {
+        let EncodeConfig {
+            data_shards,
+            parity_shards,
+            ..
+        } = config;
+        let block_size = config
+            .block_size
+            .unwrap_or(data_shards * DEFAULT_STRIPE_SIZE);
+        let shard_size = config
+            .shard_size
+            .unwrap_or_else(|| fitted_shard_size(block_size, data_shards.max(1)));
+        let valid = erasure::codec(config.codec(), data_shards, parity_shards)
+            .is_ok_and(|r| shard_size > 0 && shard_size.is_multiple_of(r.element()));
+        let encoded_size = (data_shards + parity_shards) * shard_size;
+        if !valid
+            || block_size == 0
+            || block_size > data_shards * shard_size
+            || config.max_memory.is_some_and(|max| max < encoded_size)
+        {
+            return Err(Error::Encode {
+                len: 0,
+                data_shards,
+                parity_shards,
+                shard_size,
+            });
+        }
+
+        let block = EncodeConfig::new(data_shards, parity_shards)
+            .with_shard_size(shard_size)
+            .with_codec(config.codec());
+        Ok(Self {
+            config: config.with_shard_size(shard_size),
+            block,
+            block_size,
+            encoded_size,
+            buffer: Vec::with_capacity(block_size),
+            ready: VecDeque::new(),
+            pending: 0,
+            blocks: Vec::new(),
+            checksums: Vec::new(),
+            #[cfg(feature = "signing")]
+            digests: Vec::new(),
+        })
+    }
+
+    // Content bytes written so far, including the block still being filled.
+    pub fn len(&self) -> u64 {
+        self.blocks.iter().sum::<u64>() + self.buffer.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Bytes held in memory: the block being filled and every encoded block not yet taken,
+    // the latter at their uncompressed size.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len() + self.pending
+    }
+
+    // A single empty block of the layout, which is all placement needs to know up front.
+    pub(crate) fn block(&self) -> Metadata {
+        let EncodeConfig {
+            data_shards,
+            parity_shards,
+            shard_size,
+            ..
+        } = self.config;
+        Metadata::new(
+            0,
+            shard_size.unwrap_or_default() as u32,
+            1,
+            data_shards as u32,
+            parity_shards as u32,
+            self.config.codec(),
+        )
+    }
+
+    // The shards of the oldest encoded block that was not taken yet.
+    pub fn next_block(&mut self) -> Option<Vec<Shard>> {
+        let shards = self.ready.pop_front()?;
+        self.pending -= self.encoded_size;
+        Some(shards)
+    }
+
+    // Encodes what is left as the final block and returns the metadata of the whole content
+    // along with the shards of every block that was not taken.
+    pub fn finish(mut self) -> Result<(Metadata, Vec<Shard>), Error> {
+        if !self.buffer.is_empty() || self.blocks.is_empty() {
+            self.seal()?;
+        }
+
+        let EncodeConfig {
+            data_shards,
+            parity_shards,
+            shard_size,
+            ..
+        } = self.config;
+        let meta = Metadata::new(
+            self.blocks.iter().sum(),
+            shard_size.unwrap_or_default() as u32,
+            1,
+            data_shards as u32,
+            parity_shards as u32,
+            self.config.codec(),
+        )
+        .with_blocks(self.blocks)
+        .with_checksums(self.checksums)
+        .with_compression(self.config.compression.unwrap_or_default());
+        #[cfg(feature = "signing")]
+        let meta = meta.with_digests(self.digests);
+
+        Ok((meta, self.ready.into_iter().flatten().collect()))
+    }
+
+    fn seal(&mut self) -> Result<(), Error> {
+        let file = File::encode_with(&self.buffer, self.block)?;
+        let base = self.blocks.len() * file.meta.shard_count();
+        // Checksums and digests cover the shards as encoded; only what is handed out is compressed.
+        let compression = self.config.compression.unwrap_or_default();
+        let shards = file
+            .shards
+            .inner
+            .iter()
+            .flatten()
+            .enumerate()
+            .map(|(offset, data)| Shard::new(base + offset, compression.compress(data)))
+            .collect::<Vec<_>>();
+
+        self.checksums.extend_from_slice(&file.meta.checksums);
+        #[cfg(feature = "signing")]
+        self.digests.extend(
+            file.shards
+                .inner
+                .iter()
+                .flatten()
+                .map(|data| sign::digest(data)),
+        );
+        self.pending += self.encoded_size;
+        self.blocks.push(self.buffer.len() as u64);
+        self.buffer.clear();
+        self.ready.push_back(shards);
+        Ok(())
+    }
+}
+
+impl Write for Encoder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self
+            .config
+            .max_memory
+            .is_some_and(|max| self.pending + self.encoded_size > max)
+        {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        let taken = buf.len().min(self.block_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..taken]);
+        if self.buffer.len() == self.block_size {
+            self.seal().map_err(io::Error::other)?;
+        }
+        Ok(taken)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The reading side of `Encoder`: takes shards in any order through `push` and reads the
+/// content back block by block, dropping each block's shards once it has been read. With a
+/// `max_memory` the shards held never exceed it; `push` fails with `WouldBlock` for a shard
+/// that would leave no room to complete the block being read, and reads fail with
+/// `WouldBlock` while that block is short of shards.
+pub struct Decoder {
+    file: File,
+    max_memory: Option<usize>,
+    stripe: usize,
+    out: Bytes,
+}
+
+impl Decoder {
+    pub fn new(meta: Metadata, max_memory: Option<usize>) -> Result<Self, Error> {
+        let needed = meta.data_shards() * meta.shard_size();
+        if max_memory.is_some_and(|max| max < needed) {
+            return Err(Error::Encode {
+                len: meta.len() as usize,
+                data_shards: meta.data_shards(),
+                parity_shards: meta.parity_shards(),
+                shard_size: meta.shard_size(),
+            });
+        }
+
+        Ok(Self {
+            file: File::empty(meta),
+            max_memory,
+            stripe: 0,
+            out: Bytes::new(),
+        })
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        self.file.metadata()
+    }
+
+    // Bytes of shards held for the blocks not read yet.
+    pub fn buffered(&self) -> usize {
+        self.file.shards().size()
+    }
+
+    fn group(&self) -> usize {
+        self.metadata().data_shards() + self.metadata().parity_shards()
+    }
+
+    fn block(&self) -> usize {
+        self.stripe / self.metadata().stripes().max(1)
+    }
+
+    fn present(&self, block: usize) -> usize {
+        let group = self.group();
+        (block * group..(block + 1) * group)
+            .filter(|index| self.file.shards().contains(*index))
+            .count()
+    }
+
+    // Shards of blocks already read are dropped, as are ones already held.
+    pub fn push(&mut self, shard: Shard) -> io::Result<()> {
+        let (block, current) = (shard.index() / self.group().max(1), self.block());
+        if block < current || self.file.shards().contains(shard.index()) {
+            return Ok(());
+        }
+
+        let data_shards = self.metadata().data_shards();
+        let missing = data_shards.saturating_sub(self.present(current));
+        if let Some(max) = self.max_memory {
+            let size = self.metadata().shard_size();
+            // Room for the missing shards of the block being read is kept free.
+            let reserved = match block == current {
+                true => missing.saturating_sub(1) * size,
+                false => missing * size,
+            };
+            let needed = match block == current && missing > 0 {
+                true => 0,
+                false => size + reserved,
+            };
+            if self.buffered() + needed > max {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+        }
+
+        self.file
+            .shards_mut()
+            .merge(shard)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    fn drop_block(&mut self, block: usize) {
+        let group = self.group();
+        for index in block * group..(block + 1) * group {
+            self.file.shards_mut().delete(index);
+        }
+    }
+}
+
+impl Read for Decoder {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.out.is_empty() {
+            if buf.is_empty() || self.stripe == self.metadata().stripe_count() {
+                return Ok(0);
+            }
+
+            self.out =
+                self.file
+                    .decode_stripe(self.stripe)
+                    .map_err(|err| match err.is_pending() {
+                        true => io::ErrorKind::WouldBlock.into(),
+                        false => io::Error::new(io::ErrorKind::InvalidData, err),
+                    })?;
+
+            let block = self.block();
+            self.stripe += 1;
+            if self.block() != block {
+                self.drop_block(block);
+            }
+        }
+
+        let len = buf.len().min(self.out.len());
+        buf[..len].copy_from_slice(&self.out.split_to(len));
+        Ok(len)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct File {
+    meta: Metadata,
+    shards: Shards,
+}
+
+struct Layout {
+    codec: Box<dyn Codec>,
+    blocks: Vec<usize>,
+    stripe_size: usize,
+}
+
+impl File {
+    pub fn empty(meta: Metadata) -> Self {
+        let shards = Shards {
+            inner: vec![None; meta.shard_count()],
+            checksums: meta.checksums.clone(),
+            #[cfg(feature = "signing")]
+            digests: meta.digests.clone(),
+            compression: meta.compression,
+            shard_size: meta.shard_size(),
+            version: version(),
+        };
+
+        Self { meta, shards }
+    }
+
+    pub fn encode<B: AsRef<[u8]>>(content: B) -> Result<Self, Error> {
+        let parity_shards = data_shards(content.as_ref().len(), DEFAULT_SHARD_SIZE);
+        Self::encode_with_parity(content, parity_shards)
+    }
+
+    pub fn encode_with_parity<B: AsRef<[u8]>>(
+        content: B,
+        parity_shards: usize,
+    ) -> Result<Self, Error> {
+        let data_shards = data_shards(content.as_ref().len(), DEFAULT_SHARD_SIZE);
+        Self::encode_with(content, EncodeConfig::new(data_shards, parity_shards))
+    }
+
+    pub fn encode_with<B: AsRef<[u8]>>(content: B, config: EncodeConfig) -> Result<Self, Error> {
+        let bytes = content.as_ref();
+        if let Some(block_size) = config.block_size {
+            return Self::encode_blocks(bytes, config, block_size);
+        }
+        if config.stripe_size.is_some() {
+            return Self::encode_from_reader(bytes, config);
+        }
+
+        let EncodeConfig {
+            data_shards,
+            parity_shards,
+            shard_size,
+            ..
+        } = config;
+        let invalid = |shard_size| Error::Encode {
+            len: bytes.len(),
+            data_shards,
+            parity_shards,
+            shard_size,
+        };
+
+        let shard_count = data_shards + parity_shards;
+        let codec = config.codec();
+        let r = erasure::codec(codec, data_shards, parity_shards)
+            .map_err(|_| invalid(shard_size.unwrap_or_default()))?;
+        let shard_size = shard_size.unwrap_or_else(|| fitted_shard_size(bytes.len(), data_shards));
+        let fits = data_shards * shard_size >= bytes.len() && u32::try_from(shard_size).is_ok();
+        if shard_size == 0 || !fits || !shard_size.is_multiple_of(r.element()) {
+            return Err(invalid(shard_size));
+        }
+        let mut arena = BytesMut::zeroed(shard_count * shard_size);
+        arena[..bytes.len()].copy_from_slice(bytes);
+
+        encode_arena(&*r, &mut arena, shard_size).map_err(|_| invalid(shard_size))?;
+
+        let arena = arena.freeze();
+        let shards = (0..shard_count)
+            .map(|index| arena.slice(index * shard_size..(index + 1) * shard_size))
+            .collect();
+
+        let meta = Metadata::new(
+            bytes.len() as u64,
+            shard_size as u32,
+            1,
+            data_shards as u32,
+            parity_shards as u32,
+            codec,
+        )
+        .with_compression(config.compression.unwrap_or_default());
+        Ok(Self::sealed(meta, shards))
+    }
+
+    // Encrypts the content and encodes the ciphertext, so holders store shards they cannot read.
+    #[cfg(feature = "encryption")]
+    pub fn encode_encrypted<B: AsRef<[u8]>>(content: B, key: &Key) -> Result<Self, Error> {
+        let (encryption, sealed) = crypt::seal(key, content.as_ref());
+        let mut file = Self::encode(sealed)?;
+        file.meta = file.meta.with_encryption(encryption);
+        Ok(file)
+    }
+
+    #[cfg(feature = "encryption")]
+    pub fn encode_encrypted_with<B: AsRef<[u8]>>(
+        content: B,
+        config: EncodeConfig,
+        key: &Key,
+    ) -> Result<Self, Error> {
+        let (encryption, sealed) = crypt::seal(key, content.as_ref());
+        let mut file = Self::encode_with(sealed, config)?;
+        file.meta = file.meta.with_encryption(encryption);
+        Ok(file)
+    }
+
+    // Every block is a complete Reed-Solomon group of its own, so the shard size
+    // and the galois field limit stay fixed however large the content grows.
+    fn encode_blocks(bytes: &[u8], config: EncodeConfig, block_size: usize) -> Result<Self, Error> {
+        let EncodeConfig {
+            data_shards,
+            parity_shards,
+            shard_size,
+            ..
+        } = config;
+        let shard_size =
+            shard_size.unwrap_or_else(|| fitted_shard_size(block_size, data_shards.max(1)));
+        if block_size == 0 || block_size > data_shards * shard_size {
+            return Err(Error::Encode {
+                len: bytes.len(),
+                data_shards,
+                parity_shards,
+                shard_size,
+            });
+        }
+
+        let single = EncodeConfig::new(data_shards, parity_shards)
+            .with_shard_size(shard_size)
+            .with_codec(config.codec());
+        let (mut shards, mut blocks) = (Vec::new(), Vec::new());
+        for block in bytes
+            .chunks(block_size)
+            .chain(bytes.is_empty().then_some(bytes))
+        {
+            let file = Self::encode_with(block, single)?;
+            shards.extend(file.shards.inner.into_iter().flatten());
+            blocks.push(block.len() as u64);
+        }
+
+        let meta = Metadata::new(
+            bytes.len() as u64,
+            shard_size as u32,
+            1,
+            data_shards as u32,
+            parity_shards as u32,
+            config.codec(),
+        )
+        .with_blocks(blocks)
+        .with_compression(config.compression.unwrap_or_default());
+        Ok(Self::sealed(meta, shards))
+    }
+
+    // Reads and encodes one stripe at a time, so besides the shards themselves
+    // only a single stripe of content is held in memory. The shards of the whole file are
+    // still kept, whatever `max_memory` says; `Encoder` is the bounded path.
+    pub fn encode_from_reader<R: Read>(mut reader: R, config: EncodeConfig) -> Result<Self, Error> {
+        let EncodeConfig {
+            data_shards,
+            parity_shards,
+            stripe_size,
+            ..
+        } = config;
+        let stripe_size = stripe_size.unwrap_or(DEFAULT_STRIPE_SIZE);
+        let invalid = |len| Error::Encode {
+            len,
+            data_shards,
+            parity_shards,
+            shard_size: stripe_size,
+        };
+
+        let shard_count = data_shards + parity_shards;
+        let codec = config.codec();
+        let r = erasure::codec(codec, data_shards, parity_shards).map_err(|_| invalid(0))?;
+        if stripe_size == 0 || !stripe_size.is_multiple_of(r.element()) {
+            return Err(invalid(0));
+        }
+
+        let capacity = data_shards * stripe_size;
+        let mut shards = vec![BytesMut::new(); shard_count];
+        let mut stripe = vec![0; shard_count * stripe_size];
+        let (mut len, mut stripes) = (0, 0);
+        loop {
+            stripe.fill(0);
+            let read = fill(&mut reader, &mut stripe[..capacity])?;
+            if read == 0 && stripes > 0 {
+                break;
+            }
+
+            encode_arena(&*r, &mut stripe, stripe_size).map_err(|_| invalid(len + read))?;
+            for (shard, chunk) in shards.iter_mut().zip(stripe.chunks(stripe_size)) {
+                shard.extend_from_slice(chunk);
+            }
+
+            len += read;
+            stripes += 1;
+            if read < capacity {
+                break;
+            }
+        }
+
+        let shard_size = u32::try_from(stripes * stripe_size).map_err(|_| invalid(len))?;
+        let meta = Metadata::new(
+            len as u64,
+            shard_size,
+            stripes as u32,
+            data_shards as u32,
+            parity_shards as u32,
+            codec,
+        )
+        .with_compression(config.compression.unwrap_or_default());
+        Ok(Self::sealed(
+            meta,
+            shards.into_iter().map(BytesMut::freeze).collect(),
+        ))
+    }
+
+    fn sealed(meta: Metadata, shards: Vec<Bytes>) -> Self {
+        let checksums = shards
+            .iter()
+            .map(|shard| crc32c::crc32c(shard))
+            .collect::<Arc<[u32]>>();
+
+        let shards = Shards {
+            inner: shards.into_iter().map(Some).collect(),
+            checksums: checksums.clone(),
+            #[cfg(feature = "signing")]
+            digests: meta.digests.clone(),
+            compression: meta.compression,
+            shard_size: meta.shard_size(),
+            version: version(),
+        };
+        let meta = Metadata { checksums, ..meta };
+
+        Self { meta, shards }
+    }
+
+    pub fn with_stamp(mut self, stamp: Stamp) -> Self {
+        self.meta.stamp = stamp;
+        self
+    }
+
+    pub fn with_clock(mut self, clock: VectorClock) -> Self {
+        self.meta.clock = clock;
+        self
+    }
+
+    pub fn with_created_at(mut self, created_at: u64) -> Self {
+        self.meta.created_at = created_at;
+        self
+    }
+
+    pub fn with_content_type(mut self, content_type: impl Into<String>) -> Self {
+        self.meta = self.meta.with_content_type(content_type);
+        self
+    }
+
+    pub fn with_attributes(mut self, attributes: BTreeMap<String, String>) -> Self {
+        self.meta = self.meta.with_attributes(attributes);
+        self
+    }
+
+    // Records a SHA-256 digest of every shard (rebuilding any that are missing) and signs the
+    // metadata, so holders can refuse shards and metadata the writer never produced.
+    // Anything that changes the metadata afterwards invalidates the signature.
+    #[cfg(feature = "signing")]
+    pub fn signed(mut self, key: &SigningKey) -> Result<Self, DecodeError> {
+        self.repair()?;
+        let digests = self
+            .shards
+            .inner
+            .iter()
+            .map(|data| sign::digest(data.as_deref().unwrap_or_default()))
+            .collect::<Vec<_>>();
+
+        self.meta = self.meta.with_digests(digests).signed(key);
+        self.shards.digests = self.meta.digests.clone();
+        Ok(self)
+    }
+
+    pub fn decode(&self) -> Result<Vec<u8>, DecodeError> {
+        self.reconstruct()
+    }
+
+    // Reconstructs and writes one stripe at a time; returns the number of bytes written.
+    // Works over shards already held in full; `Decoder` bounds what is held while decoding.
+    pub fn decode_to_writer<W: Write>(&self, mut writer: W) -> Result<u64, Error> {
+        self.stream(|data| Ok::<_, Error>(writer.write_all(data)?))?;
+        Ok(self.meta.len)
+    }
+
+    #[cfg(feature = "encryption")]
+    pub fn decode_encrypted(&self, key: &Key) -> Result<Vec<u8>, Error> {
+        let encryption = self.meta.encryption().ok_or(Error::Decrypt)?;
+        crypt::open(key, &encryption, &self.reconstruct()?).ok_or(Error::Decrypt)
+    }
+
+    // Opens a file whose key was wrapped for `identity` (see `Key::with_recipients`).
+    #[cfg(feature = "encryption")]
+    pub fn decode_as(&self, identity: &Identity) -> Result<Vec<u8>, Error> {
+        let key = identity.unwrap(&self.meta).ok_or(Error::Decrypt)?;
+        self.decode_encrypted(&key)
+    }
+
+    pub fn decode_string(&self) -> Result<String, Error> {
+        Ok(String::from_utf8(self.reconstruct()?)?)
+    }
+
+    // Identifies the content by its length and the CRC32C of every data shard recorded in the
+    // metadata, so it needs no shards at all; None for metadata from writers without checksums.
+    pub fn digest(&self) -> Option<u64> {
+        let meta = &self.meta;
+        if meta.checksums().len() != meta.shard_count() {
+            return None;
+        }
+
+        let stride = meta.data_shards() + meta.parity_shards();
+        let data = meta
+            .checksums()
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| index % stride < meta.data_shards())
+            .flat_map(|(_, checksum)| checksum.to_le_bytes());
+        Some(fnv1a(meta.len().to_le_bytes().into_iter().chain(data)))
+    }
+
+    // Regenerates every missing or corrupt shard in place and returns how many were rebuilt.
+    pub fn repair(&mut self) -> Result<usize, DecodeError> {
+        let missing = (0..self.meta.shard_count())
+            .filter(|index| {
+                self.shards.inner[*index]
+                    .as_ref()
+                    .is_none_or(|data| !self.shards.intact(*index, data))
+            })
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return Ok(0);
+        }
+
+        let size = self.meta.shard_size();
+        let mut rebuilt = vec![None; self.meta.shard_count()];
+        for index in &missing {
+            rebuilt[*index] = Some(BytesMut::with_capacity(size));
+        }
+
+        let group = self.meta.data_shards() + self.meta.parity_shards();
+        self.walk(true, |block, stripe, _| {
+            let stripe_size = stripe.len() / group;
+            for (offset, chunk) in stripe.chunks(stripe_size).enumerate() {
+                if let Some(shard) = &mut rebuilt[block * group + offset] {
+                    shard.extend_from_slice(chunk);
+                }
+            }
+            Ok::<_, DecodeError>(())
+        })?;
+
+        let rebuilt = missing
+            .iter()
+            .map(|index| {
+                let data = rebuilt[*index].take().unwrap_or_default().freeze();
+                match self.shards.intact(*index, &data) {
+                    true => Ok((*index, data)),
+                    false => Err(DecodeError::Inconsistent),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        for (index, data) in rebuilt {
+            self.shards.inner[index] = Some(data);
+        }
+        self.shards.version = version();
+
+        Ok(missing.len())
+    }
+
+    fn reconstruct(&self) -> Result<Vec<u8>, DecodeError> {
+        let mut content = Vec::with_capacity(usize::try_from(self.meta.len).unwrap_or(0));
+        self.stream(|data| {
+            content.extend_from_slice(data);
+            Ok::<_, DecodeError>(())
+        })?;
+        Ok(content)
+    }
+
+    fn stream<E: From<DecodeError>>(
+        &self,
+        mut emit: impl FnMut(&[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        self.walk(false, |_, stripe, remaining| emit(&stripe[..remaining]))
+    }
+
+    // Reconstructs every stripe of every block in turn and hands it to `visit` with the
+    // block index and how many content bytes it holds; `full` rebuilds the parity shards too.
+    fn walk<E: From<DecodeError>>(
+        &self,
+        full: bool,
+        mut visit: impl FnMut(usize, &[u8], usize) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let meta = self.metadata();
+        let shards = self.intact();
+
+        let group = meta.data_shards() + meta.parity_shards();
+        let short = shards
+            .chunks(group.max(1))
+            .map(|block| block.iter().flatten().count())
+            .find(|present| *present < meta.data_shards());
+        if let Some(present) = short {
+            return Err(DecodeError::Insufficient {
+                present,
+                needed: meta.data_shards(),
+            }
+            .into());
+        }
+
+        let Layout {
+            codec: r,
+            blocks,
+            stripe_size,
+        } = self.layout(&shards)?;
+        let capacity = meta.data_shards() * stripe_size;
+        let mut arena = pool::take(group * stripe_size);
+        let result = shards.chunks(group).zip(blocks).enumerate().try_for_each(
+            |(block_index, (block, len))| {
+                (0..meta.stripes()).try_for_each(|index| {
+                    let range = index * stripe_size..(index + 1) * stripe_size;
+                    let stripe = block
+                        .iter()
+                        .map(|shard| shard.map(|shard| &shard[range.clone()]))
+                        .collect::<Vec<_>>();
+                    Self::reconstruct_in(&*r, &stripe, &mut arena, stripe_size, full)?;
+
+                    let remaining = len.saturating_sub(index * capacity).min(capacity);
+                    visit(block_index, &arena, remaining)
+                })
+            },
+        );
+        pool::give(arena);
+
+        result
+    }
+
+    // Reconstructs the content of stripe `index` (counted as in `Metadata::stripe_count`), which
+    // only needs `data_shards` intact shards of the block it belongs to.
+    pub fn decode_stripe(&self, index: usize) -> Result<Bytes, DecodeError> {
+        let meta = self.metadata();
+        let shards = self
+            .shards
+            .inner
+            .iter()
+            .map(Option::as_deref)
+            .collect::<Vec<_>>();
+        let Layout {
+            codec: r,
+            blocks,
+            stripe_size,
+        } = self.layout(&shards)?;
+
+        let (block, stripe) = (index / meta.stripes(), index % meta.stripes());
+        let len = *blocks.get(block).ok_or(DecodeError::OutOfRange)?;
+        let group = meta.data_shards() + meta.parity_shards();
+        let range = stripe * stripe_size..(stripe + 1) * stripe_size;
+        let shards = (block * group..(block + 1) * group)
+            .map(|index| {
+                shards[index]
+                    .filter(|data| self.shards.intact(index, data))
+                    .map(|data| &data[range.clone()])
+            })
+            .collect::<Vec<_>>();
+        let present = shards.iter().flatten().count();
+        if present < meta.data_shards() {
+            return Err(DecodeError::Insufficient {
+                present,
+                needed: meta.data_shards(),
+            });
+        }
+
+        let capacity = meta.data_shards() * stripe_size;
+        let remaining = len.saturating_sub(stripe * capacity).min(capacity);
+        let mut arena = pool::take(group * stripe_size);
+        let result = Self::reconstruct_in(&*r, &shards, &mut arena, stripe_size, false)
+            .map(|()| Bytes::copy_from_slice(&arena[..remaining]));
+        pool::give(arena);
+
+        result
+    }
+
+    // Decodes `len` bytes from `offset`, touching only the blocks and stripes that cover them;
+    // where every data shard the range lives in is intact the bytes are copied straight out.
+    pub fn decode_range(&self, offset: u64, len: u64) -> Result<Vec<u8>, DecodeError> {
+        let meta = self.metadata();
+        let end = offset
+            .checked_add(len)
+            .filter(|end| *end <= meta.len)
+            .and_then(|end| usize::try_from(end).ok())
+            .ok_or(DecodeError::OutOfRange)?;
+        let offset = offset as usize;
+        let mut content = Vec::with_capacity(end - offset);
+        if offset == end {
+            return Ok(content);
+        }
+
+        let shards = self.intact();
+        let Layout {
+            codec: r,
+            blocks,
+            stripe_size,
+        } = self.layout(&shards)?;
+        let data_shards = meta.data_shards();
+        let group = data_shards + meta.parity_shards();
+        let capacity = data_shards * stripe_size;
+
+        let mut arena = pool::take(group * stripe_size);
+        let mut start = 0;
+        let result = shards
+            .chunks(group)
+            .zip(blocks)
+            .try_for_each(|(block, len)| {
+                let (block_start, block_end) = (start, start + len);
+                start = block_end;
+                if block_end <= offset || block_start >= end {
+                    return Ok(());
+                }
+
+                let first = offset.max(block_start) - block_start;
+                let last = end.min(block_end) - block_start;
+                (first / capacity..last.div_ceil(capacity)).try_for_each(|index| {
+                    let base = index * capacity;
+                    let (from, to) = (first.max(base) - base, last.min(base + capacity) - base);
+                    let range = index * stripe_size..(index + 1) * stripe_size;
+                    let covering = from / stripe_size..to.div_ceil(stripe_size);
+                    if covering.clone().all(|shard| block[shard].is_some()) {
+                        for shard in covering {
+                            let data = &block[shard].unwrap()[range.clone()];
+                            let at = shard * stripe_size;
+                            content.extend_from_slice(
+                                &data[from.max(at) - at..to.min(at + stripe_size) - at],
+                            );
+                        }
+                        return Ok(());
+                    }
+
+                    let present = block.iter().flatten().count();
+                    if present < data_shards {
+                        return Err(DecodeError::Insufficient {
+                            present,
+                            needed: data_shards,
+                        });
+                    }
+                    let stripe = block
+                        .iter()
+                        .map(|shard| shard.map(|shard| &shard[range.clone()]))
+                        .collect::<Vec<_>>();
+                    Self::reconstruct_in(&*r, &stripe, &mut arena, stripe_size, false)?;
+                    content.extend_from_slice(&arena[from..to]);
+                    Ok(())
+                })
+            });
+        pool::give(arena);
+
+        result.map(|()| content)
+    }
+
+    // Present shards that still match their checksum, borrowed rather than cloned.
+    fn intact(&self) -> Vec<Option<&[u8]>> {
+        self.shards
+            .inner
+            .iter()
+            .enumerate()
+            .map(|(index, data)| {
+                data.as_deref()
+                    .filter(|data| self.shards.intact(index, data))
+            })
+            .collect()
+    }
+
+    // Checks the shards against the metadata.
+    fn layout(&self, shards: &[Option<&[u8]>]) -> Result<Layout, DecodeError> {
+        let meta = self.metadata();
+        let group = meta.data_shards() + meta.parity_shards();
+        let r = erasure::codec(meta.codec(), meta.data_shards(), meta.parity_shards())
+            .map_err(|_| DecodeError::InvalidLayout)?;
+
+        let blocks = meta
+            .block_lens()
+            .into_iter()
+            .map(usize::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| DecodeError::InvalidLayout)?;
+        let size = meta.shard_size();
+        let stripes = meta.stripes();
+        let sized = shards.iter().flatten().all(|shard| shard.len() == size);
+        let striped = stripes > 0
+            && size.is_multiple_of(stripes)
+            && (size / stripes).is_multiple_of(r.element());
+        let mapped = blocks.len() * group == shards.len()
+            && blocks.iter().sum::<usize>() as u64 == meta.len
+            && blocks.iter().all(|len| meta.data_shards() * size >= *len);
+        if size == 0 || !striped || !mapped || !sized {
+            return Err(DecodeError::InvalidLayout);
+        }
+
+        Ok(Layout {
+            codec: r,
+            blocks,
+            stripe_size: size / stripes,
+        })
+    }
+
+    // Copies the present shards into the arena and rebuilds the data shards that are missing,
+    // or every missing shard when `full`. Spare shards are not cross-checked here; that is
+    // what `verify` is for.
+    fn reconstruct_in(
+        r: &dyn Codec,
+        shards: &[Option<&[u8]>],
+        arena: &mut [u8],
+        shard_size: usize,
+        full: bool,
+    ) -> Result<(), DecodeError> {
+        let mut data = arena
+            .chunks_mut(shard_size)
+            .zip(shards)
+            .map(|(buf, shard)| match shard {
+                Some(shard) => {
+                    buf.copy_from_slice(shard);
+                    (buf, true)
+                }
+                None => (buf, false),
+            })
+            .collect::<Vec<_>>();
+
+        let missing = |data: &[(&mut [u8], bool)]| data.iter().any(|(_, present)| !present);
+        if !missing(if full {
+            &data
+        } else {
+            &data[..r.data_shards()]
+        }) {
+            return Ok(());
+        }
+
+        // A fountain code can fail on a particular set of data_shards shards; that is a
+        // decode still waiting for another shard rather than a corrupt file.
+        let present = shards.iter().flatten().count();
+        r.reconstruct(&mut data, !full).map_err(|err| match err {
+            CodecError::TooFewShards => DecodeError::Insufficient {
+                present,
+                needed: r.data_shards().max(present + 1),
+            },
+            _ => DecodeError::Inconsistent,
+        })
+    }
+
+    // Whether the present shards agree with each other: rebuilds the rest from them and
+    // checks the parity against the data.
+    fn consistent_in(
+        r: &dyn Codec,
+        shards: &[Option<&[u8]>],
+        arena: &mut [u8],
+        shard_size: usize,
+    ) -> bool {
+        if Self::reconstruct_in(r, shards, arena, shard_size, true).is_err() {
+            return false;
+        }
+
+        let refs = arena.chunks(shard_size).collect::<Vec<_>>();
+        r.verify(&refs).unwrap_or(false)
+    }
+
+    pub fn holds<B: AsRef<[u8]>>(&self, content: B) -> bool {
+        let bytes = content.as_ref();
+        if self.meta.len != bytes.len() as u64 || self.shards.present() != self.meta.shard_count() {
+            return false;
+        }
+
+        let data_shards = self.meta.data_shards().max(1);
+        let group = data_shards + self.meta.parity_shards();
+        let stripe_size = self.meta.shard_size() / self.meta.stripes().max(1);
+        let mut offset = 0;
+        self.meta
+            .block_lens()
+            .into_iter()
+            .enumerate()
+            .all(|(block, len)| {
+                let start = offset;
+                offset += len as usize;
+                let Some(content) = bytes.get(start..offset) else {
+                    return false;
+                };
+
+                content
+                    .chunks(stripe_size.max(1))
+                    .enumerate()
+                    .all(|(chunk_index, chunk)| {
+                        let offset = chunk_index / data_shards * stripe_size;
+                        self.shards
+                            .inner
+                            .get(block * group + chunk_index % data_shards)
+                            .and_then(Option::as_ref)
+                            .and_then(|shard| shard.get(offset..))
+                            .is_some_and(|shard| shard.starts_with(chunk))
+                    })
+            })
+    }
+
+    // Scrubs the stored shards: returns the indices that fail their checksum or disagree with
+    // the parity of the rest of their block. A block needs a spare shard beyond `data_shards`
+    // to be checked at all, and two to single out the shard at fault; when it cannot, every
+    // shard of the inconsistent block is reported.
+    pub fn verify(&self) -> Vec<usize> {
+        let meta = self.metadata();
+        let size = meta.shard_size();
+        let mut failed = self
+            .shards
+            .inner
+            .iter()
+            .enumerate()
+            .filter(|(index, data)| {
+                data.as_ref()
+                    .is_some_and(|data| data.len() != size || !self.shards.intact(*index, data))
+            })
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+
+        let Ok(r) = erasure::codec(meta.codec(), meta.data_shards(), meta.parity_shards()) else {
+            return failed;
+        };
+        let group = meta.data_shards() + meta.parity_shards();
+        let stripes = meta.stripes().max(1);
+        let stripe_size = size / stripes;
+        if stripe_size == 0 || !stripe_size.is_multiple_of(r.element()) {
+            return failed;
+        }
+
+        let shards = self
+            .shards
+            .inner
+            .iter()
+            .enumerate()
+            .map(|(index, data)| data.as_deref().filter(|_| !failed.contains(&index)))
+            .collect::<Vec<_>>();
+
+        let mut arena = pool::take(group * stripe_size);
+        let mut consistent = |block: &[Option<&[u8]>]| {
+            block.iter().flatten().count() > r.data_shards()
+                && (0..stripes).all(|index| {
+                    let range = index * stripe_size..(index + 1) * stripe_size;
+                    let stripe = block
+                        .iter()
+                        .map(|shard| shard.map(|shard| &shard[range.clone()]))
+                        .collect::<Vec<_>>();
+                    Self::consistent_in(&*r, &stripe, &mut arena, stripe_size)
+                })
+        };
+
+        for (block_index, block) in shards.chunks(group).enumerate() {
+            if block.iter().flatten().count() <= r.data_shards() || consistent(block) {
+                continue;
+            }
+
+            let present = (0..block.len()).filter(|index| block[*index].is_some());
+            let suspects = present
+                .clone()
+                .filter(|index| {
+                    let mut without = block.to_vec();
+                    without[*index] = None;
+                    consistent(&without)
+                })
+                .collect::<Vec<_>>();
+            let offset = block_index * group;
+            match suspects.as_slice() {
+                [index] => failed.push(offset + index),
+                _ => failed.extend(present.map(|index| offset + index)),
+            }
+        }
+        pool::give(arena);
+
+        failed.sort_unstable();
+        failed
+    }
+
+    pub fn verify_shard(&self, shard: &Shard) -> bool {
+        self.shards.slot(shard.index).is_ok_and(|slot| {
+            self.shards
+                .open(&shard.data)
+                .is_some_and(|data| self.shards.intact(slot, &data))
+        })
+    }
+
+    pub fn can_decode(&self) -> bool {
+        let meta = self.metadata();
+        let group = meta.data_shards() + meta.parity_shards();
+        (0..meta.block_count()).all(|block| {
+            (block * group..(block + 1) * group)
+                .filter(|index| self.shards.contains(*index))
+                .count()
+                >= meta.data_shards()
+        })
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.meta
+    }
+
+    pub fn shards(&self) -> &Shards {
+        &self.shards
+    }
+
+    pub fn shards_mut(&mut self) -> &mut Shards {
+        &mut self.shards
+    }
+}
+
+// Files are equal when their data shards are, going by the checksums in their metadata;
+// metadata without checksums is compared as a whole instead.
+impl PartialEq for File {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.digest(), other.digest()) {
+            (Some(digest), Some(other)) => digest == other,
+            _ => self.meta == other.meta,
+        }
     }
 }
@@ -0,0 +1,358 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::network::{Command, Network};
+
+#[derive(Clone, Debug)]
+pub struct FlowConfig {
+    pub max_messages: usize,
+    pub max_bytes: usize,
+    pub ack_every: usize,
+    pub timeout: Duration,
+    pub max_queued: usize,
+}
+
+impl Default for FlowConfig {
+    fn default() -> Self {
+        Self {
+            max_messages: 32,
+            max_bytes: 32 * 1024,
+            ack_every: 8,
+            timeout: Duration::from_secs(2),
+            max_queued: 4096,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FlowStats {
+    pub queued: usize,
+    pub peak_queued: usize,
+    pub stalls: usize,
+    pub timeouts: usize,
+    pub blocked: usize,
+}
+
+// The peer's queue is full; the command is handed back for the caller to retry once
+// credit or a timeout has drained it.
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+#[error("send queue full")]
+pub struct Full(pub Box<Command>);
+
+// Credits count everything the receiver has seen, so messages lost on the way would
+// otherwise leave it behind `sent` for good. `skew` is what a timeout wrote off as lost
+// and is added to every credit after it.
+#[derive(Default)]
+struct Outbound {
+    sent: (u64, u64),
+    acked: (u64, u64),
+    skew: (u64, u64),
+    progress: Option<Instant>,
+    queue: VecDeque<(Command, usize)>,
+}
+
+impl Outbound {
+    fn outstanding(&self) -> (u64, u64) {
+        (self.sent.0 - self.acked.0, self.sent.1 - self.acked.1)
+    }
+}
+
+#[derive(Default)]
+struct Inbound {
+    received: (u64, u64),
+    acked: (u64, u64),
+}
+
+pub struct FlowControl {
+    config: FlowConfig,
+    outbound: HashMap<String, Outbound>,
+    inbound: HashMap<String, Inbound>,
+    stats: FlowStats,
+}
+
+impl FlowControl {
+    pub fn new(config: FlowConfig) -> Self {
+        let ack_every = config.ack_every.clamp(1, config.max_messages.max(1));
+        Self {
+            config: FlowConfig {
+                ack_every,
+                ..config
+            },
+            outbound: HashMap::new(),
+            inbound: HashMap::new(),
+            stats: FlowStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> FlowStats {
+        self.stats
+    }
+
+    pub fn outstanding(&self, peer: &str) -> (u64, u64) {
+        self.outbound
+            .get(peer)
+            .map(Outbound::outstanding)
+            .unwrap_or_default()
+    }
+
+    // Returns the commands to put on the wire now, or the command itself when the peer
+    // already has `max_queued` waiting. Callers should run `expire`, wait and try again
+    // rather than drop it.
+    pub fn send(
+        &mut self,
+        peer: &str,
+        command: Command,
+        now: Instant,
+    ) -> Result<Vec<(String, Command)>, Full> {
+        if let Command::Credit { .. } = command {
+            return Ok(vec![(peer.to_string(), command)]);
+        }
+
+        let full = self
+            .outbound
+            .get(peer)
+            .is_some_and(|outbound| outbound.queue.len() >= self.config.max_queued.max(1));
+        if full {
+            self.stats.blocked += 1;
+            return Err(Full(Box::new(command)));
+        }
+
+        let mut out = self.expire(now);
+
+        let size = command.size();
+        let outbound = self.outbound.entry(peer.to_string()).or_default();
+        if !outbound.queue.is_empty() || !Self::open(&self.config, outbound) {
+            self.stats.stalls += 1;
+            outbound.queue.push_back((command, size));
+            self.stats.queued += 1;
+            self.stats.peak_queued = self.stats.peak_queued.max(self.stats.queued);
+            return Ok(out);
+        }
+
+        Self::transmit(outbound, size, now);
+        out.push((peer.to_string(), command));
+        Ok(out)
+    }
+
+    pub fn received(&mut self, peer: &str, command: &Command) -> Option<Command> {
+        if let Command::Credit { .. } = command {
+            return None;
+        }
+
+        let inbound = self.inbound.entry(peer.to_string()).or_default();
+        inbound.received.0 += 1;
+        inbound.received.1 += command.size() as u64;
+
+        let unacked = (
+            inbound.received.0 - inbound.acked.0,
+            inbound.received.1 - inbound.acked.1,
+        );
+        if unacked.0 < self.config.ack_every as u64 && unacked.1 < self.config.max_bytes as u64 / 2
+        {
+            return None;
+        }
+
+        inbound.acked = inbound.received;
+        Some(Command::Credit {
+            messages: inbound.received.0,
+            bytes: inbound.received.1,
+        })
+    }
+
+    pub fn credit(
+        &mut self,
+        peer: &str,
+        messages: u64,
+        bytes: u64,
+        now: Instant,
+    ) -> Vec<(String, Command)> {
+        let mut out = self.expire(now);
+
+        let Some(outbound) = self.outbound.get_mut(peer) else {
+            return out;
+        };
+
+        let messages = (messages + outbound.skew.0).min(outbound.sent.0);
+        if messages > outbound.acked.0 {
+            let bytes = (bytes + outbound.skew.1).min(outbound.sent.1);
+            outbound.acked = (messages, bytes.max(outbound.acked.1));
+            outbound.progress = Some(now);
+        }
+
+        out.extend(self.release(peer, now));
+        out
+    }
+
+    pub fn expire(&mut self, now: Instant) -> Vec<(String, Command)> {
+        let stalled = self
+            .outbound
+            .iter()
+            .filter(|(_, outbound)| {
+                !outbound.queue.is_empty() || !Self::open(&self.config, outbound)
+            })
+            .filter(|(_, outbound)| {
+                outbound.progress.is_some_and(|progress| {
+                    now.saturating_duration_since(progress) >= self.config.timeout
+                })
+            })
+            .map(|(peer, _)| peer.clone())
+            .collect::<Vec<_>>();
+
+        let mut out = Vec::new();
+        for peer in stalled {
+            let outbound = self.outbound.get_mut(&peer).unwrap();
+            let (messages, bytes) = outbound.outstanding();
+            outbound.skew = (outbound.skew.0 + messages, outbound.skew.1 + bytes);
+            outbound.acked = outbound.sent;
+            outbound.progress = Some(now);
+            self.stats.timeouts += 1;
+            out.extend(self.release(&peer, now));
+        }
+
+        out
+    }
+
+    fn release(&mut self, peer: &str, now: Instant) -> Vec<(String, Command)> {
+        let Some(outbound) = self.outbound.get_mut(peer) else {
+            return Vec::new();
+        };
+
+        let mut out = Vec::new();
+        while Self::open(&self.config, outbound) {
+            let Some((command, size)) = outbound.queue.pop_front() else {
+                break;
+            };
+
+            self.stats.queued -= 1;
+            Self::transmit(outbound, size, now);
+            out.push((peer.to_string(), command));
+        }
+
+        out
+    }
+
+    fn open(config: &FlowConfig, outbound: &Outbound) -> bool {
+        let (messages, bytes) = outbound.outstanding();
+        messages == 0 || (messages < config.max_messages as u64 && bytes < config.max_bytes as u64)
+    }
+
+    fn transmit(outbound: &mut Outbound, size: usize, now: Instant) {
+        if outbound.outstanding().0 == 0 {
+            outbound.progress = Some(now);
+        }
+        outbound.sent.0 += 1;
+        outbound.sent.1 += size as u64;
+    }
+}
+
+pub struct Windowed<N> {
+    inner: N,
+    retry: Duration,
+    flow: Mutex<FlowControl>,
+}
+
+impl<N: Network> Windowed<N> {
+    pub fn new(inner: N, config: FlowConfig) -> Self {
+        Self {
+            inner,
+            retry: config.timeout / 4,
+            flow: Mutex::new(FlowControl::new(config)),
+        }
+    }
+
+    pub fn inner(&self) -> &N {
+        &self.inner
+    }
+
+    pub fn stats(&self) -> FlowStats {
+        self.flow.lock().unwrap().stats()
+    }
+
+    async fn transmit(&self, out: Vec<(String, Command)>) {
+        for (peer, command) in out {
+            self.inner.send(peer, command).await;
+        }
+    }
+}
+
+impl<N: Network> Network for Windowed<N> {
+    fn id(&self) -> String {
+        self.inner.id()
+    }
+
+    fn now(&self) -> Instant {
+        self.inner.now()
+    }
+
+    fn clock(&self) -> u64 {
+        self.inner.clock()
+    }
+
+    fn throughput(&self, peer: &str) -> Option<usize> {
+        self.inner.throughput(peer)
+    }
+
+    fn region(&self, peer: &str) -> Option<String> {
+        self.inner.region(peer)
+    }
+
+    fn mtu(&self) -> Option<usize> {
+        self.inner.mtu()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        self.inner.sleep(duration).await
+    }
+
+    async fn discover(&self) -> Vec<String> {
+        let out = self.flow.lock().unwrap().expire(self.inner.now());
+        self.transmit(out).await;
+        self.inner.discover().await
+    }
+
+    // Waits while the peer's queue is full; a window that stays shut is reset after the
+    // timeout, so this never waits much longer than that.
+    async fn send(&self, peer: String, mut command: Command) {
+        loop {
+            let res = self
+                .flow
+                .lock()
+                .unwrap()
+                .send(&peer, command, self.inner.now());
+            match res {
+                Ok(out) => return self.transmit(out).await,
+                Err(Full(back)) => command = *back,
+            }
+
+            self.inner.sleep(self.retry).await;
+            let out = self.flow.lock().unwrap().expire(self.inner.now());
+            self.transmit(out).await;
+        }
+    }
+
+    async fn recv(&self) -> Option<(String, Command)> {
+        loop {
+            let (peer, command) = self.inner.recv().await?;
+
+            if let Command::Credit { messages, bytes } = command {
+                let out =
+                    self.flow
+                        .lock()
+                        .unwrap()
+                        .credit(&peer, messages, bytes, self.inner.now());
+                self.transmit(out).await;
+                continue;
+            }
+
+            let credit = self.flow.lock().unwrap().received(&peer, &command);
+            if let Some(credit) = credit {
+                self.inner.send(peer.clone(), credit).await;
+            }
+
+            return Some((peer, command));
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+
+use crate::{
+    codec,
+    network::{Command, Network},
+};
+
+#[derive(Clone, Debug)]
+pub struct FragmentConfig {
+    pub timeout: Duration,
+    pub max_pending: usize,
+    // Largest frame a peer may reassemble; anything claiming more is malformed.
+    pub max_message: usize,
+}
+
+impl Default for FragmentConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(5),
+            max_pending: 256,
+            max_message: 64 << 20,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FragmentStats {
+    pub fragmented: usize,
+    pub fragments: usize,
+    pub reassembled: usize,
+    pub pending: usize,
+    pub expired: usize,
+    pub evicted: usize,
+    pub malformed: usize,
+}
+
+struct Partial {
+    parts: Vec<Option<Vec<u8>>>,
+    remaining: usize,
+    size: usize,
+    started: Instant,
+}
+
+pub struct Fragments {
+    config: FragmentConfig,
+    next: u64,
+    partials: HashMap<(String, u64), Partial>,
+    stats: FragmentStats,
+}
+
+impl Fragments {
+    pub fn new(config: FragmentConfig) -> Self {
+        Self {
+            config,
+            next: 0,
+            partials: HashMap::new(),
+            stats: FragmentStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> FragmentStats {
+        FragmentStats {
+            pending: self.partials.len(),
+            ..self.stats
+        }
+    }
+
+    pub fn split(&mut self, command: Command, mtu: usize) -> Vec<Command> {
+        // A command that cannot be framed goes out whole, for the transport to refuse.
+        let Some(frame) = codec::encode(&command) else {
+            return vec![command];
+        };
+        if frame.len() <= mtu || matches!(command, Command::Fragment { .. }) {
+            return vec![command];
+        }
+
+        let overhead = Command::Fragment {
+            id: 0,
+            index: 0,
+            count: 0,
+            data: Vec::new(),
+        }
+        .size();
+        let chunk = mtu.saturating_sub(overhead).max(1);
+
+        let id = self.next;
+        self.next += 1;
+
+        let count = frame.len().div_ceil(chunk);
+        self.stats.fragmented += 1;
+        self.stats.fragments += count;
+
+        frame
+            .chunks(chunk)
+            .enumerate()
+            .map(|(index, data)| Command::Fragment {
+                id,
+                index,
+                count,
+                data: data.to_vec(),
+            })
+            .collect()
+    }
+
+    pub fn reassemble(&mut self, peer: &str, command: Command, now: Instant) -> Option<Command> {
+        let Command::Fragment {
+            id,
+            index,
+            count,
+            data,
+        } = command
+        else {
+            return Some(command);
+        };
+
+        self.expire(now);
+
+        // Every fragment carries at least one byte, so the count alone bounds the allocation.
+        let key = (peer.to_string(), id);
+        if count == 0 || index >= count || count > self.config.max_message {
+            self.stats.malformed += 1;
+            return None;
+        }
+
+        if !self.partials.contains_key(&key) {
+            while self.partials.len() >= self.config.max_pending.max(1) {
+                let oldest = self
+                    .partials
+                    .iter()
+                    .min_by_key(|(_, partial)| partial.started)
+                    .map(|(key, _)| key.clone())?;
+
+                self.partials.remove(&oldest);
+                self.stats.evicted += 1;
+            }
+
+            self.partials.insert(
+                key.clone(),
+                Partial {
+                    parts: vec![None; count],
+                    remaining: count,
+                    size: 0,
+                    started: now,
+                },
+            );
+        }
+
+        let partial = self.partials.get_mut(&key)?;
+        if partial.parts.len() != count {
+            self.partials.remove(&key);
+            self.stats.malformed += 1;
+            return None;
+        }
+
+        if partial.parts[index].is_none() {
+            partial.size += data.len();
+            if partial.size > self.config.max_message {
+                self.partials.remove(&key);
+                self.stats.malformed += 1;
+                return None;
+            }
+
+            partial.parts[index] = Some(data);
+            partial.remaining -= 1;
+        }
+
+        if partial.remaining > 0 {
+            return None;
+        }
+
+        let partial = self.partials.remove(&key)?;
+        let frame = partial
+            .parts
+            .into_iter()
+            .flatten()
+            .flatten()
+            .collect::<Vec<_>>();
+        match codec::decode(Bytes::from(frame)) {
+            Some(command) => {
+                self.stats.reassembled += 1;
+                Some(command)
+            }
+            None => {
+                self.stats.malformed += 1;
+                None
+            }
+        }
+    }
+
+    pub fn expire(&mut self, now: Instant) {
+        let timeout = self.config.timeout;
+        let before = self.partials.len();
+        self.partials
+            .retain(|_, partial| now.saturating_duration_since(partial.started) < timeout);
+        self.stats.expired += before - self.partials.len();
+    }
+}
+
+pub struct Fragmenter<N> {
+    inner: N,
+    fragments: Mutex<Fragments>,
+}
+
+impl<N: Network> Fragmenter<N> {
+    pub fn new(inner: N, config: FragmentConfig) -> Self {
+        Self {
+            inner,
+            fragments: Mutex::new(Fragments::new(config)),
+        }
+    }
+
+    pub fn inner(&self) -> &N {
+        &self.inner
+    }
+
+    pub fn stats(&self) -> FragmentStats {
+        self.fragments.lock().unwrap().stats()
+    }
+}
+
+impl<N: Network> Network for Fragmenter<N> {
+    fn id(&self) -> String {
+        self.inner.id()
+    }
+
+    fn now(&self) -> Instant {
+        self.inner.now()
+    }
+
+    fn clock(&self) -> u64 {
+        self.inner.clock()
+    }
+
+    fn throughput(&self, peer: &str) -> Option<usize> {
+        self.inner.throughput(peer)
+    }
+
+    fn region(&self, peer: &str) -> Option<String> {
+        self.inner.region(peer)
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        self.inner.sleep(duration).await
+    }
+
+    async fn discover(&self) -> Vec<String> {
+        self.fragments.lock().unwrap().expire(self.inner.now());
+        self.inner.discover().await
+    }
+
+    async fn send(&self, peer: String, command: Command) {
+        let Some(mtu) = self.inner.mtu() else {
+            return self.inner.send(peer, command).await;
+        };
+
+        let out = self.fragments.lock().unwrap().split(command, mtu);
+        for command in out {
+            self.inner.send(peer.clone(), command).await;
+        }
+    }
+
+    async fn recv(&self) -> Option<(String, Command)> {
+        loop {
+            let (peer, command) = self.inner.recv().await?;
+            let command =
+                self.fragments
+                    .lock()
+                    .unwrap()
+                    .reassemble(&peer, command, self.inner.now());
+
+            if let Some(command) = command {
+                return Some((peer, command));
+            }
+        }
+    }
+}
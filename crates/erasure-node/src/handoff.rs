@@ -0,0 +1,129 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    audit::{self, prove},
+    file::Shard,
+};
+
+// Challenges sent to the new holder before a handoff is given up and the old copy kept.
+const ATTEMPTS: usize = 5;
+
+struct Handoff {
+    from: String,
+    to: String,
+    nonce: u64,
+    expected: u64,
+    asked: Instant,
+    attempts: usize,
+}
+
+// Shards whose placement moved from one peer to another. The old holder is only told to drop
+// its copy once the new one answers a challenge for the shard, so a move that never lands
+// leaves the old copy in place.
+pub(crate) struct Handoffs {
+    interval: Duration,
+    pending: HashMap<(String, usize), Handoff>,
+    completed: usize,
+    abandoned: usize,
+}
+
+impl Handoffs {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            pending: HashMap::new(),
+            completed: 0,
+            abandoned: 0,
+        }
+    }
+
+    // Returns the nonce to challenge `to` with.
+    pub fn start(
+        &mut self,
+        name: String,
+        shard: &Shard,
+        from: String,
+        to: String,
+        now: Instant,
+    ) -> u64 {
+        let nonce = audit::nonce();
+        let handoff = Handoff {
+            from,
+            to,
+            nonce,
+            expected: prove(shard, nonce),
+            asked: now,
+            attempts: 1,
+        };
+        self.pending.insert((name, shard.index()), handoff);
+        nonce
+    }
+
+    // None when the proof answers no handoff. Otherwise the peer to drop the shard from, once
+    // the new holder proved it has it; a missing or wrong proof is asked again later.
+    pub fn confirm(
+        &mut self,
+        peer: &str,
+        name: &str,
+        index: usize,
+        nonce: u64,
+        proof: Option<u64>,
+    ) -> Option<Option<String>> {
+        let key = (name.to_string(), index);
+        let handoff = self.pending.get(&key)?;
+        if handoff.to != peer || handoff.nonce != nonce {
+            return None;
+        }
+        if proof != Some(handoff.expected) {
+            return Some(None);
+        }
+
+        self.completed += 1;
+        Some(self.pending.remove(&key).map(|handoff| handoff.from))
+    }
+
+    // Handoffs to challenge again, as (file, index, new holder, nonce). The proof is keyed
+    // by the original nonce, so each retry re-sends it.
+    pub fn due(&mut self, now: Instant) -> Vec<(String, usize, String, u64)> {
+        let interval = self.interval;
+        let mut abandoned = 0;
+        self.pending.retain(|_, handoff| {
+            let waiting = now.saturating_duration_since(handoff.asked) < interval;
+            if !waiting && handoff.attempts >= ATTEMPTS {
+                abandoned += 1;
+                return false;
+            }
+            true
+        });
+        self.abandoned += abandoned;
+
+        self.pending
+            .iter_mut()
+            .filter(|(_, handoff)| now.saturating_duration_since(handoff.asked) >= interval)
+            .map(|((name, index), handoff)| {
+                handoff.asked = now;
+                handoff.attempts += 1;
+                (name.clone(), *index, handoff.to.clone(), handoff.nonce)
+            })
+            .collect()
+    }
+
+    pub fn cancel(&mut self, name: &str) {
+        self.pending.retain(|(file, _), _| file != name);
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn completed(&self) -> usize {
+        self.completed
+    }
+
+    pub fn abandoned(&self) -> usize {
+        self.abandoned
+    }
+}
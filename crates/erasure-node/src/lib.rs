@@ -1,3 +1,44 @@
+pub mod archive;
+pub mod audit;
+pub mod codec;
+pub mod compress;
+pub mod config;
+#[cfg(feature = "encryption")]
+pub mod crypt;
+pub mod erasure;
+pub mod error;
+pub mod events;
 pub mod file;
+pub mod flow;
+pub mod fragment;
+pub mod membership;
+pub mod metrics;
 pub mod network;
 pub mod node;
+pub mod observer;
+pub mod placement;
+pub mod pool;
+#[cfg(feature = "proto")]
+pub mod proto;
+pub mod quota;
+pub mod redundancy;
+pub mod shardfile;
+#[cfg(feature = "signing")]
+pub mod sign;
+pub mod store;
+#[cfg(feature = "tcp")]
+pub mod tcp;
+pub mod zfec;
+
+mod backfill;
+mod cache;
+mod decoded;
+mod download;
+mod encoding;
+mod handoff;
+mod nacks;
+mod orphans;
+mod peer;
+mod requests;
+
+pub use error::Error;
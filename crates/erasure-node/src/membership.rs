@@ -0,0 +1,509 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::network::{Command, Network};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum State {
+    Alive,
+    Suspect,
+    Dead,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Update {
+    pub member: String,
+    pub state: State,
+    pub incarnation: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct MembershipConfig {
+    pub probe_interval: Duration,
+    pub probe_timeout: Duration,
+    pub indirect_probes: usize,
+    pub suspicion_timeout: Duration,
+    pub piggyback: usize,
+    pub retransmit: usize,
+}
+
+impl Default for MembershipConfig {
+    fn default() -> Self {
+        Self {
+            probe_interval: Duration::from_millis(1000),
+            probe_timeout: Duration::from_millis(200),
+            indirect_probes: 3,
+            suspicion_timeout: Duration::from_millis(3000),
+            piggyback: 8,
+            retransmit: 3,
+        }
+    }
+}
+
+struct Member {
+    state: State,
+    incarnation: u64,
+    changed: Instant,
+}
+
+struct Probe {
+    target: String,
+    seq: u64,
+    sent: Instant,
+    indirect: bool,
+}
+
+pub struct Membership {
+    id: String,
+    incarnation: u64,
+    config: MembershipConfig,
+    members: HashMap<String, Member>,
+    cursor: usize,
+    next_probe: Instant,
+    probe: Option<Probe>,
+    relays: HashMap<u64, (String, u64, Instant)>,
+    seq: u64,
+    gossip: Vec<(Update, usize)>,
+}
+
+impl Membership {
+    pub fn new(
+        id: String,
+        seeds: impl IntoIterator<Item = String>,
+        config: MembershipConfig,
+        now: Instant,
+    ) -> Self {
+        let members = seeds
+            .into_iter()
+            .filter(|seed| *seed != id)
+            .map(|seed| {
+                let member = Member {
+                    state: State::Alive,
+                    incarnation: 0,
+                    changed: now,
+                };
+                (seed, member)
+            })
+            .collect();
+
+        let mut membership = Self {
+            id,
+            incarnation: 0,
+            config,
+            members,
+            cursor: 0,
+            next_probe: now,
+            probe: None,
+            relays: HashMap::new(),
+            seq: 0,
+            gossip: Vec::new(),
+        };
+        membership.disseminate(Update {
+            member: membership.id.clone(),
+            state: State::Alive,
+            incarnation: 0,
+        });
+        membership
+    }
+
+    pub fn alive(&self) -> Vec<String> {
+        let mut alive = self
+            .members
+            .iter()
+            .filter(|(_, member)| member.state != State::Dead)
+            .map(|(id, _)| id.clone())
+            .collect::<Vec<_>>();
+        alive.sort();
+        alive
+    }
+
+    pub fn state(&self, member: &str) -> Option<State> {
+        self.members.get(member).map(|member| member.state)
+    }
+
+    pub fn incarnation(&self) -> u64 {
+        self.incarnation
+    }
+
+    pub fn tick(&mut self, now: Instant) -> Vec<(String, Command)> {
+        let mut out = Vec::new();
+
+        let expired = self
+            .members
+            .iter()
+            .filter(|(_, member)| member.state == State::Suspect)
+            .filter(|(_, member)| {
+                now.saturating_duration_since(member.changed) >= self.config.suspicion_timeout
+            })
+            .map(|(id, member)| (id.clone(), member.incarnation))
+            .collect::<Vec<_>>();
+
+        for (member, incarnation) in expired {
+            self.apply(
+                Update {
+                    member,
+                    state: State::Dead,
+                    incarnation,
+                },
+                now,
+            );
+        }
+
+        let timeout = self.config.probe_timeout;
+        self.relays
+            .retain(|_, (_, _, sent)| now.saturating_duration_since(*sent) < timeout);
+
+        if let Some(probe) = &mut self.probe {
+            let elapsed = now.saturating_duration_since(probe.sent);
+            if !probe.indirect && elapsed >= self.config.probe_timeout {
+                probe.indirect = true;
+                let (target, seq) = (probe.target.clone(), probe.seq);
+
+                let helpers = self
+                    .alive()
+                    .into_iter()
+                    .filter(|member| *member != target)
+                    .cycle()
+                    .skip(self.cursor)
+                    .take(
+                        self.config
+                            .indirect_probes
+                            .min(self.members.len().saturating_sub(1)),
+                    )
+                    .collect::<Vec<_>>();
+
+                for helper in helpers {
+                    let command = Command::PingReq {
+                        seq,
+                        target: target.clone(),
+                        updates: self.piggyback(),
+                    };
+                    out.push((helper, command));
+                }
+            } else if probe.indirect && elapsed >= self.config.probe_interval {
+                let target = probe.target.clone();
+                self.probe = None;
+
+                if let Some(member) = self.members.get(&target)
+                    && member.state == State::Alive
+                {
+                    let incarnation = member.incarnation;
+                    self.apply(
+                        Update {
+                            member: target,
+                            state: State::Suspect,
+                            incarnation,
+                        },
+                        now,
+                    );
+                }
+            }
+        }
+
+        if self.probe.is_none() && now >= self.next_probe {
+            self.next_probe = now + self.config.probe_interval;
+
+            let mut candidates = self.alive();
+            if candidates.is_empty() {
+                candidates = self.members.keys().cloned().collect();
+                candidates.sort();
+            }
+
+            if !candidates.is_empty() {
+                let target = candidates[self.cursor % candidates.len()].clone();
+                self.cursor = self.cursor.wrapping_add(1);
+
+                self.seq += 1;
+                self.probe = Some(Probe {
+                    target: target.clone(),
+                    seq: self.seq,
+                    sent: now,
+                    indirect: false,
+                });
+
+                let command = Command::Ping {
+                    seq: self.seq,
+                    updates: self.piggyback(),
+                };
+                out.push((target, command));
+            }
+        }
+
+        out
+    }
+
+    /// The earliest instant at which `tick` has work to do.
+    pub fn deadline(&self) -> Instant {
+        let probe = match &self.probe {
+            Some(probe) if probe.indirect => probe.sent + self.config.probe_interval,
+            Some(probe) => probe.sent + self.config.probe_timeout,
+            None => self.next_probe,
+        };
+
+        let suspects = self
+            .members
+            .values()
+            .filter(|member| member.state == State::Suspect)
+            .map(|member| member.changed + self.config.suspicion_timeout);
+
+        let relays = self
+            .relays
+            .values()
+            .map(|(_, _, sent)| *sent + self.config.probe_timeout);
+
+        suspects.chain(relays).fold(probe, Instant::min)
+    }
+
+    pub fn handle(
+        &mut self,
+        from: &str,
+        command: &Command,
+        now: Instant,
+    ) -> Option<Vec<(String, Command)>> {
+        let updates = match command {
+            Command::Ping { updates, .. }
+            | Command::PingReq { updates, .. }
+            | Command::Ack { updates, .. } => updates,
+            _ => return None,
+        };
+
+        if from != self.id && !self.members.contains_key(from) {
+            self.apply(
+                Update {
+                    member: from.to_string(),
+                    state: State::Alive,
+                    incarnation: 0,
+                },
+                now,
+            );
+        }
+
+        for update in updates {
+            self.apply(update.clone(), now);
+        }
+
+        if let Some(member) = self.members.get(from)
+            && member.state == State::Dead
+        {
+            let update = Update {
+                member: from.to_string(),
+                state: State::Dead,
+                incarnation: member.incarnation,
+            };
+            self.disseminate(update);
+        }
+
+        let mut out = Vec::new();
+        match command {
+            Command::Ping { seq, .. } => {
+                let command = Command::Ack {
+                    seq: *seq,
+                    updates: self.piggyback(),
+                };
+                out.push((from.to_string(), command));
+            }
+            Command::PingReq { seq, target, .. } => {
+                self.seq += 1;
+                self.relays.insert(self.seq, (from.to_string(), *seq, now));
+
+                let command = Command::Ping {
+                    seq: self.seq,
+                    updates: self.piggyback(),
+                };
+                out.push((target.clone(), command));
+            }
+            Command::Ack { seq, .. } => {
+                if let Some((origin, seq, _)) = self.relays.remove(seq) {
+                    let command = Command::Ack {
+                        seq,
+                        updates: self.piggyback(),
+                    };
+                    out.push((origin, command));
+                } else if self.probe.as_ref().is_some_and(|probe| probe.seq == *seq) {
+                    self.probe = None;
+                }
+            }
+            _ => unreachable!(),
+        }
+
+        Some(out)
+    }
+
+    fn apply(&mut self, update: Update, now: Instant) {
+        if update.member == self.id {
+            if update.state != State::Alive && update.incarnation >= self.incarnation {
+                self.incarnation = update.incarnation + 1;
+                self.disseminate(Update {
+                    member: self.id.clone(),
+                    state: State::Alive,
+                    incarnation: self.incarnation,
+                });
+            }
+            return;
+        }
+
+        let accepted = match self.members.get(&update.member) {
+            None => true,
+            Some(current) => match update.state {
+                State::Alive => update.incarnation > current.incarnation,
+                State::Suspect => match current.state {
+                    State::Alive => update.incarnation >= current.incarnation,
+                    State::Suspect | State::Dead => update.incarnation > current.incarnation,
+                },
+                State::Dead => {
+                    current.state != State::Dead && update.incarnation >= current.incarnation
+                }
+            },
+        };
+
+        if !accepted {
+            return;
+        }
+
+        self.members.insert(
+            update.member.clone(),
+            Member {
+                state: update.state,
+                incarnation: update.incarnation,
+                changed: now,
+            },
+        );
+        self.disseminate(update);
+    }
+
+    fn disseminate(&mut self, update: Update) {
+        let rounds = self.config.retransmit
+            * (usize::BITS - self.members.len().leading_zeros()).max(1) as usize;
+        self.gossip
+            .retain(|(pending, _)| pending.member != update.member);
+        self.gossip.push((update, rounds));
+    }
+
+    fn piggyback(&mut self) -> Vec<Update> {
+        self.gossip
+            .sort_by_key(|(_, remaining)| std::cmp::Reverse(*remaining));
+
+        let updates = self
+            .gossip
+            .iter_mut()
+            .take(self.config.piggyback)
+            .map(|(update, remaining)| {
+                *remaining -= 1;
+                update.clone()
+            })
+            .collect();
+
+        self.gossip.retain(|(_, remaining)| *remaining > 0);
+        updates
+    }
+}
+
+pub struct Swim<N> {
+    inner: N,
+    membership: Mutex<Membership>,
+}
+
+impl<N: Network> Swim<N> {
+    pub async fn new(inner: N, config: MembershipConfig) -> Self {
+        let seeds = inner.discover().await;
+        let membership = Membership::new(inner.id(), seeds, config, inner.now());
+
+        Self {
+            inner,
+            membership: Mutex::new(membership),
+        }
+    }
+
+    pub fn inner(&self) -> &N {
+        &self.inner
+    }
+
+    pub fn state(&self, member: &str) -> Option<State> {
+        self.membership.lock().unwrap().state(member)
+    }
+
+    pub async fn tick(&self) {
+        let out = self.membership.lock().unwrap().tick(self.inner.now());
+        for (peer, command) in out {
+            self.inner.send(peer, command).await;
+        }
+    }
+}
+
+impl<N: Network> Network for Swim<N> {
+    fn id(&self) -> String {
+        self.inner.id()
+    }
+
+    fn now(&self) -> Instant {
+        self.inner.now()
+    }
+
+    fn clock(&self) -> u64 {
+        self.inner.clock()
+    }
+
+    fn throughput(&self, peer: &str) -> Option<usize> {
+        self.inner.throughput(peer)
+    }
+
+    fn region(&self, peer: &str) -> Option<String> {
+        self.inner.region(peer)
+    }
+
+    fn mtu(&self) -> Option<usize> {
+        self.inner.mtu()
+    }
+
+    /// Ticks the membership protocol for as long as the caller sleeps, so the
+    /// node's maintenance timer also drives probes and suspicion timeouts.
+    async fn sleep(&self, duration: Duration) {
+        let until = self.inner.now() + duration;
+        loop {
+            self.tick().await;
+
+            let now = self.inner.now();
+            if now >= until {
+                break;
+            }
+
+            let deadline = self.membership.lock().unwrap().deadline().min(until);
+            self.inner
+                .sleep(deadline.saturating_duration_since(now))
+                .await;
+        }
+    }
+
+    async fn discover(&self) -> Vec<String> {
+        self.tick().await;
+        self.membership.lock().unwrap().alive()
+    }
+
+    async fn send(&self, peer: String, command: Command) {
+        self.inner.send(peer, command).await
+    }
+
+    async fn recv(&self) -> Option<(String, Command)> {
+        loop {
+            let (peer, command) = self.inner.recv().await?;
+            let out = self
+                .membership
+                .lock()
+                .unwrap()
+                .handle(&peer, &command, self.inner.now());
+
+            let Some(out) = out else {
+                return Some((peer, command));
+            };
+
+            for (peer, command) in out {
+                self.inner.send(peer, command).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use std::sync::Arc;
+
+pub const MESSAGES_SENT: &str = "erasure_node_messages_sent";
+pub const MESSAGES_RECEIVED: &str = "erasure_node_messages_received";
+pub const SHARDS_STORED: &str = "erasure_node_shards_stored";
+pub const SHARDS_REJECTED: &str = "erasure_node_shards_rejected";
+pub const STORED_BYTES: &str = "erasure_node_stored_bytes";
+pub const DECODES: &str = "erasure_node_decodes";
+pub const DECODES_CACHED: &str = "erasure_node_decodes_cached";
+pub const UPLOADS: &str = "erasure_node_uploads";
+pub const UPLOAD_LATENCY: &str = "erasure_node_upload_latency_seconds";
+pub const DOWNLOADS_SUCCEEDED: &str = "erasure_node_downloads_succeeded";
+pub const DOWNLOADS_FAILED: &str = "erasure_node_downloads_failed";
+pub const DOWNLOADS_PENDING: &str = "erasure_node_downloads_pending";
+pub const DOWNLOAD_LATENCY: &str = "erasure_node_download_latency_seconds";
+pub const TCP_CONNECTIONS: &str = "erasure_node_tcp_connections";
+pub const TCP_INBOUND: &str = "erasure_node_tcp_inbound";
+pub const TCP_DIALS: &str = "erasure_node_tcp_dials";
+pub const TCP_DIAL_FAILURES: &str = "erasure_node_tcp_dial_failures";
+pub const TCP_RECONNECTS: &str = "erasure_node_tcp_reconnects";
+pub const TCP_BROKEN: &str = "erasure_node_tcp_broken";
+pub const TCP_DROPPED: &str = "erasure_node_tcp_dropped";
+pub const TCP_HEARTBEATS: &str = "erasure_node_tcp_heartbeats";
+
+pub trait MetricsSink: Send + Sync {
+    fn counter(&self, name: &'static str, value: u64);
+    fn gauge(&self, name: &'static str, value: f64);
+    fn histogram(&self, name: &'static str, value: f64);
+}
+
+impl<M: MetricsSink + ?Sized> MetricsSink for Arc<M> {
+    fn counter(&self, name: &'static str, value: u64) {
+        (**self).counter(name, value);
+    }
+
+    fn gauge(&self, name: &'static str, value: f64) {
+        (**self).gauge(name, value);
+    }
+
+    fn histogram(&self, name: &'static str, value: f64) {
+        (**self).histogram(name, value);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoopMetrics;
+
+impl MetricsSink for NoopMetrics {
+    fn counter(&self, _name: &'static str, _value: u64) {}
+    fn gauge(&self, _name: &'static str, _value: f64) {}
+    fn histogram(&self, _name: &'static str, _value: f64) {}
+}
+
+#[cfg(feature = "metrics")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MetricsRs;
+
+#[cfg(feature = "metrics")]
+impl MetricsSink for MetricsRs {
+    fn counter(&self, name: &'static str, value: u64) {
+        ::metrics::counter!(name).increment(value);
+    }
+
+    fn gauge(&self, name: &'static str, value: f64) {
+        ::metrics::gauge!(name).set(value);
+    }
+
+    fn histogram(&self, name: &'static str, value: f64) {
+        ::metrics::histogram!(name).record(value);
+    }
+}
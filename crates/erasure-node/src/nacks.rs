@@ -0,0 +1,84 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+type Key = (String, String, usize);
+
+// Tracks nacks per (peer, file, shard). An entry stays around for `ttl` after the last
+// mismatch even once its retries are used up, so a peer that keeps sending a bad shard is
+// not nacked again until it expires; entries whose retransmit never arrives expire the same way.
+pub(crate) struct Nacks {
+    retries: usize,
+    ttl: Duration,
+    attempts: HashMap<Key, (usize, Instant)>,
+    sent: usize,
+    retransmitted: usize,
+    exhausted: usize,
+}
+
+impl Nacks {
+    pub fn new(retries: usize, ttl: Duration) -> Self {
+        Self {
+            retries,
+            ttl,
+            attempts: HashMap::new(),
+            sent: 0,
+            retransmitted: 0,
+            exhausted: 0,
+        }
+    }
+
+    pub fn mismatch(&mut self, peer: &str, name: &str, index: usize, now: Instant) -> bool {
+        self.expire(now);
+
+        let key = (peer.to_string(), name.to_string(), index);
+        let (attempts, last) = self.attempts.entry(key).or_insert((0, now));
+        *last = now;
+
+        if *attempts >= self.retries {
+            self.exhausted += 1;
+            return false;
+        }
+
+        *attempts += 1;
+        self.sent += 1;
+        true
+    }
+
+    pub fn received(&mut self, peer: &str, name: &str, index: usize) {
+        if !self.attempts.is_empty() {
+            self.attempts
+                .remove(&(peer.to_string(), name.to_string(), index));
+        }
+    }
+
+    pub fn expire(&mut self, now: Instant) {
+        let ttl = self.ttl;
+        self.attempts
+            .retain(|_, (_, last)| now.saturating_duration_since(*last) < ttl);
+    }
+
+    pub fn retransmit(&mut self) {
+        self.retransmitted += 1;
+    }
+
+    pub fn pending(&self) -> usize {
+        self.attempts
+            .values()
+            .filter(|(attempts, _)| *attempts < self.retries)
+            .count()
+    }
+
+    pub fn sent(&self) -> usize {
+        self.sent
+    }
+
+    pub fn retransmitted(&self) -> usize {
+        self.retransmitted
+    }
+
+    pub fn exhausted(&self) -> usize {
+        self.exhausted
+    }
+}
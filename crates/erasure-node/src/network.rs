@@ -1,34 +1,208 @@
-use crate::file::{Metadata, Shard};
+use std::{
+    pin::pin,
+    time::{Duration, Instant, SystemTime},
+};
 
-#[derive(Clone, Debug)]
+use bytes::Bytes;
+use futures::{
+    Stream,
+    future::{self, Either},
+    stream,
+};
+
+use crate::{
+    Error, codec,
+    file::{Metadata, Shard, Stamp},
+    membership::Update,
+    metrics::MetricsSink,
+    quota::Rejection,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Command {
-    Create { name: String, meta: Metadata },
-    Replicate { name: String, shard: Shard },
-    Request { name: String },
+    Create {
+        name: String,
+        meta: Metadata,
+    },
+    Replicate {
+        name: String,
+        stamp: Stamp,
+        shard: Shard,
+        checksum: u32,
+    },
+    Request {
+        name: String,
+        bitmap: Vec<u8>,
+    },
+    Fetch {
+        name: String,
+        index: usize,
+    },
+    Cancel {
+        name: String,
+    },
+    Announce,
+    Manifest {
+        files: Vec<(String, Metadata)>,
+    },
+    Leave,
+    Challenge {
+        name: String,
+        index: usize,
+        nonce: u64,
+    },
+    Proof {
+        name: String,
+        index: usize,
+        nonce: u64,
+        proof: Option<u64>,
+    },
+    Reject {
+        name: String,
+        reason: Rejection,
+    },
+    Drop {
+        name: String,
+        indices: Vec<usize>,
+    },
+    RequestMeta {
+        name: String,
+    },
+    Meta {
+        name: String,
+        meta: Metadata,
+        bitmap: Vec<u8>,
+    },
+    Ingest {
+        name: String,
+        content: Bytes,
+    },
+    Ping {
+        seq: u64,
+        updates: Vec<Update>,
+    },
+    PingReq {
+        seq: u64,
+        target: String,
+        updates: Vec<Update>,
+    },
+    Ack {
+        seq: u64,
+        updates: Vec<Update>,
+    },
+    Nack {
+        name: String,
+        index: usize,
+    },
+    Credit {
+        messages: u64,
+        bytes: u64,
+    },
+    Fragment {
+        id: u64,
+        index: usize,
+        count: usize,
+        data: Vec<u8>,
+    },
+    Offer {
+        name: String,
+        meta: Metadata,
+        layout: Vec<String>,
+    },
+    Delete {
+        name: String,
+        stamp: Stamp,
+    },
 }
 
 impl Command {
     pub fn size(&self) -> usize {
-        match self {
-            Self::Create { name, .. } => name.len() + std::mem::size_of::<Metadata>(),
-            Self::Replicate { name, shard } => name.len() + shard.size(),
-            Self::Request { name } => name.len(),
-        }
+        codec::encoded_len(self)
+    }
+
+    // Length-prefixed frame in the same format `codec` uses on the wire.
+    pub fn to_bytes(&self) -> Result<Bytes, Error> {
+        codec::encode(self).ok_or(Error::TooLarge)
+    }
+
+    pub fn from_bytes(frame: Bytes) -> Result<Self, Error> {
+        codec::decode(frame).ok_or(Error::Malformed)
     }
 }
 
 #[allow(async_fn_in_trait)]
 pub trait Network {
+    fn id(&self) -> String;
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn clock(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|since| since.as_micros() as u64)
+            .unwrap_or_default()
+    }
+
+    fn throughput(&self, _peer: &str) -> Option<usize> {
+        None
+    }
+
+    fn mtu(&self) -> Option<usize> {
+        None
+    }
+
+    /// The region `peer` runs in, or this node's own when asked with its id. Downloads ask
+    /// holders in the node's region before those elsewhere; while either is unknown, peers
+    /// are ranked by latency alone.
+    fn region(&self, _peer: &str) -> Option<String> {
+        None
+    }
+
+    /// Resolves once `duration` has passed. Networks without a timer never resolve,
+    /// which leaves periodic work to run as commands arrive.
+    async fn sleep(&self, _duration: Duration) {
+        future::pending().await
+    }
+
     async fn discover(&self) -> Vec<String>;
     async fn send(&self, peer: String, command: Command);
     async fn recv(&self) -> Option<(String, Command)>;
+
+    /// Publishes the network's own metrics, such as connection pool state; the node calls
+    /// it on every maintenance tick.
+    fn report(&self, _sink: &dyn MetricsSink) {}
+
+    fn incoming(&self) -> impl Stream<Item = (String, Command)> {
+        stream::unfold(self, |network| async move {
+            network.recv().await.map(|msg| (msg, network))
+        })
+    }
 }
 
 #[allow(async_fn_in_trait)]
 pub trait NetworkExt {
     async fn create(&self, peer: String, name: String, meta: Metadata);
-    async fn replicate(&self, peer: String, name: String, shard: Shard);
-    async fn request(&self, peer: String, name: String);
+    async fn replicate(&self, peer: String, name: String, stamp: Stamp, shard: Shard);
+    async fn request(&self, peer: String, name: String, bitmap: Vec<u8>);
+    async fn fetch(&self, peer: String, name: String, index: usize);
+    async fn cancel(&self, peer: String, name: String);
+    async fn announce(&self, peer: String);
+    async fn manifest(&self, peer: String, files: Vec<(String, Metadata)>);
+    async fn leave(&self, peer: String);
+    async fn challenge(&self, peer: String, name: String, index: usize, nonce: u64);
+    async fn proof(&self, peer: String, name: String, index: usize, nonce: u64, proof: Option<u64>);
+    async fn reject(&self, peer: String, name: String, reason: Rejection);
+    async fn release(&self, peer: String, name: String, indices: Vec<usize>);
+    async fn request_meta(&self, peer: String, name: String);
+    async fn meta(&self, peer: String, name: String, meta: Metadata, bitmap: Vec<u8>);
+    async fn ingest(&self, peer: String, name: String, content: Bytes);
+    async fn nack(&self, peer: String, name: String, index: usize);
+    async fn offer(&self, peer: String, name: String, meta: Metadata, layout: Vec<String>);
+    async fn delete(&self, peer: String, name: String, stamp: Stamp);
+    async fn recv_timeout(&self, timeout: Duration) -> Result<(String, Command), Error>;
 }
 
 impl<N: Network> NetworkExt for N {
@@ -36,11 +210,102 @@ impl<N: Network> NetworkExt for N {
         self.send(peer, Command::Create { name, meta }).await
     }
 
-    async fn replicate(&self, peer: String, name: String, shard: Shard) {
-        self.send(peer, Command::Replicate { name, shard }).await
+    async fn replicate(&self, peer: String, name: String, stamp: Stamp, shard: Shard) {
+        let checksum = shard.crc();
+        let cmd = Command::Replicate {
+            name,
+            stamp,
+            shard,
+            checksum,
+        };
+
+        self.send(peer, cmd).await
+    }
+
+    async fn request(&self, peer: String, name: String, bitmap: Vec<u8>) {
+        self.send(peer, Command::Request { name, bitmap }).await
+    }
+
+    async fn fetch(&self, peer: String, name: String, index: usize) {
+        self.send(peer, Command::Fetch { name, index }).await
+    }
+
+    async fn cancel(&self, peer: String, name: String) {
+        self.send(peer, Command::Cancel { name }).await
+    }
+
+    async fn announce(&self, peer: String) {
+        self.send(peer, Command::Announce).await
+    }
+
+    async fn manifest(&self, peer: String, files: Vec<(String, Metadata)>) {
+        self.send(peer, Command::Manifest { files }).await
+    }
+
+    async fn leave(&self, peer: String) {
+        self.send(peer, Command::Leave).await
     }
 
-    async fn request(&self, peer: String, name: String) {
-        self.send(peer, Command::Request { name }).await
+    async fn challenge(&self, peer: String, name: String, index: usize, nonce: u64) {
+        self.send(peer, Command::Challenge { name, index, nonce })
+            .await
+    }
+
+    async fn proof(
+        &self,
+        peer: String,
+        name: String,
+        index: usize,
+        nonce: u64,
+        proof: Option<u64>,
+    ) {
+        let cmd = Command::Proof {
+            name,
+            index,
+            nonce,
+            proof,
+        };
+
+        self.send(peer, cmd).await
+    }
+
+    async fn reject(&self, peer: String, name: String, reason: Rejection) {
+        self.send(peer, Command::Reject { name, reason }).await
+    }
+
+    async fn release(&self, peer: String, name: String, indices: Vec<usize>) {
+        self.send(peer, Command::Drop { name, indices }).await
+    }
+
+    async fn request_meta(&self, peer: String, name: String) {
+        self.send(peer, Command::RequestMeta { name }).await
+    }
+
+    async fn meta(&self, peer: String, name: String, meta: Metadata, bitmap: Vec<u8>) {
+        self.send(peer, Command::Meta { name, meta, bitmap }).await
+    }
+
+    async fn ingest(&self, peer: String, name: String, content: Bytes) {
+        self.send(peer, Command::Ingest { name, content }).await
+    }
+
+    async fn nack(&self, peer: String, name: String, index: usize) {
+        self.send(peer, Command::Nack { name, index }).await
+    }
+
+    async fn offer(&self, peer: String, name: String, meta: Metadata, layout: Vec<String>) {
+        let cmd = Command::Offer { name, meta, layout };
+        self.send(peer, cmd).await
+    }
+
+    async fn delete(&self, peer: String, name: String, stamp: Stamp) {
+        self.send(peer, Command::Delete { name, stamp }).await
+    }
+
+    async fn recv_timeout(&self, timeout: Duration) -> Result<(String, Command), Error> {
+        match future::select(pin!(self.recv()), pin!(self.sleep(timeout))).await {
+            Either::Left((msg, _)) => msg.ok_or(Error::Disconnected),
+            Either::Right(_) => Err(Error::Timeout),
+        }
     }
 }
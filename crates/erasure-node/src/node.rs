@@ -1,97 +1,2560 @@
-use std::{collections::HashMap, sync::Mutex};
+use std::{
+    cmp,
+    collections::{BTreeMap, HashMap, HashSet},
+    io::{self, Write as _},
+    path::Path,
+    pin::pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use futures::{
+    FutureExt, Stream, StreamExt,
+    channel::{mpsc, oneshot},
+    select,
+    stream::{self, FuturesUnordered},
+};
 
 use crate::{
-    file::File,
+    Error,
+    archive::Archive,
+    audit::{self, Audits},
+    backfill::Backfill,
+    cache::ShardCache,
+    config::{NodeConfig, Replication},
+    decoded::Decoded,
+    download::Download,
+    encoding::{self, Encodings},
+    file::{
+        self, DEFAULT_STRIPE_SIZE, DecodeError, EncodeConfig, Encoder, File, Metadata, Shard,
+        ShardError, Stamp, VectorClock,
+    },
+    handoff::Handoffs,
+    metrics::{self, MetricsSink, NoopMetrics},
+    nacks::Nacks,
     network::{Command, Network, NetworkExt},
+    observer::{NodeObserver, NoopObserver, Observed},
+    orphans::Orphans,
+    peer::Peers,
+    placement,
+    quota::{self, Quota, Rejection, Tenants, Usage},
+    redundancy::{FixedRedundancy, RedundancyPolicy},
+    requests::Requests,
+    store::{self, ShardStore},
 };
+use tracing::{Instrument, debug, debug_span, warn};
+
+const SCORE_UNIT: usize = 1000;
+
+type Waiters = Vec<oneshot::Sender<Result<Bytes, DecodeError>>>;
+
+static OPERATIONS: AtomicU64 = AtomicU64::new(0);
+
+fn operation() -> u64 {
+    OPERATIONS.fetch_add(1, Ordering::Relaxed)
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct NodeStats {
+    pub tenants: HashMap<String, Usage>,
+    pub latency: HashMap<String, Duration>,
+    pub scores: HashMap<String, f64>,
+    pub failure_rate: f64,
+    pub audits_passed: usize,
+    pub audits_failed: usize,
+    pub audits_pending: usize,
+    pub handoffs_completed: usize,
+    pub handoffs_pending: usize,
+    pub handoffs_abandoned: usize,
+    pub cache_hits: usize,
+    pub cache_misses: usize,
+    pub encodes_reused: usize,
+    pub encode_time_saved: Duration,
+    pub backfilled: usize,
+    pub backfill_pending: usize,
+    pub backfill_abandoned: usize,
+    pub requests_deduplicated: usize,
+    pub shards_coalesced: usize,
+    pub negative_hits: usize,
+    pub shards_served: usize,
+    pub bytes_served: usize,
+    pub downloads_coalesced: usize,
+    pub decodes: usize,
+    pub decodes_cached: usize,
+    pub nacks_sent: usize,
+    pub retransmits: usize,
+    pub nacks_exhausted: usize,
+    pub orphans_adopted: usize,
+    pub orphans_dropped: usize,
+    pub conflicts: usize,
+    pub unresolved: usize,
+    pub stale_shards: usize,
+    pub downloads_active: usize,
+    pub requests_queued: usize,
+    pub nacks_pending: usize,
+    pub orphans_held: usize,
+    pub cancellations: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DurabilityWarning {
+    NoPeers,
+    Concentrated { per_peer: usize, parity: usize },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Conflict {
+    pub name: String,
+    pub kept: Stamp,
+    pub discarded: Stamp,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ImportStats {
+    pub files: usize,
+    pub shards: usize,
+    pub duplicates: usize,
+    pub corrupt: usize,
+    pub rejected: usize,
+}
+
+fn utf8(content: Bytes) -> Result<String, DecodeError> {
+    String::from_utf8(content.into()).map_err(|_| DecodeError::InvalidContent)
+}
+
+// A shard that is already present was restored before; anything else is a bad shard.
+fn restored(merged: Result<(), ShardError>) -> Result<(), ShardError> {
+    match merged {
+        Err(ShardError::Present(_)) => Ok(()),
+        merged => merged,
+    }
+}
+
+fn durability(meta: &Metadata, peers: usize) -> Option<DurabilityWarning> {
+    if peers == 0 {
+        return Some(DurabilityWarning::NoPeers);
+    }
+
+    let per_peer = meta.shard_count().div_ceil(peers + 1);
+    let parity = meta.parity_shards();
+    (per_peer > parity).then_some(DurabilityWarning::Concentrated { per_peer, parity })
+}
+
+struct Inflight<'a> {
+    inflight: &'a Mutex<HashMap<String, Waiters>>,
+    name: &'a str,
+}
+
+impl Inflight<'_> {
+    fn finish(self) -> Waiters {
+        let waiters = self.inflight.lock().unwrap().remove(self.name);
+        std::mem::forget(self);
+        waiters.unwrap_or_default()
+    }
+}
+
+impl Drop for Inflight<'_> {
+    fn drop(&mut self) {
+        self.inflight.lock().unwrap().remove(self.name);
+    }
+}
+
+/// An upload fed in pieces through `write`. Under push replication every block is sent to its
+/// holders as soon as it is encoded, and they keep the shards aside as orphans until `finish`
+/// sends the metadata; under pull replication the shards wait on this node for the offer.
+pub struct Upload<'a, N> {
+    node: &'a Node<N>,
+    name: String,
+    encoder: Encoder,
+    stamp: Stamp,
+    clock: VectorClock,
+    peers: Vec<String>,
+    order: Vec<String>,
+    kept: Vec<Shard>,
+    start: Instant,
+}
+
+impl<N: Network> Upload<'_, N> {
+    pub async fn write(&mut self, mut content: &[u8]) -> Result<(), Error> {
+        while !content.is_empty() {
+            match self.encoder.write(content) {
+                Ok(written) => content = &content[written..],
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => self.flush().await,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        self.flush().await;
+        Ok(())
+    }
+
+    async fn flush(&mut self) {
+        while let Some(shards) = self.encoder.next_block() {
+            let kept = self
+                .node
+                .stream(&self.name, self.stamp, &self.order, shards)
+                .await;
+            self.kept.extend(kept);
+        }
+    }
+
+    pub async fn finish(self) -> Result<Option<DurabilityWarning>, Error> {
+        let Self {
+            node,
+            name,
+            encoder,
+            stamp,
+            clock,
+            peers,
+            order,
+            mut kept,
+            start,
+        } = self;
+        let (meta, rest) = encoder.finish()?;
+        kept.extend(node.stream(&name, stamp, &order, rest).await);
+
+        let created_at = node
+            .metadata(&name)
+            .map_or(stamp.time, |meta| meta.created_at());
+        let meta = meta
+            .with_stamp(stamp)
+            .with_clock(clock)
+            .with_created_at(created_at);
+        #[cfg(feature = "signing")]
+        let meta = match &node.config.signing_key {
+            Some(key) => meta.signed(key),
+            None => meta,
+        };
+
+        let mut file = File::empty(meta);
+        for shard in kept {
+            file.shards_mut().merge(shard)?;
+        }
+
+        let span = debug_span!("upload", id = operation(), file = name);
+        let warning = durability(file.metadata(), peers.len());
+        if let Some(warning) = warning {
+            span.in_scope(|| debug!(?warning, "placement queued"));
+            node.unplaced.lock().unwrap().insert(name.clone());
+        }
+
+        match node.config.replication {
+            Replication::Pull => node.place(&name, &file, &peers).instrument(span).await,
+            Replication::Push => {
+                for peer in &peers {
+                    let meta = file.metadata().clone();
+                    node.network.create(peer.clone(), name.clone(), meta).await;
+                }
+                Vec::new()
+            }
+        };
+        if node.supersede(&name, file).is_ok() {
+            node.requests.lock().unwrap().present(&name);
+        }
+
+        let elapsed = node.network.now().saturating_duration_since(start);
+        node.network.metrics.counter(metrics::UPLOADS, 1);
+        node.network
+            .metrics
+            .histogram(metrics::UPLOAD_LATENCY, elapsed.as_secs_f64());
+
+        Ok(warning)
+    }
+}
 
 pub struct Node<N> {
     files: Mutex<HashMap<String, File>>,
-    network: N,
+    tenants: Mutex<Tenants>,
+    peers: Mutex<Peers>,
+    downloads: Mutex<HashMap<String, Download>>,
+    inflight: Mutex<HashMap<String, Waiters>>,
+    coalesced: AtomicU64,
+    conflicts: Mutex<Vec<Conflict>>,
+    siblings: Mutex<HashMap<String, Vec<Metadata>>>,
+    stale: AtomicU64,
+    cancelled: Mutex<HashSet<(String, String)>>,
+    requests: Mutex<Requests>,
+    nacks: Mutex<Nacks>,
+    orphans: Mutex<Orphans>,
+    audits: Mutex<Audits>,
+    handoffs: Mutex<Handoffs>,
+    backfill: Mutex<Backfill>,
+    cache: Mutex<ShardCache>,
+    decoded: Mutex<Decoded>,
+    encodings: Mutex<Encodings>,
+    unplaced: Mutex<HashSet<String>>,
+    awaiting: Mutex<HashSet<String>>,
+    advertised: Mutex<HashMap<String, HashSet<String>>>,
+    // Per file, the version and the peers known to hold its metadata.
+    manifests: Mutex<HashMap<String, (Stamp, HashSet<String>)>>,
+    repaired: Mutex<Option<Instant>>,
+    repair_interval: Mutex<Option<Duration>>,
+    // Wakes `run` to re-arm its timer when the repair interval changes.
+    retime: mpsc::UnboundedSender<()>,
+    retimed: Mutex<Option<mpsc::UnboundedReceiver<()>>>,
+    redundancy: Box<dyn RedundancyPolicy>,
+    store: Option<Box<dyn ShardStore>>,
+    config: NodeConfig,
+    decommissioning: AtomicBool,
+    shutdown: Mutex<Option<oneshot::Sender<()>>>,
+    halt: Mutex<Option<oneshot::Receiver<()>>>,
+    network: Observed<N>,
 }
 
 impl<N: Network> Node<N> {
-    pub fn new(network: N) -> Self {
+    pub fn new(network: N, config: NodeConfig) -> Self {
+        let (shutdown, halt) = oneshot::channel();
+        let (retime, retimed) = mpsc::unbounded();
+        let redundancy: Box<dyn RedundancyPolicy> = match config.adaptive_redundancy {
+            Some(policy) => Box::new(policy),
+            None => Box::new(FixedRedundancy),
+        };
+
         Self {
             files: Mutex::new(HashMap::new()),
-            network,
+            tenants: Mutex::new(Tenants::new(config.quota)),
+            peers: Mutex::new(Peers::new(config.failure_sample, config.peer_ttl)),
+            downloads: Mutex::new(HashMap::new()),
+            inflight: Mutex::new(HashMap::new()),
+            coalesced: AtomicU64::new(0),
+            conflicts: Mutex::new(Vec::new()),
+            siblings: Mutex::new(HashMap::new()),
+            stale: AtomicU64::new(0),
+            cancelled: Mutex::new(HashSet::new()),
+            requests: Mutex::new(Requests::new(config.request_window, config.negative_ttl)),
+            nacks: Mutex::new(Nacks::new(config.nack_retries, config.nack_ttl)),
+            orphans: Mutex::new(Orphans::new(config.orphan_capacity, config.orphan_ttl)),
+            audits: Mutex::new(Audits::new(config.audit_timeout)),
+            handoffs: Mutex::new(Handoffs::new(config.audit_timeout)),
+            backfill: Mutex::new(Backfill::default()),
+            cache: Mutex::new(ShardCache::new(config.cache_capacity, config.cache_ttl)),
+            decoded: Mutex::new(Decoded::new(config.decoded_capacity, config.decoded_ttl)),
+            encodings: Mutex::new(Encodings::default()),
+            unplaced: Mutex::new(HashSet::new()),
+            awaiting: Mutex::new(HashSet::new()),
+            advertised: Mutex::new(HashMap::new()),
+            manifests: Mutex::new(HashMap::new()),
+            repaired: Mutex::new(None),
+            repair_interval: Mutex::new(config.repair_interval),
+            retime,
+            retimed: Mutex::new(Some(retimed)),
+            redundancy,
+            store: None,
+            config,
+            decommissioning: AtomicBool::new(false),
+            shutdown: Mutex::new(Some(shutdown)),
+            halt: Mutex::new(Some(halt)),
+            network: Observed {
+                inner: network,
+                observer: Arc::new(NoopObserver),
+                metrics: Arc::new(NoopMetrics),
+            },
+        }
+    }
+
+    // Like `new`, but keeps what the node holds in `config.store` and starts from its contents.
+    pub fn open(network: N, config: NodeConfig) -> io::Result<Self> {
+        match store::open(&config.store)? {
+            Some(store) => Self::new(network, config).with_store(store),
+            None => Ok(Self::new(network, config)),
+        }
+    }
+
+    // Loads everything `store` holds and writes every later change through to it.
+    pub fn with_store(mut self, store: impl ShardStore + 'static) -> io::Result<Self> {
+        {
+            let mut files = self.files.lock().unwrap();
+            let mut requests = self.requests.lock().unwrap();
+            for (name, meta, shards) in store.load()? {
+                let mut file = File::empty(meta);
+                for shard in shards {
+                    let index = shard.index();
+                    if let Err(err) = restored(file.shards_mut().merge(shard)) {
+                        // Repair fetches it back like any other missing shard.
+                        warn!(file = %name, index, %err, "skipping stored shard");
+                    }
+                }
+                requests.present(&name);
+                files.insert(name, file);
+            }
+
+            // Downloads a restart interrupted; the shards that had arrived were loaded above,
+            // so the next attempt only requests the rest.
+            let mut downloads = self.downloads.lock().unwrap();
+            for (name, arrived) in store.downloads()? {
+                let Some(file) = files.get(&name) else {
+                    store.remove_download(&name)?;
+                    continue;
+                };
+                let held = arrived
+                    .iter()
+                    .filter(|index| file.shards().contains(**index))
+                    .count();
+                let download =
+                    Download::new(debug_span!("download", id = operation(), file = name));
+                download
+                    .span()
+                    .in_scope(|| debug!(arrived = held, "resuming"));
+                downloads.insert(name, download);
+            }
         }
+
+        self.store = Some(Box::new(store));
+        Ok(self)
+    }
+
+    pub fn with_redundancy(mut self, policy: impl RedundancyPolicy + 'static) -> Self {
+        self.redundancy = Box::new(policy);
+        self
+    }
+
+    pub fn with_cache(mut self, capacity: usize, ttl: Duration) -> Self {
+        self.cache = Mutex::new(ShardCache::new(capacity, ttl));
+        self
+    }
+
+    pub fn with_observer(mut self, observer: impl NodeObserver + 'static) -> Self {
+        self.network.observer = Arc::new(observer);
+        self
+    }
+
+    pub fn with_metrics(mut self, sink: impl MetricsSink + 'static) -> Self {
+        self.network.metrics = Arc::new(sink);
+        self
     }
 
     pub fn network(&self) -> &N {
-        &self.network
+        &self.network.inner
     }
 
-    pub async fn upload(&self, name: String, content: String) {
-        let file = File::encode(content).unwrap();
+    pub fn config(&self) -> &NodeConfig {
+        &self.config
+    }
 
-        let peers = self.network.discover().await;
-        for peer in &peers {
+    pub fn set_quota(&self, tenant: String, quota: Quota) {
+        self.tenants.lock().unwrap().set_quota(tenant, quota);
+    }
+
+    /// Replaces `NodeConfig::quota` and every tenant's quota at once; tenants missing from
+    /// `quotas` fall back to `default`. What tenants store and have used so far is kept.
+    pub fn set_quotas(&self, default: Quota, quotas: HashMap<String, Quota>) {
+        self.tenants.lock().unwrap().set_quotas(default, quotas);
+    }
+
+    pub fn repair_interval(&self) -> Option<Duration> {
+        *self.repair_interval.lock().unwrap()
+    }
+
+    /// Changes `NodeConfig::repair_interval` of a running node, restarting its wait for the
+    /// next maintenance pass.
+    pub fn set_repair_interval(&self, interval: Option<Duration>) {
+        *self.repair_interval.lock().unwrap() = interval;
+        let _ = self.retime.unbounded_send(());
+    }
+
+    pub fn stats(&self) -> NodeStats {
+        let mut tenants = self.tenants.lock().unwrap().usage();
+
+        for (name, file) in self.files.lock().unwrap().iter() {
+            tenants
+                .entry(quota::tenant(name).to_string())
+                .or_default()
+                .storage += file.shards().size();
+        }
+
+        let peers = self.peers.lock().unwrap();
+        let audits = self.audits.lock().unwrap();
+        let cache = self.cache.lock().unwrap();
+        let encodings = self.encodings.lock().unwrap();
+        let backfill = self.backfill.lock().unwrap();
+        let requests = self.requests.lock().unwrap();
+        let decoded = self.decoded.lock().unwrap();
+        let nacks = self.nacks.lock().unwrap();
+        let orphans = self.orphans.lock().unwrap();
+        let handoffs = self.handoffs.lock().unwrap();
+
+        NodeStats {
+            tenants,
+            latency: peers.latency(),
+            scores: peers.scores(),
+            failure_rate: peers.failure_rate(),
+            audits_passed: audits.passed(),
+            audits_failed: audits.failed(),
+            audits_pending: audits.pending(),
+            handoffs_completed: handoffs.completed(),
+            handoffs_pending: handoffs.pending(),
+            handoffs_abandoned: handoffs.abandoned(),
+            cache_hits: cache.hits(),
+            cache_misses: cache.misses(),
+            encodes_reused: encodings.reuses(),
+            encode_time_saved: encodings.saved(),
+            backfilled: backfill.completed(),
+            backfill_pending: backfill.pending(),
+            backfill_abandoned: backfill.abandoned(),
+            requests_deduplicated: requests.deduplicated(),
+            shards_coalesced: requests.coalesced(),
+            negative_hits: requests.negative_hits(),
+            shards_served: requests.shards_served(),
+            bytes_served: requests.bytes_served(),
+            downloads_coalesced: self.coalesced.load(Ordering::Relaxed) as usize,
+            decodes: decoded.decodes(),
+            decodes_cached: decoded.hits(),
+            nacks_sent: nacks.sent(),
+            retransmits: nacks.retransmitted(),
+            nacks_exhausted: nacks.exhausted(),
+            orphans_adopted: orphans.adopted(),
+            orphans_dropped: orphans.dropped(),
+            conflicts: self.conflicts.lock().unwrap().len(),
+            unresolved: self.siblings.lock().unwrap().len(),
+            stale_shards: self.stale.load(Ordering::Relaxed) as usize,
+            downloads_active: self.downloads.lock().unwrap().len(),
+            requests_queued: requests.queued(),
+            nacks_pending: nacks.pending(),
+            orphans_held: orphans.held(),
+            cancellations: self.cancelled.lock().unwrap().len(),
+        }
+    }
+
+    pub fn conflicts(&self) -> Vec<Conflict> {
+        self.conflicts.lock().unwrap().clone()
+    }
+
+    pub fn suspects(&self) -> Vec<String> {
+        self.peers.lock().unwrap().suspects()
+    }
+
+    pub fn failed(&self, name: &str) -> bool {
+        self.downloads
+            .lock()
+            .unwrap()
+            .get(name)
+            .is_some_and(|download| download.failed())
+    }
+
+    pub fn metadata(&self, name: &str) -> Option<Metadata> {
+        Some(self.files.lock().unwrap().get(name)?.metadata().clone())
+    }
+
+    pub fn shards(&self, name: &str) -> Vec<usize> {
+        let files = self.files.lock().unwrap();
+        let Some(file) = files.get(name) else {
+            return Vec::new();
+        };
+
+        (0..file.metadata().shard_count())
+            .filter(|index| file.shards().contains(*index))
+            .collect()
+    }
+
+    pub fn snapshot(&self) -> Vec<(String, Metadata, Vec<Shard>)> {
+        self.files
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, file)| {
+                let shards = file.shards().present_iter().collect();
+                (name.clone(), file.metadata().clone(), shards)
+            })
+            .collect()
+    }
+
+    pub fn export(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut files = self.snapshot();
+        files.sort_by(|(a, ..), (b, ..)| a.cmp(b));
+        Archive::write(path, &files)
+    }
+
+    pub async fn import(&self, path: impl AsRef<Path>) -> io::Result<ImportStats> {
+        let archived = Archive::open(path)?
+            .files()
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed archive"))?;
+
+        let mut stats = ImportStats::default();
+        let (mut manifest, mut stored) = (Vec::new(), Vec::new());
+        {
+            let mut files = self.files.lock().unwrap();
+            for archived in archived {
+                stats.corrupt += archived.corrupt.len();
+
+                let file = files
+                    .entry(archived.name.clone())
+                    .or_insert_with(|| File::empty(archived.meta.clone()));
+                if file.metadata() != &archived.meta {
+                    stats.rejected += archived.shards.len();
+                    continue;
+                }
+
+                let before = stored.len();
+                for shard in archived.shards {
+                    let index = shard.index();
+                    match file.shards_mut().merge(shard) {
+                        Ok(()) => stored.push((archived.name.clone(), index)),
+                        Err(ShardError::Present(_)) => stats.duplicates += 1,
+                        Err(_) => stats.rejected += 1,
+                    }
+                }
+
+                if stored.len() > before {
+                    let indices = stored[before..].iter().map(|(_, index)| *index);
+                    self.persist(&archived.name, file, indices);
+                    stats.files += 1;
+                    manifest.push((archived.name, archived.meta));
+                }
+            }
+
+            let total = files
+                .values()
+                .map(|file| file.shards().size())
+                .sum::<usize>();
             self.network
-                .create(peer.clone(), name.clone(), file.metadata().clone())
-                .await;
+                .metrics
+                .gauge(metrics::STORED_BYTES, total as f64);
         }
 
-        for shard in file.shards().present_iter() {
-            let peer = peers[shard.index() % peers.len()].clone();
-            self.network.replicate(peer, name.clone(), shard).await;
+        stats.shards = stored.len();
+        self.network
+            .metrics
+            .counter(metrics::SHARDS_STORED, stored.len() as u64);
+        for (name, index) in stored {
+            self.requests.lock().unwrap().present(&name);
+            self.network.observer.on_shard_stored(&name, index);
+        }
+
+        if !manifest.is_empty() {
+            for peer in self.healthy().await {
+                self.network.manifest(peer, manifest.clone()).await;
+            }
+        }
+
+        Ok(stats)
+    }
+
+    pub fn downloads(&self) -> Vec<String> {
+        self.downloads.lock().unwrap().keys().cloned().collect()
+    }
+
+    // Shards the node already holds are skipped, so restoring the same snapshot twice is harmless.
+    pub fn restore(
+        &self,
+        name: String,
+        meta: Metadata,
+        shards: Vec<Shard>,
+    ) -> Result<(), ShardError> {
+        self.requests.lock().unwrap().present(&name);
+        let mut files = self.files.lock().unwrap();
+        let file = files.entry(name.clone()).or_insert(File::empty(meta));
+        let merged = shards
+            .into_iter()
+            .try_for_each(|shard| restored(file.shards_mut().merge(shard)));
+        self.persist(&name, file, 0..file.metadata().shard_count());
+        merged
+    }
+
+    pub fn placements(&self) -> Vec<(String, usize, String)> {
+        self.audits.lock().unwrap().placements()
+    }
+
+    pub fn placed(&self, name: String, index: usize, peer: String) {
+        self.audits.lock().unwrap().placed(name, index, peer);
+    }
+
+    async fn discover(&self) -> Vec<String> {
+        let peers = self.network.discover().await;
+        let now = self.network.now();
+        self.peers.lock().unwrap().membership(&peers, now);
+        peers
+    }
+
+    async fn healthy(&self) -> Vec<String> {
+        let peers = self.discover().await;
+        let table = self.peers.lock().unwrap();
+        peers
+            .into_iter()
+            .filter(|peer| !table.is_suspect(peer))
+            .collect()
+    }
+
+    async fn candidates(&self) -> Vec<String> {
+        let peers = self.healthy().await;
+        self.peers.lock().unwrap().rank(peers)
+    }
+
+    fn holders(&self, name: &str, ranked: &[String]) -> HashSet<String> {
+        let Some(meta) = self.metadata(name) else {
+            return HashSet::new();
+        };
+
+        let members = ranked
+            .iter()
+            .cloned()
+            .chain([self.network.id()])
+            .collect::<Vec<_>>();
+        let mut owners = placement::order(name, &members)
+            .into_iter()
+            .take(meta.shard_count())
+            .collect::<HashSet<_>>();
+
+        if let Some(advertised) = self.advertised.lock().unwrap().get(name) {
+            owners.extend(advertised.iter().cloned());
         }
 
-        self.files.lock().unwrap().insert(name, file);
+        owners
     }
 
-    pub async fn try_download(&self, name: &String) -> Option<String> {
-        self.files.lock().unwrap().get_mut(name)?.decode()
+    // Holders in this node's region, then holders elsewhere, then everyone else; each group
+    // keeps the latency ranking.
+    fn owners_first(&self, ranked: Vec<String>, holders: &HashSet<String>) -> Vec<String> {
+        let mut ranked = ranked;
+        ranked.sort_by_cached_key(|peer| (!holders.contains(peer), !self.same_region(peer)));
+        ranked
     }
 
-    pub async fn download(&self, name: String) -> Option<String> {
-        if let Some(res) = self.try_download(&name).await {
-            return Some(res);
+    // Every peer is in the node's region while either region is unknown.
+    fn same_region(&self, peer: &str) -> bool {
+        match (
+            self.network.region(&self.network.id()),
+            self.network.region(peer),
+        ) {
+            (Some(own), Some(theirs)) => own == theirs,
+            _ => true,
         }
+    }
+
+    // Holders in the node's region, or None when the node does not know its region.
+    fn local_holders(&self, ranked: &[String], holders: &HashSet<String>) -> Option<usize> {
+        self.network.region(&self.network.id())?;
+        let local = ranked
+            .iter()
+            .filter(|peer| holders.contains(*peer) && self.same_region(peer))
+            .count();
+        Some(local)
+    }
+
+    fn batch(&self, name: &str, peers: usize, local: Option<usize>) -> usize {
+        let files = self.files.lock().unwrap();
+        let Some(file) = files.get(name) else {
+            return peers;
+        };
 
-        for peer in self.network.discover().await {
-            self.network.request(peer, name.clone()).await;
+        let meta = file.metadata();
+        let missing = meta.data_shards().saturating_sub(file.shards().present());
+        let per_peer = meta.shard_count().div_ceil(peers.max(1)).max(1);
+
+        let batch = (missing.div_ceil(per_peer) * self.config.race_factor).max(1);
+        // Holders in the node's region race among themselves when they can cover what is
+        // missing; other regions are only asked by a later attempt, once they fall short.
+        match local {
+            Some(local) if local > 0 && local * per_peer >= missing => batch.min(local),
+            _ => batch,
         }
+    }
 
-        None
+    pub fn unplaced(&self) -> Vec<String> {
+        self.unplaced.lock().unwrap().iter().cloned().collect()
     }
 
-    pub async fn run(&self) {
-        while let Some((peer, cmd)) = self.network.recv().await {
-            match cmd {
-                Command::Create { name, meta } => {
-                    self.files
-                        .lock()
-                        .unwrap()
-                        .entry(name)
-                        .or_insert(File::empty(meta));
-                }
+    pub async fn upload(
+        &self,
+        name: String,
+        content: impl Into<Bytes>,
+    ) -> Result<Option<DurabilityWarning>, Error> {
+        self.upload_with(name, content, None, BTreeMap::new()).await
+    }
 
-                Command::Replicate { name, shard } => {
-                    self.files
-                        .lock()
-                        .unwrap()
-                        .entry(name)
-                        .and_modify(|file| file.shards_mut().merge(shard));
+    // Content type and attributes are replaced on every upload; the creation time is kept.
+    pub async fn upload_with(
+        &self,
+        name: String,
+        content: impl Into<Bytes>,
+        content_type: Option<String>,
+        attributes: BTreeMap<String, String>,
+    ) -> Result<Option<DurabilityWarning>, Error> {
+        let clock = self
+            .metadata(&name)
+            .map(|meta| meta.clock().clone())
+            .unwrap_or_default();
+        self.write(name, content.into(), clock, content_type, attributes)
+            .await
+    }
+
+    // The length is not known up front, so the content is always encoded in blocks: those of
+    // the node's `encoding` when it has one, otherwise blocks sized like a 4KiB upload.
+    pub async fn upload_stream(&self, name: String) -> Result<Upload<'_, N>, Error> {
+        let start = self.network.now();
+        let peers = self.healthy().await;
+
+        let mut config = self.config.encoding.unwrap_or_else(|| {
+            let shard_size = self.config.shard_size;
+            let data_shards = file::data_shards(DEFAULT_STRIPE_SIZE, shard_size);
+            let failure_rate = self.peers.lock().unwrap().failure_rate();
+            let parity_shards = self.redundancy.parity_shards(data_shards, failure_rate);
+            EncodeConfig::new(data_shards, parity_shards)
+                .with_shard_size(shard_size)
+                .with_block_size(data_shards * shard_size)
+        });
+        config.compression = config.compression.or(self.config.compression);
+        let encoder = Encoder::new(config)?;
+
+        let mut clock = self
+            .metadata(&name)
+            .map(|meta| meta.clock().clone())
+            .unwrap_or_default();
+        clock.tick(file::writer(&self.network.id()));
+        let stamp = self.stamp(&name);
+
+        // Placement only sees one block, as the block count is not known yet.
+        let members = peers
+            .iter()
+            .cloned()
+            .chain([self.network.id()])
+            .collect::<Vec<_>>();
+        let order = self.layout(&name, &members, &encoder.block());
+
+        Ok(Upload {
+            node: self,
+            name,
+            encoder,
+            stamp,
+            clock,
+            peers,
+            order,
+            kept: Vec::new(),
+            start,
+        })
+    }
+
+    pub async fn resolve(
+        &self,
+        name: String,
+        content: impl Into<Bytes>,
+    ) -> Result<Option<DurabilityWarning>, Error> {
+        let current = self.metadata(&name);
+        let mut clock = current
+            .as_ref()
+            .map(|meta| meta.clock().clone())
+            .unwrap_or_default();
+        for sibling in self.siblings(&name) {
+            clock.merge(sibling.clock());
+        }
+        let content_type = current
+            .as_ref()
+            .and_then(|meta| meta.content_type().map(String::from));
+        let attributes = current
+            .map(|meta| meta.attributes().clone())
+            .unwrap_or_default();
+        self.write(name, content.into(), clock, content_type, attributes)
+            .await
+    }
+
+    async fn write(
+        &self,
+        name: String,
+        content: Bytes,
+        mut clock: VectorClock,
+        content_type: Option<String>,
+        attributes: BTreeMap<String, String>,
+    ) -> Result<Option<DurabilityWarning>, Error> {
+        let start = self.network.now();
+        let peers = self.healthy().await;
+
+        let mut config = self.config.encoding.unwrap_or_else(|| {
+            let shard_size = self.config.shard_size;
+            let data_shards = file::data_shards(content.len(), shard_size);
+            let failure_rate = self.peers.lock().unwrap().failure_rate();
+            let parity_shards = self.redundancy.parity_shards(data_shards, failure_rate);
+            EncodeConfig::new(data_shards, parity_shards).with_shard_size(shard_size)
+        });
+        config.compression = config.compression.or(self.config.compression);
+        let (data_shards, parity_shards) = (config.data_shards, config.parity_shards);
+        clock.tick(file::writer(&self.network.id()));
+        let stamp = self.stamp(&name);
+        let created_at = self
+            .metadata(&name)
+            .map_or(stamp.time, |meta| meta.created_at());
+        let file = self
+            .encode(&name, content, config)?
+            .with_stamp(stamp)
+            .with_clock(clock)
+            .with_created_at(created_at)
+            .with_attributes(attributes);
+        let file = match content_type {
+            Some(content_type) => file.with_content_type(content_type),
+            None => file,
+        };
+        #[cfg(feature = "signing")]
+        let file = match &self.config.signing_key {
+            Some(key) => file.signed(key)?,
+            None => file,
+        };
+
+        let span = debug_span!("upload", id = operation(), file = name);
+        span.in_scope(|| debug!(peers = peers.len(), data_shards, parity_shards, "encoded"));
+        let warning = durability(file.metadata(), peers.len());
+        if let Some(warning) = warning {
+            span.in_scope(|| debug!(?warning, "placement queued"));
+            self.unplaced.lock().unwrap().insert(name.clone());
+        }
+
+        self.place(&name, &file, &peers).instrument(span).await;
+        if self.supersede(&name, file).is_ok() {
+            self.requests.lock().unwrap().present(&name);
+        }
+
+        let elapsed = self.network.now().saturating_duration_since(start);
+        self.network.metrics.counter(metrics::UPLOADS, 1);
+        self.network
+            .metrics
+            .histogram(metrics::UPLOAD_LATENCY, elapsed.as_secs_f64());
+
+        Ok(warning)
+    }
+
+    pub fn siblings(&self, name: &str) -> Vec<Metadata> {
+        self.siblings
+            .lock()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn conflicted(&self) -> Vec<String> {
+        self.siblings.lock().unwrap().keys().cloned().collect()
+    }
+
+    fn stamp(&self, name: &str) -> Stamp {
+        let mut stamp = Stamp::new(self.network.clock(), &self.network.id());
+        if let Some(meta) = self.metadata(name) {
+            stamp.time = stamp.time.max(meta.stamp().time + 1);
+        }
+        stamp
+    }
+
+    // Signed metadata must verify, and once any signer is trusted only its metadata is admitted.
+    #[cfg(feature = "signing")]
+    fn admit(&self, name: &str, meta: &Metadata) -> bool {
+        let trusted = &self.config.trusted_signers;
+        let admitted = match meta.signer() {
+            Some(signer) => {
+                meta.verify_signature()
+                    && (trusted.is_empty() || trusted.iter().any(|key| key.to_bytes() == signer))
+            }
+            None => trusted.is_empty(),
+        };
+        if !admitted {
+            debug!(file = name, "metadata refused");
+        }
+        admitted
+    }
+
+    #[cfg(not(feature = "signing"))]
+    fn admit(&self, _name: &str, _meta: &Metadata) -> bool {
+        true
+    }
+
+    // The current metadata comes back boxed when it wins, so the sender can be told about it.
+    fn supersede(&self, name: &str, file: File) -> Result<bool, Box<Metadata>> {
+        let (conflict, res) = {
+            let mut files = self.files.lock().unwrap();
+            let current = match files.get(name) {
+                None => {
+                    self.persist(name, &file, 0..file.metadata().shard_count());
+                    files.insert(name.to_string(), file);
+                    drop(files);
+                    self.adopt(name);
+                    return Ok(true);
                 }
+                Some(current) if current.metadata() == file.metadata() => return Ok(false),
+                Some(current) => current.metadata().clone(),
+            };
 
-                Command::Request { name } => {
-                    let shards = self
-                        .files
-                        .lock()
-                        .unwrap()
-                        .get_mut(&name)
-                        .into_iter()
-                        .flat_map(|file| file.shards_mut().present_iter())
-                        .collect::<Vec<_>>();
+            let incoming = file.metadata().clone();
+            let mut siblings = self.siblings.lock().unwrap();
+            match incoming.clock().partial_cmp(current.clock()) {
+                Some(cmp::Ordering::Greater) => {
+                    self.persist(name, &file, 0..file.metadata().shard_count());
+                    files.insert(name.to_string(), file);
+                    if let Some(known) = siblings.get_mut(name) {
+                        known.retain(|sibling| {
+                            sibling.clock().partial_cmp(incoming.clock()).is_none()
+                        });
+                        if known.is_empty() {
+                            siblings.remove(name);
+                        }
+                    }
+                    (None, Ok(true))
+                }
+                Some(cmp::Ordering::Less) => (None, Err(Box::new(current))),
+                _ if siblings
+                    .get(name)
+                    .is_some_and(|known| known.contains(&incoming)) =>
+                {
+                    (None, Err(Box::new(current)))
+                }
+                _ => {
+                    let newer = incoming.stamp() > current.stamp();
+                    let (kept, discarded) = match newer {
+                        true => (incoming, current.clone()),
+                        false => (current.clone(), incoming),
+                    };
+                    let conflict = Conflict {
+                        name: name.to_string(),
+                        kept: kept.stamp(),
+                        discarded: discarded.stamp(),
+                    };
+                    siblings
+                        .entry(name.to_string())
+                        .or_default()
+                        .push(discarded);
 
-                    for shard in shards {
-                        self.network
-                            .replicate(peer.clone(), name.clone(), shard)
-                            .await;
+                    if newer {
+                        self.persist(name, &file, 0..file.metadata().shard_count());
+                        files.insert(name.to_string(), file);
+                        (Some(conflict), Ok(true))
+                    } else {
+                        (Some(conflict), Err(Box::new(current)))
                     }
                 }
             }
+        };
+
+        if let Some(conflict) = conflict {
+            debug!(file = name, kept = ?conflict.kept, discarded = ?conflict.discarded, "concurrent versions");
+            self.network.observer.on_conflict(&conflict);
+            self.conflicts.lock().unwrap().push(conflict);
+        }
+
+        if res.is_ok() {
+            self.adopt(name);
+        }
+
+        res
+    }
+
+    pub async fn delegate(&self, peer: String, name: String, content: impl Into<Bytes>) {
+        self.network.ingest(peer, name, content.into()).await;
+    }
+
+    fn encode(&self, name: &str, content: Bytes, config: EncodeConfig) -> Result<File, Error> {
+        let digest = encoding::digest(&content);
+        let known = self.encodings.lock().unwrap().lookup(digest, config);
+
+        if let Some((original, cost)) = known {
+            let reused = self
+                .files
+                .lock()
+                .unwrap()
+                .get(&original)
+                .filter(|file| file.holds(&content))
+                .cloned();
+
+            if let Some(file) = reused {
+                self.encodings.lock().unwrap().reused(cost);
+                return Ok(file);
+            }
+        }
+
+        let start = Instant::now();
+        let file = File::encode_with(content, config)?;
+        self.encodings
+            .lock()
+            .unwrap()
+            .encoded(digest, config, name.to_string(), start.elapsed());
+
+        Ok(file)
+    }
+
+    pub async fn place_pending(&self) {
+        let pending = self.unplaced();
+        if pending.is_empty() {
+            return;
+        }
+
+        let peers = self.healthy().await;
+        for name in pending {
+            let Some(file) = self.files.lock().unwrap().get(&name).cloned() else {
+                self.unplaced.lock().unwrap().remove(&name);
+                continue;
+            };
+
+            if durability(file.metadata(), peers.len()).is_some() {
+                continue;
+            }
+
+            self.place(&name, &file, &peers).await;
+            self.unplaced.lock().unwrap().remove(&name);
+        }
+    }
+
+    pub async fn repair(&self, name: &str) -> bool {
+        if self.try_download(&name.to_string()).await.is_err() {
+            return false;
+        }
+
+        let Some(mut file) = self.files.lock().unwrap().get(name).cloned() else {
+            return false;
+        };
+        if file.repair().is_err() {
+            return false;
+        }
+
+        let peers = self.healthy().await;
+        let owned = self.place(name, &file, &peers).await;
+
+        let indices = owned.iter().map(|shard| shard.index()).collect::<Vec<_>>();
+        if let Some(local) = self.files.lock().unwrap().get_mut(name) {
+            for shard in owned {
+                let _ = local.shards_mut().merge(shard);
+            }
+            self.persist(name, local, indices.iter().copied());
+        }
+        self.requests.lock().unwrap().present(name);
+
+        let mut cache = self.cache.lock().unwrap();
+        indices
+            .into_iter()
+            .for_each(|index| cache.keep(name, index));
+
+        true
+    }
+
+    async fn place(&self, name: &str, file: &File, peers: &[String]) -> Vec<Shard> {
+        let id = self.network.id();
+        let members = peers
+            .iter()
+            .cloned()
+            .chain([id.clone()])
+            .collect::<Vec<_>>();
+        let order = self.layout(name, &members, file.metadata());
+        let pull = self.config.replication == Replication::Pull;
+
+        self.manifested(name, file.metadata().stamp(), peers.iter().cloned());
+        for peer in peers {
+            let meta = file.metadata().clone();
+            if pull {
+                let layout = order.clone();
+                self.network
+                    .offer(peer.clone(), name.to_string(), meta, layout)
+                    .await;
+            } else {
+                self.network
+                    .create(peer.clone(), name.to_string(), meta)
+                    .await;
+            }
+        }
+
+        // Holders only take a drop from the writer of the version they hold.
+        let previous = match file.metadata().stamp().writer == file::writer(&id) {
+            true => self.audits.lock().unwrap().holders(name),
+            false => HashMap::new(),
+        };
+
+        let mut owned = Vec::new();
+        let mut released = HashMap::<String, Vec<usize>>::new();
+        let mut moved = Vec::new();
+        for shard in file.shards().present_iter() {
+            let index = shard.index();
+            let peer = order[index % order.len()].clone();
+            let old = previous.get(&index).filter(|old| **old != peer).cloned();
+
+            if peer == id {
+                self.audits.lock().unwrap().released(name, index);
+                if let Some(old) = old {
+                    released.entry(old).or_default().push(index);
+                }
+                owned.push(shard);
+                continue;
+            }
+
+            self.audits
+                .lock()
+                .unwrap()
+                .placed(name.to_string(), index, peer.clone());
+            if let Some(old) = old {
+                moved.push((old, peer.clone(), shard.clone()));
+            }
+
+            if pull {
+                continue;
+            }
+
+            debug!(peer, index, "placing shard");
+            let stamp = file.metadata().stamp();
+            self.network
+                .replicate(peer, name.to_string(), stamp, shard)
+                .await;
+        }
+
+        // Shards this node now keeps can go at once; the rest wait for the new holder.
+        for (peer, indices) in released {
+            debug!(peer, ?indices, "releasing shards");
+            self.network.release(peer, name.to_string(), indices).await;
         }
+        for (old, peer, shard) in moved {
+            let nonce = self.handoffs.lock().unwrap().start(
+                name.to_string(),
+                &shard,
+                old,
+                peer.clone(),
+                self.network.now(),
+            );
+            self.network
+                .challenge(peer, name.to_string(), shard.index(), nonce)
+                .await;
+        }
+
+        owned
+    }
+
+    // Sends a streamed block's shards to their holders and returns the ones this node keeps;
+    // under pull replication it keeps them all until the upload is offered.
+    async fn stream(
+        &self,
+        name: &str,
+        stamp: Stamp,
+        order: &[String],
+        shards: Vec<Shard>,
+    ) -> Vec<Shard> {
+        if self.config.replication == Replication::Pull {
+            return shards;
+        }
+
+        let id = self.network.id();
+        let mut kept = Vec::new();
+        for shard in shards {
+            let index = shard.index();
+            let peer = order[index % order.len()].clone();
+            if peer == id {
+                self.audits.lock().unwrap().released(name, index);
+                kept.push(shard);
+                continue;
+            }
+
+            self.audits
+                .lock()
+                .unwrap()
+                .placed(name.to_string(), index, peer.clone());
+            debug!(peer, index, "streaming shard");
+            self.network
+                .replicate(peer, name.to_string(), stamp, shard)
+                .await;
+        }
+
+        kept
+    }
+
+    fn layout(&self, name: &str, members: &[String], meta: &Metadata) -> Vec<String> {
+        let id = self.network.id();
+        let peers = self.peers.lock().unwrap();
+        let weights = members
+            .iter()
+            .filter(|member| **member != id)
+            .map(|member| {
+                let throughput = self.network.throughput(member).unwrap_or(SCORE_UNIT);
+                let weight = throughput as f64 * peers.score(member) * SCORE_UNIT as f64;
+                (member.clone(), weight as usize)
+            })
+            .collect::<HashMap<_, _>>();
+        drop(peers);
+
+        let layout = placement::weighted(name, members, &weights);
+
+        let mut counts = HashMap::<&String, usize>::new();
+        for index in 0..meta.shard_count() {
+            *counts.entry(&layout[index % layout.len()]).or_default() += 1;
+        }
+
+        let concentrated = counts.values().any(|count| *count > meta.parity_shards());
+        if concentrated && layout.len() > members.len() {
+            return placement::order(name, members);
+        }
+
+        layout
+    }
+
+    pub async fn join(&self) {
+        for peer in self
+            .candidates()
+            .await
+            .into_iter()
+            .take(self.config.join_sample)
+        {
+            self.network.announce(peer).await;
+        }
+    }
+
+    // Only the node that placed a file knows its holders, so every peer is told; each drops
+    // the file, metadata and shards, unless it holds a newer version.
+    pub async fn delete(&self, name: &str) -> bool {
+        let Some(file) = self.forget(name) else {
+            return false;
+        };
+
+        let stamp = file.metadata().stamp();
+        for peer in self.discover().await {
+            self.network.delete(peer, name.to_string(), stamp).await;
+        }
+
+        true
+    }
+
+    fn forget(&self, name: &str) -> Option<File> {
+        let file = self.files.lock().unwrap().remove(name)?;
+        self.unpersist(name, None);
+
+        let count = file.metadata().shard_count();
+        {
+            let mut audits = self.audits.lock().unwrap();
+            for index in 0..count {
+                audits.released(name, index);
+            }
+        }
+        self.handoffs.lock().unwrap().cancel(name);
+        self.unplaced.lock().unwrap().remove(name);
+        self.siblings.lock().unwrap().remove(name);
+        self.manifests.lock().unwrap().remove(name);
+        Some(file)
+    }
+
+    pub async fn decommission(&self) {
+        self.decommissioning.store(true, Ordering::Relaxed);
+
+        let peers = self.healthy().await;
+        let held = self
+            .files
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, file)| {
+                let shards = file.shards().present_iter().collect::<Vec<_>>();
+                (name.clone(), file.metadata().clone(), shards)
+            })
+            .collect::<Vec<_>>();
+
+        for (name, meta, shards) in held {
+            let order = placement::order(&name, &peers);
+            if order.is_empty() {
+                break;
+            }
+
+            for peer in &peers {
+                self.network
+                    .create(peer.clone(), name.clone(), meta.clone())
+                    .await;
+            }
+
+            for shard in shards {
+                let owner = order[shard.index() % order.len()].clone();
+                self.network
+                    .replicate(owner, name.clone(), meta.stamp(), shard)
+                    .await;
+            }
+        }
+
+        for peer in peers {
+            self.network.leave(peer).await;
+        }
+
+        if let Some(shutdown) = self.shutdown.lock().unwrap().take() {
+            let _ = shutdown.send(());
+        }
+    }
+
+    pub async fn audit(&self, name: &str) {
+        let holders = self.audits.lock().unwrap().holders(name);
+
+        let shards = self
+            .files
+            .lock()
+            .unwrap()
+            .get(name)
+            .into_iter()
+            .flat_map(|file| file.shards().present_iter())
+            .collect::<Vec<_>>();
+
+        for shard in shards {
+            let Some(holder) = holders.get(&shard.index()).cloned() else {
+                continue;
+            };
+
+            let now = self.network.now();
+            let nonce = self.audits.lock().unwrap().challenge(
+                holder.clone(),
+                name.to_string(),
+                &shard,
+                now,
+            );
+
+            self.network
+                .challenge(holder, name.to_string(), shard.index(), nonce)
+                .await;
+        }
+    }
+
+    // Keeps every file's metadata on `manifest_replicas` of the live members, a majority by
+    // default, picked by rendezvous hashing so every node agrees on them. Each of them that
+    // holds the metadata sends it to the others not known to hold this version yet, so after
+    // losing the uploader and any minority an owner is left to hand it to their replacements.
+    async fn spread_manifests(&self) {
+        let id = self.network.id();
+        let members = self
+            .healthy()
+            .await
+            .into_iter()
+            .chain([id.clone()])
+            .collect::<Vec<_>>();
+        let count = self
+            .config
+            .manifest_replicas
+            .unwrap_or(members.len() / 2 + 1)
+            .min(members.len());
+        let held = self
+            .files
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, file)| (name.clone(), file.metadata().clone()))
+            .collect::<Vec<_>>();
+
+        let mut sends = Vec::new();
+        {
+            let mut manifests = self.manifests.lock().unwrap();
+            for (name, meta) in held {
+                let mut owners = placement::order(&name, &members);
+                owners.truncate(count);
+                if !owners.contains(&id) {
+                    continue;
+                }
+
+                let (stamp, known) = manifests.entry(name.clone()).or_default();
+                if *stamp != meta.stamp() {
+                    (*stamp, *known) = (meta.stamp(), HashSet::new());
+                }
+                // An owner that dropped out may come back without it.
+                known.retain(|peer| owners.contains(peer));
+                for owner in owners {
+                    if owner != id && known.insert(owner.clone()) {
+                        sends.push((owner, name.clone(), meta.clone()));
+                    }
+                }
+            }
+        }
+
+        if !sends.is_empty() {
+            debug!(sent = sends.len(), "spreading manifests");
+        }
+        for (peer, name, meta) in sends {
+            self.network.create(peer, name, meta).await;
+        }
+    }
+
+    fn manifested(&self, name: &str, stamp: Stamp, peers: impl IntoIterator<Item = String>) {
+        let mut manifests = self.manifests.lock().unwrap();
+        let (current, known) = manifests.entry(name.to_string()).or_default();
+        if *current != stamp {
+            (*current, *known) = (stamp, HashSet::new());
+        }
+        known.extend(peers);
+    }
+
+    async fn bootstrap(&self, manifest: Vec<(String, Metadata)>) {
+        let peers = self.discover().await;
+        let id = self.network.id();
+        let members = peers
+            .iter()
+            .cloned()
+            .chain([id.clone()])
+            .collect::<Vec<_>>();
+
+        for (name, meta) in manifest {
+            if !self.admit(&name, &meta)
+                || self.supersede(&name, File::empty(meta.clone())) != Ok(true)
+            {
+                continue;
+            }
+
+            if peers.is_empty() {
+                continue;
+            }
+
+            let order = placement::order(&name, &peers);
+            let layout = self.layout(&name, &peers, &meta);
+            let mut backfill = self.backfill.lock().unwrap();
+            for index in 0..meta.shard_count() {
+                if placement::owner(&name, index, &members).as_ref() != Some(&id) {
+                    continue;
+                }
+
+                let mut holders = vec![layout[index % layout.len()].clone()];
+                for offset in 0..order.len() {
+                    let holder = &order[(index + offset) % order.len()];
+                    if !holders.contains(holder) {
+                        holders.push(holder.clone());
+                    }
+                }
+                holders.truncate(self.config.backfill_attempts);
+                backfill.enqueue(&name, index, holders);
+            }
+        }
+
+        self.backfill().await;
+    }
+
+    pub async fn backfill(&self) -> usize {
+        let window = match self.downloads.lock().unwrap().is_empty() {
+            true => self.config.backfill_window,
+            false => 1,
+        };
+
+        let (fetches, pending) = {
+            let mut backfill = self.backfill.lock().unwrap();
+            let now = self.network.now();
+            backfill.expire(now, self.config.backfill_timeout);
+            (backfill.next(window, now), backfill.pending())
+        };
+
+        for (holder, name, index) in fetches {
+            debug!(holder, file = name, index, "backfilling");
+            self.network.fetch(holder, name, index).await;
+        }
+
+        pending
+    }
+
+    pub async fn try_download(&self, name: &String) -> Result<Bytes, DecodeError> {
+        let res = {
+            let files = self.files.lock().unwrap();
+            let file = files.get(name).ok_or(DecodeError::Missing)?;
+            let version = file.shards().version();
+            let now = self.network.now();
+
+            let mut decoded = self.decoded.lock().unwrap();
+            match decoded.get(name, version, now) {
+                Some(content) => {
+                    self.network.metrics.counter(metrics::DECODES_CACHED, 1);
+                    Ok(content)
+                }
+                None => {
+                    self.network.metrics.counter(metrics::DECODES, 1);
+                    let res = file.decode().map(Bytes::from);
+                    if let Ok(content) = &res {
+                        decoded.insert(name, version, content, now);
+                    }
+                    res
+                }
+            }
+        };
+        self.network.observer.on_decode(name, &res);
+
+        if res.is_ok() {
+            self.complete(name);
+        }
+
+        res
+    }
+
+    fn complete(&self, name: &str) {
+        let mut downloads = self.downloads.lock().unwrap();
+        if let Some(download) = downloads.remove(name) {
+            self.advertised.lock().unwrap().remove(name);
+            download.span().in_scope(|| debug!("decoded"));
+            // Under the lock, so a shard arriving meanwhile cannot record the download again.
+            if let Some(store) = &self.store
+                && let Err(err) = store.remove_download(name)
+            {
+                warn!(file = name, %err, "store write failed");
+            }
+        }
+    }
+
+    // Yields the content stripe by stripe, each as soon as its block holds `data_shards`
+    // shards, and requests the rest as it goes. The stream ends early once the download fails
+    // or no shard arrives for `stream_timeout`, which callers can tell from `Metadata::len`.
+    pub fn download_stream(&self, name: String) -> impl Stream<Item = Bytes> + '_ {
+        let start = (0, None, self.network.now());
+        stream::unfold(start, move |(index, mut version, mut progressed)| {
+            let name = name.clone();
+            async move {
+                loop {
+                    let next = self.files.lock().unwrap().get(&name).map(|file| {
+                        let count = file.metadata().stripe_count();
+                        let stripe = (index < count).then(|| file.decode_stripe(index));
+                        (file.shards().version(), stripe)
+                    });
+
+                    let now = self.network.now();
+                    match next {
+                        Some((_, None)) => {
+                            self.complete(&name);
+                            return None;
+                        }
+                        Some((_, Some(Ok(stripe)))) => {
+                            return Some((stripe, (index + 1, version, now)));
+                        }
+                        Some((_, Some(Err(err)))) if !err.is_pending() => return None,
+                        Some((current, _)) if version != Some(current) => {
+                            version = Some(current);
+                            progressed = now;
+                        }
+                        _ if self.failed(&name)
+                            || now.saturating_duration_since(progressed)
+                                >= self.config.stream_timeout =>
+                        {
+                            return None;
+                        }
+                        _ => {}
+                    }
+
+                    let _ = self.attempt(name.clone()).await;
+                    self.network.sleep(self.config.request_window).await;
+                }
+            }
+        })
+    }
+
+    pub async fn download(&self, name: String) -> Result<Bytes, DecodeError> {
+        let start = self.network.now();
+        let res = self.coalesce(name).await;
+
+        let elapsed = self.network.now().saturating_duration_since(start);
+        let sink = &self.network.metrics;
+        match &res {
+            Ok(_) => {
+                sink.counter(metrics::DOWNLOADS_SUCCEEDED, 1);
+                sink.histogram(metrics::DOWNLOAD_LATENCY, elapsed.as_secs_f64());
+            }
+            Err(_) => sink.counter(metrics::DOWNLOADS_FAILED, 1),
+        }
+        let pending = self.downloads.lock().unwrap().len();
+        sink.gauge(metrics::DOWNLOADS_PENDING, pending as f64);
+
+        res
+    }
+
+    // UTF-8 shorthands for callers storing text.
+    pub async fn download_string(&self, name: String) -> Result<String, DecodeError> {
+        utf8(self.download(name).await?)
+    }
+
+    pub async fn try_download_string(&self, name: &String) -> Result<String, DecodeError> {
+        utf8(self.try_download(name).await?)
+    }
+
+    async fn coalesce(&self, name: String) -> Result<Bytes, DecodeError> {
+        loop {
+            let waiter = {
+                let mut inflight = self.inflight.lock().unwrap();
+                match inflight.get_mut(&name) {
+                    Some(waiters) => {
+                        let (sender, receiver) = oneshot::channel();
+                        waiters.push(sender);
+                        Some(receiver)
+                    }
+                    None => {
+                        inflight.insert(name.clone(), Vec::new());
+                        None
+                    }
+                }
+            };
+
+            let Some(waiter) = waiter else {
+                break;
+            };
+
+            if let Ok(res) = waiter.await {
+                self.coalesced.fetch_add(1, Ordering::Relaxed);
+                return res;
+            }
+        }
+
+        let guard = Inflight {
+            inflight: &self.inflight,
+            name: &name,
+        };
+        let res = self.attempt(name.clone()).await;
+
+        for waiter in guard.finish() {
+            let _ = waiter.send(res.clone());
+        }
+
+        res
+    }
+
+    async fn attempt(&self, name: String) -> Result<Bytes, DecodeError> {
+        let expired = self.cache.lock().unwrap().expire(self.network.now());
+        self.evict(expired);
+
+        let err = match self.try_download(&name).await {
+            Ok(res) => {
+                self.cache.lock().unwrap().hit(&name, self.network.now());
+                return Ok(res);
+            }
+            Err(DecodeError::Missing) => {
+                self.locate(&name).await;
+                return Err(DecodeError::Missing);
+            }
+            Err(err) => err,
+        };
+
+        if !self.downloads.lock().unwrap().contains_key(&name) {
+            self.cache.lock().unwrap().miss();
+        }
+
+        let ranked = self.candidates().await;
+        let holders = self.holders(&name, &ranked);
+        let local = self.local_holders(&ranked, &holders);
+        let candidates = self.owners_first(ranked, &holders);
+        let batch = self.batch(&name, candidates.len(), local);
+
+        let bitmap = self
+            .files
+            .lock()
+            .unwrap()
+            .get(&name)
+            .map(|file| file.shards().bitmap())
+            .unwrap_or_default();
+
+        let (peers, span, started) = {
+            let mut downloads = self.downloads.lock().unwrap();
+            let started = !downloads.contains_key(&name);
+            let download = downloads.entry(name.clone()).or_insert_with(|| {
+                Download::new(debug_span!("download", id = operation(), file = name))
+            });
+            let peers = download.schedule(candidates, batch, self.network.now());
+            (peers, download.span().clone(), started)
+        };
+        if started {
+            self.track(&name);
+        }
+
+        async {
+            debug!(?peers, "requesting shards");
+            for peer in peers {
+                self.network
+                    .request(peer, name.clone(), bitmap.clone())
+                    .await;
+            }
+        }
+        .instrument(span)
+        .await;
+
+        Err(err)
+    }
+
+    async fn locate(&self, name: &str) {
+        self.awaiting.lock().unwrap().insert(name.to_string());
+
+        for peer in self.candidates().await {
+            self.network.request_meta(peer, name.to_string()).await;
+        }
+    }
+
+    fn evict(&self, evicted: Vec<(String, Vec<usize>)>) {
+        let mut files = self.files.lock().unwrap();
+        for (name, indices) in evicted {
+            if let Some(file) = files.get_mut(&name) {
+                for index in &indices {
+                    file.shards_mut().delete(*index);
+                }
+                self.unpersist(&name, Some(&indices));
+            }
+        }
+    }
+
+    fn fetched(&self, name: &str, index: usize) -> bool {
+        let missing = self
+            .files
+            .lock()
+            .unwrap()
+            .get(name)
+            .is_some_and(|file| !file.shards().contains(index));
+
+        missing
+            && (self.downloads.lock().unwrap().contains_key(name)
+                || self.cache.lock().unwrap().contains(name))
+    }
+
+    fn adopt(&self, name: &str) {
+        let shards = self.orphans.lock().unwrap().take(name);
+        if shards.is_empty() {
+            return;
+        }
+
+        let current = self.metadata(name).map(|meta| meta.stamp());
+        let (mut adopted, mut rejected) = (0, 0);
+        for (stamp, shard) in shards {
+            if current == Some(stamp)
+                && self.verify(name, &shard)
+                && self.store(name, shard).is_ok()
+            {
+                adopted += 1;
+            } else {
+                rejected += 1;
+            }
+        }
+
+        self.orphans.lock().unwrap().adopt(adopted, rejected);
+    }
+
+    fn verify(&self, name: &str, shard: &Shard) -> bool {
+        self.files
+            .lock()
+            .unwrap()
+            .get(name)
+            .is_none_or(|file| file.verify_shard(shard))
+    }
+
+    fn held(&self, name: &str, index: usize) -> (Stamp, Vec<Shard>) {
+        self.files
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|file| {
+                let shards = file.shards().get(index).into_iter().collect();
+                (file.metadata().stamp(), shards)
+            })
+            .unwrap_or_default()
+    }
+
+    fn bitmap(&self, name: &str) -> Vec<u8> {
+        self.files
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|file| file.shards().bitmap())
+            .unwrap_or_default()
+    }
+
+    async fn mismatch(&self, peer: String, name: String, index: usize) {
+        let now = self.network.now();
+        if self
+            .nacks
+            .lock()
+            .unwrap()
+            .mismatch(&peer, &name, index, now)
+        {
+            debug!(peer, file = name, index, "checksum mismatch");
+            self.network.nack(peer, name, index).await;
+        } else {
+            debug!(
+                peer,
+                file = name,
+                index,
+                "checksum mismatch, giving up on peer"
+            );
+            self.retry(peer, name, index).await;
+        }
+    }
+
+    async fn retry(&self, peer: String, name: String, index: usize) {
+        {
+            let mut peers = self.peers.lock().unwrap();
+            peers.suspect(peer.clone());
+            peers.failed(peer.clone());
+        }
+
+        let missing = self.files.lock().unwrap().get(&name).is_some_and(|file| {
+            index < file.metadata().shard_count()
+                && !file.shards().contains(index)
+                && !file.can_decode()
+        });
+
+        if !missing {
+            return;
+        }
+
+        let mut candidates = self.candidates().await;
+        candidates.sort_by_cached_key(|peer| !self.same_region(peer));
+        let next = match self.downloads.lock().unwrap().get_mut(&name) {
+            Some(download) => download.retry(index, peer, &candidates),
+            None => return,
+        };
+
+        if let Some(next) = next {
+            self.network.fetch(next, name, index).await;
+        }
+    }
+
+    async fn finish(&self, name: &str) {
+        loop {
+            let decodable = self
+                .files
+                .lock()
+                .unwrap()
+                .get(name)
+                .is_some_and(|file| file.can_decode());
+
+            if !decodable {
+                return;
+            }
+
+            let corrupt = self.scrub(name);
+            if corrupt.is_empty() {
+                break;
+            }
+            for (index, peer) in corrupt {
+                if let Some(peer) = peer {
+                    self.retry(peer, name.to_string(), index).await;
+                }
+            }
+        }
+
+        let Some((peers, span)) = self
+            .downloads
+            .lock()
+            .unwrap()
+            .get_mut(name)
+            .map(|download| (download.finish(), download.span().clone()))
+        else {
+            return;
+        };
+
+        async {
+            debug!(cancelled = peers.len(), "decodable");
+            for peer in peers {
+                self.network.cancel(peer, name.to_string()).await;
+            }
+        }
+        .instrument(span)
+        .await;
+    }
+
+    // Without a checksum for every shard, only a shard's size is checked as it arrives, so a
+    // decodable download is checked against the parity as further shards come in. Shards it
+    // singles out are dropped and returned with the peer that sent them; a block that is
+    // inconsistent as a whole keeps its shards until another one arrives to tell them apart.
+    fn scrub(&self, name: &str) -> Vec<(usize, Option<String>)> {
+        if !self.downloads.lock().unwrap().contains_key(name) {
+            return Vec::new();
+        }
+
+        let corrupt = {
+            let mut files = self.files.lock().unwrap();
+            let Some(file) = files.get_mut(name).filter(|file| !file.shards().checked()) else {
+                return Vec::new();
+            };
+            let meta = file.metadata();
+            let group = meta.data_shards() + meta.parity_shards();
+            let reported = file.verify();
+            let corrupt = reported
+                .iter()
+                .copied()
+                .filter(|index| {
+                    let block = index / group * group..(index / group + 1) * group;
+                    let present = block.clone().filter(|i| file.shards().contains(*i)).count();
+                    reported.iter().filter(|i| block.contains(*i)).count() < present
+                })
+                .collect::<Vec<_>>();
+            for index in &corrupt {
+                file.shards_mut().delete(*index);
+            }
+            corrupt
+        };
+        if corrupt.is_empty() {
+            return Vec::new();
+        }
+
+        self.unpersist(name, Some(&corrupt));
+        let downloads = self.downloads.lock().unwrap();
+        let download = downloads.get(name);
+        if let Some(download) = download {
+            download
+                .span()
+                .in_scope(|| debug!(?corrupt, "shards disagree with the parity"));
+        }
+        corrupt
+            .into_iter()
+            .map(|index| (index, download.and_then(|download| download.sender(index))))
+            .collect()
+    }
+
+    async fn serve(&self, peer: String, name: String, stamp: Stamp, shards: Vec<Shard>) {
+        for shard in shards {
+            if self
+                .cancelled
+                .lock()
+                .unwrap()
+                .remove(&(peer.clone(), name.clone()))
+            {
+                self.requests.lock().unwrap().abandoned(&peer, &name);
+                break;
+            }
+
+            let admitted = self.tenants.lock().unwrap().admit_egress(
+                quota::tenant(&name),
+                shard.size(),
+                self.network.now(),
+            );
+
+            if let Err(reason) = admitted {
+                self.requests.lock().unwrap().abandoned(&peer, &name);
+                self.network
+                    .reject(peer.clone(), name.clone(), reason)
+                    .await;
+                break;
+            }
+
+            let (index, size) = (shard.index(), shard.size());
+            self.network
+                .replicate(peer.clone(), name.clone(), stamp, shard)
+                .await;
+            self.requests
+                .lock()
+                .unwrap()
+                .served(&peer, &name, index, size);
+        }
+    }
+
+    // Writes the file's metadata and the shards at `indices` through to the store.
+    fn persist(&self, name: &str, file: &File, indices: impl IntoIterator<Item = usize>) {
+        let Some(store) = &self.store else {
+            return;
+        };
+
+        let shards = indices
+            .into_iter()
+            .filter_map(|index| file.shards().get(index))
+            .collect::<Vec<_>>();
+        if let Err(err) = store.put(name, file.metadata(), &shards) {
+            warn!(file = name, %err, "store write failed");
+        }
+    }
+
+    // Records the shards an unfinished download holds so far, for `with_store` to resume it.
+    fn track(&self, name: &str) {
+        let Some(store) = &self.store else {
+            return;
+        };
+        let arrived = self.shards(name);
+        let downloads = self.downloads.lock().unwrap();
+        if !downloads.contains_key(name) {
+            return;
+        }
+
+        if let Err(err) = store.put_download(name, &arrived) {
+            warn!(file = name, %err, "store write failed");
+        }
+    }
+
+    fn unpersist(&self, name: &str, indices: Option<&[usize]>) {
+        let Some(store) = &self.store else {
+            return;
+        };
+
+        let res = match indices {
+            Some(indices) => store.remove(name, indices),
+            None => store.delete(name),
+        };
+        if let Err(err) = res {
+            warn!(file = name, %err, "store write failed");
+        }
+    }
+
+    fn store(&self, name: &str, shard: Shard) -> Result<(), Rejection> {
+        if self.decommissioning.load(Ordering::Relaxed) {
+            return Err(Rejection::Decommissioning);
+        }
+
+        let mut files = self.files.lock().unwrap();
+        if !files.contains_key(name) {
+            return Ok(());
+        }
+
+        let tenant = quota::tenant(name);
+
+        let stored = files
+            .iter()
+            .filter(|(other, _)| quota::tenant(other) == tenant)
+            .map(|(_, file)| file.shards().size())
+            .sum();
+
+        self.tenants
+            .lock()
+            .unwrap()
+            .admit_ingress(tenant, stored, shard.size(), self.network.now())
+            .inspect_err(|_| self.network.metrics.counter(metrics::SHARDS_REJECTED, 1))?;
+
+        let index = shard.index();
+        let file = files.get_mut(name).unwrap();
+        if file.shards_mut().merge(shard).is_ok() {
+            self.persist(name, file, [index]);
+        }
+
+        let total = files
+            .values()
+            .map(|file| file.shards().size())
+            .sum::<usize>();
+        self.network.metrics.counter(metrics::SHARDS_STORED, 1);
+        self.network
+            .metrics
+            .gauge(metrics::STORED_BYTES, total as f64);
+        self.requests.lock().unwrap().present(name);
+        self.network.observer.on_shard_stored(name, index);
+
+        Ok(())
+    }
+
+    pub async fn run(&self) {
+        let mut incoming = pin!(self.network.incoming().fuse());
+        let mut timer = pin!(self.network.sleep(self.tick()).fuse());
+        let mut serving = FuturesUnordered::new();
+        let mut halt = self
+            .halt
+            .lock()
+            .unwrap()
+            .take()
+            .expect("node is already running");
+        let mut retimed = self
+            .retimed
+            .lock()
+            .unwrap()
+            .take()
+            .expect("node is running");
+
+        loop {
+            select! {
+                msg = incoming.next() => match msg {
+                    Some((peer, cmd)) => {
+                        if let Some((peer, name, stamp, shards)) = self.handle(peer, cmd).await {
+                            serving.push(self.serve(peer, name, stamp, shards));
+                        }
+                        self.maintain().await;
+                    }
+                    None => break,
+                },
+                () = timer => {
+                    self.maintain().await;
+                    timer.set(self.network.sleep(self.tick()).fuse());
+                }
+                () = retimed.select_next_some() => {
+                    timer.set(self.network.sleep(self.tick()).fuse());
+                }
+                () = serving.select_next_some() => {}
+                _ = halt => break,
+            }
+        }
+    }
+
+    async fn maintain(&self) {
+        self.network.report(&*self.network.metrics);
+        self.nacks.lock().unwrap().expire(self.network.now());
+        self.expire_audits();
+        let handoffs = self.handoffs.lock().unwrap().due(self.network.now());
+        for (name, index, peer, nonce) in handoffs {
+            self.network.challenge(peer, name, index, nonce).await;
+        }
+        if self.backfill.lock().unwrap().pending() > 0 {
+            self.backfill().await;
+        }
+        if self.repair_due() {
+            self.place_pending().await;
+            self.spread_manifests().await;
+        }
+    }
+
+    fn expire_audits(&self) {
+        let silent = self.audits.lock().unwrap().expire(self.network.now());
+        if silent.is_empty() {
+            return;
+        }
+
+        let mut peers = self.peers.lock().unwrap();
+        for peer in silent {
+            debug!(peer, "audit challenge timed out");
+            peers.audited(peer.clone(), false);
+            peers.suspect(peer);
+        }
+    }
+
+    fn tick(&self) -> Duration {
+        let timeout = self.config.backfill_timeout.min(self.config.audit_timeout);
+        self.repair_interval()
+            .map_or(timeout, |interval| interval.min(timeout))
+    }
+
+    fn repair_due(&self) -> bool {
+        let Some(interval) = self.repair_interval() else {
+            return false;
+        };
+
+        let now = self.network.now();
+        let mut repaired = self.repaired.lock().unwrap();
+        match *repaired {
+            Some(last) if now.saturating_duration_since(last) < interval => false,
+            _ => {
+                *repaired = Some(now);
+                true
+            }
+        }
+    }
+
+    async fn handle(
+        &self,
+        peer: String,
+        cmd: Command,
+    ) -> Option<(String, String, Stamp, Vec<Shard>)> {
+        match cmd {
+            Command::Create { name, meta } => {
+                if !self.admit(&name, &meta) {
+                    return None;
+                }
+                let stamp = meta.stamp();
+                match self.supersede(&name, File::empty(meta)) {
+                    Ok(_) => self.manifested(&name, stamp, [peer]),
+                    Err(current) => {
+                        let bitmap = self.bitmap(&name);
+                        self.network.meta(peer, name, *current, bitmap).await;
+                    }
+                }
+            }
+
+            Command::Replicate {
+                name,
+                stamp,
+                shard,
+                checksum,
+            } => {
+                if shard.crc() != checksum {
+                    self.mismatch(peer, name, shard.index()).await;
+                    return None;
+                }
+
+                let current = self.metadata(&name).map(|meta| meta.stamp());
+                if current.is_none_or(|current| stamp > current) {
+                    let now = self.network.now();
+                    if self.orphans.lock().unwrap().hold(&name, stamp, shard, now) {
+                        debug!(peer, file = name, "shard for unknown file version");
+                        self.network.request_meta(peer, name).await;
+                    }
+                    return None;
+                }
+
+                if current != Some(stamp) {
+                    debug!(peer, file = name, ?stamp, "stale shard");
+                    self.stale.fetch_add(1, Ordering::Relaxed);
+                    return None;
+                }
+
+                if !self.verify(&name, &shard) {
+                    self.retry(peer, name, shard.index()).await;
+                    return None;
+                }
+
+                self.nacks
+                    .lock()
+                    .unwrap()
+                    .received(&peer, &name, shard.index());
+
+                let rtt = self
+                    .downloads
+                    .lock()
+                    .unwrap()
+                    .get_mut(&name)
+                    .and_then(|download| {
+                        let index = shard.index();
+                        download
+                            .span()
+                            .in_scope(|| debug!(peer, index, "shard received"));
+                        download.received(index, peer.clone());
+                        download.responded(&peer, self.network.now())
+                    });
+
+                {
+                    let mut peers = self.peers.lock().unwrap();
+                    if let Some(rtt) = rtt {
+                        peers.observe(peer.clone(), rtt);
+                    }
+                    peers.served(peer.clone());
+                }
+
+                let index = shard.index();
+                let fetched = self.fetched(&name, index);
+
+                match self.store(&name, shard) {
+                    Ok(()) => {
+                        if self.backfill.lock().unwrap().arrived(&name, index) {
+                            self.backfill().await;
+                        }
+                        if fetched {
+                            self.track(&name);
+                            let evicted =
+                                self.cache
+                                    .lock()
+                                    .unwrap()
+                                    .insert(&name, index, self.network.now());
+                            self.evict(evicted);
+                        }
+                        self.finish(&name).await
+                    }
+                    Err(reason) => self.network.reject(peer, name, reason).await,
+                }
+            }
+
+            Command::Request { name, bitmap } => {
+                let now = self.network.now();
+                {
+                    let mut requests = self.requests.lock().unwrap();
+                    if requests.missing(&name, now)
+                        || requests.duplicate(&peer, &name, &bitmap, now)
+                    {
+                        return None;
+                    }
+                }
+
+                let held = file::indices(&bitmap);
+                let (present, stamp, shards) = {
+                    let mut files = self.files.lock().unwrap();
+                    let file = files.get_mut(&name);
+                    let present = file
+                        .as_ref()
+                        .is_some_and(|file| file.shards().present() > 0);
+                    let stamp = file
+                        .as_ref()
+                        .map(|file| file.metadata().stamp())
+                        .unwrap_or_default();
+                    let shards = file
+                        .into_iter()
+                        .flat_map(|file| file.shards_mut().present_iter())
+                        .filter(|shard| !held.contains(&shard.index()))
+                        .collect::<Vec<_>>();
+                    (present, stamp, shards)
+                };
+
+                let shards = {
+                    let mut requests = self.requests.lock().unwrap();
+                    if !present {
+                        requests.absent(&name, now);
+                    }
+                    requests.queue(&peer, &name, shards)
+                };
+
+                self.cancelled
+                    .lock()
+                    .unwrap()
+                    .remove(&(peer.clone(), name.clone()));
+
+                return Some((peer, name, stamp, shards));
+            }
+
+            Command::Fetch { name, index } => {
+                let (stamp, shards) = self.held(&name, index);
+
+                self.cancelled
+                    .lock()
+                    .unwrap()
+                    .remove(&(peer.clone(), name.clone()));
+
+                return Some((peer, name, stamp, shards));
+            }
+
+            Command::Nack { name, index } => {
+                let (stamp, shards) = self.held(&name, index);
+
+                if !shards.is_empty() {
+                    self.nacks.lock().unwrap().retransmit();
+                }
+
+                return Some((peer, name, stamp, shards));
+            }
+
+            Command::Cancel { name } => {
+                if self.requests.lock().unwrap().serving(&peer, &name) {
+                    self.cancelled.lock().unwrap().insert((peer, name));
+                }
+            }
+
+            Command::Announce => {
+                let manifest = self
+                    .files
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|(name, file)| (name.clone(), file.metadata().clone()))
+                    .collect();
+
+                self.network.manifest(peer, manifest).await;
+                self.place_pending().await;
+            }
+
+            Command::Manifest { files } => {
+                self.bootstrap(files).await;
+            }
+
+            Command::Leave => {
+                self.peers.lock().unwrap().forget(&peer);
+            }
+
+            Command::Challenge { name, index, nonce } => {
+                let proof = self
+                    .files
+                    .lock()
+                    .unwrap()
+                    .get(&name)
+                    .and_then(|file| file.shards().get(index))
+                    .map(|shard| audit::prove(&shard, nonce));
+
+                self.network.proof(peer, name, index, nonce, proof).await;
+            }
+
+            Command::Proof {
+                name,
+                index,
+                nonce,
+                proof,
+            } => {
+                let handoff = self
+                    .handoffs
+                    .lock()
+                    .unwrap()
+                    .confirm(&peer, &name, index, nonce, proof);
+                match handoff {
+                    Some(Some(old)) => {
+                        debug!(old, peer, file = name, index, "shard handed off");
+                        self.network.release(old, name, vec![index]).await;
+                        return None;
+                    }
+                    Some(None) => return None,
+                    None => {}
+                }
+
+                let passed =
+                    self.audits
+                        .lock()
+                        .unwrap()
+                        .verify(peer.clone(), name, index, nonce, proof);
+
+                if let Some(passed) = passed {
+                    let mut peers = self.peers.lock().unwrap();
+                    peers.audited(peer.clone(), passed);
+                    if !passed {
+                        peers.suspect(peer);
+                    }
+                }
+            }
+
+            Command::Reject { name, .. } => {
+                self.peers.lock().unwrap().failed(peer);
+                self.tenants.lock().unwrap().rejected(quota::tenant(&name));
+            }
+
+            // Shards only move at the word of whoever placed them: the writer of the version held.
+            Command::Drop { name, indices } => {
+                let mut files = self.files.lock().unwrap();
+                let file = files.get_mut(&name)?;
+                if file.metadata().stamp().writer != file::writer(&peer) {
+                    debug!(peer, file = name, "drop refused");
+                    return None;
+                }
+
+                let count = file.metadata().shard_count();
+                let indices = indices
+                    .into_iter()
+                    .filter(|index| *index < count)
+                    .collect::<Vec<_>>();
+                for index in &indices {
+                    file.shards_mut().delete(*index);
+                }
+                self.unpersist(&name, Some(&indices));
+            }
+
+            // A delete covers the version the deleter saw and any older one; a newer write stays.
+            Command::Delete { name, stamp } => {
+                let current = self.metadata(&name).map(|meta| meta.stamp());
+                if current.is_some_and(|current| current <= stamp) {
+                    debug!(peer, file = name, "deleted by peer");
+                    self.forget(&name);
+                }
+            }
+
+            Command::RequestMeta { name } => {
+                let found = self
+                    .files
+                    .lock()
+                    .unwrap()
+                    .get(&name)
+                    .map(|file| (file.metadata().clone(), file.shards().bitmap()));
+
+                if let Some((meta, bitmap)) = found {
+                    self.network.meta(peer, name, meta, bitmap).await;
+                }
+            }
+
+            Command::Meta { name, meta, bitmap } => {
+                if !self.admit(&name, &meta) {
+                    return None;
+                }
+                let accepted = self.supersede(&name, File::empty(meta)).is_ok();
+
+                if accepted && !file::indices(&bitmap).is_empty() {
+                    self.advertised
+                        .lock()
+                        .unwrap()
+                        .entry(name.clone())
+                        .or_default()
+                        .insert(peer);
+                }
+
+                if self.awaiting.lock().unwrap().remove(&name) {
+                    let _ = self.download(name).await;
+                }
+            }
+
+            Command::Ingest { name, content } => {
+                if self.decommissioning.load(Ordering::Relaxed) {
+                    let reason = Rejection::Decommissioning;
+                    self.network.reject(peer, name, reason).await;
+                } else {
+                    match self.upload(name, content).await {
+                        Ok(Some(warning)) => {
+                            debug!(peer, ?warning, "ingested with durability warning")
+                        }
+                        Ok(None) => {}
+                        Err(err) => debug!(peer, %err, "ingest failed"),
+                    }
+                }
+            }
+
+            Command::Offer { name, meta, layout } => {
+                if !self.admit(&name, &meta) {
+                    return None;
+                }
+                if let Err(current) = self.supersede(&name, File::empty(meta)) {
+                    let bitmap = self.bitmap(&name);
+                    self.network.meta(peer, name, *current, bitmap).await;
+                    return None;
+                }
+
+                let id = self.network.id();
+                let owned = self
+                    .files
+                    .lock()
+                    .unwrap()
+                    .get(&name)
+                    .map(|file| {
+                        (0..file.metadata().shard_count())
+                            .filter(|index| layout.get(index % layout.len().max(1)) == Some(&id))
+                            .filter(|index| !file.shards().contains(*index))
+                            .collect::<Vec<_>>()
+                    })
+                    .unwrap_or_default();
+
+                debug!(peer, file = name, ?owned, "pulling offered shards");
+                for index in owned {
+                    self.network.fetch(peer.clone(), name.clone(), index).await;
+                }
+            }
+
+            Command::Ping { .. }
+            | Command::PingReq { .. }
+            | Command::Ack { .. }
+            | Command::Credit { .. }
+            | Command::Fragment { .. } => {}
+        }
+
+        None
     }
 }
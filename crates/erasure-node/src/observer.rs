@@ -0,0 +1,83 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+
+use crate::{
+    file::DecodeError,
+    metrics::{self, MetricsSink},
+    network::{Command, Network},
+    node::Conflict,
+};
+
+pub trait NodeObserver: Send + Sync {
+    fn on_command(&self, _peer: &str, _command: &Command) {}
+    fn on_send(&self, _peer: &str, _command: &Command) {}
+    fn on_shard_stored(&self, _name: &str, _index: usize) {}
+    fn on_decode(&self, _name: &str, _result: &Result<Bytes, DecodeError>) {}
+    fn on_conflict(&self, _conflict: &Conflict) {}
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoopObserver;
+
+impl NodeObserver for NoopObserver {}
+
+pub(crate) struct Observed<N> {
+    pub inner: N,
+    pub observer: Arc<dyn NodeObserver>,
+    pub metrics: Arc<dyn MetricsSink>,
+}
+
+impl<N: Network> Network for Observed<N> {
+    fn id(&self) -> String {
+        self.inner.id()
+    }
+
+    fn now(&self) -> Instant {
+        self.inner.now()
+    }
+
+    fn clock(&self) -> u64 {
+        self.inner.clock()
+    }
+
+    fn throughput(&self, peer: &str) -> Option<usize> {
+        self.inner.throughput(peer)
+    }
+
+    fn region(&self, peer: &str) -> Option<String> {
+        self.inner.region(peer)
+    }
+
+    fn mtu(&self) -> Option<usize> {
+        self.inner.mtu()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        self.inner.sleep(duration).await
+    }
+
+    async fn discover(&self) -> Vec<String> {
+        self.inner.discover().await
+    }
+
+    async fn send(&self, peer: String, command: Command) {
+        self.observer.on_send(&peer, &command);
+        self.metrics.counter(metrics::MESSAGES_SENT, 1);
+        self.inner.send(peer, command).await
+    }
+
+    async fn recv(&self) -> Option<(String, Command)> {
+        let (peer, command) = self.inner.recv().await?;
+        self.observer.on_command(&peer, &command);
+        self.metrics.counter(metrics::MESSAGES_RECEIVED, 1);
+        Some((peer, command))
+    }
+
+    fn report(&self, sink: &dyn MetricsSink) {
+        self.inner.report(sink)
+    }
+}
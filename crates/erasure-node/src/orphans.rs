@@ -0,0 +1,83 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::file::{Shard, Stamp};
+
+pub(crate) struct Orphans {
+    capacity: usize,
+    ttl: Duration,
+    pending: HashMap<String, (Instant, Vec<(Stamp, Shard)>)>,
+    adopted: usize,
+    dropped: usize,
+}
+
+impl Orphans {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            pending: HashMap::new(),
+            adopted: 0,
+            dropped: 0,
+        }
+    }
+
+    pub fn hold(&mut self, name: &str, stamp: Stamp, shard: Shard, now: Instant) -> bool {
+        let ttl = self.ttl;
+        let before = self.held();
+        self.pending
+            .retain(|_, (requested, _)| now.saturating_duration_since(*requested) < ttl);
+        self.dropped += before - self.held();
+
+        if self.held() >= self.capacity {
+            self.dropped += 1;
+            return false;
+        }
+
+        // Every new shard restarts the wait, so a streamed upload is held for as long as
+        // its blocks keep arriving.
+        match self.pending.get_mut(name) {
+            Some((held_since, shards)) => {
+                if !shards
+                    .iter()
+                    .any(|(held, other)| *held == stamp && other.index() == shard.index())
+                {
+                    shards.push((stamp, shard));
+                    *held_since = now;
+                }
+                false
+            }
+            None => {
+                self.pending
+                    .insert(name.to_string(), (now, vec![(stamp, shard)]));
+                true
+            }
+        }
+    }
+
+    pub fn take(&mut self, name: &str) -> Vec<(Stamp, Shard)> {
+        self.pending
+            .remove(name)
+            .map(|(_, shards)| shards)
+            .unwrap_or_default()
+    }
+
+    pub fn adopt(&mut self, adopted: usize, rejected: usize) {
+        self.adopted += adopted;
+        self.dropped += rejected;
+    }
+
+    pub fn held(&self) -> usize {
+        self.pending.values().map(|(_, shards)| shards.len()).sum()
+    }
+
+    pub fn adopted(&self) -> usize {
+        self.adopted
+    }
+
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
@@ -0,0 +1,149 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+const UNKNOWN_RTT: Duration = Duration::from_secs(1);
+const RTT_SCALE: f64 = 50.0;
+
+#[derive(Clone, Copy, Default)]
+struct History {
+    served: usize,
+    failed: usize,
+    audits_passed: usize,
+    audits_failed: usize,
+}
+
+// `known` maps every member seen to when it was last discovered; members absent for longer
+// than `ttl` are forgotten. The failure rate is sampled at most once per `interval`, however
+// often discovery runs.
+pub(crate) struct Peers {
+    suspects: HashSet<String>,
+    latency: HashMap<String, Duration>,
+    history: HashMap<String, History>,
+    known: HashMap<String, Instant>,
+    interval: Duration,
+    ttl: Duration,
+    sampled: Option<Instant>,
+    failure_rate: f64,
+}
+
+impl Peers {
+    pub fn new(interval: Duration, ttl: Duration) -> Self {
+        Self {
+            suspects: HashSet::new(),
+            latency: HashMap::new(),
+            history: HashMap::new(),
+            known: HashMap::new(),
+            interval,
+            ttl,
+            sampled: None,
+            failure_rate: 0.0,
+        }
+    }
+
+    pub fn suspect(&mut self, peer: String) {
+        self.suspects.insert(peer);
+    }
+
+    pub fn is_suspect(&self, peer: &str) -> bool {
+        self.suspects.contains(peer)
+    }
+
+    pub fn suspects(&self) -> Vec<String> {
+        self.suspects.iter().cloned().collect()
+    }
+
+    pub fn observe(&mut self, peer: String, rtt: Duration) {
+        self.latency
+            .entry(peer)
+            .and_modify(|avg| *avg = (*avg * 3 + rtt) / 4)
+            .or_insert(rtt);
+    }
+
+    pub fn served(&mut self, peer: String) {
+        self.history.entry(peer).or_default().served += 1;
+    }
+
+    pub fn failed(&mut self, peer: String) {
+        self.history.entry(peer).or_default().failed += 1;
+    }
+
+    pub fn audited(&mut self, peer: String, passed: bool) {
+        let history = self.history.entry(peer).or_default();
+        if passed {
+            history.audits_passed += 1;
+        } else {
+            history.audits_failed += 1;
+        }
+    }
+
+    pub fn score(&self, peer: &str) -> f64 {
+        let rtt = self.latency.get(peer).copied().unwrap_or(UNKNOWN_RTT);
+        let responsiveness = 1.0 / (1.0 + rtt.as_secs_f64() * 1000.0 / RTT_SCALE);
+
+        let history = self.history.get(peer).copied().unwrap_or_default();
+        let reliability =
+            (history.served + 1) as f64 / (history.served + history.failed + 2) as f64;
+        let integrity = (history.audits_passed + 1) as f64
+            / (history.audits_passed + history.audits_failed + 2) as f64;
+
+        responsiveness * reliability * integrity
+    }
+
+    pub fn scores(&self) -> HashMap<String, f64> {
+        self.known
+            .keys()
+            .map(|peer| (peer.clone(), self.score(peer)))
+            .collect()
+    }
+
+    pub fn rank(&self, mut peers: Vec<String>) -> Vec<String> {
+        peers.sort_by(|a, b| self.score(b).total_cmp(&self.score(a)));
+        peers
+    }
+
+    pub fn membership(&mut self, peers: &[String], now: Instant) {
+        let present = peers.iter().collect::<HashSet<_>>();
+        for peer in &present {
+            self.known.insert(peer.to_string(), now);
+        }
+
+        let ttl = self.ttl;
+        let gone = self
+            .known
+            .iter()
+            .filter(|(_, seen)| now.saturating_duration_since(**seen) > ttl)
+            .map(|(peer, _)| peer.clone())
+            .collect::<Vec<_>>();
+        for peer in gone {
+            self.forget(&peer);
+        }
+
+        if self
+            .sampled
+            .is_some_and(|sampled| now.saturating_duration_since(sampled) < self.interval)
+        {
+            return;
+        }
+
+        let absent = self.known.len() - present.len();
+        let sample = absent as f64 / self.known.len().max(1) as f64;
+        self.failure_rate = (self.failure_rate * 3.0 + sample) / 4.0;
+        self.sampled = Some(now);
+    }
+
+    pub fn forget(&mut self, peer: &str) {
+        self.known.remove(peer);
+        self.latency.remove(peer);
+        self.history.remove(peer);
+    }
+
+    pub fn failure_rate(&self) -> f64 {
+        self.failure_rate
+    }
+
+    pub fn latency(&self) -> HashMap<String, Duration> {
+        self.latency.clone()
+    }
+}
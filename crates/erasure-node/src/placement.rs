@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+
+use crate::file;
+
+const MAX_SLOTS: usize = 3;
+
+pub fn order(name: &str, members: &[String]) -> Vec<String> {
+    let mut order = members.to_vec();
+    order.sort_by_key(|member| hash(name, member));
+    order
+}
+
+pub fn owner(name: &str, index: usize, members: &[String]) -> Option<String> {
+    let order = order(name, members);
+    order.get(index % order.len().max(1)).cloned()
+}
+
+pub fn weighted(name: &str, members: &[String], weights: &HashMap<String, usize>) -> Vec<String> {
+    let order = order(name, members);
+    let Some(min) = order
+        .iter()
+        .filter_map(|member| weights.get(member).copied())
+        .filter(|weight| *weight > 0)
+        .min()
+    else {
+        return order;
+    };
+
+    let slots = order
+        .iter()
+        .map(|member| {
+            let weight = weights.get(member).copied().unwrap_or(min);
+            (weight / min).clamp(1, MAX_SLOTS)
+        })
+        .collect::<Vec<_>>();
+
+    (0..MAX_SLOTS)
+        .flat_map(|round| {
+            order
+                .iter()
+                .zip(&slots)
+                .filter(move |(_, slots)| **slots > round)
+                .map(|(member, _)| member.clone())
+        })
+        .collect()
+}
+
+fn hash(name: &str, member: &str) -> u64 {
+    file::fnv1a(
+        name.bytes()
+            .chain(std::iter::once(0xff))
+            .chain(member.bytes()),
+    )
+}
@@ -0,0 +1,106 @@
+use std::sync::{
+    Mutex,
+    atomic::{AtomicUsize, Ordering},
+};
+
+// Buffers are pooled by power-of-two capacity, each class behind its own lock, and the
+// pool as a whole holds at most MAX_BYTES; giving back past that evicts the largest
+// pooled buffers first.
+const CLASSES: usize = usize::BITS as usize;
+const MAX_BYTES: usize = 64 << 20;
+
+static FREE: [Mutex<Vec<Vec<u8>>>; CLASSES] = [const { Mutex::new(Vec::new()) }; CLASSES];
+static POOLED: AtomicUsize = AtomicUsize::new(0);
+static BYTES: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static REUSES: AtomicUsize = AtomicUsize::new(0);
+static EVICTIONS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    pub allocations: usize,
+    pub reuses: usize,
+    pub pooled: usize,
+    pub bytes: usize,
+    pub evictions: usize,
+}
+
+// Smallest class whose buffers all hold at least `size` bytes.
+fn class(size: usize) -> usize {
+    size.max(1).next_power_of_two().trailing_zeros() as usize
+}
+
+pub fn take(size: usize) -> Vec<u8> {
+    let reused = FREE[class(size)].lock().unwrap().pop();
+
+    match reused {
+        Some(mut buf) => {
+            POOLED.fetch_sub(1, Ordering::Relaxed);
+            BYTES.fetch_sub(buf.capacity(), Ordering::Relaxed);
+            REUSES.fetch_add(1, Ordering::Relaxed);
+            buf.clear();
+            buf.resize(size, 0);
+            buf
+        }
+        None => {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            let mut buf = Vec::with_capacity(size.max(1).next_power_of_two());
+            buf.resize(size, 0);
+            buf
+        }
+    }
+}
+
+pub fn copy(data: &[u8]) -> Vec<u8> {
+    let mut buf = take(data.len());
+    buf.copy_from_slice(data);
+    buf
+}
+
+pub fn give(buf: Vec<u8>) {
+    let capacity = buf.capacity();
+    if capacity == 0 || capacity > MAX_BYTES {
+        return;
+    }
+
+    // Filed under the largest class it can serve in full.
+    let class = capacity.ilog2() as usize;
+    evict(capacity, class);
+    if BYTES.load(Ordering::Relaxed) + capacity > MAX_BYTES {
+        return;
+    }
+
+    BYTES.fetch_add(capacity, Ordering::Relaxed);
+    POOLED.fetch_add(1, Ordering::Relaxed);
+    FREE[class].lock().unwrap().push(buf);
+}
+
+// Drops pooled buffers, largest class first and never from classes below `floor`, until
+// `incoming` more bytes fit.
+fn evict(incoming: usize, floor: usize) {
+    for class in (floor..CLASSES).rev() {
+        if BYTES.load(Ordering::Relaxed) + incoming <= MAX_BYTES {
+            return;
+        }
+
+        let mut free = FREE[class].lock().unwrap();
+        while BYTES.load(Ordering::Relaxed) + incoming > MAX_BYTES {
+            let Some(buf) = free.pop() else {
+                break;
+            };
+            POOLED.fetch_sub(1, Ordering::Relaxed);
+            BYTES.fetch_sub(buf.capacity(), Ordering::Relaxed);
+            EVICTIONS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+pub fn stats() -> PoolStats {
+    PoolStats {
+        allocations: ALLOCATIONS.load(Ordering::Relaxed),
+        reuses: REUSES.load(Ordering::Relaxed),
+        pooled: POOLED.load(Ordering::Relaxed),
+        bytes: BYTES.load(Ordering::Relaxed),
+        evictions: EVICTIONS.load(Ordering::Relaxed),
+    }
+}
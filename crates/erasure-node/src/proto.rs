@@ -0,0 +1,458 @@
+use bytes::Bytes;
+use prost::Message;
+
+use crate::{compress, file, membership, network, quota};
+
+pub const VERSION: u32 = 1;
+
+// Generated from proto/erasure.proto by build.rs.
+include!(concat!(env!("OUT_DIR"), "/erasure.v1.rs"));
+
+pub use command::Kind;
+
+// Fails when a count or index does not fit the schema's 32-bit fields.
+pub fn encode(command: &network::Command) -> Option<Vec<u8>> {
+    Some(to_proto(command)?.encode_to_vec())
+}
+
+pub fn decode(buf: &[u8]) -> Option<network::Command> {
+    from_proto(Command::decode(buf).ok()?)
+}
+
+pub fn to_proto(command: &network::Command) -> Option<Command> {
+    let kind = match command.clone() {
+        network::Command::Create { name, meta } => Kind::Create(Create {
+            name,
+            meta: Some(meta_to_proto(&meta)?),
+        }),
+        network::Command::Replicate {
+            name,
+            stamp,
+            shard,
+            checksum,
+        } => Kind::Replicate(Replicate {
+            name,
+            shard: Some(Shard {
+                index: u32::try_from(shard.index()).ok()?,
+                data: Bytes::copy_from_slice(shard.data()),
+            }),
+            checksum,
+            stamp: Some(stamp_to_proto(stamp)),
+        }),
+        network::Command::Request { name, bitmap } => Kind::Request(Request { name, bitmap }),
+        network::Command::Fetch { name, index } => Kind::Fetch(Fetch {
+            name,
+            index: u32::try_from(index).ok()?,
+        }),
+        network::Command::Cancel { name } => Kind::Cancel(Cancel { name }),
+        network::Command::Announce => Kind::Announce(Announce {}),
+        network::Command::Manifest { files } => Kind::Manifest(Manifest {
+            files: files
+                .into_iter()
+                .map(|(name, meta)| {
+                    Some(FileEntry {
+                        name,
+                        meta: Some(meta_to_proto(&meta)?),
+                    })
+                })
+                .collect::<Option<_>>()?,
+        }),
+        network::Command::Leave => Kind::Leave(Leave {}),
+        network::Command::Challenge { name, index, nonce } => Kind::Challenge(Challenge {
+            name,
+            index: u32::try_from(index).ok()?,
+            nonce,
+        }),
+        network::Command::Proof {
+            name,
+            index,
+            nonce,
+            proof,
+        } => Kind::Proof(Proof {
+            name,
+            index: u32::try_from(index).ok()?,
+            nonce,
+            proof,
+        }),
+        network::Command::Reject { name, reason } => Kind::Reject(Reject {
+            name,
+            reason: match reason {
+                quota::Rejection::Storage => Rejection::Storage,
+                quota::Rejection::Bandwidth => Rejection::Bandwidth,
+                quota::Rejection::Decommissioning => Rejection::Decommissioning,
+            } as i32,
+        }),
+        network::Command::Drop { name, indices } => Kind::Drop(Drop {
+            name,
+            indices: indices
+                .into_iter()
+                .map(|index| u32::try_from(index).ok())
+                .collect::<Option<_>>()?,
+        }),
+        network::Command::RequestMeta { name } => Kind::RequestMeta(RequestMeta { name }),
+        network::Command::Meta { name, meta, bitmap } => Kind::Meta(Meta {
+            name,
+            meta: Some(meta_to_proto(&meta)?),
+            bitmap,
+        }),
+        network::Command::Ingest { name, content } => Kind::Ingest(Ingest { name, content }),
+        network::Command::Ping { seq, updates } => Kind::Ping(Ping {
+            seq,
+            updates: updates.iter().map(update_to_proto).collect(),
+        }),
+        network::Command::PingReq {
+            seq,
+            target,
+            updates,
+        } => Kind::PingReq(PingReq {
+            seq,
+            target,
+            updates: updates.iter().map(update_to_proto).collect(),
+        }),
+        network::Command::Ack { seq, updates } => Kind::Ack(Ack {
+            seq,
+            updates: updates.iter().map(update_to_proto).collect(),
+        }),
+        network::Command::Nack { name, index } => Kind::Nack(Nack {
+            name,
+            index: u32::try_from(index).ok()?,
+        }),
+        network::Command::Credit { messages, bytes } => Kind::Credit(Credit { messages, bytes }),
+        network::Command::Fragment {
+            id,
+            index,
+            count,
+            data,
+        } => Kind::Fragment(Fragment {
+            id,
+            index: u32::try_from(index).ok()?,
+            count: u32::try_from(count).ok()?,
+            data,
+        }),
+        network::Command::Offer { name, meta, layout } => Kind::Offer(Offer {
+            name,
+            meta: Some(meta_to_proto(&meta)?),
+            layout,
+        }),
+        network::Command::Delete { name, stamp } => Kind::Delete(Delete {
+            name,
+            stamp: Some(stamp_to_proto(stamp)),
+        }),
+    };
+
+    Some(Command {
+        version: VERSION,
+        kind: Some(kind),
+    })
+}
+
+pub fn from_proto(command: Command) -> Option<network::Command> {
+    // Prost already skips fields it does not know, so newer versions decode as far as they can.
+    if command.version < VERSION {
+        return None;
+    }
+
+    let command = match command.kind? {
+        Kind::Create(Create { name, meta }) => network::Command::Create {
+            name,
+            meta: meta_from_proto(meta?)?,
+        },
+        Kind::Replicate(Replicate {
+            name,
+            shard,
+            checksum,
+            stamp,
+        }) => {
+            let shard = shard?;
+            network::Command::Replicate {
+                name,
+                stamp: stamp.map(stamp_from_proto).unwrap_or_default(),
+                shard: file::Shard::new(shard.index as usize, shard.data),
+                checksum,
+            }
+        }
+        Kind::Request(Request { name, bitmap }) => network::Command::Request { name, bitmap },
+        Kind::Fetch(Fetch { name, index }) => network::Command::Fetch {
+            name,
+            index: index as usize,
+        },
+        Kind::Cancel(Cancel { name }) => network::Command::Cancel { name },
+        Kind::Announce(_) => network::Command::Announce,
+        Kind::Manifest(Manifest { files }) => network::Command::Manifest {
+            files: files
+                .into_iter()
+                .map(|entry| Some((entry.name, meta_from_proto(entry.meta?)?)))
+                .collect::<Option<_>>()?,
+        },
+        Kind::Leave(_) => network::Command::Leave,
+        Kind::Challenge(Challenge { name, index, nonce }) => network::Command::Challenge {
+            name,
+            index: index as usize,
+            nonce,
+        },
+        Kind::Proof(Proof {
+            name,
+            index,
+            nonce,
+            proof,
+        }) => network::Command::Proof {
+            name,
+            index: index as usize,
+            nonce,
+            proof,
+        },
+        Kind::Reject(Reject { name, reason }) => network::Command::Reject {
+            name,
+            reason: match Rejection::try_from(reason).ok()? {
+                Rejection::Storage => quota::Rejection::Storage,
+                Rejection::Bandwidth => quota::Rejection::Bandwidth,
+                Rejection::Decommissioning => quota::Rejection::Decommissioning,
+            },
+        },
+        Kind::Drop(Drop { name, indices }) => network::Command::Drop {
+            name,
+            indices: indices.into_iter().map(|index| index as usize).collect(),
+        },
+        Kind::RequestMeta(RequestMeta { name }) => network::Command::RequestMeta { name },
+        Kind::Meta(Meta { name, meta, bitmap }) => network::Command::Meta {
+            name,
+            meta: meta_from_proto(meta?)?,
+            bitmap,
+        },
+        Kind::Ingest(Ingest { name, content }) => network::Command::Ingest { name, content },
+        Kind::Ping(Ping { seq, updates }) => network::Command::Ping {
+            seq,
+            updates: updates_from_proto(updates)?,
+        },
+        Kind::PingReq(PingReq {
+            seq,
+            target,
+            updates,
+        }) => network::Command::PingReq {
+            seq,
+            target,
+            updates: updates_from_proto(updates)?,
+        },
+        Kind::Ack(Ack { seq, updates }) => network::Command::Ack {
+            seq,
+            updates: updates_from_proto(updates)?,
+        },
+        Kind::Nack(Nack { name, index }) => network::Command::Nack {
+            name,
+            index: index as usize,
+        },
+        Kind::Credit(Credit { messages, bytes }) => network::Command::Credit { messages, bytes },
+        Kind::Fragment(Fragment {
+            id,
+            index,
+            count,
+            data,
+        }) => network::Command::Fragment {
+            id,
+            index: index as usize,
+            count: count as usize,
+            data,
+        },
+        Kind::Offer(Offer { name, meta, layout }) => network::Command::Offer {
+            name,
+            meta: meta_from_proto(meta?)?,
+            layout,
+        },
+        Kind::Delete(Delete { name, stamp }) => network::Command::Delete {
+            name,
+            stamp: stamp.map(stamp_from_proto).unwrap_or_default(),
+        },
+    };
+
+    Some(command)
+}
+
+fn meta_to_proto(meta: &file::Metadata) -> Option<Metadata> {
+    Some(Metadata {
+        len: meta.len(),
+        data_shards: u32::try_from(meta.data_shards()).ok()?,
+        parity_shards: u32::try_from(meta.parity_shards()).ok()?,
+        shard_size: u32::try_from(meta.shard_size()).ok()?,
+        stripes: u32::try_from(meta.stripes()).ok()?,
+        codec: match meta.codec() {
+            file::CodecId::ReedSolomon8 => Codec::ReedSolomon8,
+            file::CodecId::ReedSolomon16 => Codec::ReedSolomon16,
+            file::CodecId::Xor => Codec::Xor,
+            file::CodecId::Replication => Codec::Replication,
+            #[cfg(feature = "raptorq")]
+            file::CodecId::RaptorQ => Codec::RaptorQ,
+        } as i32,
+        stamp: Some(stamp_to_proto(meta.stamp())),
+        clock: meta
+            .clock()
+            .entries()
+            .map(|(writer, counter)| ClockEntry { writer, counter })
+            .collect(),
+        checksums: meta.checksums().to_vec(),
+        blocks: meta.blocks().to_vec(),
+        compression: match meta.compression() {
+            compress::Compression::None => Compression::None,
+            #[cfg(feature = "lz4")]
+            compress::Compression::Lz4 => Compression::Lz4,
+            #[cfg(feature = "zstd")]
+            compress::Compression::Zstd => Compression::Zstd,
+        } as i32,
+        encryption: meta.encryption().map(|encryption| Encryption {
+            key_id: encryption.key_id,
+            nonce: encryption.nonce.to_vec(),
+            recipients: encryption
+                .recipients
+                .into_iter()
+                .map(|wrapped| WrappedKey {
+                    recipient: wrapped.recipient.to_vec(),
+                    ephemeral: wrapped.ephemeral.to_vec(),
+                    sealed: wrapped.sealed,
+                })
+                .collect(),
+        }),
+        digests: meta
+            .digests()
+            .iter()
+            .map(|digest| digest.to_vec())
+            .collect(),
+        signature: meta.signature().map(|signature| Signature {
+            signer: signature.signer.to_vec(),
+            signature: signature.signature.clone(),
+        }),
+        created_at: meta.created_at(),
+        content_type: meta.content_type().map(String::from),
+        attributes: meta.attributes().clone(),
+    })
+}
+
+fn meta_from_proto(meta: Metadata) -> Option<file::Metadata> {
+    let codec = match Codec::try_from(meta.codec).ok()? {
+        Codec::ReedSolomon8 => file::CodecId::ReedSolomon8,
+        Codec::ReedSolomon16 => file::CodecId::ReedSolomon16,
+        Codec::Xor => file::CodecId::Xor,
+        Codec::Replication => file::CodecId::Replication,
+        #[cfg(feature = "raptorq")]
+        Codec::RaptorQ => file::CodecId::RaptorQ,
+        #[allow(unreachable_patterns)]
+        _ => return None,
+    };
+    let compression = match Compression::try_from(meta.compression).ok()? {
+        Compression::None => compress::Compression::None,
+        #[cfg(feature = "lz4")]
+        Compression::Lz4 => compress::Compression::Lz4,
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => compress::Compression::Zstd,
+        #[allow(unreachable_patterns)]
+        _ => return None,
+    };
+    let encryption = match meta.encryption {
+        Some(encryption) => Some(file::Encryption {
+            key_id: encryption.key_id,
+            nonce: encryption.nonce.try_into().ok()?,
+            recipients: encryption
+                .recipients
+                .into_iter()
+                .map(|wrapped| {
+                    Some(file::WrappedKey {
+                        recipient: wrapped.recipient.try_into().ok()?,
+                        ephemeral: wrapped.ephemeral.try_into().ok()?,
+                        sealed: wrapped.sealed,
+                    })
+                })
+                .collect::<Option<_>>()?,
+        }),
+        None => None,
+    };
+    let digests = meta
+        .digests
+        .into_iter()
+        .map(|digest| digest.try_into().ok())
+        .collect::<Option<_>>()?;
+    let signature = match meta.signature {
+        Some(signature) => Some(file::Signature {
+            signer: signature.signer.try_into().ok()?,
+            signature: signature.signature,
+        }),
+        None => None,
+    };
+
+    Some(
+        file::Metadata::new(
+            meta.len,
+            meta.shard_size,
+            meta.stripes,
+            meta.data_shards,
+            meta.parity_shards,
+            codec,
+        )
+        .with_stamp(meta.stamp.map(stamp_from_proto).unwrap_or_default())
+        .with_clock(
+            meta.clock
+                .into_iter()
+                .map(|entry| (entry.writer, entry.counter))
+                .collect(),
+        )
+        .with_checksums(meta.checksums)
+        .with_blocks(meta.blocks)
+        .with_compression(compression)
+        .with_digests(digests)
+        .with_created_at(meta.created_at)
+        .with_attributes(meta.attributes),
+    )
+    .map(|file| match meta.content_type {
+        Some(content_type) => file.with_content_type(content_type),
+        None => file,
+    })
+    .map(|meta| match encryption {
+        Some(encryption) => meta.with_encryption(encryption),
+        None => meta,
+    })
+    .map(|meta| match signature {
+        Some(signature) => meta.with_signature(signature),
+        None => meta,
+    })
+    .filter(file::Metadata::is_consistent)
+}
+
+fn stamp_to_proto(stamp: file::Stamp) -> Stamp {
+    Stamp {
+        time: stamp.time,
+        writer: stamp.writer,
+    }
+}
+
+fn stamp_from_proto(stamp: Stamp) -> file::Stamp {
+    file::Stamp {
+        time: stamp.time,
+        writer: stamp.writer,
+    }
+}
+
+fn update_to_proto(update: &membership::Update) -> Update {
+    Update {
+        member: update.member.clone(),
+        state: match update.state {
+            membership::State::Alive => State::Alive,
+            membership::State::Suspect => State::Suspect,
+            membership::State::Dead => State::Dead,
+        } as i32,
+        incarnation: update.incarnation,
+    }
+}
+
+fn updates_from_proto(updates: Vec<Update>) -> Option<Vec<membership::Update>> {
+    updates
+        .into_iter()
+        .map(|update| {
+            Some(membership::Update {
+                state: match State::try_from(update.state).ok()? {
+                    State::Alive => membership::State::Alive,
+                    State::Suspect => membership::State::Suspect,
+                    State::Dead => membership::State::Dead,
+                },
+                member: update.member,
+                incarnation: update.incarnation,
+            })
+        })
+        .collect()
+}
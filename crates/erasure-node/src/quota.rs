@@ -0,0 +1,159 @@
+use std::{collections::HashMap, time::Instant};
+
+pub fn tenant(name: &str) -> &str {
+    name.split_once('/')
+        .map(|(tenant, _)| tenant)
+        .unwrap_or_default()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Rejection {
+    Storage,
+    Bandwidth,
+    Decommissioning,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Quota {
+    pub storage: Option<usize>,
+    /// Bytes per second a tenant may replicate into this node, with a one second burst.
+    pub ingress: Option<usize>,
+    /// Bytes per second a tenant may be served from this node, with a one second burst.
+    pub egress: Option<usize>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub storage: usize,
+    pub ingress: usize,
+    pub egress: usize,
+    pub rejections_sent: usize,
+    pub rejections_received: usize,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    fn take(&mut self, rate: usize, bytes: usize, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate as f64).min(rate as f64);
+        self.refilled = now;
+
+        if self.tokens < bytes as f64 {
+            return false;
+        }
+
+        self.tokens -= bytes as f64;
+        true
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Tenants {
+    default: Quota,
+    quotas: HashMap<String, Quota>,
+    usage: HashMap<String, Usage>,
+    ingress: HashMap<String, Bucket>,
+    egress: HashMap<String, Bucket>,
+}
+
+fn admit(
+    buckets: &mut HashMap<String, Bucket>,
+    tenant: &str,
+    rate: Option<usize>,
+    bytes: usize,
+    now: Instant,
+) -> bool {
+    let Some(rate) = rate else {
+        return true;
+    };
+
+    buckets
+        .entry(tenant.to_string())
+        .or_insert(Bucket {
+            tokens: rate as f64,
+            refilled: now,
+        })
+        .take(rate, bytes, now)
+}
+
+impl Tenants {
+    pub fn new(default: Quota) -> Self {
+        Self {
+            default,
+            ..Self::default()
+        }
+    }
+
+    pub fn set_quota(&mut self, tenant: String, quota: Quota) {
+        self.quotas.insert(tenant, quota);
+    }
+
+    // Usage and the tokens left in each bucket carry over; a lowered rate caps the bucket
+    // at its next refill.
+    pub fn set_quotas(&mut self, default: Quota, quotas: HashMap<String, Quota>) {
+        self.default = default;
+        self.quotas = quotas;
+    }
+
+    pub fn admit_ingress(
+        &mut self,
+        tenant: &str,
+        stored: usize,
+        bytes: usize,
+        now: Instant,
+    ) -> Result<(), Rejection> {
+        let quota = self.quotas.get(tenant).copied().unwrap_or(self.default);
+
+        let result = if quota.storage.is_some_and(|limit| stored + bytes > limit) {
+            Err(Rejection::Storage)
+        } else if !admit(&mut self.ingress, tenant, quota.ingress, bytes, now) {
+            Err(Rejection::Bandwidth)
+        } else {
+            Ok(())
+        };
+
+        let usage = self.usage.entry(tenant.to_string()).or_default();
+        match result {
+            Ok(()) => usage.ingress += bytes,
+            Err(_) => usage.rejections_sent += 1,
+        }
+
+        result
+    }
+
+    pub fn admit_egress(
+        &mut self,
+        tenant: &str,
+        bytes: usize,
+        now: Instant,
+    ) -> Result<(), Rejection> {
+        let quota = self.quotas.get(tenant).copied().unwrap_or(self.default);
+        let admitted = admit(&mut self.egress, tenant, quota.egress, bytes, now);
+
+        let usage = self.usage.entry(tenant.to_string()).or_default();
+        if !admitted {
+            usage.rejections_sent += 1;
+            return Err(Rejection::Bandwidth);
+        }
+
+        usage.egress += bytes;
+        Ok(())
+    }
+
+    pub fn rejected(&mut self, tenant: &str) {
+        self.usage
+            .entry(tenant.to_string())
+            .or_default()
+            .rejections_received += 1;
+    }
+
+    pub fn usage(&self) -> HashMap<String, Usage> {
+        self.usage.clone()
+    }
+}
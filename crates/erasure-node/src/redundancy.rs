@@ -0,0 +1,40 @@
+pub trait RedundancyPolicy: Send + Sync {
+    fn parity_shards(&self, data_shards: usize, failure_rate: f64) -> usize;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FixedRedundancy;
+
+impl RedundancyPolicy for FixedRedundancy {
+    fn parity_shards(&self, data_shards: usize, _: f64) -> usize {
+        data_shards
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AdaptiveRedundancy {
+    pub min_ratio: f64,
+    pub max_ratio: f64,
+    pub safety: f64,
+}
+
+impl Default for AdaptiveRedundancy {
+    fn default() -> Self {
+        Self {
+            min_ratio: 0.25,
+            max_ratio: 2.0,
+            safety: 2.0,
+        }
+    }
+}
+
+impl RedundancyPolicy for AdaptiveRedundancy {
+    fn parity_shards(&self, data_shards: usize, failure_rate: f64) -> usize {
+        let failure_rate = failure_rate.clamp(0.0, 0.99);
+        let ratio = (failure_rate / (1.0 - failure_rate) * self.safety)
+            .clamp(self.min_ratio, self.max_ratio);
+
+        (data_shards as f64 * ratio).ceil() as usize
+    }
+}
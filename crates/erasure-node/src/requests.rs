@@ -0,0 +1,145 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use smallvec::SmallVec;
+
+use crate::file::Shard;
+
+type Key = (String, String);
+
+pub(crate) struct Requests {
+    window: Duration,
+    negative_ttl: Duration,
+    seen: HashMap<Key, (Vec<u8>, Instant)>,
+    queued: HashMap<Key, SmallVec<[usize; 8]>>,
+    missing: HashMap<String, Instant>,
+    deduplicated: usize,
+    coalesced: usize,
+    negative_hits: usize,
+    served: usize,
+    served_bytes: usize,
+}
+
+impl Requests {
+    pub fn new(window: Duration, negative_ttl: Duration) -> Self {
+        Self {
+            window,
+            negative_ttl,
+            seen: HashMap::new(),
+            queued: HashMap::new(),
+            missing: HashMap::new(),
+            deduplicated: 0,
+            coalesced: 0,
+            negative_hits: 0,
+            served: 0,
+            served_bytes: 0,
+        }
+    }
+
+    pub fn duplicate(&mut self, peer: &str, name: &str, bitmap: &[u8], now: Instant) -> bool {
+        let window = self.window;
+        self.seen
+            .retain(|_, (_, seen)| now.saturating_duration_since(*seen) < window);
+
+        let key = (peer.to_string(), name.to_string());
+        if self.seen.get(&key).is_some_and(|(seen, _)| seen == bitmap) {
+            self.deduplicated += 1;
+            return true;
+        }
+
+        self.seen.insert(key, (bitmap.to_vec(), now));
+        false
+    }
+
+    pub fn missing(&mut self, name: &str, now: Instant) -> bool {
+        let Some(since) = self.missing.get(name) else {
+            return false;
+        };
+
+        if now.saturating_duration_since(*since) >= self.negative_ttl {
+            self.missing.remove(name);
+            return false;
+        }
+
+        self.negative_hits += 1;
+        true
+    }
+
+    pub fn absent(&mut self, name: &str, now: Instant) {
+        self.missing.insert(name.to_string(), now);
+    }
+
+    pub fn present(&mut self, name: &str) {
+        self.missing.remove(name);
+    }
+
+    pub fn queue(&mut self, peer: &str, name: &str, shards: Vec<Shard>) -> Vec<Shard> {
+        let queued = self
+            .queued
+            .entry((peer.to_string(), name.to_string()))
+            .or_default();
+
+        let total = shards.len();
+        let shards = shards
+            .into_iter()
+            .filter(|shard| {
+                let fresh = !queued.contains(&shard.index());
+                if fresh {
+                    queued.push(shard.index());
+                }
+                fresh
+            })
+            .collect::<Vec<_>>();
+
+        self.coalesced += total - shards.len();
+        shards
+    }
+
+    pub fn served(&mut self, peer: &str, name: &str, index: usize, bytes: usize) {
+        self.served += 1;
+        self.served_bytes += bytes;
+
+        let key = (peer.to_string(), name.to_string());
+        if let Some(queued) = self.queued.get_mut(&key) {
+            queued.retain(|queued| *queued != index);
+            if queued.is_empty() {
+                self.queued.remove(&key);
+            }
+        }
+    }
+
+    pub fn serving(&self, peer: &str, name: &str) -> bool {
+        self.queued
+            .contains_key(&(peer.to_string(), name.to_string()))
+    }
+
+    pub fn abandoned(&mut self, peer: &str, name: &str) {
+        self.queued.remove(&(peer.to_string(), name.to_string()));
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queued.values().map(SmallVec::len).sum()
+    }
+
+    pub fn deduplicated(&self) -> usize {
+        self.deduplicated
+    }
+
+    pub fn coalesced(&self) -> usize {
+        self.coalesced
+    }
+
+    pub fn negative_hits(&self) -> usize {
+        self.negative_hits
+    }
+
+    pub fn shards_served(&self) -> usize {
+        self.served
+    }
+
+    pub fn bytes_served(&self) -> usize {
+        self.served_bytes
+    }
+}
@@ -0,0 +1,97 @@
+use std::{
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    codec,
+    file::{File, Metadata, Shard},
+    network::Command,
+};
+
+const MAGIC: &[u8] = b"ERSHARD1";
+
+pub fn file_name(prefix: &str, index: usize, count: usize) -> String {
+    let width = count.to_string().len();
+    format!("{prefix}.{index:0width$}_{count:0width$}.shard")
+}
+
+pub fn encode(name: &str, meta: &Metadata, shard: &Shard) -> Option<Bytes> {
+    let create = Command::Create {
+        name: name.to_string(),
+        meta: meta.clone(),
+    };
+    let replicate = Command::Replicate {
+        name: name.to_string(),
+        stamp: meta.stamp(),
+        checksum: shard.crc(),
+        shard: shard.clone(),
+    };
+
+    let mut buf = BytesMut::new();
+    buf.put_slice(MAGIC);
+    buf.put_slice(&codec::encode(&create)?);
+    buf.put_slice(&codec::encode(&replicate)?);
+    Some(buf.freeze())
+}
+
+pub fn decode(data: &[u8]) -> Option<(String, Metadata, Shard)> {
+    let mut buf = BytesMut::from(data.strip_prefix(MAGIC)?);
+
+    let Command::Create { name, meta } = codec::decode(codec::split(&mut buf)?)? else {
+        return None;
+    };
+    let Command::Replicate {
+        name: other,
+        stamp,
+        shard,
+        checksum,
+    } = codec::decode(codec::split(&mut buf)?)?
+    else {
+        return None;
+    };
+
+    let valid = buf.is_empty()
+        && other == name
+        && stamp == meta.stamp()
+        && shard.crc() == checksum
+        && File::empty(meta.clone()).verify_shard(&shard);
+    valid.then_some((name, meta, shard))
+}
+
+pub fn write(dir: impl AsRef<Path>, name: &str, file: &File) -> io::Result<Vec<PathBuf>> {
+    let count = file.metadata().shard_count();
+    file.shards()
+        .present_iter()
+        .map(|shard| {
+            let path = dir.as_ref().join(file_name(name, shard.index(), count));
+            let data = encode(name, file.metadata(), &shard).ok_or_else(|| {
+                io::Error::new(ErrorKind::InvalidInput, "shard too large for a shard file")
+            })?;
+            std::fs::write(&path, data)?;
+            Ok(path)
+        })
+        .collect()
+}
+
+pub fn open(path: impl AsRef<Path>) -> io::Result<(String, Metadata, Shard)> {
+    decode(&std::fs::read(path)?)
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "malformed shard file"))
+}
+
+pub fn assemble(shards: impl IntoIterator<Item = (String, Metadata, Shard)>) -> Option<File> {
+    let mut shards = shards.into_iter();
+    let (name, meta, shard) = shards.next()?;
+
+    let mut file = File::empty(meta);
+    let _ = file.shards_mut().merge(shard);
+    for (other, meta, shard) in shards {
+        if other == name && &meta == file.metadata() {
+            let _ = file.shards_mut().merge(shard);
+        }
+    }
+
+    Some(file)
+}
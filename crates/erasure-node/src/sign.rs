@@ -0,0 +1,27 @@
+use ed25519_dalek::{Signer, Verifier};
+use sha2::{Digest, Sha256};
+
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+
+use crate::file::Signature;
+
+pub(crate) fn digest(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+pub(crate) fn sign(key: &SigningKey, message: &[u8]) -> Signature {
+    Signature {
+        signer: key.verifying_key().to_bytes(),
+        signature: key.sign(message).to_bytes().to_vec().into(),
+    }
+}
+
+pub(crate) fn verify(signature: &Signature, message: &[u8]) -> bool {
+    let Ok(key) = VerifyingKey::from_bytes(&signature.signer) else {
+        return false;
+    };
+    let Ok(signature) = ed25519_dalek::Signature::from_slice(&signature.signature) else {
+        return false;
+    };
+    key.verify(message, &signature).is_ok()
+}
@@ -0,0 +1,839 @@
+use std::{
+    fs,
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use bytes::Bytes;
+use tracing::warn;
+
+use crate::{
+    file::{self, Metadata, Shard},
+    network::Command,
+    shardfile,
+};
+
+const META: &str = "meta";
+const DOWNLOAD: &str = "download";
+
+/// Where a node keeps what it holds so it survives a restart. The node keeps serving from
+/// memory and writes every change through; `load` brings it all back when the node opens.
+pub trait ShardStore: Send + Sync {
+    fn load(&self) -> io::Result<Vec<(String, Metadata, Vec<Shard>)>>;
+
+    /// Records `meta` and adds `shards` in one batch. Metadata other than what is stored
+    /// replaces the file, dropping the shards and the download record of the old version.
+    fn put(&self, name: &str, meta: &Metadata, shards: &[Shard]) -> io::Result<()>;
+
+    fn remove(&self, name: &str, indices: &[usize]) -> io::Result<()>;
+
+    /// Drops the file along with its download record.
+    fn delete(&self, name: &str) -> io::Result<()>;
+
+    /// Records that a download of a stored file is under way and which of its shards have
+    /// arrived, replacing the previous record. A file the store does not hold is ignored.
+    fn put_download(&self, name: &str, arrived: &[usize]) -> io::Result<()>;
+
+    fn remove_download(&self, name: &str) -> io::Result<()>;
+
+    /// The unfinished downloads with the shards that had arrived when each was last recorded.
+    fn downloads(&self) -> io::Result<Vec<(String, Vec<usize>)>>;
+}
+
+impl<S: ShardStore + ?Sized> ShardStore for Box<S> {
+    fn load(&self) -> io::Result<Vec<(String, Metadata, Vec<Shard>)>> {
+        (**self).load()
+    }
+
+    fn put(&self, name: &str, meta: &Metadata, shards: &[Shard]) -> io::Result<()> {
+        (**self).put(name, meta, shards)
+    }
+
+    fn remove(&self, name: &str, indices: &[usize]) -> io::Result<()> {
+        (**self).remove(name, indices)
+    }
+
+    fn delete(&self, name: &str) -> io::Result<()> {
+        (**self).delete(name)
+    }
+
+    fn put_download(&self, name: &str, arrived: &[usize]) -> io::Result<()> {
+        (**self).put_download(name, arrived)
+    }
+
+    fn remove_download(&self, name: &str) -> io::Result<()> {
+        (**self).remove_download(name)
+    }
+
+    fn downloads(&self) -> io::Result<Vec<(String, Vec<usize>)>> {
+        (**self).downloads()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum StoreConfig {
+    #[default]
+    Memory,
+    Directory(PathBuf),
+    #[cfg(feature = "redb")]
+    Redb(PathBuf),
+    #[cfg(feature = "rocksdb")]
+    RocksDb(PathBuf),
+}
+
+// None for a node that keeps everything in memory only.
+pub fn open(config: &StoreConfig) -> io::Result<Option<Box<dyn ShardStore>>> {
+    Ok(match config {
+        StoreConfig::Memory => None,
+        StoreConfig::Directory(path) => Some(Box::new(Directory::open(path)?)),
+        #[cfg(feature = "redb")]
+        StoreConfig::Redb(path) => Some(Box::new(Redb::open(path)?)),
+        #[cfg(feature = "rocksdb")]
+        StoreConfig::RocksDb(path) => Some(Box::new(RocksDb::open(path)?)),
+    })
+}
+
+fn encode_meta(name: &str, meta: &Metadata) -> io::Result<Bytes> {
+    Command::Create {
+        name: name.to_string(),
+        meta: meta.clone(),
+    }
+    .to_bytes()
+    .map_err(|_| oversized())
+}
+
+fn decode_meta(data: &[u8]) -> Option<(String, Metadata)> {
+    match Command::from_bytes(Bytes::copy_from_slice(data)) {
+        Ok(Command::Create { name, meta }) => Some((name, meta)),
+        _ => None,
+    }
+}
+
+// Download records hold the arrived indices as a bitmap, like `Command::Request`.
+fn encode_arrived(arrived: &[usize]) -> Vec<u8> {
+    let len = arrived.iter().max().map_or(0, |max| max / 8 + 1);
+    let mut bitmap = vec![0; len];
+    for index in arrived {
+        bitmap[index / 8] |= 1 << (index % 8);
+    }
+    bitmap
+}
+
+fn decode_arrived(bitmap: &[u8]) -> Vec<usize> {
+    file::indices(bitmap).to_vec()
+}
+
+fn oversized() -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, "store entry too large to frame")
+}
+
+fn missing_ok(res: io::Result<()>) -> io::Result<()> {
+    match res {
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+        res => res,
+    }
+}
+
+/// One directory per file (its name hex encoded) holding the metadata and a shard file
+/// (see `shardfile`) per shard. Every write goes to a temporary file that is then renamed,
+/// and a new version of a file is written to a directory of its own that is then swapped in.
+/// A shard file that fails to decode is renamed to `.corrupt` and left for repair to replace.
+/// Files and the directories they are renamed in are synced before a write returns.
+pub struct Directory {
+    root: PathBuf,
+    // Steps left before the store acts as if the process died; see `crash_after`.
+    steps: Option<AtomicUsize>,
+}
+
+impl Directory {
+    pub fn open(root: impl AsRef<Path>) -> io::Result<Self> {
+        fs::create_dir_all(&root)?;
+        let store = Self {
+            root: root.as_ref().to_path_buf(),
+            steps: None,
+        };
+        store.recover()?;
+        Ok(store)
+    }
+
+    /// Fails every file system step after the first `steps`, leaving the directory the way
+    /// a crash at that point would. For crash-consistency tests.
+    #[doc(hidden)]
+    pub fn crash_after(mut self, steps: usize) -> Self {
+        self.steps = Some(AtomicUsize::new(steps));
+        self
+    }
+
+    fn step(&self) -> io::Result<()> {
+        let Some(steps) = &self.steps else {
+            return Ok(());
+        };
+        steps
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+                left.checked_sub(1)
+            })
+            .map(|_| ())
+            .map_err(|_| io::Error::other("store crashed"))
+    }
+
+    fn dir(&self, name: &str) -> PathBuf {
+        let hex = name
+            .bytes()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+        self.root.join(hex)
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        self.step()?;
+        let mut file = fs::File::create(&tmp)?;
+        // A crash mid-write leaves half a temporary file, which load ignores.
+        let (head, tail) = data.split_at(data.len() / 2);
+        file.write_all(head)?;
+        self.step()?;
+        file.write_all(tail)?;
+        file.sync_all()?;
+        self.rename(&tmp, path)
+    }
+
+    // Renames and syncs the parent directory so the rename survives a power loss.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.step()?;
+        fs::rename(from, to)?;
+        sync_dir(to.parent().unwrap_or(&self.root))
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        self.step()?;
+        missing_ok(fs::remove_dir_all(path))
+    }
+
+    // Writes a file version into `dir.new`, then moves the old directory aside to `dir.old`
+    // and the new one into place. `recover` finishes or undoes a swap a crash interrupted.
+    fn replace(&self, name: &str, meta: &Metadata, shards: &[Shard]) -> io::Result<()> {
+        let dir = self.dir(name);
+        let new = dir.with_extension("new");
+        let old = dir.with_extension("old");
+        self.remove_dir(&new)?;
+        self.remove_dir(&old)?;
+        self.step()?;
+        fs::create_dir_all(&new)?;
+        self.write(&new.join(META), &encode_meta(name, meta)?)?;
+        for shard in shards {
+            self.write(
+                &new.join(shard_name(shard.index())),
+                &encode_shard(name, meta, shard)?,
+            )?;
+        }
+
+        // `dir.old` existing marks `dir.new` as complete, even for a file's first version.
+        match self.rename(&dir, &old) {
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                self.step()?;
+                fs::create_dir(&old)?;
+                sync_dir(&self.root)?;
+            }
+            res => res?,
+        }
+        self.rename(&new, &dir)?;
+        self.remove_dir(&old)
+    }
+
+    // Run on open. A `.new` directory only replaces the file once the swap started, which
+    // `.old` marks; before that the old version stands and the new one is discarded.
+    fn recover(&self) -> io::Result<()> {
+        for entry in fs::read_dir(&self.root)? {
+            let new = entry?.path();
+            if new.extension().is_none_or(|ext| ext != "new") {
+                continue;
+            }
+            let dir = new.with_extension("");
+            match dir.with_extension("old").exists() {
+                true => self.rename(&new, &dir)?,
+                false => self.remove_dir(&new)?,
+            }
+        }
+        for entry in fs::read_dir(&self.root)? {
+            let old = entry?.path();
+            if old.extension().is_some_and(|ext| ext == "old") {
+                self.remove_dir(&old)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn sync_dir(path: &Path) -> io::Result<()> {
+    fs::File::open(path)?.sync_all()
+}
+
+fn shard_name(index: usize) -> String {
+    format!("{index}.shard")
+}
+
+fn encode_shard(name: &str, meta: &Metadata, shard: &Shard) -> io::Result<Bytes> {
+    shardfile::encode(name, meta, shard).ok_or_else(oversized)
+}
+
+impl ShardStore for Directory {
+    fn load(&self) -> io::Result<Vec<(String, Metadata, Vec<Shard>)>> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.root)? {
+            let dir = entry?.path();
+            if !dir.is_dir() || dir.extension().is_some() {
+                continue;
+            }
+            let Some((name, meta)) = fs::read(dir.join(META))
+                .ok()
+                .and_then(|data| decode_meta(&data))
+            else {
+                warn!(path = %dir.display(), "skipping store entry without readable metadata");
+                continue;
+            };
+
+            let mut shards = Vec::new();
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.extension().is_none_or(|ext| ext != "shard") {
+                    continue;
+                }
+                match shardfile::open(&path) {
+                    Ok((other, stored, shard)) if other == name && stored == meta => {
+                        shards.push(shard)
+                    }
+                    _ => {
+                        warn!(path = %path.display(), "quarantining malformed shard file");
+                        self.rename(&path, &path.with_extension("corrupt"))?;
+                    }
+                }
+            }
+            files.push((name, meta, shards));
+        }
+
+        Ok(files)
+    }
+
+    fn put(&self, name: &str, meta: &Metadata, shards: &[Shard]) -> io::Result<()> {
+        let dir = self.dir(name);
+        let current = fs::read(dir.join(META))
+            .ok()
+            .and_then(|data| decode_meta(&data));
+        if current.as_ref().map(|(_, meta)| meta) != Some(meta) {
+            return self.replace(name, meta, shards);
+        }
+
+        for shard in shards {
+            self.write(
+                &dir.join(shard_name(shard.index())),
+                &encode_shard(name, meta, shard)?,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn remove(&self, name: &str, indices: &[usize]) -> io::Result<()> {
+        let dir = self.dir(name);
+        for index in indices {
+            self.step()?;
+            missing_ok(fs::remove_file(dir.join(shard_name(*index))))?;
+        }
+        missing_ok(sync_dir(&dir))
+    }
+
+    // Moved aside first so a crash never leaves half the file; `recover` finishes it.
+    fn delete(&self, name: &str) -> io::Result<()> {
+        let dir = self.dir(name);
+        let old = dir.with_extension("old");
+        self.remove_dir(&old)?;
+        match self.rename(&dir, &old) {
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            res => res?,
+        }
+        self.remove_dir(&old)
+    }
+
+    // Kept in the file's directory, so a new version or a delete drops it with the shards.
+    fn put_download(&self, name: &str, arrived: &[usize]) -> io::Result<()> {
+        let dir = self.dir(name);
+        if !dir.join(META).exists() {
+            return Ok(());
+        }
+        self.write(&dir.join(DOWNLOAD), &encode_arrived(arrived))
+    }
+
+    fn remove_download(&self, name: &str) -> io::Result<()> {
+        let dir = self.dir(name);
+        self.step()?;
+        missing_ok(fs::remove_file(dir.join(DOWNLOAD)))?;
+        missing_ok(sync_dir(&dir))
+    }
+
+    fn downloads(&self) -> io::Result<Vec<(String, Vec<usize>)>> {
+        let mut downloads = Vec::new();
+        for entry in fs::read_dir(&self.root)? {
+            let dir = entry?.path();
+            if !dir.is_dir() || dir.extension().is_some() {
+                continue;
+            }
+            let Ok(bitmap) = fs::read(dir.join(DOWNLOAD)) else {
+                continue;
+            };
+            if let Some((name, _)) = fs::read(dir.join(META))
+                .ok()
+                .and_then(|data| decode_meta(&data))
+            {
+                downloads.push((name, decode_arrived(&bitmap)));
+            }
+        }
+        Ok(downloads)
+    }
+}
+
+#[cfg(feature = "redb")]
+pub use embedded::Redb;
+
+#[cfg(feature = "redb")]
+mod embedded {
+    use std::{io, path::Path};
+
+    use redb::{Database, ReadableTable, TableDefinition};
+
+    use tracing::warn;
+
+    use super::{ShardStore, decode_arrived, decode_meta, encode_arrived, encode_meta};
+    use crate::file::{File, Metadata, Shard};
+
+    const FILES: TableDefinition<&str, &[u8]> = TableDefinition::new("files");
+    const SHARDS: TableDefinition<(&str, u64), &[u8]> = TableDefinition::new("shards");
+    const DOWNLOADS: TableDefinition<&str, &[u8]> = TableDefinition::new("downloads");
+    // redb's own default.
+    const DEFAULT_CACHE: usize = 1024 * 1024 * 1024;
+
+    fn other(err: impl Into<redb::Error>) -> io::Error {
+        io::Error::other(err.into())
+    }
+
+    /// An embedded redb database: metadata and shards live in separate tables, and every
+    /// `put` is a single transaction, so a crash never leaves half a batch behind.
+    pub struct Redb {
+        db: Database,
+    }
+
+    impl Redb {
+        pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+            Self::open_with_cache(path, DEFAULT_CACHE)
+        }
+
+        /// Opens the database with a page cache of `bytes`; nodes holding millions of small
+        /// shards want it large enough for the interior pages of the shard table.
+        pub fn open_with_cache(path: impl AsRef<Path>, bytes: usize) -> io::Result<Self> {
+            let db = Database::builder()
+                .set_cache_size(bytes)
+                .create(path)
+                .map_err(other)?;
+            let txn = db.begin_write().map_err(other)?;
+            txn.open_table(FILES).map_err(other)?;
+            txn.open_table(SHARDS).map_err(other)?;
+            txn.open_table(DOWNLOADS).map_err(other)?;
+            txn.commit().map_err(other)?;
+            Ok(Self { db })
+        }
+
+        /// Gives the space of deleted and superseded shards back to the file system. It needs
+        /// the database to itself, so it runs before the store is handed to `Node::with_store`;
+        /// returns whether anything was reclaimed.
+        pub fn compact(&mut self) -> io::Result<bool> {
+            self.db.compact().map_err(other)
+        }
+    }
+
+    impl ShardStore for Redb {
+        fn load(&self) -> io::Result<Vec<(String, Metadata, Vec<Shard>)>> {
+            let txn = self.db.begin_read().map_err(other)?;
+            let files = txn.open_table(FILES).map_err(other)?;
+            let shards = txn.open_table(SHARDS).map_err(other)?;
+
+            let mut loaded = Vec::new();
+            let mut corrupt = Vec::new();
+            for entry in files.iter().map_err(other)? {
+                let (key, value) = entry.map_err(other)?;
+                let Some((name, meta)) = decode_meta(value.value()) else {
+                    warn!(
+                        file = key.value(),
+                        "skipping store entry with malformed metadata"
+                    );
+                    continue;
+                };
+                let empty = File::empty(meta.clone());
+                let mut held = Vec::new();
+                let mut bad = Vec::new();
+                for entry in shards
+                    .range((name.as_str(), 0)..=(name.as_str(), u64::MAX))
+                    .map_err(other)?
+                {
+                    let (key, value) = entry.map_err(other)?;
+                    let shard = Shard::new(key.value().1 as usize, value.value().to_vec());
+                    match empty.verify_shard(&shard) {
+                        true => held.push(shard),
+                        false => bad.push(shard.index()),
+                    }
+                }
+                if !bad.is_empty() {
+                    warn!(file = %name, shards = ?bad, "dropping malformed shards");
+                    corrupt.push((name.clone(), bad));
+                }
+                loaded.push((name, meta, held));
+            }
+            drop((files, shards, txn));
+
+            // Repair fetches these back; until then they would only fail verification again.
+            for (name, indices) in corrupt {
+                self.remove(&name, &indices)?;
+            }
+            Ok(loaded)
+        }
+
+        fn put(&self, name: &str, meta: &Metadata, shards: &[Shard]) -> io::Result<()> {
+            let txn = self.db.begin_write().map_err(other)?;
+            {
+                let mut files = txn.open_table(FILES).map_err(other)?;
+                let mut table = txn.open_table(SHARDS).map_err(other)?;
+                let current = files
+                    .get(name)
+                    .map_err(other)?
+                    .and_then(|value| decode_meta(value.value()));
+                if current.as_ref().map(|(_, meta)| meta) != Some(meta) {
+                    table
+                        .retain_in((name, 0)..=(name, u64::MAX), |_, _| false)
+                        .map_err(other)?;
+                    txn.open_table(DOWNLOADS)
+                        .map_err(other)?
+                        .remove(name)
+                        .map_err(other)?;
+                    files
+                        .insert(name, encode_meta(name, meta)?.as_ref())
+                        .map_err(other)?;
+                }
+
+                for shard in shards {
+                    table
+                        .insert((name, shard.index() as u64), shard.data())
+                        .map_err(other)?;
+                }
+            }
+            txn.commit().map_err(other)
+        }
+
+        fn remove(&self, name: &str, indices: &[usize]) -> io::Result<()> {
+            let txn = self.db.begin_write().map_err(other)?;
+            {
+                let mut table = txn.open_table(SHARDS).map_err(other)?;
+                for index in indices {
+                    table.remove((name, *index as u64)).map_err(other)?;
+                }
+            }
+            txn.commit().map_err(other)
+        }
+
+        fn delete(&self, name: &str) -> io::Result<()> {
+            let txn = self.db.begin_write().map_err(other)?;
+            {
+                let mut files = txn.open_table(FILES).map_err(other)?;
+                let mut table = txn.open_table(SHARDS).map_err(other)?;
+                let mut downloads = txn.open_table(DOWNLOADS).map_err(other)?;
+                files.remove(name).map_err(other)?;
+                table
+                    .retain_in((name, 0)..=(name, u64::MAX), |_, _| false)
+                    .map_err(other)?;
+                downloads.remove(name).map_err(other)?;
+            }
+            txn.commit().map_err(other)
+        }
+
+        fn put_download(&self, name: &str, arrived: &[usize]) -> io::Result<()> {
+            let txn = self.db.begin_write().map_err(other)?;
+            {
+                let files = txn.open_table(FILES).map_err(other)?;
+                if files.get(name).map_err(other)?.is_some() {
+                    txn.open_table(DOWNLOADS)
+                        .map_err(other)?
+                        .insert(name, encode_arrived(arrived).as_slice())
+                        .map_err(other)?;
+                }
+            }
+            txn.commit().map_err(other)
+        }
+
+        fn remove_download(&self, name: &str) -> io::Result<()> {
+            let txn = self.db.begin_write().map_err(other)?;
+            txn.open_table(DOWNLOADS)
+                .map_err(other)?
+                .remove(name)
+                .map_err(other)?;
+            txn.commit().map_err(other)
+        }
+
+        fn downloads(&self) -> io::Result<Vec<(String, Vec<usize>)>> {
+            let txn = self.db.begin_read().map_err(other)?;
+            let downloads = txn.open_table(DOWNLOADS).map_err(other)?;
+            let mut listed = Vec::new();
+            for entry in downloads.iter().map_err(other)? {
+                let (key, value) = entry.map_err(other)?;
+                listed.push((key.value().to_string(), decode_arrived(value.value())));
+            }
+            Ok(listed)
+        }
+    }
+}
+
+#[cfg(feature = "rocksdb")]
+pub use rocks::{RocksDb, RocksDbConfig};
+
+#[cfg(feature = "rocksdb")]
+mod rocks {
+    use std::{io, path::Path};
+
+    use rocksdb::{
+        ColumnFamily, ColumnFamilyDescriptor, DB, DBCompactionStyle, Direction, IteratorMode,
+        Options, WriteBatch, WriteOptions,
+    };
+    use tracing::warn;
+
+    use super::{ShardStore, decode_arrived, decode_meta, encode_arrived, encode_meta};
+    use crate::file::{File, Metadata, Shard};
+
+    const FILES: &str = "files";
+    const SHARDS: &str = "shards";
+    const DOWNLOADS: &str = "downloads";
+
+    fn other(err: rocksdb::Error) -> io::Error {
+        io::Error::other(err)
+    }
+
+    // A shard is keyed by its file's name, length prefixed so one name is never a prefix of
+    // another's keys, then its index big endian so a file's shards sort together in order.
+    fn prefix(name: &str) -> Vec<u8> {
+        let mut key = Vec::with_capacity(4 + name.len() + 8);
+        key.extend_from_slice(&(name.len() as u32).to_be_bytes());
+        key.extend_from_slice(name.as_bytes());
+        key
+    }
+
+    fn shard_key(name: &str, index: usize) -> Vec<u8> {
+        let mut key = prefix(name);
+        key.extend_from_slice(&(index as u64).to_be_bytes());
+        key
+    }
+
+    // Sorts after every shard key of the file: one byte longer than any of them.
+    fn shards_end(name: &str) -> Vec<u8> {
+        let mut key = prefix(name);
+        key.extend_from_slice(&[0xff; 9]);
+        key
+    }
+
+    /// How the shard column family is compacted. The defaults suit a node holding many
+    /// shards of a few hundred KiB: levelled compaction sized per level from the data, so
+    /// space amplification stays near 1.1 as the store grows.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct RocksDbConfig {
+        pub style: DBCompactionStyle,
+        /// Memtable size before it is flushed to level 0.
+        pub write_buffer_size: usize,
+        /// Level 0 files that trigger a compaction into level 1.
+        pub level_zero_trigger: i32,
+        pub target_file_size: u64,
+        /// Size of level 1; every following level is ten times larger.
+        pub level_base: u64,
+        pub background_jobs: i32,
+    }
+
+    impl Default for RocksDbConfig {
+        fn default() -> Self {
+            Self {
+                style: DBCompactionStyle::Level,
+                write_buffer_size: 64 << 20,
+                level_zero_trigger: 4,
+                target_file_size: 64 << 20,
+                level_base: 256 << 20,
+                background_jobs: 2,
+            }
+        }
+    }
+
+    /// A RocksDB database with metadata and shards in separate column families, so the many
+    /// small metadata entries are not compacted along with the shard data. Every `put`,
+    /// `remove` and `delete` is one synced write batch.
+    pub struct RocksDb {
+        db: DB,
+    }
+
+    impl RocksDb {
+        pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+            Self::open_with(path, RocksDbConfig::default())
+        }
+
+        pub fn open_with(path: impl AsRef<Path>, config: RocksDbConfig) -> io::Result<Self> {
+            let mut opts = Options::default();
+            opts.create_if_missing(true);
+            opts.create_missing_column_families(true);
+            opts.set_max_background_jobs(config.background_jobs);
+
+            let mut shards = Options::default();
+            shards.set_compaction_style(config.style);
+            shards.set_write_buffer_size(config.write_buffer_size);
+            shards.set_level_zero_file_num_compaction_trigger(config.level_zero_trigger);
+            shards.set_target_file_size_base(config.target_file_size);
+            shards.set_max_bytes_for_level_base(config.level_base);
+            shards.set_level_compaction_dynamic_level_bytes(true);
+
+            let cfs = [
+                ColumnFamilyDescriptor::new(FILES, Options::default()),
+                ColumnFamilyDescriptor::new(SHARDS, shards),
+                ColumnFamilyDescriptor::new(DOWNLOADS, Options::default()),
+            ];
+            let db = DB::open_cf_descriptors(&opts, path, cfs).map_err(other)?;
+            Ok(Self { db })
+        }
+
+        /// Compacts every column family down, dropping the space of removed shards.
+        pub fn compact(&self) {
+            for cf in [self.files(), self.shards(), self.downloads_cf()] {
+                self.db.compact_range_cf(cf, None::<&[u8]>, None::<&[u8]>);
+            }
+        }
+
+        fn files(&self) -> &ColumnFamily {
+            self.db.cf_handle(FILES).expect("created on open")
+        }
+
+        fn shards(&self) -> &ColumnFamily {
+            self.db.cf_handle(SHARDS).expect("created on open")
+        }
+
+        fn downloads_cf(&self) -> &ColumnFamily {
+            self.db.cf_handle(DOWNLOADS).expect("created on open")
+        }
+
+        fn write(&self, batch: WriteBatch) -> io::Result<()> {
+            let mut opts = WriteOptions::default();
+            opts.set_sync(true);
+            self.db.write_opt(batch, &opts).map_err(other)
+        }
+    }
+
+    impl ShardStore for RocksDb {
+        fn load(&self) -> io::Result<Vec<(String, Metadata, Vec<Shard>)>> {
+            let mut loaded = Vec::new();
+            let mut corrupt = WriteBatch::default();
+            for entry in self.db.iterator_cf(self.files(), IteratorMode::Start) {
+                let (key, value) = entry.map_err(other)?;
+                let Some((name, meta)) = decode_meta(&value) else {
+                    warn!(file = %String::from_utf8_lossy(&key), "skipping store entry with malformed metadata");
+                    continue;
+                };
+
+                let empty = File::empty(meta.clone());
+                let start = prefix(&name);
+                let mut held = Vec::new();
+                let mode = IteratorMode::From(start.as_slice(), Direction::Forward);
+                for entry in self.db.iterator_cf(self.shards(), mode) {
+                    let (key, value) = entry.map_err(other)?;
+                    let Some(index) = key
+                        .strip_prefix(start.as_slice())
+                        .and_then(|index| <[u8; 8]>::try_from(index).ok())
+                    else {
+                        break;
+                    };
+                    let shard = Shard::new(u64::from_be_bytes(index) as usize, value.to_vec());
+                    match empty.verify_shard(&shard) {
+                        true => held.push(shard),
+                        false => {
+                            warn!(file = %name, index = shard.index(), "dropping malformed shard");
+                            corrupt.delete_cf(self.shards(), &key);
+                        }
+                    }
+                }
+                loaded.push((name, meta, held));
+            }
+
+            // Repair fetches these back; until then they would only fail verification again.
+            if !corrupt.is_empty() {
+                self.write(corrupt)?;
+            }
+            Ok(loaded)
+        }
+
+        fn put(&self, name: &str, meta: &Metadata, shards: &[Shard]) -> io::Result<()> {
+            let current = self
+                .db
+                .get_cf(self.files(), name)
+                .map_err(other)?
+                .and_then(|value| decode_meta(&value));
+
+            let mut batch = WriteBatch::default();
+            if current.as_ref().map(|(_, meta)| meta) != Some(meta) {
+                batch.delete_range_cf(self.shards(), prefix(name), shards_end(name));
+                batch.delete_cf(self.downloads_cf(), name);
+                batch.put_cf(self.files(), name, encode_meta(name, meta)?);
+            }
+            for shard in shards {
+                batch.put_cf(self.shards(), shard_key(name, shard.index()), shard.data());
+            }
+            self.write(batch)
+        }
+
+        fn remove(&self, name: &str, indices: &[usize]) -> io::Result<()> {
+            let mut batch = WriteBatch::default();
+            for index in indices {
+                batch.delete_cf(self.shards(), shard_key(name, *index));
+            }
+            self.write(batch)
+        }
+
+        fn delete(&self, name: &str) -> io::Result<()> {
+            let mut batch = WriteBatch::default();
+            batch.delete_cf(self.files(), name);
+            batch.delete_range_cf(self.shards(), prefix(name), shards_end(name));
+            batch.delete_cf(self.downloads_cf(), name);
+            self.write(batch)
+        }
+
+        fn put_download(&self, name: &str, arrived: &[usize]) -> io::Result<()> {
+            if self.db.get_cf(self.files(), name).map_err(other)?.is_none() {
+                return Ok(());
+            }
+            let mut batch = WriteBatch::default();
+            batch.put_cf(self.downloads_cf(), name, encode_arrived(arrived));
+            self.write(batch)
+        }
+
+        fn remove_download(&self, name: &str) -> io::Result<()> {
+            let mut batch = WriteBatch::default();
+            batch.delete_cf(self.downloads_cf(), name);
+            self.write(batch)
+        }
+
+        fn downloads(&self) -> io::Result<Vec<(String, Vec<usize>)>> {
+            let mut listed = Vec::new();
+            for entry in self
+                .db
+                .iterator_cf(self.downloads_cf(), IteratorMode::Start)
+            {
+                let (key, value) = entry.map_err(other)?;
+                listed.push((
+                    String::from_utf8_lossy(&key).into_owned(),
+                    decode_arrived(&value),
+                ));
+            }
+            Ok(listed)
+        }
+    }
+}
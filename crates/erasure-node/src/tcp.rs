@@ -0,0 +1,546 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    sync::{
+        Arc, Mutex, Weak,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use bytes::BytesMut;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{
+        TcpListener, TcpStream, ToSocketAddrs,
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+    },
+    sync::{self, mpsc},
+    task::JoinHandle,
+};
+use tracing::{debug, warn};
+
+use crate::{
+    codec,
+    metrics::{self, MetricsSink},
+    network::{Command, Network},
+};
+
+// An empty frame: written to idle connections to find out whether they still work, and
+// skipped by readers.
+const HEARTBEAT: [u8; 4] = [0; 4];
+// Longest id a peer may introduce itself with.
+const MAX_HELLO: usize = 1024;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct TcpConfig {
+    /// The id peers reach this node by; the bound address when None, which only works when
+    /// bound to a routable address rather than a wildcard one.
+    pub advertise: Option<String>,
+    pub connect_timeout: Duration,
+    /// Idle connections are written an empty frame this often, and broken ones redialed.
+    pub heartbeat: Duration,
+    /// Wait after the first failed dial to a peer; it doubles with every further failure
+    /// up to `max_backoff`, and commands for the peer are dropped while it lasts.
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    /// Largest frame read from a peer; one claiming more closes the connection.
+    pub max_frame: usize,
+    /// Received commands buffered before readers stop reading from their connections.
+    pub queue: usize,
+    /// Region of each node by id, this one's included, for `Network::region`.
+    pub regions: HashMap<String, String>,
+}
+
+impl Default for TcpConfig {
+    fn default() -> Self {
+        Self {
+            advertise: None,
+            connect_timeout: Duration::from_secs(2),
+            heartbeat: Duration::from_secs(5),
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(30),
+            max_frame: 64 << 20,
+            queue: 1024,
+            regions: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TcpStats {
+    /// Pooled connections to peers that are currently open.
+    pub connections: usize,
+    /// Connections from peers that are currently open.
+    pub inbound: usize,
+    pub dials: usize,
+    pub dial_failures: usize,
+    /// Dials to a peer that was connected before.
+    pub reconnects: usize,
+    /// Connections found dead by a failed write or the peer closing them.
+    pub broken: usize,
+    /// Commands dropped because their peer could not be reached.
+    pub dropped: usize,
+    pub heartbeats: usize,
+}
+
+#[derive(Default)]
+struct Counters {
+    connections: AtomicUsize,
+    inbound: AtomicUsize,
+    dials: AtomicUsize,
+    dial_failures: AtomicUsize,
+    reconnects: AtomicUsize,
+    broken: AtomicUsize,
+    dropped: AtomicUsize,
+    heartbeats: AtomicUsize,
+}
+
+impl Counters {
+    fn add(counter: &AtomicUsize) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> TcpStats {
+        TcpStats {
+            connections: self.connections.load(Ordering::Relaxed),
+            inbound: self.inbound.load(Ordering::Relaxed),
+            dials: self.dials.load(Ordering::Relaxed),
+            dial_failures: self.dial_failures.load(Ordering::Relaxed),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+            broken: self.broken.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            heartbeats: self.heartbeats.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct Conn {
+    writer: OwnedWriteHalf,
+    // Cleared by the watcher once the peer closes the connection.
+    alive: Arc<AtomicBool>,
+    used: Instant,
+    watcher: JoinHandle<()>,
+    counters: Arc<Counters>,
+}
+
+impl Drop for Conn {
+    fn drop(&mut self) {
+        self.watcher.abort();
+        self.counters.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Default)]
+struct Backoff {
+    failures: u32,
+    retry_at: Option<Instant>,
+    connected: bool,
+}
+
+#[derive(Default)]
+struct Slot {
+    // Held across writes, so frames to one peer never interleave.
+    conn: sync::Mutex<Option<Conn>>,
+    backoff: Mutex<Backoff>,
+}
+
+// Persistent connections, one per peer. Connections only carry frames from the dialer, so
+// the read half of a pooled connection hearing anything, end of stream included, means the
+// peer has gone.
+struct Pool {
+    id: String,
+    config: TcpConfig,
+    peers: Mutex<Vec<String>>,
+    regions: Mutex<HashMap<String, String>>,
+    slots: Mutex<HashMap<String, Arc<Slot>>>,
+    counters: Arc<Counters>,
+}
+
+impl Pool {
+    fn slot(&self, peer: &str) -> Arc<Slot> {
+        let mut slots = self.slots.lock().unwrap();
+        Arc::clone(slots.entry(peer.to_string()).or_default())
+    }
+
+    async fn send(&self, peer: &str, frame: &[u8]) {
+        let slot = self.slot(peer);
+        let mut conn = slot.conn.lock().await;
+        // A write to a connection that broke since its last use fails, which costs one retry
+        // on a fresh connection.
+        for _ in 0..2 {
+            if conn
+                .as_ref()
+                .is_some_and(|conn| !conn.alive.load(Ordering::Relaxed))
+            {
+                Counters::add(&self.counters.broken);
+                *conn = None;
+            }
+            if conn.is_none() {
+                *conn = self.connect(peer, &slot).await;
+            }
+            let Some(open) = conn.as_mut() else {
+                Counters::add(&self.counters.dropped);
+                return;
+            };
+
+            match open.writer.write_all(frame).await {
+                Ok(()) => {
+                    open.used = Instant::now();
+                    return;
+                }
+                Err(err) => {
+                    debug!(peer, %err, "connection broke");
+                    Counters::add(&self.counters.broken);
+                    *conn = None;
+                }
+            }
+        }
+        Counters::add(&self.counters.dropped);
+    }
+
+    async fn connect(&self, peer: &str, slot: &Slot) -> Option<Conn> {
+        {
+            let backoff = slot.backoff.lock().unwrap();
+            if backoff.retry_at.is_some_and(|at| Instant::now() < at) {
+                return None;
+            }
+        }
+
+        Counters::add(&self.counters.dials);
+        let dialed = tokio::time::timeout(self.config.connect_timeout, self.dial(peer))
+            .await
+            .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()));
+        let mut backoff = slot.backoff.lock().unwrap();
+        match dialed {
+            Ok((reader, writer)) => {
+                if backoff.connected {
+                    Counters::add(&self.counters.reconnects);
+                }
+                *backoff = Backoff {
+                    connected: true,
+                    ..Backoff::default()
+                };
+                Counters::add(&self.counters.connections);
+                let alive = Arc::new(AtomicBool::new(true));
+                Some(Conn {
+                    writer,
+                    alive: Arc::clone(&alive),
+                    used: Instant::now(),
+                    watcher: tokio::spawn(watch(reader, alive)),
+                    counters: Arc::clone(&self.counters),
+                })
+            }
+            Err(err) => {
+                Counters::add(&self.counters.dial_failures);
+                let wait = self
+                    .config
+                    .backoff
+                    .saturating_mul(1 << backoff.failures.min(16))
+                    .min(self.config.max_backoff);
+                backoff.failures += 1;
+                backoff.retry_at = Some(Instant::now() + wait);
+                debug!(peer, %err, ?wait, "dial failed, backing off");
+                None
+            }
+        }
+    }
+
+    async fn dial(&self, peer: &str) -> io::Result<(OwnedReadHalf, OwnedWriteHalf)> {
+        let stream = TcpStream::connect(peer).await?;
+        stream.set_nodelay(true)?;
+        let (reader, mut writer) = stream.into_split();
+
+        let mut hello = Vec::with_capacity(4 + self.id.len());
+        hello.extend_from_slice(&(self.id.len() as u32).to_be_bytes());
+        hello.extend_from_slice(self.id.as_bytes());
+        writer.write_all(&hello).await?;
+        Ok((reader, writer))
+    }
+
+    // Writes an empty frame to connections idle for a heartbeat and redials peers whose
+    // connection broke once their backoff is over. Connections to nodes outside the peer
+    // list, such as clients that replies went to, are dropped once they break instead.
+    async fn check(&self) {
+        let slots = self
+            .slots
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(peer, slot)| (peer.clone(), Arc::clone(slot)))
+            .collect::<Vec<_>>();
+
+        for (peer, slot) in slots {
+            // Busy connections are in use and need no check.
+            let Ok(mut conn) = slot.conn.try_lock() else {
+                continue;
+            };
+            match conn.as_mut() {
+                Some(open) if !open.alive.load(Ordering::Relaxed) => {
+                    Counters::add(&self.counters.broken);
+                    *conn = None;
+                }
+                Some(open) if open.used.elapsed() >= self.config.heartbeat => {
+                    Counters::add(&self.counters.heartbeats);
+                    match open.writer.write_all(&HEARTBEAT).await {
+                        Ok(()) => open.used = Instant::now(),
+                        Err(_) => {
+                            Counters::add(&self.counters.broken);
+                            *conn = None;
+                        }
+                    }
+                }
+                _ => {}
+            }
+
+            if conn.is_some() {
+                continue;
+            }
+            if !self.peers.lock().unwrap().contains(&peer) {
+                drop(conn);
+                self.slots.lock().unwrap().remove(&peer);
+            } else if slot.backoff.lock().unwrap().connected {
+                *conn = self.connect(&peer, &slot).await;
+            }
+        }
+    }
+}
+
+async fn watch(mut reader: OwnedReadHalf, alive: Arc<AtomicBool>) {
+    let mut buf = [0; 1];
+    let _ = reader.read(&mut buf).await;
+    alive.store(false, Ordering::Relaxed);
+}
+
+async fn heartbeat(pool: Weak<Pool>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        let Some(pool) = pool.upgrade() else {
+            return;
+        };
+        pool.check().await;
+    }
+}
+
+async fn accept(
+    listener: TcpListener,
+    inbox: mpsc::Sender<(String, Command)>,
+    config: TcpConfig,
+    counters: Arc<Counters>,
+) {
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                warn!(%err, "accept failed");
+                continue;
+            }
+        };
+        let (inbox, counters) = (inbox.clone(), Arc::clone(&counters));
+        let max_frame = config.max_frame;
+        tokio::spawn(async move {
+            counters.inbound.fetch_add(1, Ordering::Relaxed);
+            if let Err(err) = read(stream, inbox, max_frame).await {
+                debug!(%addr, %err, "inbound connection closed");
+            }
+            counters.inbound.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+}
+
+fn malformed(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, what.to_string())
+}
+
+async fn read(
+    mut stream: TcpStream,
+    inbox: mpsc::Sender<(String, Command)>,
+    max_frame: usize,
+) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let len = stream.read_u32().await? as usize;
+    if len > MAX_HELLO {
+        return Err(malformed("hello too long"));
+    }
+    let mut id = vec![0; len];
+    stream.read_exact(&mut id).await?;
+    let peer = String::from_utf8(id).map_err(|_| malformed("peer id not utf-8"))?;
+
+    let mut buf = BytesMut::with_capacity(64 * 1024);
+    loop {
+        while let Some(frame) = codec::split(&mut buf) {
+            if frame.len() == HEARTBEAT.len() {
+                continue;
+            }
+            let cmd = Command::from_bytes(frame).map_err(|_| malformed("malformed frame"))?;
+            if inbox.send((peer.clone(), cmd)).await.is_err() {
+                return Ok(());
+            }
+        }
+
+        if let Some(len) = buf.get(..HEARTBEAT.len()) {
+            let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+            if len > max_frame {
+                return Err(malformed("frame too large"));
+            }
+        }
+        // Dropping the network closes its inbound connections too.
+        tokio::select! {
+            read = stream.read_buf(&mut buf) => if read? == 0 {
+                return Ok(());
+            },
+            () = inbox.closed() => return Ok(()),
+        }
+    }
+}
+
+/// A `Network` over TCP. Peers are known by the address they listen on, which every
+/// connection opens with; each node dials its own connection to every peer it sends to and
+/// keeps it open, so replies travel over the connection the other side dialed. Nothing
+/// authenticates that opening id, so the listener belongs on a trusted network.
+pub struct TcpNetwork {
+    id: String,
+    addr: SocketAddr,
+    pool: Arc<Pool>,
+    inbox: sync::Mutex<mpsc::Receiver<(String, Command)>>,
+    reported: Mutex<TcpStats>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl TcpNetwork {
+    pub async fn bind(addr: impl ToSocketAddrs, peers: Vec<String>) -> io::Result<Self> {
+        Self::bind_with(addr, peers, TcpConfig::default()).await
+    }
+
+    /// Listens on `addr` and treats `peers` as the cluster; must run inside a tokio runtime,
+    /// which the listener and the connection checks are spawned on.
+    pub async fn bind_with(
+        addr: impl ToSocketAddrs,
+        peers: Vec<String>,
+        config: TcpConfig,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        let id = config.advertise.clone().unwrap_or_else(|| addr.to_string());
+
+        let counters = Arc::new(Counters::default());
+        let pool = Arc::new(Pool {
+            id: id.clone(),
+            config: config.clone(),
+            peers: Mutex::new(peers),
+            regions: Mutex::new(config.regions.clone()),
+            slots: Mutex::new(HashMap::new()),
+            counters: Arc::clone(&counters),
+        });
+        let (sender, inbox) = mpsc::channel(config.queue.max(1));
+        let tasks = vec![
+            tokio::spawn(accept(listener, sender, config.clone(), counters)),
+            tokio::spawn(heartbeat(Arc::downgrade(&pool), config.heartbeat)),
+        ];
+
+        Ok(Self {
+            id,
+            addr,
+            pool,
+            inbox: sync::Mutex::new(inbox),
+            reported: Mutex::new(TcpStats::default()),
+            tasks,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Replaces the peers `discover` returns. Connections to peers no longer listed close.
+    pub fn set_peers(&self, peers: Vec<String>) {
+        self.pool
+            .slots
+            .lock()
+            .unwrap()
+            .retain(|peer, _| peers.contains(peer));
+        *self.pool.peers.lock().unwrap() = peers;
+    }
+
+    /// Replaces `TcpConfig::regions`.
+    pub fn set_regions(&self, regions: HashMap<String, String>) {
+        *self.pool.regions.lock().unwrap() = regions;
+    }
+
+    pub fn stats(&self) -> TcpStats {
+        self.pool.counters.snapshot()
+    }
+}
+
+impl Drop for TcpNetwork {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+impl Network for TcpNetwork {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn region(&self, peer: &str) -> Option<String> {
+        self.pool.regions.lock().unwrap().get(peer).cloned()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+
+    async fn discover(&self) -> Vec<String> {
+        let peers = self.pool.peers.lock().unwrap();
+        peers
+            .iter()
+            .filter(|peer| **peer != self.id)
+            .cloned()
+            .collect()
+    }
+
+    async fn send(&self, peer: String, command: Command) {
+        match command.to_bytes() {
+            Ok(frame) => self.pool.send(&peer, &frame).await,
+            Err(err) => warn!(peer, %err, "dropping command"),
+        }
+    }
+
+    async fn recv(&self) -> Option<(String, Command)> {
+        self.inbox.lock().await.recv().await
+    }
+
+    fn report(&self, sink: &dyn MetricsSink) {
+        let stats = self.stats();
+        let mut reported = self.reported.lock().unwrap();
+        let delta = |now: usize, then: usize| now.saturating_sub(then) as u64;
+
+        sink.gauge(metrics::TCP_CONNECTIONS, stats.connections as f64);
+        sink.gauge(metrics::TCP_INBOUND, stats.inbound as f64);
+        sink.counter(metrics::TCP_DIALS, delta(stats.dials, reported.dials));
+        sink.counter(
+            metrics::TCP_DIAL_FAILURES,
+            delta(stats.dial_failures, reported.dial_failures),
+        );
+        sink.counter(
+            metrics::TCP_RECONNECTS,
+            delta(stats.reconnects, reported.reconnects),
+        );
+        sink.counter(metrics::TCP_BROKEN, delta(stats.broken, reported.broken));
+        sink.counter(metrics::TCP_DROPPED, delta(stats.dropped, reported.dropped));
+        sink.counter(
+            metrics::TCP_HEARTBEATS,
+            delta(stats.heartbeats, reported.heartbeats),
+        );
+        *reported = stats;
+    }
+}
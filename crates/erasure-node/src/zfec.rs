@@ -0,0 +1,286 @@
+use reed_solomon_erasure::galois_8;
+
+use crate::file::File;
+
+pub const CHUNK_SIZE: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub m: usize,
+    pub k: usize,
+    pub pad: usize,
+    pub shnum: usize,
+}
+
+impl Header {
+    pub fn encode(&self) -> Vec<u8> {
+        let kbits = log_ceil(self.m);
+        let padbits = log_ceil(self.k);
+        let bits = 8 + kbits + padbits + kbits;
+        let bytes = header_len(bits);
+
+        let mut value = (self.m - 1) as u64;
+        value = (value << kbits) | (self.k - 1) as u64;
+        value = (value << padbits) | self.pad as u64;
+        value = (value << kbits) | self.shnum as u64;
+        value <<= bytes * 8 - bits;
+
+        value.to_be_bytes()[8 - bytes..].to_vec()
+    }
+
+    pub fn decode(data: &[u8]) -> Option<(Self, usize)> {
+        let m = *data.first()? as usize + 1;
+        let kbits = log_ceil(m);
+        let bits = 8 + kbits + kbits;
+
+        let k = read_bits(data, 8, kbits)? + 1;
+        let padbits = log_ceil(k);
+        let bytes = header_len(bits + padbits);
+
+        let pad = read_bits(data, 8 + kbits, padbits)?;
+        let shnum = read_bits(data, 8 + kbits + padbits, kbits)?;
+        if data.len() < bytes || k > m || pad >= k.max(1) || shnum >= m {
+            return None;
+        }
+
+        Some((Self { m, k, pad, shnum }, bytes))
+    }
+}
+
+fn log_ceil(n: usize) -> usize {
+    let (mut power, mut bits) = (1, 0);
+    while power < n {
+        power *= 2;
+        bits += 1;
+    }
+    bits
+}
+
+fn header_len(bits: usize) -> usize {
+    match bits {
+        0..=16 => 2,
+        17..=24 => 3,
+        _ => 4,
+    }
+}
+
+fn read_bits(data: &[u8], offset: usize, len: usize) -> Option<usize> {
+    (offset..offset + len).try_fold(0, |value, bit| {
+        let byte = *data.get(bit / 8)?;
+        Some((value << 1) | ((byte >> (7 - bit % 8)) & 1) as usize)
+    })
+}
+
+pub struct Zfec {
+    k: usize,
+    m: usize,
+    matrix: Vec<u8>,
+}
+
+impl Zfec {
+    pub fn new(k: usize, m: usize) -> Option<Self> {
+        if k == 0 || k > m || m > 256 {
+            return None;
+        }
+
+        let mut vandermonde = vec![0; m * k];
+        vandermonde[0] = 1;
+        for row in 0..m - 1 {
+            for col in 0..k {
+                vandermonde[(row + 1) * k + col] = galois_8::exp(2, (row * col) % 255);
+            }
+        }
+
+        let top = invert(&vandermonde[..k * k], k)?;
+        let mut matrix = vec![0; m * k];
+        for index in 0..k {
+            matrix[index * k + index] = 1;
+        }
+        for row in k..m {
+            for col in 0..k {
+                matrix[row * k + col] = (0..k).fold(0, |sum, index| {
+                    sum ^ galois_8::mul(vandermonde[row * k + index], top[index * k + col])
+                });
+            }
+        }
+
+        Some(Self { k, m, matrix })
+    }
+
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    pub fn m(&self) -> usize {
+        self.m
+    }
+
+    pub fn encode(&self, blocks: &[&[u8]]) -> Vec<Vec<u8>> {
+        let size = blocks.first().map_or(0, |block| block.len());
+        (self.k..self.m)
+            .map(|row| {
+                let mut out = vec![0; size];
+                for (col, block) in blocks.iter().enumerate() {
+                    galois_8::mul_slice_xor(self.matrix[row * self.k + col], block, &mut out);
+                }
+                out
+            })
+            .collect()
+    }
+
+    pub fn decode(&self, shares: &[(usize, &[u8])]) -> Option<Vec<Vec<u8>>> {
+        let mut chosen = Vec::with_capacity(self.k);
+        for (shnum, data) in shares {
+            if *shnum < self.m && !chosen.iter().any(|(other, _)| other == shnum) {
+                chosen.push((*shnum, *data));
+            }
+        }
+        chosen.sort_by_key(|(shnum, _)| *shnum);
+        chosen.truncate(self.k);
+
+        let size = chosen.first()?.1.len();
+        if chosen.len() < self.k || chosen.iter().any(|(_, data)| data.len() != size) {
+            return None;
+        }
+
+        let rows = chosen
+            .iter()
+            .flat_map(|(shnum, _)| &self.matrix[shnum * self.k..(shnum + 1) * self.k])
+            .copied()
+            .collect::<Vec<_>>();
+        let inverse = invert(&rows, self.k)?;
+
+        let blocks = (0..self.k)
+            .map(|row| {
+                let mut out = vec![0; size];
+                for (col, (_, data)) in chosen.iter().enumerate() {
+                    galois_8::mul_slice_xor(inverse[row * self.k + col], data, &mut out);
+                }
+                out
+            })
+            .collect();
+
+        Some(blocks)
+    }
+}
+
+fn invert(matrix: &[u8], n: usize) -> Option<Vec<u8>> {
+    let mut work = matrix.to_vec();
+    let mut inverse = vec![0; n * n];
+    for index in 0..n {
+        inverse[index * n + index] = 1;
+    }
+
+    for col in 0..n {
+        let pivot = (col..n).find(|row| work[row * n + col] != 0)?;
+        for index in 0..n {
+            work.swap(col * n + index, pivot * n + index);
+            inverse.swap(col * n + index, pivot * n + index);
+        }
+
+        let scale = galois_8::div(1, work[col * n + col]);
+        for index in 0..n {
+            work[col * n + index] = galois_8::mul(work[col * n + index], scale);
+            inverse[col * n + index] = galois_8::mul(inverse[col * n + index], scale);
+        }
+
+        for row in (0..n).filter(|row| *row != col) {
+            let factor = work[row * n + col];
+            if factor == 0 {
+                continue;
+            }
+            for index in 0..n {
+                work[row * n + index] ^= galois_8::mul(factor, work[col * n + index]);
+                inverse[row * n + index] ^= galois_8::mul(factor, inverse[col * n + index]);
+            }
+        }
+    }
+
+    Some(inverse)
+}
+
+pub fn file_name(prefix: &str, shnum: usize, m: usize) -> String {
+    let width = m.to_string().len();
+    format!("{prefix}.{shnum:0width$}_{m:0width$}.fec")
+}
+
+pub fn encode(content: &[u8], k: usize, m: usize) -> Option<Vec<Vec<u8>>> {
+    let zfec = Zfec::new(k, m)?;
+    let pad = (k - content.len() % k) % k;
+
+    let mut shares = (0..m)
+        .map(|shnum| Header { m, k, pad, shnum }.encode())
+        .collect::<Vec<_>>();
+
+    for chunk in content.chunks(k * CHUNK_SIZE) {
+        let size = chunk.len().div_ceil(k);
+        let blocks = (0..k)
+            .map(|index| {
+                let start = (index * size).min(chunk.len());
+                let end = ((index + 1) * size).min(chunk.len());
+                let mut block = chunk[start..end].to_vec();
+                block.resize(size, 0);
+                block
+            })
+            .collect::<Vec<_>>();
+
+        let refs = blocks.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        let parity = zfec.encode(&refs);
+        for (share, block) in shares.iter_mut().zip(blocks.iter().chain(&parity)) {
+            share.extend_from_slice(block);
+        }
+    }
+
+    Some(shares)
+}
+
+pub fn decode<S: AsRef<[u8]>>(shares: &[S]) -> Option<Vec<u8>> {
+    let parsed = shares
+        .iter()
+        .map(|share| {
+            let share = share.as_ref();
+            let (header, len) = Header::decode(share)?;
+            Some((header, &share[len..]))
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    let (first, body) = parsed.first()?;
+    let size = body.len();
+    if parsed.iter().any(|(header, body)| {
+        (header.m, header.k, header.pad) != (first.m, first.k, first.pad) || body.len() != size
+    }) {
+        return None;
+    }
+
+    let zfec = Zfec::new(first.k, first.m)?;
+    let mut content = Vec::with_capacity(size * first.k);
+    for offset in (0..size).step_by(CHUNK_SIZE) {
+        let end = (offset + CHUNK_SIZE).min(size);
+        let round = parsed
+            .iter()
+            .map(|(header, body)| (header.shnum, &body[offset..end]))
+            .collect::<Vec<_>>();
+
+        for block in zfec.decode(&round)? {
+            content.extend_from_slice(&block);
+        }
+    }
+
+    content.truncate(content.len().checked_sub(first.pad)?);
+    Some(content)
+}
+
+pub fn from_file(file: &File) -> Option<Vec<Vec<u8>>> {
+    let content = file.decode().ok()?;
+    let meta = file.metadata();
+    encode(
+        &content,
+        meta.data_shards().max(1),
+        meta.shard_count().max(1),
+    )
+}
+
+pub fn to_file<S: AsRef<[u8]>>(shares: &[S]) -> Option<File> {
+    let (header, _) = Header::decode(shares.first()?.as_ref())?;
+    File::encode_with_parity(decode(shares)?, header.m - header.k).ok()
+}
@@ -1,5 +1,13 @@
 mod file {
-    use erasure_node::file::File;
+    use std::cmp::Ordering;
+
+    use erasure_node::{
+        Error,
+        file::{
+            self, CodecId, DecodeError, Decoder, EncodeConfig, Encoder, File, Shard, ShardError,
+            ShardIndex, VectorClock, Write,
+        },
+    };
 
     #[test]
     fn simple() {
@@ -8,7 +16,17 @@ mod file {
         file.shards_mut().delete(0);
         assert!(file.can_decode());
         let s2 = file.decode().unwrap();
-        assert_eq!(s1, s2);
+        assert_eq!(s1.as_bytes(), s2);
+    }
+
+    #[test]
+    fn shared() {
+        let file = File::encode("hello world!".repeat(100)).unwrap();
+        let copy = file.clone();
+        for shard in file.shards().present_iter() {
+            let again = copy.shards().get(shard.index()).unwrap();
+            assert_eq!(shard.data().as_ptr(), again.data().as_ptr());
+        }
     }
 
     #[test]
@@ -22,7 +40,17 @@ mod file {
         file.shards_mut().delete(20);
         assert!(file.can_decode());
         let s2 = file.decode().unwrap();
-        assert_eq!(s1, s2);
+        assert_eq!(s1.as_bytes(), s2);
+    }
+
+    #[test]
+    fn binary() {
+        let content = (0..=255u8).cycle().take(1000).collect::<Vec<_>>();
+        let mut file = File::encode(&content).unwrap();
+        assert!(file.holds(&content));
+        file.shards_mut().delete(0);
+        assert_eq!(file.decode().unwrap(), content);
+        assert!(matches!(file.decode_string(), Err(Error::Utf8(_))));
     }
 
     #[test]
@@ -32,246 +60,6109 @@ mod file {
         file.shards_mut().delete(0);
         file.shards_mut().delete(1);
         assert!(!file.can_decode());
-        assert!(file.decode().is_none());
+        assert_eq!(
+            file.decode(),
+            Err(DecodeError::Insufficient {
+                present: 0,
+                needed: 1
+            })
+        );
     }
-}
 
-mod node {
-    use std::{
-        collections::{HashMap, HashSet},
-        ops::Deref,
-        pin::pin,
-        sync::{
-            Arc, Mutex,
-            mpsc::{Receiver, Sender, channel},
-        },
-        task::{Context, Poll, Waker},
-    };
+    fn unchecked(file: &File) -> File {
+        let meta = file.metadata().clone().with_checksums(Vec::new());
+        let mut unchecked = File::empty(meta);
+        for shard in file.shards().present_iter() {
+            unchecked.shards_mut().merge(shard).unwrap();
+        }
+        unchecked
+    }
 
-    use erasure_node::{
-        network::{Command, Network},
-        node::Node,
-    };
+    #[test]
+    fn inconsistent() {
+        let s1 = "hello world!".repeat(3);
+        let mut file = unchecked(&File::encode(&s1).unwrap());
+        file.shards_mut().insert(vec![b'x'; 64], 0).unwrap();
+        assert!(file.can_decode());
 
-    struct TestNetworkBuilder {
-        inner: Arc<Mutex<TestNetworkBuilderInner>>,
+        // Without checksums decoding trusts the data shards; catching the bad one is up to `verify`.
+        // With a single parity shard it can tell the block disagrees but not which shard is bad.
+        assert_ne!(file.decode().unwrap(), s1.as_bytes());
+        assert_eq!(file.verify(), vec![0, 1]);
     }
 
-    struct TestNetworkBuilderInner {
-        id: usize,
-        senders: HashMap<usize, Sender<(usize, Command)>>,
-        receivers: HashMap<usize, Receiver<(usize, Command)>>,
-        disabled: HashSet<usize>,
+    #[test]
+    fn invalid() {
+        let s1 = "hello world!".repeat(3);
+        let mut file = unchecked(&File::encode(&s1).unwrap());
+        file.shards_mut().insert(vec![0xff; 64], 0).unwrap();
+        file.shards_mut().delete(1);
+        assert!(file.can_decode());
+        assert!(file.decode().is_ok());
+        assert!(matches!(file.decode_string(), Err(Error::Utf8(_))));
     }
 
-    impl TestNetworkBuilder {
-        fn new() -> Self {
-            Self {
-                inner: Arc::new(Mutex::new(TestNetworkBuilderInner {
-                    id: 0,
-                    senders: HashMap::new(),
-                    receivers: HashMap::new(),
-                    disabled: HashSet::new(),
-                })),
-            }
-        }
+    #[test]
+    fn checksums() {
+        let s1 = "hello world!".repeat(10);
+        let mut file = File::encode(&s1).unwrap();
+        assert_eq!(file.metadata().checksums().len(), 4);
 
-        fn spawn(&self) -> TestNetwork {
-            let mut inner = self.inner.lock().unwrap();
-            let id = inner.id;
-            inner.id += 1;
+        let shard = file.shards().get(1).unwrap();
+        assert_eq!(shard.crc(), file.metadata().checksums()[1]);
 
-            let (sender, receiver) = channel();
-            inner.senders.insert(id, sender);
-            inner.receivers.insert(id, receiver);
+        file.shards_mut().insert(vec![b'x'; 64], 0).unwrap();
+        assert_eq!(file.shards().corrupt(), vec![0]);
+        assert_eq!(file.decode().unwrap(), s1.as_bytes());
 
-            TestNetwork {
-                id,
-                builder: self.inner.clone(),
-            }
-        }
+        file.shards_mut().delete(2);
+        file.shards_mut().delete(3);
+        assert!(matches!(
+            file.decode(),
+            Err(DecodeError::Insufficient { present: 1, .. })
+        ));
 
-        fn disable(&self, id: usize) {
-            self.inner.lock().unwrap().disabled.insert(id);
-        }
+        let bad = Shard::new(2, vec![b'x'; 64]);
+        assert!(!file.verify_shard(&bad));
+        assert_eq!(
+            file.shards_mut().merge(bad),
+            Err(ShardError::Corrupt(ShardIndex::new(2)))
+        );
+        assert_eq!(
+            file.shards_mut()
+                .merge(File::encode(&s1).unwrap().shards().get(2).unwrap()),
+            Ok(())
+        );
+        assert_eq!(file.decode().unwrap(), s1.as_bytes());
     }
 
-    struct TestNetwork {
-        id: usize,
-        builder: Arc<Mutex<TestNetworkBuilderInner>>,
+    #[test]
+    fn validated() {
+        let content = "hello world!".repeat(10);
+        let file = File::encode(&content).unwrap();
+        let mut empty = File::empty(file.metadata().clone());
+        let out = ShardIndex::new(9);
+        assert_eq!(
+            empty.shards_mut().merge(Shard::new(out, vec![0; 64])),
+            Err(ShardError::OutOfRange {
+                index: out,
+                count: 4
+            })
+        );
+        assert!(empty.shards_mut().insert(vec![0; 64], out).is_err());
+        assert!(!empty.verify_shard(&Shard::new(out, vec![0; 64])));
+        empty.shards_mut().delete(9);
+
+        let short = Shard::new(1, file.shards().get(1).unwrap().data()[..32].to_vec());
+        assert_eq!(
+            empty.shards_mut().merge(short),
+            Err(ShardError::WrongSize {
+                index: ShardIndex::new(1),
+                size: 32,
+                expected: 64
+            })
+        );
+
+        let shard = file.shards().get(1).unwrap();
+        assert_eq!(empty.shards_mut().merge(shard.clone()), Ok(()));
+        assert_eq!(
+            empty.shards_mut().merge(shard),
+            Err(ShardError::Present(ShardIndex::new(1)))
+        );
+        assert_eq!(empty.shards().present(), 1);
     }
 
-    impl Network for TestNetwork {
-        async fn discover(&self) -> Vec<String> {
-            self.builder
-                .lock()
-                .unwrap()
-                .senders
-                .keys()
-                .filter(|id| **id != self.id)
-                .map(|id| format!("{id}"))
-                .collect()
+    #[test]
+    fn edges() {
+        let empty = File::encode("").unwrap();
+        let meta = empty.metadata();
+        assert_eq!(
+            (meta.len(), meta.data_shards(), meta.parity_shards()),
+            (0, 1, 1)
+        );
+        assert_eq!(meta.shard_size(), file::DEFAULT_SHARD_SIZE);
+        assert_eq!(empty.decode_string(), Ok(String::new()));
+        assert_eq!(empty.decode_range(0, 0), Ok(Vec::new()));
+        assert_eq!(empty.decode_range(0, 1), Err(DecodeError::OutOfRange));
+        assert!(empty.holds(""));
+
+        let mut single = File::encode("x").unwrap();
+        assert_eq!(single.metadata().data_shards(), 1);
+        single.shards_mut().delete(0);
+        assert_eq!(single.decode_string(), Ok("x".to_string()));
+        assert_eq!(single.repair(), Ok(1));
+
+        for (len, data_shards) in [(63, 1), (64, 1), (65, 2), (128, 2), (129, 3)] {
+            let content = "y".repeat(len);
+            let mut file = File::encode(&content).unwrap();
+            assert_eq!(file.metadata().data_shards(), data_shards);
+            assert_eq!(file.metadata().shard_size(), file::DEFAULT_SHARD_SIZE);
+            file.shards_mut().delete(0);
+            assert_eq!(file.decode_string(), Ok(content));
         }
 
-        async fn send(&self, peer: String, cmd: Command) {
-            let id = peer.parse().unwrap();
-            let inner = self.builder.lock().unwrap();
-            if inner.disabled.contains(&id) {
-                return;
-            }
+        assert!(File::encode_with("", EncodeConfig::new(0, 0)).is_err());
+        assert!(File::encode_with("x", EncodeConfig::new(0, 2)).is_err());
+    }
 
-            // println!("{} > SENDING to {}: {:?}", self.id, peer, cmd);
-            inner.senders[&id].send((self.id, cmd)).unwrap();
+    #[test]
+    fn holds() {
+        let content = "hello world!".repeat(10);
+        let mut file = File::encode(&content).unwrap();
+        assert!(file.holds(&content));
+        assert!(!file.holds("hello world?".repeat(10)));
+        assert!(!file.holds(&content[1..]));
+
+        file.shards_mut().delete(0);
+        assert!(!file.holds(&content));
+    }
+
+    #[test]
+    fn bitmap() {
+        let mut file = File::encode("hello world!".repeat(10)).unwrap();
+        file.shards_mut().delete(1);
+        file.shards_mut().delete(3);
+
+        let bitmap = file.shards().bitmap();
+        assert_eq!(bitmap.len(), file.metadata().shard_count().div_ceil(8));
+        assert_eq!(
+            file::indices(&bitmap).into_vec(),
+            (0..file.metadata().shard_count())
+                .filter(|index| *index != 1 && *index != 3)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn metadata() {
+        let file = File::encode("hello world!".repeat(10)).unwrap();
+        let meta = file.metadata();
+        assert_eq!(meta.len(), 120);
+        assert_eq!(meta.shard_size(), 64);
+        assert_eq!(meta.stripes(), 1);
+        assert_eq!(meta.codec(), CodecId::ReedSolomon8);
+        assert_eq!(meta.data_shards(), 2);
+        assert_eq!(meta.shard_count(), 4);
+    }
+
+    #[test]
+    fn layout() {
+        let content = "hello world!".repeat(100);
+        let mut file = File::encode_with(&content, EncodeConfig::new(10, 4)).unwrap();
+        let meta = file.metadata();
+        assert_eq!((meta.data_shards(), meta.parity_shards()), (10, 4));
+        assert_eq!(meta.shard_size(), 128);
+
+        for index in [0, 3, 7, 12] {
+            file.shards_mut().delete(index);
         }
+        assert_eq!(file.decode().unwrap(), content.as_bytes());
 
-        async fn recv(&self) -> Option<(String, Command)> {
-            loop {
-                if let Some(res) = self.builder.lock().unwrap().receivers[&self.id]
-                    .try_recv()
-                    .map(|(id, cmd)| (format!("{id}"), cmd))
-                    .ok()
-                {
-                    // println!("{} > RECEIVED from {}: {:?}", self.id, &res.0, &res.1);
-                    return Some(res);
-                }
-            }
+        file.shards_mut().delete(13);
+        assert!(!file.can_decode());
+
+        assert!(File::encode_with(&content, EncodeConfig::new(0, 4)).is_err());
+    }
+
+    #[test]
+    fn wide() {
+        let content = (0..1000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let narrow = File::encode_with(&content, EncodeConfig::new(4, 252)).unwrap();
+        assert_eq!(narrow.metadata().codec(), CodecId::ReedSolomon8);
+
+        let mut file = File::encode_with(&content, EncodeConfig::new(4, 296)).unwrap();
+        let meta = file.metadata().clone();
+        assert_eq!((meta.shard_count(), meta.shard_size()), (300, 256));
+        assert_eq!(meta.codec(), CodecId::ReedSolomon16);
+
+        for index in (0..300).filter(|index| index % 50 != 7) {
+            file.shards_mut().delete(index);
         }
+        assert_eq!(file.shards().present(), 6);
+        assert_eq!(file.decode().unwrap(), content);
+
+        let config = EncodeConfig::new(4, 296).with_shard_size(251);
+        assert!(File::encode_with(&content, config).is_err());
     }
 
-    struct TestNode {
-        inner: Arc<Node<TestNetwork>>,
+    #[test]
+    fn shard_size() {
+        let content = "hello world!".repeat(100);
+        let config = EncodeConfig::new(3, 2).with_shard_size(512);
+        let mut file = File::encode_with(&content, config).unwrap();
+        let meta = file.metadata().clone();
+        assert_eq!(meta.shard_size(), 512);
+        assert_eq!(meta.encode_config(), config);
+        assert_eq!(file::data_shards(content.len(), 512), 3);
+
+        file.shards_mut().delete(1);
+        file.shards_mut().delete(4);
+        assert_eq!(file.decode().unwrap(), content.as_bytes());
+
+        let mut file = unchecked(&file);
+        assert_eq!(
+            file.shards_mut().insert(vec![0; 64], 1),
+            Err(ShardError::WrongSize {
+                index: ShardIndex::new(1),
+                size: 64,
+                expected: 512
+            })
+        );
+        assert!(!file.shards().contains(1));
+
+        let config = EncodeConfig::new(3, 2).with_shard_size(256);
+        let err = Error::Encode {
+            len: 1200,
+            data_shards: 3,
+            parity_shards: 2,
+            shard_size: 256,
+        };
+        assert_eq!(File::encode_with(&content, config), Err(err));
     }
 
-    impl TestNode {
-        fn new(network: TestNetwork) -> Self {
-            let inner = Arc::new(Node::new(network));
-            let inner_clone = Arc::clone(&inner);
-            std::thread::spawn(move || aw(inner_clone.run()));
-            Self { inner }
+    #[test]
+    fn parallel() {
+        let content = (0..300_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let config = EncodeConfig::new(3, 2);
+        let mut file = File::encode_with(&content, config).unwrap();
+        assert_eq!(file.metadata().shard_size(), 100_032);
+        assert!(file.verify().is_empty());
+
+        let streamed =
+            File::encode_from_reader(content.as_slice(), config.with_stripe_size(100_032)).unwrap();
+        assert_eq!(streamed.metadata().checksums(), file.metadata().checksums());
+
+        file.shards_mut().delete(0);
+        file.shards_mut().delete(2);
+        assert_eq!(file.decode().unwrap(), content);
+    }
+
+    #[test]
+    fn stream() {
+        let content = (0..1000).map(|i| i as u8).collect::<Vec<_>>();
+        let config = EncodeConfig::new(4, 2).with_stripe_size(64);
+        let mut file = File::encode_from_reader(content.as_slice(), config).unwrap();
+        let meta = file.metadata().clone();
+        assert_eq!((meta.stripes(), meta.shard_size()), (4, 256));
+        assert_eq!(meta.encode_config(), config);
+        assert!(file.holds(&content));
+
+        let again = File::encode_with(&content, meta.encode_config()).unwrap();
+        assert_eq!(again.metadata().checksums(), meta.checksums());
+
+        file.shards_mut().delete(0);
+        file.shards_mut().delete(5);
+        let mut out = Vec::new();
+        assert_eq!(file.decode_to_writer(&mut out), Ok(1000));
+        assert_eq!(out, content);
+        assert_eq!(file.decode().unwrap(), content);
+
+        let exact = File::encode_from_reader(&content[..512], config).unwrap();
+        assert_eq!(exact.metadata().stripes(), 2);
+        assert_eq!(exact.decode().unwrap(), &content[..512]);
+
+        let empty = File::encode_from_reader(&[][..], config).unwrap();
+        assert_eq!(empty.metadata().stripes(), 1);
+        assert_eq!(empty.decode().unwrap(), b"");
+
+        struct Broken;
+        impl std::io::Read for Broken {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::BrokenPipe.into())
+            }
         }
+        let err = File::encode_from_reader(Broken, config).unwrap_err();
+        assert_eq!(err, Error::Io(std::io::ErrorKind::BrokenPipe));
     }
 
-    impl Deref for TestNode {
-        type Target = Node<TestNetwork>;
+    #[test]
+    fn blocks() {
+        let content = (0..100_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let config = EncodeConfig::new(10, 4).with_block_size(2560);
+        let mut file = File::encode_with(&content, config).unwrap();
+        let meta = file.metadata().clone();
+        assert_eq!((meta.block_count(), meta.shard_count()), (40, 560));
+        assert_eq!(meta.shard_size(), 256);
+        assert_eq!(meta.blocks()[39], 100_000 - 39 * 2560);
+        assert_eq!(meta.encode_config(), config.with_shard_size(256));
+        assert!(file.holds(&content));
 
-        fn deref(&self) -> &Self::Target {
-            self.inner.deref()
+        for index in (0..560).step_by(4) {
+            file.shards_mut().delete(index);
         }
+        assert!(file.can_decode());
+        assert_eq!(file.decode().unwrap(), content);
+
+        for index in [1, 2, 3] {
+            file.shards_mut().delete(index);
+        }
+        assert!(!file.can_decode());
+        assert_eq!(
+            file.decode(),
+            Err(DecodeError::Insufficient {
+                present: 7,
+                needed: 10
+            })
+        );
+
+        let empty = File::encode_with("", config).unwrap();
+        assert_eq!(empty.metadata().block_count(), 1);
+        assert_eq!(empty.decode().unwrap(), b"");
+
+        assert!(File::encode_with(&content, config.with_shard_size(128)).is_err());
+        assert!(File::encode_with(&content, EncodeConfig::new(10, 4).with_block_size(0)).is_err());
     }
 
-    fn aw<F, T>(fut: F) -> T
-    where
-        F: Future<Output = T>,
-    {
-        let mut fut = pin!(fut);
-        loop {
-            if let Poll::Ready(res) = fut.as_mut().poll(&mut Context::from_waker(&Waker::noop())) {
-                return res;
+    #[test]
+    fn encoder() {
+        let content = (0..100_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let config = EncodeConfig::new(10, 4).with_block_size(2560);
+        let max_memory = 2 * 14 * 256;
+        let mut encoder = Encoder::new(config.with_max_memory(max_memory)).unwrap();
+        let (mut taken, mut written, mut peak) = (Vec::new(), 0, 0);
+        while written < content.len() {
+            match encoder.write(&content[written..]) {
+                Ok(n) => written += n,
+                Err(err) => {
+                    assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+                    taken.extend(encoder.next_block().unwrap());
+                }
             }
+            peak = peak.max(encoder.buffered());
+        }
+        assert!(peak <= max_memory);
+        assert_eq!(encoder.len(), 100_000);
+
+        let (meta, rest) = encoder.finish().unwrap();
+        taken.extend(rest);
+        let whole = File::encode_with(&content, config).unwrap();
+        assert_eq!(meta.checksums(), whole.metadata().checksums());
+        assert_eq!(meta.blocks(), whole.metadata().blocks());
+        assert_eq!(meta.encode_config(), whole.metadata().encode_config());
+
+        let mut file = File::empty(meta);
+        for shard in taken {
+            file.shards_mut().merge(shard).unwrap();
         }
+        assert_eq!(file.decode().unwrap(), content);
+
+        // Without a bound encoded blocks pile up until they are taken.
+        let mut encoder = Encoder::new(config).unwrap();
+        encoder.write_all(&content).unwrap();
+        assert_eq!(encoder.next_block().unwrap().len(), 14);
+        let (meta, rest) = encoder.finish().unwrap();
+        assert_eq!((meta.block_count(), rest.len()), (40, 39 * 14));
+
+        let (empty, shards) = Encoder::new(config).unwrap().finish().unwrap();
+        assert_eq!((empty.block_count(), shards.len()), (1, 14));
+        assert!(Encoder::new(EncodeConfig::new(10, 4).with_block_size(0)).is_err());
+        // Too small to hold a single encoded block.
+        assert!(Encoder::new(config.with_max_memory(14 * 256 - 1)).is_err());
     }
 
+    #[cfg(any(feature = "lz4", feature = "zstd"))]
     #[test]
-    fn simple() {
-        let builder = TestNetworkBuilder::new();
+    fn encoder_compressed() {
+        use erasure_node::compress::Compression;
 
-        let net = builder.spawn();
-        let n1 = TestNode::new(builder.spawn());
-        let n2 = TestNode::new(builder.spawn());
+        let content = "hello world! ".repeat(100);
+        let algorithms = [
+            #[cfg(feature = "lz4")]
+            Compression::Lz4,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd,
+        ];
+        for compression in algorithms {
+            let config = EncodeConfig::new(4, 2)
+                .with_block_size(256)
+                .with_compression(compression);
+            let mut encoder = Encoder::new(config).unwrap();
+            encoder.write_all(content.as_bytes()).unwrap();
+            let (meta, shards) = encoder.finish().unwrap();
+            assert_eq!(meta.compression(), compression);
+            assert!(shards.iter().all(|shard| shard.size() < meta.shard_size()));
 
-        assert_eq!(aw(net.discover()).len(), 2);
+            let mut file = File::empty(meta);
+            for shard in shards {
+                assert!(file.verify_shard(&shard));
+                file.shards_mut().merge(shard).unwrap();
+            }
+            assert_eq!(file.decode_string(), Ok(content.clone()));
+        }
+    }
+
+    #[test]
+    fn decoder() {
+        use std::io::{ErrorKind, Read};
 
-        aw(n1.upload("test".to_string(), "content".to_string()));
-        assert!(aw(n1.download("test".to_string())).is_some());
+        let content = (0..10_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let config = EncodeConfig::new(4, 2).with_block_size(1024);
+        let file = File::encode_with(&content, config).unwrap();
+        let meta = file.metadata().clone();
+        assert_eq!(meta.shard_size(), 256);
 
-        aw(n2.download("test".to_string()));
-        std::thread::sleep(std::time::Duration::from_millis(10));
+        // Shards arrive last block first, so most of them have to wait for room.
+        let max_memory = 2 * 4 * 256;
+        let mut decoder = Decoder::new(meta.clone(), Some(max_memory)).unwrap();
+        let mut queue = file.shards().present_iter().collect::<Vec<_>>();
+        queue.reverse();
+        let (mut decoded, mut peak, mut buf) = (Vec::new(), 0, [0; 100]);
+        while decoded.len() < content.len() {
+            let mut progressed = false;
+            queue.retain(|shard| match decoder.push(shard.clone()) {
+                Ok(()) => {
+                    progressed = true;
+                    false
+                }
+                Err(err) => {
+                    assert_eq!(err.kind(), ErrorKind::WouldBlock);
+                    true
+                }
+            });
+            peak = peak.max(decoder.buffered());
+            match decoder.read(&mut buf) {
+                Ok(read) => decoded.extend_from_slice(&buf[..read]),
+                Err(err) => {
+                    assert_eq!(err.kind(), ErrorKind::WouldBlock);
+                    assert!(progressed);
+                }
+            }
+        }
+        assert_eq!(decoded, content);
+        assert!(peak <= max_memory);
+        assert_eq!(decoder.read(&mut buf).unwrap(), 0);
 
-        assert!(aw(n2.download("test".to_string())).is_some());
+        assert!(Decoder::new(meta.clone(), Some(4 * 256 - 1)).is_err());
+        let mut decoder = Decoder::new(meta, None).unwrap();
+        let bad = Shard::new(0, vec![0; 256]);
+        assert_eq!(
+            decoder.push(bad).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
     }
 
     #[test]
-    fn big() {
-        let builder = TestNetworkBuilder::new();
-        let n1 = TestNode::new(builder.spawn());
-        let n2 = TestNode::new(builder.spawn());
+    fn stripes() {
+        let content = (0..1000).map(|i| i as u8).collect::<Vec<_>>();
+        let striped = EncodeConfig::new(4, 2).with_stripe_size(64);
+        let mut file = File::encode_from_reader(content.as_slice(), striped).unwrap();
+        file.shards_mut().delete(1);
+        let count = file.metadata().stripe_count();
+        assert_eq!(count, 4);
+        let decoded = (0..count)
+            .flat_map(|index| file.decode_stripe(index).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(decoded, content);
+        assert_eq!(file.decode_stripe(count), Err(DecodeError::OutOfRange));
+
+        // A block short of shards holds back only its own stripes.
+        let blocked = EncodeConfig::new(4, 2).with_block_size(256);
+        let mut file = File::encode_with(&content, blocked).unwrap();
+        for index in 12..15 {
+            file.shards_mut().delete(index);
+        }
+        assert_eq!(file.metadata().stripe_count(), 4);
+        assert_eq!(file.decode_stripe(1).unwrap(), &content[256..512]);
+        assert_eq!(file.decode_stripe(3).unwrap(), &content[768..]);
+        assert_eq!(
+            file.decode_stripe(2),
+            Err(DecodeError::Insufficient {
+                present: 3,
+                needed: 4
+            })
+        );
+    }
 
+    #[test]
+    fn codecs() {
         let content = "hello world!".repeat(100);
-        let name = "hello".to_string();
+        let config = EncodeConfig::new(6, 1).with_codec(CodecId::Xor);
+        let mut file = File::encode_with(&content, config).unwrap();
+        assert_eq!(file.metadata().codec(), CodecId::Xor);
+        assert_eq!(file.metadata().encode_config(), config.with_shard_size(256));
 
-        aw(n1.upload(name.clone(), content.clone()));
-        assert!(aw(n1.download(name.clone())).is_some());
+        file.shards_mut().delete(2);
+        assert_eq!(file.decode_string(), Ok(content.clone()));
+        file.shards_mut().delete(6);
+        assert!(!file.can_decode());
 
-        aw(n2.download(name.clone()));
-        std::thread::sleep(std::time::Duration::from_millis(20));
+        let config = EncodeConfig::new(1, 3).with_codec(CodecId::Replication);
+        let mut file = File::encode_with(&content, config).unwrap();
+        assert_eq!(file.metadata().shard_size(), 1216);
+        for index in 0..3 {
+            file.shards_mut().delete(index);
+        }
+        assert_eq!(file.decode_string(), Ok(content.clone()));
 
-        let res = aw(n2.download(name.clone()));
-        assert!(res.is_some());
-        assert_eq!(res.unwrap(), content);
+        let config = EncodeConfig::new(4, 2).with_codec(CodecId::Xor);
+        assert!(File::encode_with(&content, config).is_err());
     }
 
+    #[cfg(feature = "raptorq")]
     #[test]
-    fn many() {
-        let builder = TestNetworkBuilder::new();
-        let nodes = (0..32)
-            .map(|_| TestNode::new(builder.spawn()))
-            .collect::<Vec<_>>();
+    fn raptorq() {
+        let content = (0..5000).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+        let config = EncodeConfig::new(8, 24)
+            .with_codec(CodecId::RaptorQ)
+            .with_stripe_size(128);
+        let original = File::encode_with(&content, config).unwrap();
+        assert_eq!(original.metadata().codec(), CodecId::RaptorQ);
+        assert!(original.verify().is_empty());
 
-        let content = "hello world!".repeat(100);
-        let name1 = "hello".to_string();
-        let name2 = "hello".to_string();
-        let name3 = "hello".to_string();
+        // Any ten of the repair symbols stand in for the eight data shards.
+        let mut file = original.clone();
+        for index in (0..8).chain((8..32).step_by(2)).chain([9, 11]) {
+            file.shards_mut().delete(index);
+        }
+        assert_eq!(file.shards().present(), 10);
+        assert_eq!(file.decode().unwrap(), content);
+        assert_eq!(file.repair(), Ok(22));
+        assert_eq!(file, original);
 
-        aw(nodes[0].upload(name1.clone(), content.clone()));
-        aw(nodes[10].upload(name2.clone(), content.clone()));
-        aw(nodes[20].upload(name3.clone(), content.clone()));
+        // These three repair symbols happen not to decode; that asks for one more shard
+        // instead of reporting the file as corrupt.
+        let config = EncodeConfig::new(3, 29)
+            .with_codec(CodecId::RaptorQ)
+            .with_stripe_size(64);
+        let original = File::encode_with(&content[..1000], config).unwrap();
+        let mut file = original.clone();
+        for index in (0..32).filter(|index| ![9, 10, 27].contains(index)) {
+            file.shards_mut().delete(index);
+        }
+        assert!(file.can_decode());
+        let err = file.decode().unwrap_err();
+        assert_eq!(
+            err,
+            DecodeError::Insufficient {
+                present: 3,
+                needed: 4
+            }
+        );
+        assert!(err.is_pending());
+        file.shards_mut()
+            .merge(original.shards().get(11).unwrap())
+            .unwrap();
+        assert_eq!(file.decode().unwrap(), &content[..1000]);
+    }
 
-        builder.disable(nodes[0].network().id);
-        builder.disable(nodes[10].network().id);
+    #[test]
+    fn repair() {
+        let content = (0..1000).map(|i| i as u8).collect::<Vec<_>>();
+        let config = EncodeConfig::new(4, 2).with_stripe_size(64);
+        let original = File::encode_with(&content, config).unwrap();
+        let mut file = original.clone();
+        assert_eq!(file.repair(), Ok(0));
 
-        aw(nodes[7].download(name1.clone()));
-        aw(nodes[13].download(name2.clone()));
-        aw(nodes[17].download(name3.clone()));
-        std::thread::sleep(std::time::Duration::from_millis(20));
+        file.shards_mut().delete(1);
+        file.shards_mut().delete(4);
+        let version = file.shards().version();
+        assert_eq!(file.repair(), Ok(2));
+        assert_ne!(file.shards().version(), version);
+        assert_eq!(file.shards().present(), 6);
+        for index in 0..6 {
+            assert_eq!(file.shards().get(index), original.shards().get(index));
+        }
 
-        let res = aw(nodes[7].download(name1.clone()));
-        assert!(res.is_some());
-        assert_eq!(res.unwrap(), content);
+        file.shards_mut().insert(vec![b'x'; 256], 0).unwrap();
+        file.shards_mut().delete(5);
+        assert_eq!(file.repair(), Ok(2));
+        assert!(file.shards().corrupt().is_empty());
+        assert!(file.holds(&content));
 
-        let res = aw(nodes[13].download(name2.clone()));
-        assert!(res.is_some());
-        assert_eq!(res.unwrap(), content);
+        let config = EncodeConfig::new(3, 1)
+            .with_block_size(300)
+            .with_codec(CodecId::Xor);
+        let original = File::encode_with(&content, config).unwrap();
+        let mut file = original.clone();
+        for index in [0, 7, 11] {
+            file.shards_mut().delete(index);
+        }
+        assert_eq!(file.repair(), Ok(3));
+        assert!(file.holds(&content));
 
-        let res = aw(nodes[17].download(name3.clone()));
-        assert!(res.is_some());
-        assert_eq!(res.unwrap(), content);
+        file.shards_mut().delete(0);
+        file.shards_mut().delete(1);
+        assert!(matches!(
+            file.repair(),
+            Err(DecodeError::Insufficient { present: 2, .. })
+        ));
+        assert!(!file.shards().contains(0));
     }
 
     #[test]
-    fn lost() {
-        let builder = TestNetworkBuilder::new();
-        let nodes = (0..8)
-            .map(|_| TestNode::new(builder.spawn()))
-            .collect::<Vec<_>>();
+    fn verify() {
+        let content = (0..1000).map(|i| i as u8).collect::<Vec<_>>();
+        let file = File::encode_with(&content, EncodeConfig::new(4, 2)).unwrap();
+        assert!(file.verify().is_empty());
 
-        let content = "hello world!".repeat(30);
-        let name = "hello".to_string();
+        let mut checked = file.clone();
+        checked.shards_mut().insert(vec![b'x'; 256], 3).unwrap();
+        assert_eq!(checked.verify(), vec![3]);
+
+        let mut file = unchecked(&file);
+        assert!(file.verify().is_empty());
+        let mut flipped = file.shards().get(2).unwrap().data().to_vec();
+        flipped[17] ^= 1;
+        file.shards_mut().insert(flipped, 2).unwrap();
+        assert_eq!(file.verify(), vec![2]);
+
+        assert!(matches!(
+            file.shards_mut().insert(vec![0; 8], 5),
+            Err(ShardError::WrongSize { .. })
+        ));
+        file.shards_mut().insert(vec![0; 256], 5).unwrap();
+        assert_eq!(file.verify(), vec![0, 1, 2, 3, 4, 5]);
+
+        let config = EncodeConfig::new(3, 1).with_codec(CodecId::Xor);
+        let mut file = unchecked(&File::encode_with(&content, config).unwrap());
+        file.shards_mut().insert(vec![b'x'; 384], 1).unwrap();
+        assert_eq!(file.verify(), vec![0, 1, 2, 3]);
+        file.shards_mut().delete(0);
+        assert!(file.verify().is_empty());
+    }
 
-        aw(nodes[0].upload(name.clone(), content.clone()));
-        for i in 0..6 {
-            builder.disable(nodes[i].network().id);
+    #[test]
+    fn range() {
+        let content = (0..20_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let config = EncodeConfig::new(4, 2)
+            .with_block_size(3000)
+            .with_shard_size(768);
+        let mut file = File::encode_with(&content, config).unwrap();
+        let ranges = [
+            (0, 10),
+            (750, 20),
+            (2990, 20),
+            (5999, 3002),
+            (19_990, 10),
+            (0, 20_000),
+        ];
+        for (offset, len) in ranges {
+            let range = offset as usize..(offset + len) as usize;
+            assert_eq!(file.decode_range(offset, len).unwrap(), content[range]);
         }
 
-        aw(nodes[7].download(name.clone()));
-        std::thread::sleep(std::time::Duration::from_millis(40));
+        let striped =
+            File::encode_with(&content, EncodeConfig::new(4, 2).with_stripe_size(64)).unwrap();
+        assert_eq!(striped.metadata().stripes(), 79);
+        assert_eq!(
+            striped.decode_range(1000, 500).unwrap(),
+            content[1000..1500]
+        );
 
-        aw(nodes[7].download(name.clone()));
-        std::thread::sleep(std::time::Duration::from_millis(40));
+        file.shards_mut().delete(0);
+        file.shards_mut().insert(vec![b'x'; 768], 7).unwrap();
+        assert_eq!(file.decode_range(100, 50).unwrap(), content[100..150]);
+        assert_eq!(file.decode_range(2900, 400).unwrap(), content[2900..3300]);
 
-        aw(nodes[7].download(name.clone()));
-        std::thread::sleep(std::time::Duration::from_millis(40));
+        for index in [12, 13, 14] {
+            file.shards_mut().delete(index);
+        }
+        assert_eq!(file.decode_range(0, 3000).unwrap(), content[..3000]);
+        assert_eq!(file.decode_range(8400, 10).unwrap(), content[8400..8410]);
+        assert_eq!(
+            file.decode_range(6800, 10),
+            Err(DecodeError::Insufficient {
+                present: 3,
+                needed: 4
+            })
+        );
+
+        assert_eq!(file.decode_range(20_000, 0), Ok(Vec::new()));
+        assert_eq!(file.decode_range(19_999, 2), Err(DecodeError::OutOfRange));
+        assert_eq!(file.decode_range(u64::MAX, 2), Err(DecodeError::OutOfRange));
+    }
+
+    #[cfg(any(feature = "lz4", feature = "zstd"))]
+    #[test]
+    fn compression() {
+        use erasure_node::{compress::Compression, shardfile};
+
+        let content = "hello world! ".repeat(500);
+        let algorithms = [
+            #[cfg(feature = "lz4")]
+            Compression::Lz4,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd,
+        ];
+        for compression in algorithms {
+            let config = EncodeConfig::new(4, 2).with_compression(compression);
+            let file = File::encode_with(&content, config).unwrap();
+            let meta = file.metadata().clone();
+            assert_eq!(meta.compression(), compression);
+            assert_eq!(meta.encode_config(), config.with_shard_size(1664));
+
+            let shard = file.shards().get(0).unwrap();
+            assert!(shard.size() < meta.shard_size() / 4);
+            assert_eq!(Compression::from_id(compression.id()), Some(compression));
+            let decoded = shardfile::decode(&shardfile::encode("hello", &meta, &shard).unwrap());
+            assert_eq!(decoded, Some(("hello".to_string(), meta.clone(), shard)));
+
+            let mut copy = File::empty(meta.clone());
+            for shard in file.shards().present_iter().skip(2) {
+                assert!(copy.verify_shard(&shard));
+                assert_eq!(copy.shards_mut().merge(shard), Ok(()));
+            }
+            assert_eq!(copy.decode_string(), Ok(content.clone()));
+            assert_eq!(copy.repair(), Ok(2));
+            assert_eq!(copy.shards().get(0), file.shards().get(0));
+
+            let mut empty = File::empty(meta);
+            let raw = File::encode_with(&content, EncodeConfig::new(4, 2)).unwrap();
+            for shard in [raw.shards().get(1).unwrap(), Shard::new(1, vec![1, 2, 3])] {
+                assert!(!empty.verify_shard(&shard));
+                assert!(empty.shards_mut().merge(shard).is_err());
+            }
+        }
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted() {
+        use erasure_node::{crypt::Key, shardfile};
+
+        let content = "hello world! ".repeat(50);
+        let key = Key::new(7, [3; 32]);
+        assert_eq!(format!("{key:?}"), "Key { id: 7 }");
+
+        let mut file = File::encode_encrypted(&content, &key).unwrap();
+        let meta = file.metadata().clone();
+        let encryption = meta.encryption().unwrap();
+        assert_eq!(encryption.key_id, 7);
+        assert_eq!(meta.len(), content.len() as u64 + 16);
+        assert!(
+            file.shards()
+                .present_iter()
+                .all(|shard| !shard.data().windows(5).any(|window| window == b"hello"))
+        );
+
+        let shard = file.shards().get(0).unwrap();
+        let decoded =
+            shardfile::decode(&shardfile::encode("hello", &meta, &shard).unwrap()).unwrap();
+        assert_eq!(decoded.1.encryption(), Some(encryption.clone()));
+
+        file.shards_mut().delete(0);
+        file.shards_mut().delete(3);
+        assert_eq!(
+            file.decode_encrypted(&key),
+            Ok(content.clone().into_bytes())
+        );
+        assert_ne!(file.decode().unwrap(), content.as_bytes());
+        assert_eq!(
+            file.decode_encrypted(&Key::new(7, [4; 32])),
+            Err(Error::Decrypt)
+        );
+        assert_eq!(
+            file.decode_encrypted(&Key::new(8, [3; 32])),
+            Err(Error::Decrypt)
+        );
+
+        let again = File::encode_encrypted(&content, &key).unwrap();
+        assert_ne!(again.metadata().encryption(), Some(encryption));
+
+        let config = EncodeConfig::new(4, 2).with_shard_size(256);
+        let file = File::encode_encrypted_with(&content, config, &key).unwrap();
+        assert_eq!(file.metadata().encode_config(), config);
+        assert_eq!(
+            file.decode_encrypted(&key),
+            Ok(content.clone().into_bytes())
+        );
+
+        let plain = File::encode(&content).unwrap();
+        assert_eq!(plain.metadata().encryption(), None);
+        assert_eq!(plain.decode_encrypted(&key), Err(Error::Decrypt));
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn wrapped() {
+        use erasure_node::{
+            crypt::{Identity, Key},
+            shardfile,
+        };
+
+        let content = "hello world! ".repeat(50);
+        let (alice, bob, eve) = (
+            Identity::generate(),
+            Identity::from_bytes([9; 32]),
+            Identity::generate(),
+        );
+        assert_eq!(Identity::from_bytes(bob.to_bytes()).public(), bob.public());
+        let key = Key::new(7, [3; 32]).with_recipients([alice.public(), bob.public()]);
+
+        let mut file = File::encode_encrypted(&content, &key).unwrap();
+        let encryption = file.metadata().encryption().unwrap();
+        assert_eq!(encryption.recipients.len(), 2);
+        assert!(
+            encryption
+                .recipients
+                .iter()
+                .all(|wrapped| !wrapped.sealed.windows(32).any(|window| window == [3; 32]))
+        );
+
+        // The wrapped keys travel with the metadata, so a holder's copy is enough to open it.
+        let shard = file.shards().get(0).unwrap();
+        let (_, meta, _) =
+            shardfile::decode(&shardfile::encode("hello", file.metadata(), &shard).unwrap())
+                .unwrap();
+        assert_eq!(bob.unwrap(&meta).map(|key| key.id()), Some(7));
+
+        file.shards_mut().delete(1);
+        assert_eq!(file.decode_as(&alice), Ok(content.clone().into_bytes()));
+        assert_eq!(file.decode_as(&bob), Ok(content.clone().into_bytes()));
+        assert_eq!(file.decode_as(&eve), Err(Error::Decrypt));
+        assert!(eve.unwrap(file.metadata()).is_none());
+
+        // A wrapped key moved to another file id does not open.
+        let mut forged = encryption.clone();
+        forged.key_id = 8;
+        assert!(bob.unwrap(&meta.clone().with_encryption(forged)).is_none());
+
+        let plain = File::encode(&content).unwrap();
+        assert_eq!(plain.decode_as(&alice), Err(Error::Decrypt));
+        let unshared = File::encode_encrypted(&content, &Key::new(7, [3; 32])).unwrap();
+        assert_eq!(unshared.decode_as(&alice), Err(Error::Decrypt));
+    }
+
+    #[cfg(feature = "signing")]
+    #[test]
+    fn signed() {
+        use erasure_node::{shardfile, sign::SigningKey};
+
+        let content = "hello world! ".repeat(50);
+        let key = SigningKey::from_bytes(&[5; 32]);
+        let config = EncodeConfig::new(4, 2).with_shard_size(256);
+        let mut file = File::encode_with(&content, config).unwrap();
+        file.shards_mut().delete(1);
+
+        let file = file.signed(&key).unwrap();
+        let meta = file.metadata().clone();
+        assert_eq!(meta.signer(), Some(key.verifying_key().to_bytes()));
+        assert_eq!(meta.digests().len(), 6);
+        assert!(meta.verify_signature());
+        assert_eq!(file.decode().unwrap(), content.as_bytes());
+
+        let shard = file.shards().get(1).unwrap();
+        let decoded =
+            shardfile::decode(&shardfile::encode("hello", &meta, &shard).unwrap()).unwrap();
+        assert_eq!(decoded.1, meta);
+        assert!(decoded.1.verify_signature());
+
+        let mut forged = shard.data().to_vec();
+        forged[0] ^= 1;
+        let mut empty = File::empty(meta.clone());
+        assert_eq!(
+            empty.shards_mut().merge(Shard::new(1, forged.clone())),
+            Err(ShardError::Corrupt(ShardIndex::new(1)))
+        );
+        assert_eq!(empty.shards_mut().merge(shard), Ok(()));
+
+        // Digests catch tampering even without checksums, and stripping them breaks the signature.
+        let stripped = meta.clone().with_checksums(Vec::new());
+        assert!(!stripped.verify_signature());
+        assert!(
+            File::empty(stripped)
+                .shards_mut()
+                .merge(Shard::new(1, forged))
+                .is_err()
+        );
+
+        // Attributes are covered by the signature too.
+        assert!(
+            !meta
+                .clone()
+                .with_attribute("owner", "mallory")
+                .verify_signature()
+        );
+
+        let plain = File::encode(&content).unwrap();
+        assert_eq!(plain.metadata().signer(), None);
+        assert!(!plain.metadata().verify_signature());
+    }
+
+    #[test]
+    fn attributes() {
+        let file = File::encode("hello world!").unwrap();
+        let meta = file.metadata();
+        assert_eq!(meta.created_at(), 0);
+        assert_eq!(meta.content_type(), None);
+        assert!(meta.attributes().is_empty());
+
+        let file = file
+            .with_created_at(42)
+            .with_content_type("text/plain")
+            .with_attributes([("owner".to_string(), "alice".to_string())].into());
+        let meta = file.metadata().clone().with_attribute("tier", "cold");
+        assert_eq!(meta.created_at(), 42);
+        assert_eq!(meta.content_type(), Some("text/plain"));
+        assert_eq!(meta.attribute("owner"), Some("alice"));
+        assert_eq!(meta.attribute("tier"), Some("cold"));
+        assert_eq!(meta.attribute("missing"), None);
+        assert_eq!(file.metadata().attributes().len(), 1);
+    }
+
+    #[test]
+    fn digest() {
+        let s1 = "hello world!".repeat(10);
+        let mut f1 = File::encode(&s1).unwrap();
+        let f2 = File::encode_with_parity(&s1, 5).unwrap();
+        let f3 = File::encode("hello world?".repeat(10)).unwrap();
+
+        f1.shards_mut().delete(0);
+        assert_eq!(f1.digest(), f2.digest());
+        assert_eq!(f1, f2);
+        assert_ne!(f1, f3);
+
+        // Comes from the metadata alone, so it holds even when the shards cannot be decoded.
+        f1.shards_mut().delete(1);
+        f1.shards_mut().delete(2);
+        f1.shards_mut().delete(3);
+        assert!(f1.decode().is_err());
+        assert_eq!(f1.digest(), f2.digest());
+        assert_eq!(f1, f1.clone());
+        assert_eq!(f1, f2);
+
+        let empty = File::empty(f3.metadata().clone().with_checksums(Vec::new()));
+        assert!(empty.digest().is_none());
+        assert_eq!(empty, empty.clone());
+    }
+
+    #[test]
+    fn clock() {
+        let mut a = VectorClock::default();
+        let mut b = VectorClock::default();
+        assert_eq!(a, b);
+
+        a.tick(1);
+        assert_eq!(a.partial_cmp(&b), Some(Ordering::Greater));
+        b.tick(2);
+        assert!(a.concurrent(&b));
+        assert_eq!(a.partial_cmp(&b), None);
+
+        let mut merged = a.clone();
+        merged.merge(&b);
+        assert!(merged > a && merged > b);
+        assert_eq!(merged.get(1), 1);
+        assert_eq!(merged.get(2), 1);
+        assert_eq!(merged.get(3), 0);
+    }
+
+    #[test]
+    fn writer() {
+        // Clock entries are exchanged between nodes and persisted, so these must never change.
+        assert_eq!(file::writer(""), 0xcbf29ce484222325);
+        assert_eq!(file::writer("node-1"), 0xe696ecc2207879c3);
+        assert_ne!(file::writer("node-1"), file::writer("node-2"));
+    }
+}
+
+mod archive {
+    use bytes::Bytes;
+    use erasure_node::{
+        archive::Archive,
+        file::{File, Metadata, Shard},
+    };
+
+    fn files() -> Vec<(String, Metadata, Vec<Shard>)> {
+        ["hello", "world"]
+            .into_iter()
+            .map(|name| {
+                let file = File::encode(name.repeat(20)).unwrap();
+                let shards = file.shards().present_iter().collect();
+                (name.to_string(), file.metadata().clone(), shards)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let files = files();
+        let archive = Archive::decode(Archive::encode(&files).unwrap()).unwrap();
+
+        assert_eq!(archive.index().len(), 2);
+        for (archived, (name, meta, shards)) in archive.files().zip(&files) {
+            let archived = archived.unwrap();
+            assert_eq!(&archived.name, name);
+            assert_eq!(&archived.meta, meta);
+            assert_eq!(&archived.shards, shards);
+            assert!(archived.corrupt.is_empty());
+        }
+        assert!(archive.get("missing").is_none());
+    }
+
+    #[test]
+    fn corrupt() {
+        let files = files();
+        let mut data = Archive::encode(&files).unwrap().to_vec();
+
+        let shard = files[1].2[2].data().to_vec();
+        let offset = data
+            .windows(shard.len())
+            .position(|window| window == shard)
+            .unwrap();
+        data[offset] ^= 1;
+
+        let archive = Archive::decode(Bytes::from(data)).unwrap();
+        let archived = archive.get("world").unwrap();
+        assert_eq!(archived.corrupt, vec![2]);
+        assert_eq!(archived.shards.len(), files[1].2.len() - 1);
+        assert!(archive.get("hello").unwrap().corrupt.is_empty());
+    }
+
+    #[test]
+    fn malformed() {
+        let data = Archive::encode(&files()).unwrap();
+
+        assert!(Archive::decode(data.slice(..data.len() - 1)).is_none());
+        assert!(Archive::decode(data.slice(1..)).is_none());
+        assert!(Archive::decode(Bytes::new()).is_none());
+    }
+}
+
+mod shardfile {
+    use erasure_node::{file::File, shardfile};
+
+    #[test]
+    fn roundtrip() {
+        let dir = std::env::temp_dir().join(format!("erasure-shards-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = File::encode("hello world".repeat(20)).unwrap();
+
+        let paths = shardfile::write(&dir, "hello", &file).unwrap();
+        assert_eq!(paths.len(), file.metadata().shard_count());
+        assert!(paths[0].ends_with(shardfile::file_name("hello", 0, paths.len())));
+
+        let shards = paths
+            .iter()
+            .skip(file.metadata().parity_shards())
+            .map(|path| shardfile::open(path).unwrap());
+        let assembled = shardfile::assemble(shards).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(assembled.decode_string().unwrap(), "hello world".repeat(20));
+    }
+
+    #[test]
+    fn corrupt() {
+        let file = File::encode("hello world".repeat(20)).unwrap();
+        let shard = file.shards().get(1).unwrap();
+        let data = shardfile::encode("hello", file.metadata(), &shard)
+            .unwrap()
+            .to_vec();
+
+        let (name, meta, decoded) = shardfile::decode(&data).unwrap();
+        assert_eq!(
+            (name.as_str(), &meta, &decoded),
+            ("hello", file.metadata(), &shard)
+        );
+
+        let mut flipped = data.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        assert!(shardfile::decode(&flipped).is_none());
+        assert!(shardfile::decode(&data[..data.len() - 1]).is_none());
+        assert!(shardfile::decode(&data[1..]).is_none());
+    }
+}
+
+mod store {
+    use std::{
+        collections::{BTreeMap, BTreeSet},
+        path::Path,
+    };
+
+    use erasure_node::{
+        file::{File, Metadata},
+        store::{Directory, ShardStore},
+    };
+
+    fn roundtrip(store: &dyn ShardStore) {
+        let v1 = File::encode("hello world".repeat(20)).unwrap();
+        let shards = v1.shards().present_iter().collect::<Vec<_>>();
+        store.put("a/hello", v1.metadata(), &shards[..2]).unwrap();
+        store.put("a/hello", v1.metadata(), &shards[2..]).unwrap();
+        store.put("empty", v1.metadata(), &[]).unwrap();
+        store.remove("a/hello", &[0]).unwrap();
+
+        let mut loaded = store.load().unwrap();
+        loaded.sort_by(|(a, ..), (b, ..)| a.cmp(b));
+        assert_eq!(loaded.len(), 2);
+        let (name, meta, held) = &loaded[0];
+        assert_eq!((name.as_str(), meta), ("a/hello", v1.metadata()));
+        assert_eq!(held.len(), shards.len() - 1);
+        let mut file = File::empty(meta.clone());
+        for shard in held.clone() {
+            file.shards_mut().merge(shard).unwrap();
+        }
+        assert_eq!(file.decode_string().unwrap(), "hello world".repeat(20));
+        assert!(loaded[1].2.is_empty());
+
+        // Download records replace each other and are only kept for stored files.
+        store.put_download("a/hello", &[1]).unwrap();
+        store.put_download("a/hello", &[1, 2, 9]).unwrap();
+        store.put_download("empty", &[]).unwrap();
+        store.put_download("missing", &[0]).unwrap();
+        let mut downloads = store.downloads().unwrap();
+        downloads.sort();
+        assert_eq!(
+            downloads,
+            [
+                ("a/hello".to_string(), vec![1, 2, 9]),
+                ("empty".to_string(), vec![])
+            ]
+        );
+        store.remove_download("empty").unwrap();
+        store.remove_download("missing").unwrap();
+
+        // A new version replaces the shards and the download record of the old one.
+        let v2 = File::encode("goodbye".repeat(20)).unwrap();
+        let shard = v2.shards().get(0).unwrap();
+        store.put("a/hello", v2.metadata(), &[shard]).unwrap();
+        let loaded = store.load().unwrap();
+        let (_, meta, held) = loaded.iter().find(|(name, ..)| name == "a/hello").unwrap();
+        assert_eq!((meta, held.len()), (v2.metadata(), 1));
+        assert!(store.downloads().unwrap().is_empty());
+
+        store.put_download("a/hello", &[0]).unwrap();
+        store.delete("a/hello").unwrap();
+        store.delete("missing").unwrap();
+        let loaded = store.load().unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].0, "empty");
+        assert!(store.downloads().unwrap().is_empty());
+    }
+
+    #[test]
+    fn directory() {
+        let dir = std::env::temp_dir().join(format!("erasure-store-{}", std::process::id()));
+        let store = Directory::open(&dir).unwrap();
+        roundtrip(&store);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn directory_malformed() {
+        let dir = std::env::temp_dir().join(format!("erasure-malformed-{}", std::process::id()));
+        let store = Directory::open(&dir).unwrap();
+        let file = File::encode("hello world".repeat(20)).unwrap();
+        let shards = file.shards().present_iter().collect::<Vec<_>>();
+        store.put("hello", file.metadata(), &shards).unwrap();
+        let entry = std::fs::read_dir(&dir)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        std::fs::write(entry.join("0.shard"), b"garbage").unwrap();
+        std::fs::create_dir(dir.join("bad")).unwrap();
+        std::fs::write(dir.join("bad").join("meta"), b"garbage").unwrap();
+
+        // One bad shard and one bad entry leave the rest loadable.
+        let loaded = store.load().unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].2.len(), shards.len() - 1);
+        assert!(entry.join("0.corrupt").exists());
+
+        // A swap interrupted after the old version was moved aside is finished on open.
+        std::fs::rename(&entry, entry.with_extension("new")).unwrap();
+        std::fs::create_dir(entry.with_extension("old")).unwrap();
+        let loaded = Directory::open(&dir).unwrap().load().unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert!(!entry.with_extension("old").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    enum Op<'a> {
+        Put(&'a str, &'a File, Vec<usize>),
+        Remove(&'a str, Vec<usize>),
+        Delete(&'a str),
+    }
+
+    type State = BTreeMap<String, (Metadata, BTreeSet<usize>)>;
+
+    fn apply(store: &dyn ShardStore, op: &Op) -> std::io::Result<()> {
+        match op {
+            Op::Put(name, file, indices) => {
+                let shards = indices
+                    .iter()
+                    .map(|index| file.shards().get(*index).unwrap())
+                    .collect::<Vec<_>>();
+                store.put(name, file.metadata(), &shards)
+            }
+            Op::Remove(name, indices) => store.remove(name, indices),
+            Op::Delete(name) => store.delete(name),
+        }
+    }
+
+    // What `op` leaves behind when it completes.
+    fn model(state: &mut State, op: &Op) {
+        match op {
+            Op::Put(name, file, indices) => {
+                let entry = state
+                    .entry(name.to_string())
+                    .or_insert_with(|| (file.metadata().clone(), BTreeSet::new()));
+                if entry.0 != *file.metadata() {
+                    *entry = (file.metadata().clone(), BTreeSet::new());
+                }
+                entry.1.extend(indices);
+            }
+            Op::Remove(name, indices) => {
+                if let Some((_, held)) = state.get_mut(*name) {
+                    held.retain(|index| !indices.contains(index));
+                }
+            }
+            Op::Delete(name) => {
+                state.remove(*name);
+            }
+        }
+    }
+
+    fn corrupt(dir: &Path) -> bool {
+        std::fs::read_dir(dir).unwrap().any(|entry| {
+            let path = entry.unwrap().path();
+            path.extension().is_some_and(|ext| ext == "corrupt")
+                || (path.is_dir() && corrupt(&path))
+        })
+    }
+
+    // Crashes the store at every file system step of a workload, reopens it and checks that
+    // each file is as it was before the interrupted operation or after it; a batch of shards
+    // may land in part, but a new version or a delete never does.
+    #[test]
+    fn crash() {
+        let v1 = File::encode("hello world".repeat(20)).unwrap();
+        let v2 = File::encode("goodbye".repeat(20)).unwrap();
+        let other = File::encode("other".repeat(20)).unwrap();
+        let all = |file: &File| (0..file.metadata().shard_count()).collect::<Vec<_>>();
+        let ops = [
+            Op::Put("a", &v1, vec![0, 1]),
+            Op::Put("a", &v1, all(&v1)[2..].to_vec()),
+            Op::Put("b", &other, all(&other)),
+            Op::Remove("a", vec![0, 2]),
+            Op::Put("a", &v2, vec![0, 1]),
+            Op::Delete("b"),
+            Op::Put("b", &v1, vec![0]),
+        ];
+
+        let dir = std::env::temp_dir().join(format!("erasure-crash-{}", std::process::id()));
+        for steps in 0.. {
+            let _ = std::fs::remove_dir_all(&dir);
+            let store = Directory::open(&dir).unwrap().crash_after(steps);
+            let mut before = State::new();
+            let mut crashed = None;
+            for op in &ops {
+                let mut after = before.clone();
+                model(&mut after, op);
+                if apply(&store, op).is_err() {
+                    crashed = Some(after);
+                    break;
+                }
+                before = after;
+            }
+
+            let loaded = Directory::open(&dir)
+                .unwrap()
+                .load()
+                .unwrap()
+                .into_iter()
+                .map(|(name, meta, shards)| {
+                    let held = shards.iter().map(|shard| shard.index()).collect();
+                    (name, (meta, held))
+                })
+                .collect::<State>();
+            assert!(!corrupt(&dir), "torn shard file after {steps} steps");
+            let Some(after) = crashed else {
+                assert_eq!(loaded, before);
+                break;
+            };
+
+            let names = before.keys().chain(after.keys()).collect::<BTreeSet<_>>();
+            for name in names {
+                let got = loaded.get(name);
+                let (old, new) = (before.get(name), after.get(name));
+                let partial = match (got, old, new) {
+                    (Some(got), Some(old), Some(new)) if got.0 == old.0 && old.0 == new.0 => {
+                        old.1
+                            .intersection(&new.1)
+                            .all(|index| got.1.contains(index))
+                            && got.1.iter().all(|i| old.1.contains(i) || new.1.contains(i))
+                    }
+                    _ => false,
+                };
+                assert!(
+                    got == old || got == new || partial,
+                    "{name} torn after {steps} steps: {got:?}"
+                );
+            }
+            assert!(
+                loaded
+                    .keys()
+                    .all(|name| before.contains_key(name) || after.contains_key(name))
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "redb")]
+    #[test]
+    fn redb() {
+        use erasure_node::store::Redb;
+
+        let path = std::env::temp_dir().join(format!("erasure-redb-{}", std::process::id()));
+        let store = Redb::open(&path).unwrap();
+        roundtrip(&store);
+        drop(store);
+
+        let mut store = Redb::open_with_cache(&path, 1 << 20).unwrap();
+        store.compact().unwrap();
+        let loaded = store.load().unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].0, "empty");
+        drop(store);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "rocksdb")]
+    #[test]
+    fn rocksdb() {
+        use erasure_node::store::{RocksDb, RocksDbConfig};
+
+        let path = std::env::temp_dir().join(format!("erasure-rocksdb-{}", std::process::id()));
+        let store = RocksDb::open(&path).unwrap();
+        roundtrip(&store);
+        drop(store);
+
+        let config = RocksDbConfig {
+            write_buffer_size: 1 << 20,
+            ..RocksDbConfig::default()
+        };
+        let store = RocksDb::open_with(&path, config).unwrap();
+        store.compact();
+        let loaded = store.load().unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].0, "empty");
+        drop(store);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
+
+mod zfec {
+    use erasure_node::{
+        file::File,
+        zfec::{self, Header, Zfec},
+    };
+
+    #[test]
+    fn header() {
+        let header = Header {
+            m: 10,
+            k: 3,
+            pad: 1,
+            shnum: 4,
+        };
+        assert_eq!(header.encode(), vec![0x09, 0x25, 0x00]);
+        assert_eq!(Header::decode(&header.encode()), Some((header, 3)));
+        assert!(Header::decode(&[0x09]).is_none());
+    }
+
+    #[test]
+    fn compatible() {
+        let shares = zfec::encode(b"hello world", 3, 5).unwrap();
+        let expected: [&[u8]; 5] = [
+            &[0x68, 0x65, 0x6c, 0x6c],
+            &[0x6f, 0x20, 0x77, 0x6f],
+            &[0x72, 0x6c, 0x64, 0x00],
+            &[0x0c, 0x41, 0x84, 0x01],
+            &[0xfd, 0xf5, 0x66, 0x58],
+        ];
+
+        for (shnum, (share, body)) in shares.iter().zip(expected).enumerate() {
+            let header = Header {
+                m: 5,
+                k: 3,
+                pad: 1,
+                shnum,
+            }
+            .encode();
+            assert_eq!(&share[..header.len()], header.as_slice());
+            assert_eq!(&share[header.len()..], body);
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let content = (0..50_000)
+            .map(|i| (i * 31 % 251) as u8)
+            .collect::<Vec<_>>();
+        let shares = zfec::encode(&content, 3, 7).unwrap();
+
+        assert_eq!(zfec::decode(&shares[4..]).unwrap(), content);
+        assert_eq!(
+            zfec::decode(&[&shares[6], &shares[0], &shares[3]]).unwrap(),
+            content
+        );
+        assert!(zfec::decode(&shares[5..]).is_none());
+
+        let zfec = Zfec::new(2, 4).unwrap();
+        let parity = zfec.encode(&[b"ab", b"cd"]);
+        let blocks = zfec.decode(&[(2, &parity[0]), (3, &parity[1])]).unwrap();
+        assert_eq!(blocks, vec![b"ab".to_vec(), b"cd".to_vec()]);
+        assert!(Zfec::new(3, 2).is_none());
+    }
+
+    #[test]
+    fn convert() {
+        let file = File::encode("hello world".repeat(100)).unwrap();
+        let meta = file.metadata();
+
+        let shares = zfec::from_file(&file).unwrap();
+        assert_eq!(shares.len(), meta.shard_count());
+        assert_eq!(
+            zfec::decode(&shares[meta.parity_shards()..]).unwrap(),
+            "hello world".repeat(100).into_bytes()
+        );
+
+        let converted = zfec::to_file(&shares[meta.parity_shards()..]).unwrap();
+        assert_eq!(converted.metadata().shard_count(), meta.shard_count());
+        assert_eq!(
+            converted.decode_string().unwrap(),
+            "hello world".repeat(100)
+        );
+    }
+}
+
+mod node {
+    use std::{
+        collections::{BTreeMap, HashMap, HashSet},
+        ops::Deref,
+        pin::pin,
+        sync::{
+            Arc, Mutex,
+            mpsc::{Receiver, Sender, channel},
+        },
+        task::{Context, Poll, Waker},
+        time::{Duration, Instant},
+    };
+
+    use bytes::Bytes;
+    use erasure_node::{
+        Error,
+        archive::Archive,
+        config::{NodeConfig, Replication},
+        file::{self, CodecId, DecodeError, EncodeConfig, File, Shard, Stamp, VectorClock},
+        flow::{FlowConfig, Windowed},
+        fragment::{FragmentConfig, Fragmenter},
+        membership::{MembershipConfig, State, Swim},
+        metrics::{self, MetricsSink},
+        network::{Command, Network, NetworkExt},
+        node::{DurabilityWarning, ImportStats, Node},
+        observer::NodeObserver,
+        placement,
+        quota::Quota,
+        redundancy::AdaptiveRedundancy,
+        store::StoreConfig,
+    };
+    use futures::StreamExt;
+
+    struct TestNetworkBuilder {
+        inner: Arc<Mutex<TestNetworkBuilderInner>>,
+    }
+
+    struct TestNetworkBuilderInner {
+        id: usize,
+        senders: HashMap<usize, Sender<(usize, Command)>>,
+        receivers: HashMap<usize, Receiver<(usize, Command)>>,
+        disabled: HashSet<usize>,
+        throughput: HashMap<usize, usize>,
+        regions: HashMap<usize, String>,
+        mtu: Option<usize>,
+    }
+
+    impl TestNetworkBuilder {
+        fn new() -> Self {
+            Self {
+                inner: Arc::new(Mutex::new(TestNetworkBuilderInner {
+                    id: 0,
+                    senders: HashMap::new(),
+                    receivers: HashMap::new(),
+                    disabled: HashSet::new(),
+                    throughput: HashMap::new(),
+                    regions: HashMap::new(),
+                    mtu: None,
+                })),
+            }
+        }
+
+        fn spawn(&self) -> TestNetwork {
+            let mut inner = self.inner.lock().unwrap();
+            let id = inner.id;
+            inner.id += 1;
+
+            let (sender, receiver) = channel();
+            inner.senders.insert(id, sender);
+            inner.receivers.insert(id, receiver);
+
+            TestNetwork {
+                id,
+                builder: self.inner.clone(),
+            }
+        }
+
+        fn disable(&self, id: usize) {
+            self.inner.lock().unwrap().disabled.insert(id);
+        }
+
+        fn enable(&self, id: usize) {
+            self.inner.lock().unwrap().disabled.remove(&id);
+        }
+
+        fn throughput(&self, id: usize, throughput: usize) {
+            self.inner.lock().unwrap().throughput.insert(id, throughput);
+        }
+
+        fn region(&self, id: usize, region: &str) {
+            let mut inner = self.inner.lock().unwrap();
+            inner.regions.insert(id, region.to_string());
+        }
+
+        fn mtu(&self, mtu: usize) {
+            self.inner.lock().unwrap().mtu = Some(mtu);
+        }
+    }
+
+    struct TestNetwork {
+        id: usize,
+        builder: Arc<Mutex<TestNetworkBuilderInner>>,
+    }
+
+    impl Network for TestNetwork {
+        fn id(&self) -> String {
+            format!("{}", self.id)
+        }
+
+        fn throughput(&self, peer: &str) -> Option<usize> {
+            let id = peer.parse().ok()?;
+            self.builder.lock().unwrap().throughput.get(&id).copied()
+        }
+
+        fn region(&self, peer: &str) -> Option<String> {
+            let id = peer.parse().ok()?;
+            self.builder.lock().unwrap().regions.get(&id).cloned()
+        }
+
+        fn mtu(&self) -> Option<usize> {
+            self.builder.lock().unwrap().mtu
+        }
+
+        async fn sleep(&self, duration: Duration) {
+            let deadline = Instant::now() + duration;
+            std::future::poll_fn(|cx| {
+                if Instant::now() >= deadline {
+                    return Poll::Ready(());
+                }
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            })
+            .await
+        }
+
+        async fn discover(&self) -> Vec<String> {
+            let inner = self.builder.lock().unwrap();
+            inner
+                .senders
+                .keys()
+                .filter(|id| **id != self.id && !inner.disabled.contains(id))
+                .map(|id| format!("{id}"))
+                .collect()
+        }
+
+        async fn send(&self, peer: String, cmd: Command) {
+            let id = peer.parse().unwrap();
+            let inner = self.builder.lock().unwrap();
+            if inner.disabled.contains(&id) || inner.mtu.is_some_and(|mtu| cmd.size() > mtu) {
+                return;
+            }
+
+            inner.senders[&id].send((self.id, cmd)).unwrap();
+        }
+
+        async fn recv(&self) -> Option<(String, Command)> {
+            std::future::poll_fn(|cx| {
+                match self.builder.lock().unwrap().receivers[&self.id].try_recv() {
+                    Ok((id, cmd)) => Poll::Ready(Some((format!("{id}"), cmd))),
+                    Err(_) => {
+                        cx.waker().wake_by_ref();
+                        Poll::Pending
+                    }
+                }
+            })
+            .await
+        }
+    }
+
+    struct TestNode {
+        inner: Arc<Node<TestNetwork>>,
+    }
+
+    impl TestNode {
+        fn new(network: TestNetwork) -> Self {
+            Self::from_node(Node::new(network, NodeConfig::default()))
+        }
+
+        fn from_node(node: Node<TestNetwork>) -> Self {
+            let inner = Arc::new(node);
+            let inner_clone = Arc::clone(&inner);
+            std::thread::spawn(move || aw(inner_clone.run()));
+            Self { inner }
+        }
+    }
+
+    impl Deref for TestNode {
+        type Target = Node<TestNetwork>;
+
+        fn deref(&self) -> &Self::Target {
+            self.inner.deref()
+        }
+    }
+
+    type Log<T> = Arc<Mutex<Vec<(String, T)>>>;
+
+    #[derive(Clone, Default)]
+    struct Recorder {
+        received: Log<Command>,
+        sent: Log<Command>,
+        stored: Log<usize>,
+        decoded: Log<Result<Bytes, DecodeError>>,
+    }
+
+    impl NodeObserver for Recorder {
+        fn on_command(&self, peer: &str, command: &Command) {
+            let entry = (peer.to_string(), command.clone());
+            self.received.lock().unwrap().push(entry);
+        }
+
+        fn on_send(&self, peer: &str, command: &Command) {
+            let entry = (peer.to_string(), command.clone());
+            self.sent.lock().unwrap().push(entry);
+        }
+
+        fn on_shard_stored(&self, name: &str, index: usize) {
+            self.stored.lock().unwrap().push((name.to_string(), index));
+        }
+
+        fn on_decode(&self, name: &str, result: &Result<Bytes, DecodeError>) {
+            let entry = (name.to_string(), result.clone());
+            self.decoded.lock().unwrap().push(entry);
+        }
+    }
+
+    fn corrupt(network: TestNetwork) -> Arc<Mutex<Vec<Command>>> {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received_clone = Arc::clone(&received);
+        std::thread::spawn(move || {
+            let mut stamps = HashMap::new();
+            while let Some((peer, cmd)) = aw(network.recv()) {
+                received_clone.lock().unwrap().push(cmd.clone());
+                if let Command::Create { name, meta } = &cmd {
+                    stamps.insert(name.clone(), meta.stamp());
+                }
+                if let Command::Request { name, .. } = cmd {
+                    let stamp = stamps.get(&name).copied().unwrap_or_default();
+                    for index in 0..64 {
+                        let shard = Shard::new(index, vec![0; 3]);
+                        let cmd = Command::Replicate {
+                            name: name.clone(),
+                            stamp,
+                            checksum: shard.crc(),
+                            shard,
+                        };
+                        aw(network.send(peer.clone(), cmd));
+                    }
+                }
+            }
+        });
+
+        received
+    }
+
+    fn liar(network: TestNetwork) {
+        std::thread::spawn(move || {
+            while let Some((peer, cmd)) = aw(network.recv()) {
+                if let Command::Challenge { name, index, nonce } = cmd {
+                    let cmd = Command::Proof {
+                        name,
+                        index,
+                        nonce,
+                        proof: Some(0),
+                    };
+                    aw(network.send(peer, cmd));
+                }
+            }
+        });
+    }
+
+    fn spy(network: TestNetwork) -> Arc<Mutex<Vec<Command>>> {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received_clone = Arc::clone(&received);
+        std::thread::spawn(move || {
+            while let Some((_, cmd)) = aw(network.recv()) {
+                received_clone.lock().unwrap().push(cmd);
+            }
+        });
+
+        received
+    }
+
+    fn aw<F, T>(fut: F) -> T
+    where
+        F: Future<Output = T>,
+    {
+        let mut fut = pin!(fut);
+        loop {
+            if let Poll::Ready(res) = fut.as_mut().poll(&mut Context::from_waker(Waker::noop())) {
+                return res;
+            }
+
+            std::thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn simple() {
+        let builder = TestNetworkBuilder::new();
+
+        let net = builder.spawn();
+        let n1 = TestNode::new(builder.spawn());
+        let n2 = TestNode::new(builder.spawn());
+
+        assert_eq!(aw(net.discover()).len(), 2);
+
+        aw(n1.upload("test".to_string(), "content".to_string())).unwrap();
+        assert!(aw(n1.download("test".to_string())).is_ok());
+
+        let _ = aw(n2.download("test".to_string()));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+
+        assert!(aw(n2.download("test".to_string())).is_ok());
+    }
+
+    #[test]
+    fn big() {
+        let builder = TestNetworkBuilder::new();
+        let n1 = TestNode::new(builder.spawn());
+        let n2 = TestNode::new(builder.spawn());
+
+        let content = "hello world!".repeat(100);
+        let name = "hello".to_string();
+
+        aw(n1.upload(name.clone(), content.clone())).unwrap();
+        assert!(aw(n1.download(name.clone())).is_ok());
+
+        let _ = aw(n2.download(name.clone()));
+        std::thread::sleep(std::time::Duration::from_millis(20));
+
+        let res = aw(n2.download(name.clone()));
+        assert!(res.is_ok());
+        assert_eq!(res.unwrap(), content);
+    }
+
+    #[test]
+    fn many() {
+        let builder = TestNetworkBuilder::new();
+        let nodes = (0..32)
+            .map(|_| TestNode::new(builder.spawn()))
+            .collect::<Vec<_>>();
+
+        let content = "hello world!".repeat(100);
+        let name1 = "hello".to_string();
+        let name2 = "hello".to_string();
+        let name3 = "hello".to_string();
+
+        aw(nodes[0].upload(name1.clone(), content.clone())).unwrap();
+        aw(nodes[10].upload(name2.clone(), content.clone())).unwrap();
+        aw(nodes[20].upload(name3.clone(), content.clone())).unwrap();
+
+        builder.disable(nodes[0].network().id);
+        builder.disable(nodes[10].network().id);
+
+        let _ = aw(nodes[7].download(name1.clone()));
+        let _ = aw(nodes[13].download(name2.clone()));
+        let _ = aw(nodes[17].download(name3.clone()));
+        std::thread::sleep(std::time::Duration::from_millis(20));
+
+        let res = aw(nodes[7].download(name1.clone()));
+        assert!(res.is_ok());
+        assert_eq!(res.unwrap(), content);
+
+        let res = aw(nodes[13].download(name2.clone()));
+        assert!(res.is_ok());
+        assert_eq!(res.unwrap(), content);
+
+        let res = aw(nodes[17].download(name3.clone()));
+        assert!(res.is_ok());
+        assert_eq!(res.unwrap(), content);
+    }
+
+    #[test]
+    fn lost() {
+        let builder = TestNetworkBuilder::new();
+        let nodes = (0..8)
+            .map(|_| TestNode::new(builder.spawn()))
+            .collect::<Vec<_>>();
+
+        let content = "hello world!".repeat(30);
+        let name = "hello".to_string();
+
+        aw(nodes[0].upload(name.clone(), content.clone())).unwrap();
+        for node in nodes.iter().take(6) {
+            builder.disable(node.network().id);
+        }
+
+        let _ = aw(nodes[7].download(name.clone()));
+        std::thread::sleep(std::time::Duration::from_millis(40));
+
+        let _ = aw(nodes[7].download(name.clone()));
+        std::thread::sleep(std::time::Duration::from_millis(40));
+
+        let _ = aw(nodes[7].download(name.clone()));
+        std::thread::sleep(std::time::Duration::from_millis(40));
 
         let res = aw(nodes[7].download(name.clone()));
-        assert!(res.is_none());
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn quota() {
+        let builder = TestNetworkBuilder::new();
+        let n1 = TestNode::new(builder.spawn());
+        let n2 = TestNode::new(builder.spawn());
+
+        n2.set_quota(
+            "tenant".to_string(),
+            Quota {
+                storage: Some(640),
+                ingress: None,
+                egress: None,
+            },
+        );
+
+        let content = "hello world!".repeat(100);
+        aw(n1.upload("tenant/hello".to_string(), content.clone())).unwrap();
+        aw(n1.upload("other/hello".to_string(), content.clone())).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+
+        let stats = n2.stats();
+        assert_eq!(stats.tenants["tenant"].storage, 640);
+        assert!(stats.tenants["tenant"].rejections_sent > 0);
+        assert!(stats.tenants["other"].storage > 640);
+
+        let stats = n1.stats();
+        assert_eq!(
+            stats.tenants["tenant"].rejections_received,
+            n2.stats().tenants["tenant"].rejections_sent
+        );
+    }
+
+    #[test]
+    fn set_quotas() {
+        let builder = TestNetworkBuilder::new();
+        let n1 = TestNode::new(builder.spawn());
+        let n2 = TestNode::new(builder.spawn());
+
+        let limit = Quota {
+            storage: Some(640),
+            ingress: None,
+            egress: None,
+        };
+        n2.set_quota("tenant".to_string(), limit);
+
+        let content = "hello world!".repeat(100);
+        aw(n1.upload("tenant/a".to_string(), content.clone())).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        let usage = n2.stats().tenants["tenant"].clone();
+        assert_eq!(usage.storage, 640);
+        assert!(usage.rejections_sent > 0);
+
+        // Dropping the tenant's entry falls back to the new default, and what it stored
+        // and was refused so far stays on the books.
+        n2.set_quotas(Quota::default(), HashMap::new());
+        aw(n1.upload("tenant/b".to_string(), content.clone())).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        let reloaded = n2.stats().tenants["tenant"].clone();
+        assert!(reloaded.storage > 640);
+        assert_eq!(reloaded.rejections_sent, usage.rejections_sent);
+
+        n2.set_quotas(limit, HashMap::new());
+        aw(n1.upload("other/a".to_string(), content.clone())).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(n2.stats().tenants["other"].storage, 640);
+    }
+
+    #[test]
+    fn rate_quota() {
+        let builder = TestNetworkBuilder::new();
+        let n1 = TestNode::new(builder.spawn());
+        let n2 = TestNode::new(builder.spawn());
+
+        // Egress is exhausted outright, which must not affect ingress.
+        n2.set_quota(
+            "tenant".to_string(),
+            Quota {
+                storage: None,
+                ingress: Some(640),
+                egress: Some(0),
+            },
+        );
+
+        let content = "hello world!".repeat(100);
+        aw(n1.upload("tenant/a".to_string(), content.clone())).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+
+        let usage = n2.stats().tenants["tenant"].clone();
+        assert!(usage.ingress > 0 && usage.ingress <= 640);
+        assert!(usage.rejections_sent > 0);
+        assert_eq!(usage.egress, 0);
+
+        // The bucket refills over time rather than counting bytes forever.
+        std::thread::sleep(Duration::from_millis(500));
+        aw(n1.upload("tenant/b".to_string(), content.clone())).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert!(n2.stats().tenants["tenant"].ingress > usage.ingress);
+    }
+
+    #[test]
+    fn suspect() {
+        let builder = TestNetworkBuilder::new();
+        let n1 = TestNode::new(builder.spawn());
+        let n2 = TestNode::new(builder.spawn());
+        let _n3 = TestNode::new(builder.spawn());
+        let bad = builder.spawn();
+        let bad_id = format!("{}", bad.id);
+        corrupt(bad);
+
+        let content = "hello world!".repeat(100);
+        let name = "hello".to_string();
+
+        aw(n1.upload(name.clone(), content.clone())).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        builder.disable(n1.network().id);
+
+        let _ = aw(n2.download(name.clone()));
+        std::thread::sleep(std::time::Duration::from_millis(20));
+
+        assert_eq!(n2.suspects(), vec![bad_id]);
+        assert!(!n2.failed(&name));
+
+        let res = aw(n2.download(name.clone()));
+        assert_eq!(res.unwrap(), content);
+    }
+
+    // Without checksums a corrupt shard of the right size is only caught by the parity, once
+    // the download is decodable.
+    #[test]
+    fn unchecked() {
+        use erasure_node::store::{Directory, ShardStore};
+
+        let dir = std::env::temp_dir().join(format!("erasure-unchecked-{}", std::process::id()));
+        let content = "hello world!".repeat(100);
+        let file = File::encode_with(&content, EncodeConfig::new(4, 3)).unwrap();
+        let meta = file.metadata().clone().with_checksums(Vec::new());
+        let store = Directory::open(&dir).unwrap();
+        store.put("hello", &meta, &[]).unwrap();
+
+        let builder = TestNetworkBuilder::new();
+        let node = Node::new(builder.spawn(), NodeConfig::default());
+        let node = TestNode::from_node(node.with_store(store).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let forger = builder.spawn();
+        let forger_id = format!("{}", forger.id);
+        std::thread::spawn(move || {
+            while let Some((peer, cmd)) = aw(forger.recv()) {
+                let Command::Request { name, .. } = cmd else {
+                    continue;
+                };
+                for shard in file.shards().present_iter() {
+                    let mut data = shard.data().to_vec();
+                    if shard.index() == 0 {
+                        data[0] ^= 1;
+                    }
+                    let shard = Shard::new(shard.index(), data);
+                    let cmd = Command::Replicate {
+                        name: name.clone(),
+                        stamp: meta.stamp(),
+                        checksum: shard.crc(),
+                        shard,
+                    };
+                    aw(forger.send(peer.clone(), cmd));
+                }
+            }
+        });
+
+        let _ = aw(node.download("hello".to_string()));
+        for _ in 0..50 {
+            if !node.suspects().is_empty() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+
+        assert_eq!(node.suspects(), vec![forger_id]);
+        let res = aw(node.download_string("hello".to_string()));
+        assert_eq!(res, Ok(content));
+    }
+
+    #[test]
+    fn unhealthy() {
+        let builder = TestNetworkBuilder::new();
+        let nodes = (0..3)
+            .map(|_| TestNode::new(builder.spawn()))
+            .collect::<Vec<_>>();
+        let bad = builder.spawn();
+        let bad_id = format!("{}", bad.id);
+        let received = corrupt(bad);
+
+        let content = "hello world!".repeat(100);
+
+        aw(nodes[0].upload("first".to_string(), content.clone())).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        builder.disable(nodes[0].network().id);
+
+        let _ = aw(nodes[1].download("first".to_string()));
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(nodes[1].suspects(), vec![bad_id]);
+
+        received.lock().unwrap().clear();
+        for name in (0..8).map(|i| format!("file-{i}")) {
+            aw(nodes[1].upload(name, content.clone())).unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert!(
+            received
+                .lock()
+                .unwrap()
+                .iter()
+                .all(|cmd| !matches!(cmd, Command::Replicate { .. }))
+        );
+    }
+
+    #[test]
+    fn scores() {
+        let builder = TestNetworkBuilder::new();
+        let nodes = (0..3)
+            .map(|_| TestNode::new(builder.spawn()))
+            .collect::<Vec<_>>();
+        let bad = builder.spawn();
+        let bad_id = format!("{}", bad.id);
+        corrupt(bad);
+
+        let content = "hello world!".repeat(100);
+
+        aw(nodes[0].upload("first".to_string(), content.clone())).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        builder.disable(nodes[0].network().id);
+
+        let _ = aw(nodes[1].download("first".to_string()));
+        std::thread::sleep(Duration::from_millis(20));
+
+        let scores = nodes[1].stats().scores;
+        let good = scores[&nodes[2].network().id()];
+        assert!(scores[&bad_id] < good);
+        assert!(good > 0.0 && good <= 1.0);
+    }
+
+    #[test]
+    fn exhausted() {
+        let builder = TestNetworkBuilder::new();
+        let n1 = TestNode::new(builder.spawn());
+        let n2 = TestNode::new(builder.spawn());
+        let n3 = TestNode::new(builder.spawn());
+        corrupt(builder.spawn());
+
+        let content = "hello world!".repeat(100);
+        let name = "hello".to_string();
+
+        aw(n1.upload(name.clone(), content.clone())).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        builder.disable(n1.network().id);
+        builder.disable(n3.network().id);
+
+        let _ = aw(n2.download(name.clone()));
+        std::thread::sleep(std::time::Duration::from_millis(20));
+
+        assert!(n2.failed(&name));
+        assert!(aw(n2.download(name.clone())).is_err());
+    }
+
+    #[test]
+    fn latency() {
+        let builder = TestNetworkBuilder::new();
+        let nodes = (0..8)
+            .map(|_| TestNode::new(builder.spawn()))
+            .collect::<Vec<_>>();
+
+        let content = "hello world!".repeat(15);
+        let name = "hello".to_string();
+
+        aw(nodes[0].upload(name.clone(), content.clone())).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+
+        let mut res = Err(DecodeError::Missing);
+        for _ in 0..4 {
+            res = aw(nodes[7].download(name.clone()));
+            if res.is_ok() {
+                break;
+            }
+
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+
+        assert_eq!(res.unwrap(), content);
+
+        assert!(!nodes[7].stats().latency.is_empty());
+    }
+
+    #[test]
+    fn cancel() {
+        let builder = TestNetworkBuilder::new();
+        let n1 = TestNode::new(builder.spawn());
+        let n2 = TestNode::new(builder.spawn());
+        let n3 = TestNode::new(builder.spawn());
+        let received = spy(builder.spawn());
+
+        let content = "hello world!".repeat(100);
+        let name = "hello".to_string();
+
+        aw(n1.upload(name.clone(), content.clone())).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        builder.disable(n3.network().id);
+
+        let _ = aw(n2.download(name.clone()));
+        std::thread::sleep(std::time::Duration::from_millis(20));
+
+        let res = aw(n2.download(name.clone()));
+        assert_eq!(res.unwrap(), content);
+
+        let received = received.lock().unwrap();
+        assert!(
+            received
+                .iter()
+                .any(|cmd| matches!(cmd, Command::Request { .. }))
+        );
+        assert!(
+            received
+                .iter()
+                .any(|cmd| matches!(cmd, Command::Cancel { .. }))
+        );
+    }
+
+    #[test]
+    fn cancel_idle() {
+        let builder = TestNetworkBuilder::new();
+        let node = TestNode::new(builder.spawn());
+        let client = builder.spawn();
+        let sender = TestNetwork {
+            id: client.id,
+            builder: builder.inner.clone(),
+        };
+
+        for name in ["a", "b", "c"] {
+            let cancel = Command::Cancel { name: name.into() };
+            aw(sender.send(node.network().id(), cancel));
+        }
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(node.stats().cancellations, 0);
+        assert_eq!(node.stats().requests_queued, 0);
+    }
+
+    #[test]
+    fn adaptive() {
+        let builder = TestNetworkBuilder::new();
+        let config = NodeConfig::default()
+            .with_failure_tracking(Duration::from_millis(1), Duration::from_secs(600));
+        let n1 = TestNode::from_node(
+            Node::new(builder.spawn(), config).with_redundancy(AdaptiveRedundancy::default()),
+        );
+        let nodes = (0..7)
+            .map(|_| TestNode::new(builder.spawn()))
+            .collect::<Vec<_>>();
+
+        let content = "hello world!".repeat(100);
+
+        aw(n1.upload("calm".to_string(), content.clone())).unwrap();
+        let calm = n1.metadata("calm").unwrap();
+        assert!(calm.parity_shards() < calm.data_shards());
+
+        for node in nodes.iter().take(4) {
+            builder.disable(node.network().id);
+        }
+
+        for _ in 0..8 {
+            std::thread::sleep(Duration::from_millis(2));
+            aw(n1.upload("stormy".to_string(), content.clone())).unwrap();
+        }
+
+        let stormy = n1.metadata("stormy").unwrap();
+        assert!(stormy.parity_shards() > calm.parity_shards());
+        assert!(n1.stats().failure_rate > 0.4);
+    }
+
+    #[test]
+    fn failure_tracking() {
+        let builder = TestNetworkBuilder::new();
+        let config = NodeConfig::default()
+            .with_failure_tracking(Duration::from_millis(50), Duration::from_millis(200));
+        let n1 = TestNode::from_node(Node::new(builder.spawn(), config));
+        let nodes = (0..4)
+            .map(|_| TestNode::new(builder.spawn()))
+            .collect::<Vec<_>>();
+
+        let content = "hello world!".repeat(10);
+        aw(n1.upload("hello".to_string(), content.clone())).unwrap();
+        builder.disable(nodes[0].network().id);
+
+        // However many uploads run within one interval, the rate takes a single sample.
+        for _ in 0..8 {
+            aw(n1.upload("hello".to_string(), content.clone())).unwrap();
+        }
+        assert_eq!(n1.stats().failure_rate, 0.0);
+
+        std::thread::sleep(Duration::from_millis(60));
+        aw(n1.upload("hello".to_string(), content.clone())).unwrap();
+        let rate = n1.stats().failure_rate;
+        assert!(rate > 0.0 && rate < 0.25, "{rate}");
+
+        // Once it has been gone for the ttl, the disabled node is forgotten.
+        let gone = format!("{}", nodes[0].network().id);
+        assert!(n1.stats().scores.contains_key(&gone));
+        std::thread::sleep(Duration::from_millis(250));
+        aw(n1.upload("hello".to_string(), content.clone())).unwrap();
+        assert!(!n1.stats().scores.contains_key(&gone));
+        assert_eq!(n1.stats().scores.len(), 3);
+    }
+
+    #[test]
+    fn join() {
+        let builder = TestNetworkBuilder::new();
+        let nodes = (0..5)
+            .map(|_| TestNode::new(builder.spawn()))
+            .collect::<Vec<_>>();
+
+        let content = "hello world!".repeat(100);
+        let name = "hello".to_string();
+
+        aw(nodes[0].upload(name.clone(), content.clone())).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+
+        let joiner = TestNode::new(builder.spawn());
+        assert!(joiner.metadata(&name).is_none());
+
+        aw(joiner.join());
+        std::thread::sleep(std::time::Duration::from_millis(20));
+
+        assert!(joiner.metadata(&name).is_some());
+        assert!(joiner.stats().tenants[""].storage > 0);
+    }
+
+    #[test]
+    fn backfill() {
+        let builder = TestNetworkBuilder::new();
+        let nodes = (0..5)
+            .map(|_| TestNode::new(builder.spawn()))
+            .collect::<Vec<_>>();
+
+        let content = "hello world!".repeat(100);
+        let name = "hello".to_string();
+
+        aw(nodes[0].upload(name.clone(), content.clone())).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+
+        let recorder = Recorder::default();
+        let joiner = TestNode::from_node(
+            Node::new(builder.spawn(), NodeConfig::default()).with_observer(recorder.clone()),
+        );
+        aw(joiner.join());
+
+        for _ in 0..50 {
+            if joiner.metadata(&name).is_some() && joiner.stats().backfill_pending == 0 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+
+        let members = nodes
+            .iter()
+            .chain([&joiner])
+            .map(|node| node.network().id())
+            .collect::<Vec<_>>();
+        let owned = (0..joiner.metadata(&name).unwrap().shard_count())
+            .filter(|index| {
+                placement::owner(&name, *index, &members) == Some(joiner.network().id())
+            })
+            .collect::<Vec<_>>();
+
+        let stats = joiner.stats();
+        assert!(!owned.is_empty());
+        assert_eq!(stats.backfill_pending, 0);
+        assert_eq!(stats.backfilled, owned.len());
+        assert_eq!(joiner.shards(&name), owned);
+
+        let fetches = recorder
+            .sent
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, cmd)| matches!(cmd, Command::Fetch { .. }))
+            .count();
+        assert_eq!(fetches, owned.len());
+    }
+
+    #[test]
+    fn throttled() {
+        let builder = TestNetworkBuilder::new();
+        let recorder = Recorder::default();
+        let joiner = TestNode::from_node(
+            Node::new(builder.spawn(), NodeConfig::default()).with_observer(recorder.clone()),
+        );
+        let probe = builder.spawn();
+        for _ in 0..3 {
+            spy(builder.spawn());
+        }
+
+        let file = File::encode("hello world!".repeat(100)).unwrap();
+        let name = "hello".to_string();
+        let files = vec![(name.clone(), file.metadata().clone())];
+        aw(probe.send(joiner.network().id(), Command::Manifest { files }));
+        std::thread::sleep(Duration::from_millis(20));
+
+        let pending = joiner.stats().backfill_pending;
+        assert!(pending > 4);
+
+        let fetches = recorder
+            .sent
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, cmd)| matches!(cmd, Command::Fetch { .. }))
+            .count();
+        assert_eq!(fetches, 4);
+        assert_eq!(aw(joiner.backfill()), pending);
+    }
+
+    #[test]
+    fn transfer() {
+        let builder = TestNetworkBuilder::new();
+        let mut nodes = (0..3)
+            .map(|_| TestNode::new(builder.spawn()))
+            .collect::<Vec<_>>();
+
+        let content = "hello world!".repeat(100);
+        let name = "hello".to_string();
+
+        aw(nodes[0].upload(name.clone(), content.clone())).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+
+        nodes.extend((0..5).map(|_| TestNode::new(builder.spawn())));
+        assert!(aw(nodes[0].repair(&name)));
+        std::thread::sleep(Duration::from_millis(20));
+
+        let placements = nodes[0].placements();
+        for node in &nodes[1..] {
+            let id = node.network().id();
+            let mut placed = placements
+                .iter()
+                .filter(|(file, _, peer)| *file == name && *peer == id)
+                .map(|(_, index, _)| *index)
+                .collect::<Vec<_>>();
+            placed.sort();
+
+            assert_eq!(node.shards(&name), placed);
+        }
+    }
+
+    #[test]
+    fn handoff() {
+        let builder = TestNetworkBuilder::new();
+        let mut nodes = (0..3)
+            .map(|_| TestNode::new(builder.spawn()))
+            .collect::<Vec<_>>();
+
+        let content = "hello world!".repeat(100);
+        let name = "hello".to_string();
+
+        aw(nodes[0].upload(name.clone(), content.clone())).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        let before = nodes[0].placements();
+
+        // Shards moved to a holder that never answers stay with their old holder.
+        let silent = builder.spawn();
+        nodes.extend((0..2).map(|_| TestNode::new(builder.spawn())));
+        assert!(aw(nodes[0].repair(&name)));
+        std::thread::sleep(Duration::from_millis(20));
+
+        let stranded = nodes[0]
+            .placements()
+            .into_iter()
+            .filter(|(_, _, peer)| *peer == silent.id())
+            .map(|(_, index, _)| index)
+            .collect::<Vec<_>>();
+        assert!(!stranded.is_empty());
+        for index in &stranded {
+            let Some((_, _, old)) = before.iter().find(|(_, held, _)| held == index) else {
+                continue;
+            };
+            let old = nodes
+                .iter()
+                .find(|node| node.network().id() == *old)
+                .unwrap();
+            assert!(old.shards(&name).contains(index));
+        }
+
+        let stats = nodes[0].stats();
+        assert_eq!(stats.handoffs_pending, stranded.len());
+        assert!(stats.handoffs_completed > 0);
+    }
+
+    #[test]
+    fn drop_refused() {
+        let builder = TestNetworkBuilder::new();
+        let n1 = TestNode::new(builder.spawn());
+        let n2 = TestNode::new(builder.spawn());
+
+        let name = "hello".to_string();
+        aw(n1.upload(name.clone(), "hello world!".repeat(100))).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        let held = n2.shards(&name);
+        assert!(!held.is_empty());
+
+        // Only the writer of the version held may move its shards away.
+        let probe = builder.spawn();
+        aw(probe.release(n2.network().id(), name.clone(), held.clone()));
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(n2.shards(&name), held);
+    }
+
+    #[test]
+    fn partial() {
+        let builder = TestNetworkBuilder::new();
+        let n1 = TestNode::new(builder.spawn());
+        let n2 = TestNode::new(builder.spawn());
+
+        let content = "hello world!".repeat(100);
+        let name = "hello".to_string();
+
+        aw(n1.upload(name.clone(), content.clone())).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(!n2.shards(&name).is_empty());
+
+        let probe = builder.spawn();
+
+        let held = n1.shards(&name);
+        let (skipped, wanted) = held.split_at(held.len() / 2);
+        let mut bitmap = vec![0; n1.metadata(&name).unwrap().shard_count().div_ceil(8)];
+        for index in skipped {
+            bitmap[index / 8] |= 1 << (index % 8);
+        }
+
+        aw(probe.send(
+            n1.network().id(),
+            Command::Request {
+                name: name.clone(),
+                bitmap,
+            },
+        ));
+
+        let mut received = Vec::new();
+        while received.len() < wanted.len() {
+            match aw(probe.recv()) {
+                Some((_, Command::Replicate { shard, .. })) => received.push(shard.index()),
+                Some(_) => {}
+                None => break,
+            }
+        }
+        received.sort();
+
+        assert_eq!(received, wanted);
+    }
+
+    #[test]
+    fn resume() {
+        let builder = TestNetworkBuilder::new();
+        let n1 = TestNode::new(builder.spawn());
+        let n2 = TestNode::new(builder.spawn());
+        let received = spy(builder.spawn());
+
+        let content = "hello world!".repeat(100);
+        let name = "hello".to_string();
+
+        aw(n1.upload(name.clone(), content.clone())).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+
+        let restarted = TestNode::new(builder.spawn());
+        for (file, meta, shards) in n2.snapshot() {
+            restarted.restore(file, meta, shards).unwrap();
+        }
+        let held = restarted.shards(&name);
+        assert!(!held.is_empty());
+
+        let _ = aw(restarted.download(name.clone()));
+        assert_eq!(restarted.downloads(), vec![name.clone()]);
+
+        let mut res = Err(DecodeError::Missing);
+        for _ in 0..10 {
+            std::thread::sleep(Duration::from_millis(20));
+            res = aw(restarted.download(name.clone()));
+            if res.is_ok() {
+                break;
+            }
+        }
+        assert_eq!(res.unwrap(), content);
+        assert!(restarted.downloads().is_empty());
+
+        let received = received.lock().unwrap();
+        for cmd in received.iter() {
+            if let Command::Request { bitmap, .. } = cmd {
+                let requested = file::indices(bitmap);
+                assert!(held.iter().all(|index| requested.contains(index)));
+            }
+        }
+    }
+
+    // A download the node was restarted in the middle of picks up from the shards its store
+    // kept instead of fetching the file again.
+    #[test]
+    fn resume_stored() {
+        use erasure_node::store::{Directory, ShardStore};
+
+        let builder = TestNetworkBuilder::new();
+        let n1 = TestNode::new(builder.spawn());
+        let _n2 = TestNode::new(builder.spawn());
+        let received = spy(builder.spawn());
+
+        let content = "hello world!".repeat(100);
+        let name = "hello".to_string();
+        aw(n1.upload(name.clone(), content.clone())).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+
+        let dir = std::env::temp_dir().join(format!("erasure-resume-{}", std::process::id()));
+        let (_, meta, shards) = n1.snapshot().into_iter().next().unwrap();
+        let arrived = &shards[..meta.data_shards() - 1];
+        let held = arrived
+            .iter()
+            .map(|shard| shard.index())
+            .collect::<Vec<_>>();
+        {
+            let store = Directory::open(&dir).unwrap();
+            store.put(&name, &meta, arrived).unwrap();
+            store.put_download(&name, &held).unwrap();
+        }
+
+        let node = Node::new(builder.spawn(), NodeConfig::default());
+        let restarted =
+            TestNode::from_node(node.with_store(Directory::open(&dir).unwrap()).unwrap());
+        assert_eq!(restarted.downloads(), vec![name.clone()]);
+        assert_eq!(restarted.shards(&name), held);
+
+        let mut res = Err(DecodeError::Missing);
+        for _ in 0..10 {
+            res = aw(restarted.download(name.clone()));
+            if res.is_ok() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(res.unwrap(), content);
+        assert!(restarted.downloads().is_empty());
+        assert!(
+            Directory::open(&dir)
+                .unwrap()
+                .downloads()
+                .unwrap()
+                .is_empty()
+        );
+        // Shards still arriving may be written meanwhile.
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let received = received.lock().unwrap();
+        for cmd in received.iter() {
+            if let Command::Request { bitmap, .. } = cmd {
+                let requested = file::indices(bitmap);
+                assert!(held.iter().all(|index| requested.contains(index)));
+            }
+        }
+    }
+
+    #[test]
+    fn ingest() {
+        let builder = TestNetworkBuilder::new();
+        let nodes = (0..4)
+            .map(|_| TestNode::new(builder.spawn()))
+            .collect::<Vec<_>>();
+
+        let content = "hello world!".repeat(100);
+        let name = "hello".to_string();
+
+        aw(nodes[0].delegate(nodes[1].network().id(), name.clone(), content.clone()));
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert!(nodes[0].metadata(&name).is_some());
+        assert!(!nodes[2].shards(&name).is_empty());
+        assert_eq!(aw(nodes[1].download(name.clone())).unwrap(), content);
+
+        let _ = aw(nodes[3].download(name.clone()));
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(aw(nodes[3].download(name.clone())).unwrap(), content);
+    }
+
+    #[test]
+    fn unknown() {
+        let builder = TestNetworkBuilder::new();
+        let nodes = (0..3)
+            .map(|_| TestNode::new(builder.spawn()))
+            .collect::<Vec<_>>();
+
+        let content = "hello world!".repeat(100);
+        let name = "hello".to_string();
+
+        aw(nodes[0].upload(name.clone(), content.clone())).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+
+        let stranger = TestNode::new(builder.spawn());
+        assert!(stranger.metadata(&name).is_none());
+        assert_eq!(
+            aw(stranger.download(name.clone())),
+            Err(DecodeError::Missing)
+        );
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(stranger.metadata(&name), nodes[0].metadata(&name));
+        assert_eq!(aw(stranger.try_download(&name)).unwrap(), content);
+    }
+
+    #[test]
+    fn observer() {
+        let builder = TestNetworkBuilder::new();
+        let uploader = Recorder::default();
+        let n1 = TestNode::from_node(
+            Node::new(builder.spawn(), NodeConfig::default()).with_observer(uploader.clone()),
+        );
+        let downloader = Recorder::default();
+        let n2 = TestNode::from_node(
+            Node::new(builder.spawn(), NodeConfig::default()).with_observer(downloader.clone()),
+        );
+
+        let content = "hello world!".repeat(100);
+        let name = "hello".to_string();
+
+        aw(n1.upload(name.clone(), content.clone())).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+
+        let sent = uploader.sent.lock().unwrap().clone();
+        let received = downloader.received.lock().unwrap().clone();
+        assert!(!sent.is_empty());
+        assert_eq!(
+            sent.iter().map(|(_, cmd)| cmd).collect::<Vec<_>>(),
+            received.iter().map(|(_, cmd)| cmd).collect::<Vec<_>>()
+        );
+        assert!(sent.iter().all(|(peer, _)| *peer == n2.network().id()));
+
+        let mut stored = downloader.stored.lock().unwrap().clone();
+        stored.sort();
+        assert_eq!(
+            stored,
+            n2.shards(&name)
+                .into_iter()
+                .map(|index| (name.clone(), index))
+                .collect::<Vec<_>>()
+        );
+
+        let _ = aw(n2.download(name.clone()));
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(aw(n2.try_download(&name)).unwrap(), content);
+
+        let decoded = downloader.decoded.lock().unwrap().clone();
+        assert_eq!(decoded.last(), Some(&(name, Ok(content.into()))));
+    }
+
+    #[test]
+    fn reuse() {
+        let builder = TestNetworkBuilder::new();
+        let n1 = TestNode::new(builder.spawn());
+        let n2 = TestNode::new(builder.spawn());
+        let _n3 = TestNode::new(builder.spawn());
+
+        let content = "hello world!".repeat(100);
+        aw(n1.upload("first".to_string(), content.clone())).unwrap();
+        aw(n1.upload("second".to_string(), content.clone())).unwrap();
+        aw(n1.upload("third".to_string(), "goodbye world!".repeat(100))).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+
+        let stats = n1.stats();
+        assert_eq!(stats.encodes_reused, 1);
+        assert!(stats.encode_time_saved > Duration::ZERO);
+        let unstamped = |name| {
+            n1.metadata(name)
+                .map(|meta| meta.with_stamp(Stamp::default()).with_created_at(0))
+        };
+        assert_eq!(unstamped("first"), unstamped("second"));
+        assert_ne!(n1.metadata("first"), n1.metadata("second"));
+
+        let _ = aw(n2.download("second".to_string()));
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(aw(n2.try_download(&"second".to_string())).unwrap(), content);
+    }
+
+    #[test]
+    fn bandwidth() {
+        let builder = TestNetworkBuilder::new();
+        let nodes = (0..5)
+            .map(|_| TestNode::new(builder.spawn()))
+            .collect::<Vec<_>>();
+        for id in 0..5 {
+            builder.throughput(id, if id == 1 { 10000 } else { 1000 });
+        }
+
+        let content = "hello world!".repeat(100);
+        let name = "hello".to_string();
+
+        aw(nodes[0].upload(name.clone(), content.clone())).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+
+        let meta = nodes[0].metadata(&name).unwrap();
+        let fast = nodes[1].shards(&name).len();
+        for node in &nodes[2..] {
+            assert!(node.shards(&name).len() < fast);
+        }
+        assert!(fast <= meta.parity_shards());
+
+        let _ = aw(nodes[2].download(name.clone()));
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(aw(nodes[2].try_download(&name)).unwrap(), content);
+    }
+
+    #[test]
+    fn orphaned() {
+        let builder = TestNetworkBuilder::new();
+        let nodes = (0..5)
+            .map(|_| TestNode::new(builder.spawn()))
+            .collect::<Vec<_>>();
+
+        let content = "hello world!".repeat(100);
+        let name = "hello".to_string();
+
+        aw(nodes[0].upload(name.clone(), content.clone())).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+
+        let meta = nodes[0].metadata(&name);
+        for node in &nodes[1..] {
+            assert_eq!(node.metadata(&name), meta);
+        }
+
+        builder.disable(nodes[0].network().id);
+        builder.disable(nodes[1].network().id);
+
+        let stranger = TestNode::new(builder.spawn());
+        let _ = aw(stranger.download(name.clone()));
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(stranger.metadata(&name), meta);
+        assert_eq!(aw(stranger.try_download(&name)).unwrap(), content);
+    }
+
+    // A member that was away for the upload gets the metadata from the other manifest owners
+    // once it is back, even with the uploader gone.
+    #[test]
+    fn manifests() {
+        let builder = TestNetworkBuilder::new();
+        let config = NodeConfig::default()
+            .with_repair_interval(Duration::from_millis(10))
+            .with_manifest_replicas(5);
+        let nodes = (0..5)
+            .map(|_| TestNode::from_node(Node::new(builder.spawn(), config.clone())))
+            .collect::<Vec<_>>();
+        builder.disable(nodes[4].network().id);
+
+        let content = "hello world!".repeat(100);
+        let name = "hello".to_string();
+        aw(nodes[0].upload(name.clone(), content)).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(nodes[4].metadata(&name), None);
+
+        builder.enable(nodes[4].network().id);
+        builder.disable(nodes[0].network().id);
+        let meta = nodes[1].metadata(&name);
+        for _ in 0..50 {
+            if nodes[4].metadata(&name).is_some() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(meta.is_some());
+        assert_eq!(nodes[4].metadata(&name), meta);
+    }
+
+    #[test]
+    fn encoding() {
+        let builder = TestNetworkBuilder::new();
+        let config = NodeConfig::default().with_encoding(EncodeConfig::new(4, 2));
+        let n1 = TestNode::from_node(Node::new(builder.spawn(), config));
+        let n2 = TestNode::new(builder.spawn());
+
+        let content = "hello world!".repeat(20);
+        let name = "hello".to_string();
+        aw(n1.upload(name.clone(), content.clone())).unwrap();
+
+        let meta = n1.metadata(&name).unwrap();
+        assert_eq!((meta.data_shards(), meta.parity_shards()), (4, 2));
+        std::thread::sleep(Duration::from_millis(20));
+
+        let _ = aw(n2.download(name.clone()));
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(aw(n2.try_download_string(&name)), Ok(content));
+    }
+
+    #[test]
+    fn codec() {
+        let builder = TestNetworkBuilder::new();
+        let encoding = EncodeConfig::new(1, 2).with_codec(CodecId::Replication);
+        let config = NodeConfig::default().with_encoding(encoding);
+        let n1 = TestNode::from_node(Node::new(builder.spawn(), config));
+        let n2 = TestNode::new(builder.spawn());
+
+        let content = "hello world!".repeat(20);
+        let name = "hello".to_string();
+        aw(n1.upload(name.clone(), content.clone())).unwrap();
+
+        let meta = n1.metadata(&name).unwrap();
+        assert_eq!(
+            (meta.codec(), meta.shard_count()),
+            (CodecId::Replication, 3)
+        );
+        std::thread::sleep(Duration::from_millis(20));
+
+        let _ = aw(n2.download(name.clone()));
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(aw(n2.try_download_string(&name)), Ok(content));
+    }
+
+    #[test]
+    fn blocks() {
+        let builder = TestNetworkBuilder::new();
+        let encoding = EncodeConfig::new(4, 2).with_block_size(256);
+        let config = NodeConfig::default().with_encoding(encoding);
+        let n1 = TestNode::from_node(Node::new(builder.spawn(), config));
+        let n2 = TestNode::new(builder.spawn());
+
+        let content = "hello world!".repeat(100);
+        let name = "hello".to_string();
+        aw(n1.upload(name.clone(), content.clone())).unwrap();
+
+        let meta = n1.metadata(&name).unwrap();
+        assert_eq!((meta.block_count(), meta.shard_count()), (5, 30));
+        std::thread::sleep(Duration::from_millis(20));
+
+        let _ = aw(n2.download(name.clone()));
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(aw(n2.try_download_string(&name)), Ok(content));
+    }
+
+    #[test]
+    fn stream() {
+        let builder = TestNetworkBuilder::new();
+        let encoding = EncodeConfig::new(4, 2).with_block_size(256);
+        let config = NodeConfig::default().with_encoding(encoding);
+        let n1 = TestNode::from_node(Node::new(builder.spawn(), config));
+        let config = NodeConfig::default().with_stream_timeout(Duration::from_millis(100));
+        let n2 = TestNode::from_node(Node::new(builder.spawn(), config));
+
+        let content = "hello world!".repeat(100);
+        let name = "hello".to_string();
+        aw(n1.upload(name.clone(), content.clone())).unwrap();
+
+        let stripes = aw(n2.download_stream(name.clone()).collect::<Vec<_>>());
+        assert_eq!(stripes.len(), 5);
+        assert!(stripes[..4].iter().all(|stripe| stripe.len() == 256));
+        assert_eq!(stripes.concat(), content.as_bytes());
+        assert!(n2.downloads().is_empty());
+
+        let start = Instant::now();
+        let missing = aw(n2
+            .download_stream("missing".to_string())
+            .collect::<Vec<_>>());
+        assert!(missing.is_empty());
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn upload_stream() {
+        let builder = TestNetworkBuilder::new();
+        let encoding = EncodeConfig::new(4, 2)
+            .with_block_size(256)
+            .with_max_memory(6 * 64);
+        let config = NodeConfig::default().with_encoding(encoding);
+        let n1 = TestNode::from_node(Node::new(builder.spawn(), config));
+        let n2 = TestNode::new(builder.spawn());
+        let n3 = TestNode::new(builder.spawn());
+
+        let content = "hello world!".repeat(100);
+        let name = "hello".to_string();
+        let mut upload = aw(n1.upload_stream(name.clone())).unwrap();
+        aw(upload.write(&content.as_bytes()[..600])).unwrap();
+
+        // Both full blocks are out before the upload finishes; holders keep them as orphans.
+        let placed = n1.placements().len();
+        assert!(placed > 0);
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while n2.stats().orphans_held + n3.stats().orphans_held < placed {
+            assert!(Instant::now() < deadline);
+            std::thread::yield_now();
+        }
+        assert!(n2.metadata(&name).is_none());
+
+        aw(upload.write(&content.as_bytes()[600..])).unwrap();
+        aw(upload.finish()).unwrap();
+        let meta = n1.metadata(&name).unwrap();
+        assert_eq!((meta.len(), meta.block_count()), (1200, 5));
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while n2.stats().orphans_adopted + n3.stats().orphans_adopted < placed {
+            assert!(Instant::now() < deadline);
+            std::thread::yield_now();
+        }
+        let _ = aw(n2.download(name.clone()));
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(aw(n2.try_download_string(&name)), Ok(content));
+    }
+
+    #[test]
+    #[cfg(any(feature = "lz4", feature = "zstd"))]
+    fn upload_stream_compressed() {
+        use erasure_node::compress::Compression;
+
+        let algorithms = [
+            #[cfg(feature = "lz4")]
+            Compression::Lz4,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd,
+        ];
+        for compression in algorithms {
+            let builder = TestNetworkBuilder::new();
+            let encoding = EncodeConfig::new(4, 2).with_block_size(256);
+            let config = NodeConfig::default()
+                .with_encoding(encoding)
+                .with_compression(compression);
+            let n1 = TestNode::from_node(Node::new(builder.spawn(), config));
+            let n2 = TestNode::new(builder.spawn());
+
+            let content = "hello world!".repeat(100);
+            let name = "hello".to_string();
+            let mut upload = aw(n1.upload_stream(name.clone())).unwrap();
+            aw(upload.write(content.as_bytes())).unwrap();
+            aw(upload.finish()).unwrap();
+            let meta = n1.metadata(&name).unwrap();
+            assert_eq!(meta.compression(), compression);
+
+            std::thread::sleep(Duration::from_millis(20));
+            let _ = aw(n2.download(name.clone()));
+            std::thread::sleep(Duration::from_millis(20));
+            assert_eq!(aw(n2.try_download_string(&name)), Ok(content));
+        }
+    }
+
+    #[test]
+    fn store() {
+        let dir = std::env::temp_dir().join(format!("erasure-node-store-{}", std::process::id()));
+        let builder = TestNetworkBuilder::new();
+        let config = NodeConfig::default().with_store(StoreConfig::Directory(dir.clone()));
+        let node = Node::open(builder.spawn(), config.clone()).unwrap();
+        aw(node.upload("hello".to_string(), "hello world".repeat(20))).unwrap();
+        aw(node.upload("gone".to_string(), "goodbye")).unwrap();
+        assert!(aw(node.delete("gone")));
+        drop(node);
+
+        let node = Node::open(builder.spawn(), config).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            aw(node.download_string("hello".to_string())),
+            Ok("hello world".repeat(20))
+        );
+        assert!(node.metadata("gone").is_none());
+    }
+
+    #[test]
+    fn empty() {
+        let builder = TestNetworkBuilder::new();
+        let n1 = TestNode::new(builder.spawn());
+        let n2 = TestNode::new(builder.spawn());
+
+        aw(n1.upload("empty".to_string(), String::new())).unwrap();
+        assert_eq!(n1.metadata("empty").unwrap().len(), 0);
+        assert_eq!(
+            aw(n1.download_string("empty".to_string())),
+            Ok(String::new())
+        );
+
+        let _ = aw(n2.download("empty".to_string()));
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(
+            aw(n2.download_string("empty".to_string())),
+            Ok(String::new())
+        );
+    }
+
+    #[test]
+    fn binary() {
+        let builder = TestNetworkBuilder::new();
+        let n1 = TestNode::new(builder.spawn());
+        let n2 = TestNode::new(builder.spawn());
+
+        let name = "binary".to_string();
+        let content = (0..4096).map(|i| (i * 31 % 256) as u8).collect::<Vec<_>>();
+        assert!(String::from_utf8(content.clone()).is_err());
+
+        aw(n1.upload(name.clone(), content.clone())).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let mut res = aw(n2.download(name.clone()));
+        while res.is_err() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+            res = aw(n2.download(name.clone()));
+        }
+        assert_eq!(res, Ok(Bytes::from(content)));
+        assert_eq!(
+            aw(n2.download_string(name)),
+            Err(DecodeError::InvalidContent)
+        );
+    }
+
+    #[test]
+    fn delete() {
+        let builder = TestNetworkBuilder::new();
+        let nodes = (0..3)
+            .map(|_| TestNode::new(builder.spawn()))
+            .collect::<Vec<_>>();
+
+        let content = "hello world!".repeat(100);
+        let name = "hello".to_string();
+
+        aw(nodes[0].upload(name.clone(), content)).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(nodes[1..].iter().all(|node| !node.shards(&name).is_empty()));
+
+        assert!(aw(nodes[1].delete(&name)));
+        assert!(!aw(nodes[1].delete(&name)));
+        std::thread::sleep(Duration::from_millis(20));
+
+        for node in &nodes {
+            assert_eq!(node.metadata(&name), None);
+            assert!(node.shards(&name).is_empty());
+        }
+    }
+
+    #[test]
+    fn upload_with() {
+        let builder = TestNetworkBuilder::new();
+        let n1 = TestNode::new(builder.spawn());
+        let n2 = TestNode::new(builder.spawn());
+
+        let name = "hello".to_string();
+        let attributes = BTreeMap::from([("owner".to_string(), "alice".to_string())]);
+        aw(n1.upload_with(
+            name.clone(),
+            "hello world!",
+            Some("text/plain".into()),
+            attributes.clone(),
+        ))
+        .unwrap();
+
+        let meta = n1.metadata(&name).unwrap();
+        let created_at = meta.created_at();
+        assert_eq!(created_at, meta.stamp().time);
+        assert_eq!(meta.content_type(), Some("text/plain"));
+        assert_eq!(meta.attributes(), &attributes);
+
+        // Overwrites keep the creation time but replace the rest.
+        aw(n1.upload(name.clone(), "goodbye world!")).unwrap();
+        let meta = n1.metadata(&name).unwrap();
+        assert_eq!(meta.created_at(), created_at);
+        assert!(meta.stamp().time > created_at);
+        assert_eq!(meta.content_type(), None);
+        assert!(meta.attributes().is_empty());
+
+        let _ = aw(n2.download(name.clone()));
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(aw(n2.download(name.clone())), Ok("goodbye world!".into()));
+        assert_eq!(n2.metadata(&name).unwrap().created_at(), created_at);
+    }
+
+    #[test]
+    fn shard_size() {
+        let builder = TestNetworkBuilder::new();
+        let config = NodeConfig::default().with_shard_size(256);
+        let n1 = TestNode::from_node(Node::new(builder.spawn(), config));
+        let _n2 = TestNode::new(builder.spawn());
+
+        let content = "hello world!".repeat(50);
+        let name = "hello".to_string();
+        aw(n1.upload(name.clone(), content.clone())).unwrap();
+
+        let meta = n1.metadata(&name).unwrap();
+        assert_eq!((meta.shard_size(), meta.data_shards()), (256, 3));
+        assert_eq!(aw(n1.download_string(name)), Ok(content));
+    }
+
+    #[cfg(feature = "signing")]
+    #[test]
+    fn signed() {
+        use erasure_node::sign::SigningKey;
+
+        let builder = TestNetworkBuilder::new();
+        let trusted = SigningKey::from_bytes(&[5; 32]);
+        let config = NodeConfig::default().with_signing_key(trusted.clone());
+        let n1 = TestNode::from_node(Node::new(builder.spawn(), config));
+        let config = NodeConfig::default().with_signing_key(SigningKey::from_bytes(&[6; 32]));
+        let n2 = TestNode::from_node(Node::new(builder.spawn(), config));
+        let config = NodeConfig::default().with_trusted_signer(trusted.verifying_key());
+        let n3 = TestNode::from_node(Node::new(builder.spawn(), config));
+
+        let content = "hello world!".repeat(20);
+        aw(n1.upload("trusted".to_string(), content.clone())).unwrap();
+        aw(n2.upload("untrusted".to_string(), content.clone())).unwrap();
+
+        // Ed25519 is slow in debug builds, so give the peers time to check both uploads.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while (n3.metadata("trusted").is_none() || n1.metadata("untrusted").is_none())
+            && Instant::now() < deadline
+        {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let meta = n3.metadata("trusted").unwrap();
+        assert_eq!(meta.signer(), Some(trusted.verifying_key().to_bytes()));
+        assert!(meta.verify_signature());
+        assert!(n3.metadata("untrusted").is_none());
+        assert!(n1.metadata("untrusted").is_some());
+
+        let mut res = aw(n3.download_string("trusted".to_string()));
+        while res.is_err() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+            res = aw(n3.download_string("trusted".to_string()));
+        }
+        assert_eq!(res, Ok(content));
+    }
+
+    #[test]
+    fn repair_interval() {
+        let builder = TestNetworkBuilder::new();
+        let config = NodeConfig::default().with_repair_interval(Duration::ZERO);
+        let n1 = TestNode::from_node(Node::new(builder.spawn(), config));
+
+        let name = "hello".to_string();
+        aw(n1.upload(name.clone(), "hello world!".repeat(10))).unwrap();
+        assert_eq!(n1.unplaced(), vec![name.clone()]);
+
+        let n2 = TestNode::new(builder.spawn());
+        aw(n2
+            .network()
+            .send(n1.network().id(), Command::Cancel { name }));
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert!(n1.unplaced().is_empty());
+        assert!(n2.metadata("hello").is_some());
+    }
+
+    #[test]
+    fn repair_timer() {
+        let builder = TestNetworkBuilder::new();
+        let config = NodeConfig::default().with_repair_interval(Duration::from_millis(5));
+        let n1 = TestNode::from_node(Node::new(builder.spawn(), config));
+
+        let name = "hello".to_string();
+        aw(n1.upload(name.clone(), "hello world!".repeat(10))).unwrap();
+        assert_eq!(n1.unplaced(), vec![name.clone()]);
+
+        let n2 = TestNode::new(builder.spawn());
+        std::thread::sleep(Duration::from_millis(50));
+
+        assert!(n1.unplaced().is_empty());
+        assert!(n2.metadata(&name).is_some());
+    }
+
+    #[test]
+    fn set_repair_interval() {
+        let builder = TestNetworkBuilder::new();
+        let config = NodeConfig {
+            audit_timeout: Duration::from_millis(5),
+            ..NodeConfig::default()
+        };
+        let n1 = TestNode::from_node(Node::new(builder.spawn(), config));
+
+        let name = "hello".to_string();
+        aw(n1.upload(name.clone(), "hello world!".repeat(10))).unwrap();
+        let n2 = TestNode::new(builder.spawn());
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(n1.unplaced(), vec![name.clone()]);
+
+        n1.set_repair_interval(Some(Duration::from_millis(5)));
+        assert_eq!(n1.repair_interval(), Some(Duration::from_millis(5)));
+        std::thread::sleep(Duration::from_millis(50));
+
+        assert!(n1.unplaced().is_empty());
+        assert!(n2.metadata(&name).is_some());
+    }
+
+    #[test]
+    fn locality() {
+        let builder = TestNetworkBuilder::new();
+        let config = NodeConfig::default().with_encoding(EncodeConfig::new(1, 3));
+        let recorders = (0..6).map(|_| Recorder::default()).collect::<Vec<_>>();
+        let nodes = recorders
+            .iter()
+            .map(|recorder| {
+                let node = Node::new(builder.spawn(), config.clone());
+                TestNode::from_node(node.with_observer(recorder.clone()))
+            })
+            .collect::<Vec<_>>();
+
+        let content = "hello world!".repeat(10);
+        let name = "hello".to_string();
+        aw(nodes[0].upload(name.clone(), content.clone())).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+
+        // Every shard decodes the file alone, so one holder in the downloader's region is
+        // enough and nobody in the other region is asked.
+        let holders = nodes[0]
+            .placements()
+            .into_iter()
+            .map(|(_, _, peer)| peer.parse::<usize>().unwrap())
+            .collect::<Vec<_>>();
+        let downloader = (1..6)
+            .find(|id| nodes[*id].shards(&name).is_empty())
+            .unwrap();
+        for id in 0..6 {
+            builder.region(id, "west");
+        }
+        builder.region(downloader, "east");
+        builder.region(holders[0], "east");
+
+        let meta = nodes[0].metadata(&name).unwrap();
+        let manifest = Command::Manifest {
+            files: vec![(name.clone(), meta)],
+        };
+        aw(nodes[0]
+            .network()
+            .send(downloader.to_string(), manifest.clone()));
+        std::thread::sleep(Duration::from_millis(20));
+        recorders[downloader].sent.lock().unwrap().clear();
+
+        let requested = || {
+            recorders[downloader]
+                .sent
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(_, cmd)| matches!(cmd, Command::Request { .. }))
+                .map(|(peer, _)| peer.parse::<usize>().unwrap())
+                .collect::<HashSet<_>>()
+        };
+
+        let mut res = aw(nodes[downloader].download_string(name.clone()));
+        for _ in 0..50 {
+            if res.is_ok() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+            res = aw(nodes[downloader].download_string(name.clone()));
+        }
+        assert_eq!(res, Ok(content.clone()));
+        assert_eq!(requested(), HashSet::from([holders[0]]));
+
+        // With no holder left in the region, the download crosses over.
+        builder.disable(holders[0]);
+        let other = (1..6)
+            .find(|id| *id != downloader && !holders[..1].contains(id))
+            .unwrap();
+        builder.region(other, "east");
+        aw(nodes[0].network().send(other.to_string(), manifest));
+        std::thread::sleep(Duration::from_millis(20));
+
+        let mut res = aw(nodes[other].download_string(name.clone()));
+        for _ in 0..50 {
+            if res.is_ok() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+            res = aw(nodes[other].download_string(name.clone()));
+        }
+        assert_eq!(res, Ok(content));
+    }
+
+    #[test]
+    fn recv_timeout() {
+        let builder = TestNetworkBuilder::new();
+        let (n1, n2) = (builder.spawn(), builder.spawn());
+
+        let timeout = Duration::from_millis(5);
+        assert_eq!(aw(n1.recv_timeout(timeout)), Err(Error::Timeout));
+
+        aw(n2.announce(n1.id()));
+        let msg = aw(n1.recv_timeout(timeout));
+        assert_eq!(msg, Ok((n2.id(), Command::Announce)));
+    }
+
+    #[test]
+    fn pull() {
+        let builder = TestNetworkBuilder::new();
+        let config = NodeConfig::default().with_replication(Replication::Pull);
+        let n1 = TestNode::from_node(Node::new(builder.spawn(), config));
+        let peers = (0..3)
+            .map(|_| TestNode::new(builder.spawn()))
+            .collect::<Vec<_>>();
+
+        let content = "hello world!".repeat(20);
+        let name = "hello".to_string();
+        aw(n1.upload(name.clone(), content.clone())).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+
+        let meta = n1.metadata(&name).unwrap();
+        for (index, peer) in n1.placements().iter().map(|(_, index, peer)| (index, peer)) {
+            let holder = peers
+                .iter()
+                .find(|node| node.network().id() == *peer)
+                .unwrap();
+            assert!(holder.shards(&name).contains(index));
+        }
+        assert_eq!(n1.stats().shards_served, n1.placements().len());
+        assert_eq!(peers[0].metadata(&name), Some(meta));
+
+        builder.disable(n1.network().id);
+        let _ = aw(peers[0].download(name.clone()));
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(aw(peers[0].try_download(&name)).unwrap(), content);
+    }
+
+    #[test]
+    fn default_quota() {
+        let builder = TestNetworkBuilder::new();
+        let n1 = TestNode::new(builder.spawn());
+        let quota = Quota {
+            storage: Some(0),
+            ingress: None,
+            egress: None,
+        };
+        let config = NodeConfig::default().with_quota(quota);
+        let n2 = TestNode::from_node(Node::new(builder.spawn(), config));
+
+        aw(n1.upload("a/hello".into(), "hello world!".repeat(10))).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert!(n2.shards("a/hello").is_empty());
+        assert!(n2.stats().tenants["a"].rejections_sent > 0);
+    }
+
+    #[test]
+    fn lonely() {
+        let builder = TestNetworkBuilder::new();
+        let n1 = TestNode::new(builder.spawn());
+
+        let content = "hello world!".repeat(100);
+        let name = "hello".to_string();
+
+        let warning = aw(n1.upload(name.clone(), content.clone()));
+        assert_eq!(warning, Ok(Some(DurabilityWarning::NoPeers)));
+        assert_eq!(n1.unplaced(), vec![name.clone()]);
+        assert_eq!(aw(n1.download(name.clone())).unwrap(), content);
+
+        let n2 = TestNode::new(builder.spawn());
+        aw(n2.join());
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert!(n1.unplaced().is_empty());
+        assert!(!n2.shards(&name).is_empty());
+    }
+
+    #[test]
+    fn concentrated() {
+        let builder = TestNetworkBuilder::new();
+        let node = Node::new(builder.spawn(), NodeConfig::default())
+            .with_redundancy(AdaptiveRedundancy::default());
+        let n1 = TestNode::from_node(node);
+        let _n2 = TestNode::new(builder.spawn());
+
+        let content = "hello world!".repeat(100);
+        let warning = aw(n1.upload("hello".to_string(), content));
+        assert!(matches!(
+            warning,
+            Ok(Some(DurabilityWarning::Concentrated { per_peer, parity })) if per_peer > parity
+        ));
+    }
+
+    #[test]
+    fn decommission() {
+        let builder = TestNetworkBuilder::new();
+        let nodes = (0..6)
+            .map(|_| TestNode::new(builder.spawn()))
+            .collect::<Vec<_>>();
+
+        let content = "hello world!".repeat(100);
+        let name = "hello".to_string();
+
+        aw(nodes[0].upload(name.clone(), content.clone())).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+
+        let stored = |nodes: &[TestNode]| -> usize {
+            nodes
+                .iter()
+                .map(|node| node.stats().tenants[""].storage)
+                .sum()
+        };
+
+        let held = nodes[1].stats().tenants[""].storage;
+        let before = stored(&nodes[2..]);
+        assert!(held > 0);
+
+        aw(nodes[1].decommission());
+        builder.disable(nodes[1].network().id);
+        std::thread::sleep(std::time::Duration::from_millis(20));
+
+        assert!(stored(&nodes[2..]) > before);
+
+        builder.disable(nodes[0].network().id);
+        let _ = aw(nodes[2].download(name.clone()));
+        std::thread::sleep(std::time::Duration::from_millis(20));
+
+        let res = aw(nodes[2].download(name.clone()));
+        assert_eq!(res.unwrap(), content);
+    }
+
+    #[test]
+    fn audit() {
+        let builder = TestNetworkBuilder::new();
+        let nodes = (0..4)
+            .map(|_| TestNode::new(builder.spawn()))
+            .collect::<Vec<_>>();
+
+        let bad = builder.spawn();
+        let bad_id = format!("{}", bad.id);
+        liar(bad);
+
+        let content = "hello world!".repeat(100);
+        let name = "hello".to_string();
+
+        aw(nodes[0].upload(name.clone(), content.clone())).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+
+        aw(nodes[0].audit(&name));
+        std::thread::sleep(std::time::Duration::from_millis(20));
+
+        let stats = nodes[0].stats();
+        assert!(stats.audits_passed > 0);
+        assert!(stats.audits_failed > 0);
+        assert_eq!(nodes[0].suspects(), vec![bad_id]);
+    }
+
+    #[test]
+    fn audit_timeout() {
+        let builder = TestNetworkBuilder::new();
+        let config = NodeConfig::default().with_audit_timeout(Duration::from_millis(50));
+        let node = TestNode::from_node(Node::new(builder.spawn(), config));
+
+        // Takes the challenge but never answers it.
+        let silent = builder.spawn();
+        let silent_id = format!("{}", silent.id);
+        let received = spy(silent);
+
+        let file = File::encode("hello world!").unwrap();
+        let shards = file.shards().present_iter().collect::<Vec<_>>();
+        node.restore("hello".into(), file.metadata().clone(), shards)
+            .unwrap();
+        node.placed("hello".into(), 0, silent_id.clone());
+
+        aw(node.audit("hello"));
+        assert_eq!(node.stats().audits_pending, 1);
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while node.stats().audits_pending > 0 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let stats = node.stats();
+        assert_eq!(stats.audits_pending, 0);
+        assert_eq!(stats.audits_failed, 1);
+        assert_eq!(stats.audits_passed, 0);
+        assert_eq!(node.suspects(), vec![silent_id]);
+        assert!(
+            received
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|cmd| matches!(cmd, Command::Challenge { index: 0, .. }))
+        );
+    }
+
+    #[test]
+    fn repair() {
+        let builder = TestNetworkBuilder::new();
+        let nodes = (0..4)
+            .map(|_| TestNode::new(builder.spawn()))
+            .collect::<Vec<_>>();
+
+        let content = "hello world!".repeat(15);
+        let name = "hello".to_string();
+
+        aw(nodes[0].upload(name.clone(), content.clone())).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        builder.disable(nodes[0].network().id);
+
+        assert!(aw(nodes[1].download(name.clone())).is_err());
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(aw(nodes[1].repair(&name)));
+        std::thread::sleep(Duration::from_millis(20));
+
+        let held = nodes[1..]
+            .iter()
+            .flat_map(|node| node.shards(&name))
+            .collect::<HashSet<_>>();
+        let meta = nodes[2].metadata(&name).unwrap();
+        assert_eq!(held.len(), meta.shard_count());
+    }
+
+    #[test]
+    fn snapshot() {
+        let builder = TestNetworkBuilder::new();
+        let n1 = TestNode::new(builder.spawn());
+        let _n2 = TestNode::new(builder.spawn());
+
+        let content = "hello world!".repeat(15);
+        let name = "hello".to_string();
+
+        aw(n1.upload(name.clone(), content.clone())).unwrap();
+
+        let other = TestNetworkBuilder::new();
+        let copy = TestNode::new(other.spawn());
+        for (name, meta, shards) in n1.snapshot() {
+            copy.restore(name, meta, shards).unwrap();
+        }
+
+        assert_eq!(copy.shards(&name), n1.shards(&name));
+        assert_eq!(copy.metadata(&name), n1.metadata(&name));
+    }
+
+    #[test]
+    fn cache() {
+        let builder = TestNetworkBuilder::new();
+        let nodes = (0..7)
+            .map(|_| TestNode::new(builder.spawn()))
+            .collect::<Vec<_>>();
+        let node = Node::new(builder.spawn(), NodeConfig::default())
+            .with_cache(8, Duration::from_millis(100));
+        let reader = TestNode::from_node(node);
+
+        let content = "hello world!".repeat(15);
+        let name = "hello".to_string();
+
+        aw(nodes[0].upload(name.clone(), content.clone())).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert!(aw(reader.download(name.clone())).is_err());
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(aw(reader.try_download(&name)).unwrap(), content);
+
+        assert_eq!(aw(reader.download(name.clone())).unwrap(), content);
+        let stats = reader.stats();
+        assert_eq!((stats.cache_hits, stats.cache_misses), (1, 1));
+
+        std::thread::sleep(Duration::from_millis(120));
+        assert!(aw(reader.download(name.clone())).is_err());
+        assert_eq!(reader.stats().cache_misses, 2);
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(aw(reader.try_download(&name)).unwrap(), content);
+    }
+    #[test]
+    fn storm() {
+        let builder = TestNetworkBuilder::new();
+        let holder = TestNode::new(builder.spawn());
+        let file = File::encode("hello world!".repeat(10)).unwrap();
+        let shards = file.shards().present_iter().collect::<Vec<_>>();
+        holder
+            .restore("hello".into(), file.metadata().clone(), shards)
+            .unwrap();
+
+        let client = builder.spawn();
+        let sender = TestNetwork {
+            id: client.id,
+            builder: builder.inner.clone(),
+        };
+        let received = spy(client);
+
+        let bitmap = vec![0; file.metadata().shard_count().div_ceil(8)];
+        for _ in 0..10 {
+            let request = Command::Request {
+                name: "hello".into(),
+                bitmap: bitmap.clone(),
+            };
+            aw(sender.send(holder.network().id(), request));
+        }
+        std::thread::sleep(Duration::from_millis(20));
+
+        let replicated = received
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|cmd| matches!(cmd, Command::Replicate { .. }))
+            .count();
+        assert_eq!(replicated, file.metadata().shard_count());
+        assert_eq!(holder.stats().requests_deduplicated, 9);
+        assert_eq!(holder.stats().shards_served, replicated);
+        assert_eq!(holder.stats().bytes_served, file.shards().size());
+    }
+
+    #[test]
+    fn negative_cache() {
+        let builder = TestNetworkBuilder::new();
+        let holder = TestNode::new(builder.spawn());
+        let client = builder.spawn();
+        let sender = TestNetwork {
+            id: client.id,
+            builder: builder.inner.clone(),
+        };
+        let received = spy(client);
+
+        for bitmap in [vec![], vec![0]] {
+            let request = Command::Request {
+                name: "hello".into(),
+                bitmap,
+            };
+            aw(sender.send(holder.network().id(), request));
+        }
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(holder.stats().negative_hits, 1);
+
+        let file = File::encode("hello world!").unwrap();
+        let shards = file.shards().present_iter().collect::<Vec<_>>();
+        holder
+            .restore("hello".into(), file.metadata().clone(), shards)
+            .unwrap();
+
+        let request = Command::Request {
+            name: "hello".into(),
+            bitmap: vec![0b10],
+        };
+        aw(sender.send(holder.network().id(), request));
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(holder.stats().negative_hits, 1);
+        assert_eq!(
+            received.lock().unwrap().len(),
+            file.metadata().shard_count() - 1
+        );
+    }
+
+    #[test]
+    fn orphans() {
+        let builder = TestNetworkBuilder::new();
+        let node = TestNode::new(builder.spawn());
+        let client = builder.spawn();
+        let sender = TestNetwork {
+            id: client.id,
+            builder: builder.inner.clone(),
+        };
+        let received = spy(client);
+
+        let file = File::encode("hello world!".repeat(20)).unwrap();
+        for shard in file.shards().present_iter().take(3) {
+            let stamp = file.metadata().stamp();
+            aw(sender.replicate(node.network().id(), "hello".into(), stamp, shard));
+        }
+        std::thread::sleep(Duration::from_millis(20));
+
+        let requested = received
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|cmd| matches!(cmd, Command::RequestMeta { name } if name == "hello"))
+            .count();
+        assert_eq!(requested, 1);
+        assert!(node.metadata("hello").is_none());
+
+        let meta = Command::Meta {
+            name: "hello".into(),
+            meta: file.metadata().clone(),
+            bitmap: file.shards().bitmap(),
+        };
+        aw(sender.send(node.network().id(), meta));
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(node.shards("hello"), vec![0, 1, 2]);
+        assert_eq!(node.stats().orphans_adopted, 3);
+        assert_eq!(node.stats().orphans_dropped, 0);
+    }
+
+    #[test]
+    fn overwrite() {
+        let builder = TestNetworkBuilder::new();
+        let n1 = TestNode::new(builder.spawn());
+        let n2 = TestNode::new(builder.spawn());
+        let n3 = TestNode::new(builder.spawn());
+
+        let content = "goodbye world!".repeat(30);
+        aw(n1.upload("hello".to_string(), "hello world!".repeat(20))).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        aw(n2.upload("hello".to_string(), content.clone())).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+
+        let meta = n2.metadata("hello").unwrap();
+        assert_eq!(meta.clock().entries().count(), 2);
+        assert_eq!(n1.metadata("hello").as_ref(), Some(&meta));
+        assert_eq!(n3.metadata("hello").as_ref(), Some(&meta));
+        assert!(n1.conflicts().is_empty());
+        assert!(n1.siblings("hello").is_empty());
+
+        let _ = aw(n3.download("hello".to_string()));
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(aw(n3.try_download(&"hello".to_string())).unwrap(), content);
+    }
+
+    #[test]
+    fn siblings() {
+        let builder = TestNetworkBuilder::new();
+        let node = TestNode::new(builder.spawn());
+        let client = builder.spawn();
+        let sender = TestNetwork {
+            id: client.id,
+            builder: builder.inner.clone(),
+        };
+
+        let ours = File::encode("hello world!".repeat(20))
+            .unwrap()
+            .with_stamp(Stamp::new(1, "a"))
+            .with_clock(VectorClock::from_iter([(file::writer("a"), 1)]));
+        let theirs = File::encode("goodbye world!".repeat(20))
+            .unwrap()
+            .with_stamp(Stamp::new(2, "b"))
+            .with_clock(VectorClock::from_iter([(file::writer("b"), 1)]));
+        node.restore("hello".into(), ours.metadata().clone(), Vec::new())
+            .unwrap();
+
+        let create = Command::Create {
+            name: "hello".into(),
+            meta: theirs.metadata().clone(),
+        };
+        aw(sender.send(node.network().id(), create.clone()));
+        aw(sender.send(node.network().id(), create));
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(node.metadata("hello").as_ref(), Some(theirs.metadata()));
+        assert_eq!(node.siblings("hello"), vec![ours.metadata().clone()]);
+        assert_eq!(node.conflicted(), vec!["hello".to_string()]);
+        assert_eq!(node.stats().conflicts, 1);
+        assert_eq!(node.stats().unresolved, 1);
+
+        aw(node.resolve("hello".into(), "merged world!".repeat(20))).unwrap();
+        let meta = node.metadata("hello").unwrap();
+        assert!(meta.clock() > ours.metadata().clock());
+        assert!(meta.clock() > theirs.metadata().clock());
+        assert!(node.siblings("hello").is_empty());
+        assert_eq!(node.stats().unresolved, 0);
+    }
+
+    #[test]
+    fn stale() {
+        let builder = TestNetworkBuilder::new();
+        let node = TestNode::new(builder.spawn());
+        let client = builder.spawn();
+        let sender = TestNetwork {
+            id: client.id,
+            builder: builder.inner.clone(),
+        };
+        let received = spy(client);
+
+        let old = File::encode("hello world!".repeat(20))
+            .unwrap()
+            .with_stamp(Stamp::new(1, "a"))
+            .with_clock(VectorClock::from_iter([(file::writer("a"), 1)]));
+        let new = File::encode("goodbye world!".repeat(20))
+            .unwrap()
+            .with_stamp(Stamp::new(2, "b"))
+            .with_clock(VectorClock::from_iter([
+                (file::writer("a"), 1),
+                (file::writer("b"), 1),
+            ]));
+        node.restore("hello".into(), new.metadata().clone(), Vec::new())
+            .unwrap();
+
+        let create = Command::Create {
+            name: "hello".into(),
+            meta: old.metadata().clone(),
+        };
+        aw(sender.send(node.network().id(), create));
+        for shard in old.shards().present_iter() {
+            let stamp = old.metadata().stamp();
+            aw(sender.replicate(node.network().id(), "hello".into(), stamp, shard));
+        }
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(node.metadata("hello").as_ref(), Some(new.metadata()));
+        assert!(node.shards("hello").is_empty());
+        assert_eq!(node.stats().stale_shards, old.shards().present());
+        assert_eq!(node.stats().conflicts, 0);
+
+        let replied = received
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|cmd| matches!(cmd, Command::Meta { meta, .. } if meta == new.metadata()));
+        assert!(replied);
+    }
+
+    #[test]
+    fn nack() {
+        let builder = TestNetworkBuilder::new();
+        let config = NodeConfig::default()
+            .with_nack_retries(2)
+            .with_nack_ttl(Duration::from_millis(200));
+        let node = TestNode::from_node(Node::new(builder.spawn(), config));
+        let client = builder.spawn();
+        let sender = TestNetwork {
+            id: client.id,
+            builder: builder.inner.clone(),
+        };
+        let received = spy(client);
+
+        let file = File::encode("hello world!").unwrap();
+        node.restore("hello".into(), file.metadata().clone(), Vec::new())
+            .unwrap();
+
+        let shard = file.shards().get(0).unwrap();
+        let corrupted = Command::Replicate {
+            name: "hello".into(),
+            stamp: file.metadata().stamp(),
+            shard: Shard::new(0, vec![0; shard.size()]),
+            checksum: shard.crc(),
+        };
+        let nacks = || {
+            received
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|cmd| matches!(cmd, Command::Nack { index: 0, .. }))
+                .count()
+        };
+        // The cap holds however many bad copies keep arriving.
+        for _ in 0..5 {
+            aw(sender.send(node.network().id(), corrupted.clone()));
+        }
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(nacks(), 2);
+        assert_eq!(node.stats().nacks_exhausted, 3);
+        assert_eq!(node.stats().nacks_pending, 0);
+        assert!(node.shards("hello").is_empty());
+
+        // Once the peer has been quiet for the ttl it gets a fresh set of retries.
+        std::thread::sleep(Duration::from_millis(250));
+        aw(sender.send(node.network().id(), corrupted.clone()));
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(nacks(), 3);
+        assert_eq!(node.stats().nacks_pending, 1);
+
+        let stamp = file.metadata().stamp();
+        aw(sender.replicate(node.network().id(), "hello".into(), stamp, shard));
+        let nack = Command::Nack {
+            name: "hello".into(),
+            index: 0,
+        };
+        aw(sender.send(node.network().id(), nack));
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(node.shards("hello"), vec![0]);
+        assert_eq!(node.stats().retransmits, 1);
+        let resent = received.lock().unwrap().iter().any(|cmd| {
+            matches!(cmd, Command::Replicate { shard, checksum, .. } if shard.crc() == *checksum)
+        });
+        assert!(resent);
+    }
+
+    #[derive(Clone, Default)]
+    struct RecordingSink {
+        counters: Arc<Mutex<HashMap<&'static str, u64>>>,
+        gauges: Arc<Mutex<HashMap<&'static str, f64>>>,
+        histograms: Arc<Mutex<HashMap<&'static str, usize>>>,
+    }
+
+    impl MetricsSink for RecordingSink {
+        fn counter(&self, name: &'static str, value: u64) {
+            *self.counters.lock().unwrap().entry(name).or_default() += value;
+        }
+
+        fn gauge(&self, name: &'static str, value: f64) {
+            self.gauges.lock().unwrap().insert(name, value);
+        }
+
+        fn histogram(&self, name: &'static str, _value: f64) {
+            *self.histograms.lock().unwrap().entry(name).or_default() += 1;
+        }
+    }
+
+    #[test]
+    fn metrics() {
+        let builder = TestNetworkBuilder::new();
+        let uploader = RecordingSink::default();
+        let reader = RecordingSink::default();
+        let n1 = TestNode::from_node(
+            Node::new(builder.spawn(), NodeConfig::default()).with_metrics(uploader.clone()),
+        );
+        let n2 = TestNode::from_node(
+            Node::new(builder.spawn(), NodeConfig::default()).with_metrics(reader.clone()),
+        );
+
+        let name = "hello".to_string();
+        let content = "hello world!".repeat(10);
+        aw(n1.upload(name.clone(), content.clone())).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(aw(n2.download(name.clone())).unwrap(), content);
+
+        let counters = uploader.counters.lock().unwrap();
+        assert_eq!(counters[metrics::UPLOADS], 1);
+        assert!(counters[metrics::MESSAGES_SENT] > 0);
+        assert_eq!(
+            uploader.histograms.lock().unwrap()[metrics::UPLOAD_LATENCY],
+            1
+        );
+
+        let counters = reader.counters.lock().unwrap();
+        assert!(counters[metrics::SHARDS_STORED] > 0);
+        assert!(counters[metrics::MESSAGES_RECEIVED] > 0);
+        assert_eq!(counters[metrics::DOWNLOADS_SUCCEEDED], 1);
+        assert!(reader.gauges.lock().unwrap()[metrics::STORED_BYTES] > 0.0);
+        assert_eq!(
+            reader.gauges.lock().unwrap()[metrics::DOWNLOADS_PENDING],
+            0.0
+        );
+    }
+
+    #[test]
+    fn decoded_cache() {
+        let builder = TestNetworkBuilder::new();
+        let node = TestNode::new(builder.spawn());
+
+        let name = "hello".to_string();
+        let content = "hello world!".repeat(10);
+        let file = File::encode(&content).unwrap();
+        let data = file.metadata().data_shards();
+        let mut shards = file.shards().present_iter();
+
+        let initial = shards.by_ref().take(data).collect();
+        node.restore(name.clone(), file.metadata().clone(), initial)
+            .unwrap();
+        assert_eq!(aw(node.try_download(&name)).unwrap(), content);
+        assert_eq!(aw(node.try_download(&name)).unwrap(), content);
+        assert_eq!((node.stats().decodes, node.stats().decodes_cached), (1, 1));
+
+        node.restore(name.clone(), file.metadata().clone(), shards.collect())
+            .unwrap();
+        assert_eq!(aw(node.try_download(&name)).unwrap(), content);
+        assert_eq!((node.stats().decodes, node.stats().decodes_cached), (2, 1));
+
+        let config = NodeConfig::default().with_decoded_cache(64, Duration::ZERO);
+        let uncached = TestNode::from_node(Node::new(builder.spawn(), config));
+        let shards = file.shards().present_iter().collect();
+        uncached
+            .restore(name.clone(), file.metadata().clone(), shards)
+            .unwrap();
+        assert_eq!(aw(uncached.try_download(&name)).unwrap(), content);
+        assert_eq!(aw(uncached.try_download(&name)).unwrap(), content);
+        assert_eq!(uncached.stats().decodes_cached, 0);
+    }
+
+    #[test]
+    fn export() {
+        let builder = TestNetworkBuilder::new();
+        let node = TestNode::new(builder.spawn());
+
+        let content = "hello world!".repeat(10);
+        let file = File::encode(&content).unwrap();
+        for name in ["b", "a"] {
+            let shards = file.shards().present_iter().take(4).collect();
+            node.restore(name.to_string(), file.metadata().clone(), shards)
+                .unwrap();
+        }
+
+        let path = std::env::temp_dir().join(format!("erasure-export-{}", std::process::id()));
+        node.export(&path).unwrap();
+        let archive = Archive::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let names = archive
+            .index()
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.shards))
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec![("a", 4), ("b", 4)]);
+
+        let archived = archive.get("a").unwrap();
+        assert_eq!(&archived.meta, file.metadata());
+        assert_eq!(
+            archived.shards,
+            file.shards().present_iter().take(4).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn import() {
+        let builder = TestNetworkBuilder::new();
+        let source = TestNode::new(builder.spawn());
+
+        let name = "hello".to_string();
+        let content = "hello world!".repeat(10);
+        let file = File::encode(&content).unwrap();
+        let count = file.metadata().shard_count();
+        let shards = file.shards().present_iter().collect();
+        source
+            .restore(name.clone(), file.metadata().clone(), shards)
+            .unwrap();
+
+        let path = std::env::temp_dir().join(format!("erasure-import-{}", std::process::id()));
+        source.export(&path).unwrap();
+        builder.disable(0);
+
+        let importer = TestNode::new(builder.spawn());
+        let peer = TestNode::new(builder.spawn());
+        let stats = aw(importer.import(&path)).unwrap();
+        assert_eq!(
+            stats,
+            ImportStats {
+                files: 1,
+                shards: count,
+                ..ImportStats::default()
+            }
+        );
+        assert_eq!(aw(importer.import(&path)).unwrap().duplicates, count);
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(peer.metadata(&name).as_ref(), Some(file.metadata()));
+        assert_eq!(aw(peer.download(name)).unwrap(), content);
+
+        let mut data = std::fs::read(&path).unwrap();
+        let shard = file.shards().get(0).unwrap().data().to_vec();
+        let offset = data
+            .windows(shard.len())
+            .position(|window| window == shard)
+            .unwrap();
+        data[offset] ^= 1;
+        std::fs::write(&path, data).unwrap();
+
+        let corrupted = TestNode::new(builder.spawn());
+        let stats = aw(corrupted.import(&path)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((stats.shards, stats.corrupt), (count - 1, 1));
+    }
+
+    struct Yielding(TestNetwork);
+
+    impl Network for Yielding {
+        fn id(&self) -> String {
+            self.0.id()
+        }
+
+        async fn discover(&self) -> Vec<String> {
+            self.0.discover().await
+        }
+
+        async fn send(&self, peer: String, cmd: Command) {
+            let mut yielded = false;
+            std::future::poll_fn(|cx| {
+                if yielded {
+                    return Poll::Ready(());
+                }
+                yielded = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            })
+            .await;
+
+            self.0.send(peer, cmd).await
+        }
+
+        async fn recv(&self) -> Option<(String, Command)> {
+            self.0.recv().await
+        }
+    }
+
+    #[test]
+    fn coalesced() {
+        let builder = TestNetworkBuilder::new();
+        let nodes = (0..6)
+            .map(|_| TestNode::new(builder.spawn()))
+            .collect::<Vec<_>>();
+
+        let recorder = Recorder::default();
+        let reader = Node::new(Yielding(builder.spawn()), NodeConfig::default())
+            .with_observer(recorder.clone());
+        let reader = Arc::new(reader);
+        let reader_clone = Arc::clone(&reader);
+        std::thread::spawn(move || aw(reader_clone.run()));
+
+        let content = "hello world!".repeat(15);
+        let name = "hello".to_string();
+        aw(nodes[0].upload(name.clone(), content.clone())).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+
+        let meta = nodes[0].metadata(&name).unwrap();
+        reader.restore(name.clone(), meta, Vec::new()).unwrap();
+
+        let (first, second) = aw(futures::future::join(
+            reader.download(name.clone()),
+            reader.download(name.clone()),
+        ));
+        assert!(first.is_err());
+        assert_eq!(first, second);
+        assert_eq!(reader.stats().downloads_coalesced, 1);
+
+        let requested = recorder
+            .sent
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, cmd)| matches!(cmd, Command::Request { .. }))
+            .map(|(peer, _)| peer.clone())
+            .collect::<Vec<_>>();
+        assert!(!requested.is_empty());
+        assert_eq!(
+            requested.len(),
+            requested.iter().collect::<HashSet<_>>().len()
+        );
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(aw(reader.try_download(&name)).unwrap(), content);
+
+        let requests = requested.len();
+        let _ = aw(reader.download(name.clone()));
+        assert_eq!(reader.stats().downloads_coalesced, 1);
+        assert_eq!(
+            recorder
+                .sent
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(_, cmd)| matches!(cmd, Command::Request { .. }))
+                .count(),
+            requests
+        );
+    }
+
+    #[test]
+    fn swim() {
+        let builder = TestNetworkBuilder::new();
+        let config = MembershipConfig {
+            probe_interval: Duration::from_millis(40),
+            probe_timeout: Duration::from_millis(10),
+            indirect_probes: 2,
+            suspicion_timeout: Duration::from_millis(100),
+            ..MembershipConfig::default()
+        };
+
+        let networks = (0..4).map(|_| builder.spawn()).collect::<Vec<_>>();
+        let swims = networks
+            .into_iter()
+            .map(|network| Arc::new(aw(Swim::new(network, config.clone()))))
+            .collect::<Vec<_>>();
+
+        for swim in &swims {
+            let swim_clone = Arc::clone(swim);
+            std::thread::spawn(move || while aw(swim_clone.recv()).is_some() {});
+        }
+
+        builder.disable(3);
+
+        let mut view = Vec::new();
+        for _ in 0..200 {
+            std::thread::sleep(Duration::from_millis(10));
+            view = swims[..3]
+                .iter()
+                .map(|swim| aw(swim.discover()))
+                .collect::<Vec<_>>();
+            if view.iter().all(|alive| alive.len() == 2) {
+                break;
+            }
+        }
+
+        assert_eq!(view[0], vec!["1", "2"]);
+        assert_eq!(view[1], vec!["0", "2"]);
+        assert_eq!(view[2], vec!["0", "1"]);
+        assert_eq!(swims[0].state("3"), Some(State::Dead));
+    }
+
+    #[test]
+    fn swim_timer() {
+        let builder = TestNetworkBuilder::new();
+        let config = MembershipConfig {
+            probe_interval: Duration::from_millis(40),
+            probe_timeout: Duration::from_millis(10),
+            indirect_probes: 2,
+            suspicion_timeout: Duration::from_millis(100),
+            ..MembershipConfig::default()
+        };
+
+        // Nothing calls discover(): the nodes' own timers drive the probes.
+        let nodes = (0..4)
+            .map(|_| {
+                let network = aw(Swim::new(builder.spawn(), config.clone()));
+                let node = Arc::new(Node::new(network, NodeConfig::default()));
+                let node_clone = Arc::clone(&node);
+                std::thread::spawn(move || aw(node_clone.run()));
+                node
+            })
+            .collect::<Vec<_>>();
+
+        builder.disable(3);
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while Instant::now() < deadline
+            && nodes[..3]
+                .iter()
+                .any(|node| node.network().state("3") != Some(State::Dead))
+        {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        for node in &nodes[..3] {
+            assert_eq!(node.network().state("3"), Some(State::Dead));
+        }
+    }
+
+    #[test]
+    fn windowed() {
+        let builder = TestNetworkBuilder::new();
+        let config = FlowConfig {
+            max_messages: 2,
+            ack_every: 1,
+            ..FlowConfig::default()
+        };
+
+        let nodes = (0..2)
+            .map(|_| {
+                let network = Windowed::new(builder.spawn(), config.clone());
+                let node = Arc::new(Node::new(network, NodeConfig::default()));
+                let node_clone = Arc::clone(&node);
+                std::thread::spawn(move || aw(node_clone.run()));
+                node
+            })
+            .collect::<Vec<_>>();
+
+        let name = "hello".to_string();
+        let content = "hello world!".repeat(20);
+        aw(nodes[0].upload(name.clone(), content.clone())).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(aw(nodes[1].download(name)).unwrap(), content);
+        assert!(nodes[0].network().stats().stalls > 0);
+        assert_eq!(nodes[0].network().stats().queued, 0);
+    }
+
+    #[test]
+    fn fragmented() {
+        let builder = TestNetworkBuilder::new();
+        builder.mtu(64);
+
+        let nodes = (0..2)
+            .map(|_| {
+                let network = Fragmenter::new(builder.spawn(), FragmentConfig::default());
+                let node = Arc::new(Node::new(network, NodeConfig::default()));
+                let node_clone = Arc::clone(&node);
+                std::thread::spawn(move || aw(node_clone.run()));
+                node
+            })
+            .collect::<Vec<_>>();
+
+        let name = "hello".to_string();
+        let content = "hello world!".repeat(40);
+        aw(nodes[0].upload(name.clone(), content.clone())).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(aw(nodes[1].download(name)).unwrap(), content);
+        assert!(nodes[0].network().stats().fragmented > 0);
+        assert!(nodes[1].network().stats().reassembled > 0);
+        assert_eq!(nodes[1].network().stats().pending, 0);
+    }
+}
+
+mod placement {
+    use std::collections::HashMap;
+
+    use erasure_node::placement;
+
+    #[test]
+    fn balanced() {
+        let members = (0..8).map(|id| format!("{id}")).collect::<Vec<_>>();
+
+        let mut counts = HashMap::new();
+        for index in 0..64 {
+            let owner = placement::owner("hello", index, &members).unwrap();
+            *counts.entry(owner).or_insert(0) += 1;
+        }
+
+        assert_eq!(counts.len(), 8);
+        assert!(counts.values().all(|count| *count == 8));
+    }
+
+    #[test]
+    fn deterministic() {
+        let members = (0..8).map(|id| format!("{id}")).collect::<Vec<_>>();
+        let mut shuffled = members.clone();
+        shuffled.reverse();
+
+        assert_eq!(
+            placement::order("hello", &members),
+            placement::order("hello", &shuffled)
+        );
+        assert!(placement::owner("hello", 0, &[]).is_none());
+    }
+
+    #[test]
+    fn weighted() {
+        let members = (0..4).map(|id| format!("{id}")).collect::<Vec<_>>();
+        let order = placement::order("hello", &members);
+
+        assert_eq!(
+            placement::weighted("hello", &members, &HashMap::new()),
+            order
+        );
+
+        let weights = HashMap::from([("0".to_string(), 5000), ("1".to_string(), 1000)]);
+        let layout = placement::weighted("hello", &members, &weights);
+        assert_eq!(layout[..4], order[..]);
+        assert_eq!(layout.len(), 6);
+        assert_eq!(layout.iter().filter(|member| *member == "0").count(), 3);
+        assert_eq!(layout.iter().filter(|member| *member == "1").count(), 1);
+    }
+}
+
+mod events {
+    use std::{
+        io::Write,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use erasure_node::{
+        events::EventLog,
+        file::{DecodeError, Shard, Stamp},
+        network::Command,
+        observer::NodeObserver,
+    };
+
+    #[derive(Clone, Default)]
+    struct Sink(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Sink {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(String::from)
+                .collect()
+        }
+    }
+
+    #[test]
+    fn lines() {
+        let sink = Sink::default();
+        let log = EventLog::new("7", sink.clone()).with_clock(|| Duration::from_millis(3));
+
+        let command = Command::Replicate {
+            name: "say \"hi\"".into(),
+            stamp: Stamp::default(),
+            shard: Shard::new(4, vec![1, 2, 3]),
+            checksum: 0,
+        };
+        log.on_command("2", &command);
+        log.on_send("5", &Command::Announce);
+        log.on_shard_stored("hello", 4);
+        log.on_decode("hello", &Ok("hi".into()));
+        log.on_decode(
+            "hello",
+            &Err(DecodeError::Insufficient {
+                present: 3,
+                needed: 5,
+            }),
+        );
+
+        let size = command.size();
+        assert_eq!(
+            sink.lines(),
+            vec![
+                format!(
+                    r#"{{"at_us":3000,"node":"7","event":"received","peer":"2","command":"replicate","file":"say \"hi\"","index":4,"size":{size}}}"#
+                ),
+                format!(
+                    r#"{{"at_us":3000,"node":"7","event":"sent","peer":"5","command":"announce","size":{}}}"#,
+                    Command::Announce.size()
+                ),
+                r#"{"at_us":3000,"node":"7","event":"stored","file":"hello","index":4}"#.into(),
+                r#"{"at_us":3000,"node":"7","event":"decoded","file":"hello","outcome":"ok"}"#
+                    .into(),
+                r#"{"at_us":3000,"node":"7","event":"decoded","file":"hello","outcome":"insufficient","present":3,"needed":5}"#
+                    .into(),
+            ]
+        );
+    }
+
+    #[test]
+    fn escape() {
+        let sink = Sink::default();
+        let log = EventLog::new("a\tb", sink.clone()).with_clock(|| Duration::ZERO);
+        log.on_shard_stored("line\nbreak\u{1}", 0);
+
+        assert_eq!(
+            sink.lines(),
+            vec![
+                r#"{"at_us":0,"node":"a\tb","event":"stored","file":"line\nbreak\u0001","index":0}"#
+            ]
+        );
+    }
+}
+
+mod flow {
+    use std::time::{Duration, Instant};
+
+    use erasure_node::{
+        file::File,
+        flow::{FlowConfig, FlowControl, Full},
+        network::Command,
+    };
+
+    fn replicate(index: usize) -> Command {
+        let file = File::encode("hello world!".repeat(20)).unwrap();
+        let shard = file.shards().get(index).unwrap();
+        Command::Replicate {
+            name: "hello".into(),
+            stamp: file.metadata().stamp(),
+            checksum: shard.crc(),
+            shard,
+        }
+    }
+
+    fn config() -> FlowConfig {
+        FlowConfig {
+            max_messages: 2,
+            max_bytes: 1024,
+            ack_every: 2,
+            timeout: Duration::from_millis(100),
+            max_queued: 2,
+        }
+    }
+
+    #[test]
+    fn window() {
+        let now = Instant::now();
+        let mut sender = FlowControl::new(config());
+        let mut receiver = FlowControl::new(config());
+
+        let out = (0..3)
+            .flat_map(|index| sender.send("b", replicate(index), now).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(out.len(), 2);
+        assert_eq!(sender.stats().queued, 1);
+
+        assert_eq!(receiver.received("a", &out[0].1), None);
+        let Some(Command::Credit { messages, bytes }) = receiver.received("a", &out[1].1) else {
+            panic!("expected credit");
+        };
+        assert_eq!(messages, 2);
+
+        let released = sender.credit("b", messages, bytes, now);
+        assert_eq!(released, vec![("b".to_string(), replicate(2))]);
+        assert_eq!(sender.outstanding("b").0, 1);
+        assert_eq!(sender.stats().queued, 0);
+    }
+
+    #[test]
+    fn bytes() {
+        let now = Instant::now();
+        let config = FlowConfig {
+            max_messages: 16,
+            max_bytes: 64,
+            ..config()
+        };
+        let mut sender = FlowControl::new(config);
+
+        assert_eq!(sender.send("b", replicate(0), now).unwrap().len(), 1);
+        assert!(sender.send("b", replicate(1), now).unwrap().is_empty());
+        assert_eq!(sender.send("c", replicate(1), now).unwrap().len(), 1);
+        assert_eq!(sender.stats().stalls, 1);
+    }
+
+    #[test]
+    fn timeout() {
+        let now = Instant::now();
+        let mut sender = FlowControl::new(config());
+
+        for index in 0..4 {
+            sender.send("b", replicate(index), now).unwrap();
+        }
+
+        // A full queue hands the command back instead of dropping anything.
+        assert_eq!(
+            sender.send("b", replicate(4), now),
+            Err(Full(Box::new(replicate(4))))
+        );
+        assert_eq!(sender.stats().blocked, 1);
+        assert_eq!(sender.stats().queued, 2);
+        assert!(sender.expire(now + Duration::from_millis(50)).is_empty());
+
+        let released = sender.expire(now + Duration::from_millis(100));
+        assert_eq!(
+            released,
+            vec![
+                ("b".to_string(), replicate(2)),
+                ("b".to_string(), replicate(3))
+            ]
+        );
+        assert_eq!(sender.stats().timeouts, 1);
+        assert!(sender.send("b", replicate(4), now).is_ok());
+    }
+
+    #[test]
+    fn loss() {
+        let now = Instant::now();
+        let mut sender = FlowControl::new(config());
+        let mut receiver = FlowControl::new(config());
+
+        // The first message never arrives, so the receiver's count trails the sender's.
+        let out = (0..2)
+            .flat_map(|index| sender.send("b", replicate(index), now).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(receiver.received("a", &out[1].1), None);
+
+        let later = now + Duration::from_millis(100);
+        assert!(sender.expire(later).is_empty());
+        assert_eq!(sender.stats().timeouts, 1);
+        assert_eq!(sender.outstanding("b").0, 0);
+
+        // Credits after the reset count the lost message as written off, so the window
+        // reopens on credit instead of stalling until the next timeout.
+        let out = (2..4)
+            .flat_map(|index| sender.send("b", replicate(index), later).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(out.len(), 2);
+        let Some(Command::Credit { messages, bytes }) = receiver.received("a", &out[0].1) else {
+            panic!("expected credit");
+        };
+        assert_eq!(messages, 2);
+        assert!(sender.credit("b", messages, bytes, later).is_empty());
+        assert_eq!(sender.outstanding("b").0, 0);
+        assert_eq!(sender.send("b", replicate(4), later).unwrap().len(), 1);
+        assert_eq!(sender.stats().stalls, 0);
+        assert_eq!(sender.stats().timeouts, 1);
+    }
+
+    #[test]
+    fn credit_passes() {
+        let now = Instant::now();
+        let mut sender = FlowControl::new(config());
+        let credit = Command::Credit {
+            messages: 1,
+            bytes: 1,
+        };
+
+        for _ in 0..4 {
+            assert_eq!(sender.send("b", credit.clone(), now).unwrap().len(), 1);
+        }
+        assert_eq!(sender.outstanding("b"), (0, 0));
+    }
+}
+
+mod fragment {
+    use std::time::{Duration, Instant};
+
+    use erasure_node::{
+        file::File,
+        fragment::{FragmentConfig, Fragments},
+        network::Command,
+    };
+
+    fn replicate() -> Command {
+        let file = File::encode("hello world!".repeat(40)).unwrap();
+        let shard = file.shards().get(0).unwrap();
+        Command::Replicate {
+            name: "hello".into(),
+            stamp: file.metadata().stamp(),
+            checksum: shard.crc(),
+            shard,
+        }
+    }
+
+    fn config() -> FragmentConfig {
+        FragmentConfig {
+            timeout: Duration::from_millis(100),
+            max_pending: 2,
+            max_message: 1024,
+        }
+    }
+
+    #[test]
+    fn reassemble() {
+        let now = Instant::now();
+        let mut sender = Fragments::new(config());
+        let mut receiver = Fragments::new(config());
+
+        let mut fragments = sender.split(replicate(), 32);
+        assert!(fragments.len() > 1);
+        assert!(fragments.iter().all(|fragment| fragment.size() <= 32));
+
+        fragments.reverse();
+        let last = fragments.pop().unwrap();
+        for fragment in fragments {
+            assert_eq!(receiver.reassemble("a", fragment, now), None);
+        }
+        assert_eq!(receiver.stats().pending, 1);
+
+        assert_eq!(receiver.reassemble("a", last, now), Some(replicate()));
+        assert_eq!(receiver.stats().reassembled, 1);
+        assert_eq!(receiver.stats().pending, 0);
+    }
+
+    #[test]
+    fn passthrough() {
+        let now = Instant::now();
+        let mut fragments = Fragments::new(config());
+        let command = Command::Cancel {
+            name: "hello".into(),
+        };
+
+        assert_eq!(
+            fragments.split(command.clone(), 1024),
+            vec![command.clone()]
+        );
+        assert_eq!(
+            fragments.reassemble("a", command.clone(), now),
+            Some(command)
+        );
+        assert_eq!(fragments.stats().fragmented, 0);
+    }
+
+    #[test]
+    fn timeout() {
+        let now = Instant::now();
+        let mut sender = Fragments::new(config());
+        let mut receiver = Fragments::new(config());
+
+        let mut fragments = sender.split(replicate(), 64);
+        let last = fragments.pop().unwrap();
+        for fragment in fragments {
+            receiver.reassemble("a", fragment, now);
+        }
+
+        assert_eq!(
+            receiver.reassemble("a", last, now + Duration::from_millis(100)),
+            None
+        );
+        assert_eq!(receiver.stats().expired, 1);
+        assert_eq!(receiver.stats().pending, 1);
+
+        receiver.expire(now + Duration::from_millis(200));
+        assert_eq!(receiver.stats().pending, 0);
+    }
+
+    #[test]
+    fn evict() {
+        let now = Instant::now();
+        let mut sender = Fragments::new(config());
+        let mut receiver = Fragments::new(config());
+
+        for (offset, peer) in ["a", "b", "c"].into_iter().enumerate() {
+            let fragment = sender.split(replicate(), 64).remove(0);
+            let at = now + Duration::from_millis(offset as u64);
+            assert_eq!(receiver.reassemble(peer, fragment, at), None);
+        }
+
+        assert_eq!(receiver.stats().evicted, 1);
+        assert_eq!(receiver.stats().pending, 2);
+    }
+
+    #[test]
+    fn oversized() {
+        let now = Instant::now();
+        let mut receiver = Fragments::new(config());
+
+        // A count straight off the wire must not size the reassembly buffer.
+        let huge = Command::Fragment {
+            id: 0,
+            index: 0,
+            count: usize::MAX / 2,
+            data: vec![0; 8],
+        };
+        assert_eq!(receiver.reassemble("a", huge, now), None);
+        assert_eq!(receiver.stats().malformed, 1);
+        assert_eq!(receiver.stats().pending, 0);
+
+        // Nor can a plausible count smuggle in more bytes than the limit.
+        let fragment = |index| Command::Fragment {
+            id: 1,
+            index,
+            count: 4,
+            data: vec![0; 400],
+        };
+        assert_eq!(receiver.reassemble("a", fragment(0), now), None);
+        assert_eq!(receiver.reassemble("a", fragment(1), now), None);
+        assert_eq!(receiver.stats().pending, 1);
+        assert_eq!(receiver.reassemble("a", fragment(2), now), None);
+        assert_eq!(receiver.stats().malformed, 2);
+        assert_eq!(receiver.stats().pending, 0);
+    }
+}
+
+mod membership {
+    use std::{
+        collections::{HashSet, VecDeque},
+        time::{Duration, Instant},
+    };
+
+    use erasure_node::{
+        membership::{Membership, MembershipConfig, State},
+        network::Command,
+    };
+
+    struct Cluster {
+        now: Instant,
+        members: Vec<Membership>,
+        down: HashSet<usize>,
+    }
+
+    impl Cluster {
+        fn new(size: usize) -> Self {
+            let now = Instant::now();
+            let ids = (0..size).map(|id| format!("{id}")).collect::<Vec<_>>();
+            let members = ids
+                .iter()
+                .map(|id| Membership::new(id.clone(), ids.clone(), config(), now))
+                .collect();
+
+            Self {
+                now,
+                members,
+                down: HashSet::new(),
+            }
+        }
+
+        fn join(&mut self, seed: usize) -> usize {
+            let id = self.members.len();
+            let seeds = vec![format!("{seed}")];
+            let member = Membership::new(format!("{id}"), seeds, config(), self.now);
+            self.members.push(member);
+            id
+        }
+
+        fn step(&mut self, elapsed: Duration) {
+            self.now += elapsed;
+
+            let mut queue = VecDeque::new();
+            for (id, member) in self.members.iter_mut().enumerate() {
+                for (peer, command) in member.tick(self.now) {
+                    queue.push_back((id, peer.parse::<usize>().unwrap(), command));
+                }
+            }
+
+            while let Some((from, to, command)) = queue.pop_front() {
+                if self.down.contains(&from) || self.down.contains(&to) {
+                    continue;
+                }
+
+                let replies = self.members[to]
+                    .handle(&format!("{from}"), &command, self.now)
+                    .unwrap();
+                for (peer, command) in replies {
+                    queue.push_back((to, peer.parse().unwrap(), command));
+                }
+            }
+        }
+
+        fn run(&mut self, duration: Duration) {
+            let step = Duration::from_millis(50);
+            for _ in 0..duration.as_millis() / step.as_millis() {
+                self.step(step);
+            }
+        }
+
+        fn state(&self, observer: usize, member: usize) -> Option<State> {
+            self.members[observer].state(&format!("{member}"))
+        }
+    }
+
+    fn config() -> MembershipConfig {
+        MembershipConfig {
+            probe_interval: Duration::from_millis(500),
+            probe_timeout: Duration::from_millis(100),
+            indirect_probes: 2,
+            suspicion_timeout: Duration::from_millis(1500),
+            ..MembershipConfig::default()
+        }
+    }
+
+    #[test]
+    fn detect() {
+        let mut cluster = Cluster::new(6);
+        cluster.run(Duration::from_secs(2));
+        for observer in 0..6 {
+            assert_eq!(cluster.members[observer].alive().len(), 5);
+        }
+
+        cluster.down.insert(3);
+        cluster.run(Duration::from_secs(10));
+
+        for observer in (0..6).filter(|id| *id != 3) {
+            assert_eq!(cluster.state(observer, 3), Some(State::Dead));
+            assert!(!cluster.members[observer].alive().contains(&"3".to_string()));
+            assert_eq!(cluster.members[observer].alive().len(), 4);
+        }
+    }
+
+    #[test]
+    fn join() {
+        let mut cluster = Cluster::new(5);
+        let joined = cluster.join(0);
+        cluster.run(Duration::from_secs(10));
+
+        for observer in 0..5 {
+            assert_eq!(cluster.state(observer, joined), Some(State::Alive));
+        }
+        assert_eq!(cluster.members[joined].alive().len(), 5);
+    }
+
+    #[test]
+    fn refute() {
+        let mut cluster = Cluster::new(4);
+        cluster.run(Duration::from_secs(1));
+
+        let accusation = Command::Ping {
+            seq: 99,
+            updates: vec![erasure_node::membership::Update {
+                member: "2".into(),
+                state: State::Suspect,
+                incarnation: 0,
+            }],
+        };
+        let now = cluster.now;
+        cluster.members[0].handle("1", &accusation, now);
+        cluster.members[2].handle("1", &accusation, now);
+        assert_eq!(cluster.state(0, 2), Some(State::Suspect));
+        assert_eq!(cluster.members[2].incarnation(), 1);
+
+        cluster.run(Duration::from_secs(5));
+        for observer in [0, 1, 3] {
+            assert_eq!(cluster.state(observer, 2), Some(State::Alive));
+        }
+    }
+
+    #[test]
+    fn rejoin() {
+        let mut cluster = Cluster::new(4);
+        cluster.down.insert(1);
+        cluster.run(Duration::from_secs(10));
+        assert_eq!(cluster.state(0, 1), Some(State::Dead));
+
+        cluster.down.clear();
+        cluster.run(Duration::from_secs(10));
+        for observer in [0, 2, 3] {
+            assert_eq!(cluster.state(observer, 1), Some(State::Alive));
+        }
+        assert_eq!(cluster.members[1].alive().len(), 3);
+    }
+
+    #[test]
+    fn relay_expires() {
+        let now = Instant::now();
+        let mut member = Membership::new("0".into(), vec!["1".into(), "2".into()], config(), now);
+
+        let request = Command::PingReq {
+            seq: 7,
+            target: "2".into(),
+            updates: vec![],
+        };
+        let out = member.handle("1", &request, now).unwrap();
+        let Command::Ping { seq, .. } = out[0].1 else {
+            panic!("expected a relayed ping");
+        };
+        assert!(member.deadline() <= now + config().probe_timeout);
+
+        // An ack arriving after the probe timeout is no longer forwarded.
+        let later = now + config().probe_timeout;
+        member.tick(later);
+        let ack = Command::Ack {
+            seq,
+            updates: vec![],
+        };
+        let out = member.handle("2", &ack, later).unwrap();
+        assert!(out.iter().all(|(peer, _)| peer != "1"));
+    }
+
+    #[test]
+    fn ignores_other_commands() {
+        let mut member = Membership::new("0".into(), vec![], config(), Instant::now());
+        assert!(
+            member
+                .handle("1", &Command::Announce, Instant::now())
+                .is_none()
+        );
+        assert!(member.alive().is_empty());
+    }
+}
+
+mod config {
+    use std::time::Duration;
+
+    use erasure_node::{config::NodeConfig, redundancy::AdaptiveRedundancy};
+
+    #[test]
+    fn builder() {
+        let config = NodeConfig::default()
+            .with_cache(8, Duration::from_secs(1))
+            .with_race_factor(0)
+            .with_backfill(0, Duration::from_millis(500), 0)
+            .with_adaptive_redundancy(AdaptiveRedundancy::default());
+
+        assert_eq!(
+            (config.cache_capacity, config.cache_ttl),
+            (8, Duration::from_secs(1))
+        );
+        assert_eq!(config.race_factor, 1);
+        assert_eq!((config.backfill_window, config.backfill_attempts), (1, 1));
+        assert!(config.adaptive_redundancy.is_some());
+        assert!(config.repair_interval.is_none());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        use erasure_node::{config::Replication, file::EncodeConfig};
+
+        let config: NodeConfig = serde_json::from_str(
+            r#"{"cache_capacity": 16, "quota": {"storage": 1024, "ingress": null}, "replication": "pull", "encoding": {"data_shards": 10, "parity_shards": 4}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.cache_capacity, 16);
+        assert_eq!(config.replication, Replication::Pull);
+        assert_eq!(config.encoding, Some(EncodeConfig::new(10, 4)));
+        assert_eq!(config.quota.storage, Some(1024));
+        assert_eq!(config.join_sample, NodeConfig::default().join_sample);
+
+        let json = serde_json::to_string(&config).unwrap();
+        let parsed: NodeConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.cache_capacity, 16);
+        assert_eq!(parsed.backfill_timeout, config.backfill_timeout);
+    }
+}
+
+mod redundancy {
+    use erasure_node::redundancy::{AdaptiveRedundancy, FixedRedundancy, RedundancyPolicy};
+
+    #[test]
+    fn fixed() {
+        assert_eq!(FixedRedundancy.parity_shards(10, 0.0), 10);
+        assert_eq!(FixedRedundancy.parity_shards(10, 0.9), 10);
+    }
+
+    #[test]
+    fn adaptive() {
+        let policy = AdaptiveRedundancy::default();
+        assert_eq!(policy.parity_shards(10, 0.0), 3);
+        assert_eq!(policy.parity_shards(10, 0.2), 5);
+        assert_eq!(policy.parity_shards(10, 0.5), 20);
+        assert_eq!(policy.parity_shards(10, 1.0), 20);
+    }
+}
+
+mod erasure {
+    use erasure_node::{
+        erasure::{self, Codec, CodecError, Replicas, Xor},
+        file::CodecId,
+    };
+
+    fn shards(codec: &dyn Codec, size: usize) -> Vec<Vec<u8>> {
+        let mut shards = (0..codec.data_shards() + codec.parity_shards())
+            .map(|index| vec![index as u8 * 3 + 1; size])
+            .collect::<Vec<_>>();
+        let mut refs = shards.iter_mut().map(Vec::as_mut_slice).collect::<Vec<_>>();
+        codec.encode(&mut refs).unwrap();
+        shards
+    }
+
+    fn restore(codec: &dyn Codec, shards: &[Vec<u8>], lost: &[usize]) -> Result<(), CodecError> {
+        let mut copy = shards.to_vec();
+        for index in lost {
+            copy[*index].fill(0);
+        }
+        let mut refs = copy
+            .iter_mut()
+            .enumerate()
+            .map(|(index, shard)| (shard.as_mut_slice(), !lost.contains(&index)))
+            .collect::<Vec<_>>();
+        codec.reconstruct(&mut refs, false)?;
+        drop(refs);
+        assert_eq!(copy, shards);
+        Ok(())
+    }
+
+    #[test]
+    fn xor() {
+        let codec = Xor::new(4, 1).unwrap();
+        let shards = shards(&codec, 16);
+        let refs = shards.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        assert_eq!(codec.verify(&refs), Ok(true));
+        assert_eq!(codec.overhead(), 1.25);
+
+        for lost in 0..5 {
+            restore(&codec, &shards, &[lost]).unwrap();
+        }
+        assert_eq!(
+            restore(&codec, &shards, &[0, 4]),
+            Err(CodecError::TooFewShards)
+        );
+        assert!(Xor::new(4, 2).is_err());
+    }
+
+    #[test]
+    fn replicas() {
+        let codec = Replicas::new(1, 2).unwrap();
+        let shards = shards(&codec, 16);
+        assert!(shards.iter().all(|shard| *shard == shards[0]));
+        assert_eq!(codec.overhead(), 3.0);
+
+        restore(&codec, &shards, &[0, 1]).unwrap();
+        assert_eq!(
+            restore(&codec, &shards, &[0, 1, 2]),
+            Err(CodecError::TooFewShards)
+        );
+        assert!(Replicas::new(2, 2).is_err());
+    }
+
+    #[test]
+    fn reed_solomon() {
+        let codec = erasure::codec(CodecId::ReedSolomon8, 4, 2).unwrap();
+        let shards = shards(&*codec, 16);
+        assert_eq!(codec.overhead(), 1.5);
+        restore(&*codec, &shards, &[1, 5]).unwrap();
+        assert_eq!(
+            restore(&*codec, &shards, &[0, 1, 2]),
+            Err(CodecError::TooFewShards)
+        );
+    }
+
+    #[cfg(feature = "raptorq")]
+    #[test]
+    fn raptorq() {
+        use erasure_node::erasure::RaptorQ;
+
+        let codec = RaptorQ::new(4, 8).unwrap();
+        let shards = shards(&codec, 16);
+        assert_eq!(&shards[0], &[1; 16]);
+        let mut refs = shards.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        assert_eq!(codec.verify(&refs), Ok(true));
+        assert_eq!(codec.overhead(), 3.0);
+
+        restore(&codec, &shards, &[0, 1, 2, 3, 4, 5]).unwrap();
+        restore(&codec, &shards, &[1, 6, 7, 11]).unwrap();
+        assert_eq!(
+            restore(&codec, &shards, &[0, 1, 2, 3, 4, 5, 6, 7, 8]),
+            Err(CodecError::TooFewShards)
+        );
+
+        let forged = vec![0; 16];
+        refs[9] = &forged;
+        assert_eq!(codec.verify(&refs), Ok(false));
+
+        assert!(RaptorQ::new(0, 4).is_err());
+        assert!(RaptorQ::new(56404, 4).is_err());
+        assert!(RaptorQ::new(4, 1 << 24).is_err());
+        let mut large = vec![vec![0; 1 << 16]; 12];
+        let mut refs = large.iter_mut().map(Vec::as_mut_slice).collect::<Vec<_>>();
+        assert_eq!(codec.encode(&mut refs), Err(CodecError::InvalidShardSize));
+    }
+}
+
+mod codec {
+    use bytes::BytesMut;
+    use erasure_node::{
+        Error, codec,
+        file::{EncodeConfig, Encryption, File, Stamp, WrappedKey},
+        membership::{State, Update},
+        network::Command,
+        quota::Rejection,
+    };
+
+    #[test]
+    fn roundtrip() {
+        let stamp = Stamp::new(42, "7");
+        let file = File::encode("hello world!".repeat(10))
+            .unwrap()
+            .with_stamp(stamp);
+        let meta = file.metadata().clone();
+        let shard = file.shards().get(3).unwrap();
+        let config = EncodeConfig::new(2, 1).with_block_size(64);
+        let blocks = File::encode_with("hello world!".repeat(10), config).unwrap();
+
+        let commands = vec![
+            Command::Create {
+                name: "a/hello".into(),
+                meta: meta.clone(),
+            },
+            Command::Replicate {
+                name: "hello".into(),
+                stamp,
+                checksum: shard.crc(),
+                shard,
+            },
+            Command::Request {
+                name: "hello".into(),
+                bitmap: vec![0b101],
+            },
+            Command::Fetch {
+                name: "hello".into(),
+                index: 7,
+            },
+            Command::Cancel {
+                name: "hello".into(),
+            },
+            Command::Announce,
+            Command::Manifest {
+                files: vec![("hello".into(), meta.clone()), ("world".into(), meta)],
+            },
+            Command::Leave,
+            Command::Challenge {
+                name: "hello".into(),
+                index: 1,
+                nonce: 42,
+            },
+            Command::Proof {
+                name: "hello".into(),
+                index: 1,
+                nonce: 42,
+                proof: Some(7),
+            },
+            Command::Proof {
+                name: "hello".into(),
+                index: 1,
+                nonce: 42,
+                proof: None,
+            },
+            Command::Reject {
+                name: "hello".into(),
+                reason: Rejection::Decommissioning,
+            },
+            Command::Drop {
+                name: "hello".into(),
+                indices: vec![0, 3, 7],
+            },
+            Command::RequestMeta {
+                name: "hello".into(),
+            },
+            Command::Meta {
+                name: "hello".into(),
+                meta: file.metadata().clone(),
+                bitmap: file.shards().bitmap(),
+            },
+            Command::Ingest {
+                name: "hello".into(),
+                content: "hello world!".into(),
+            },
+            Command::Ping {
+                seq: 1,
+                updates: vec![],
+            },
+            Command::PingReq {
+                seq: 2,
+                target: "peer".into(),
+                updates: vec![Update {
+                    member: "peer".into(),
+                    state: State::Suspect,
+                    incarnation: 3,
+                }],
+            },
+            Command::Ack {
+                seq: 2,
+                updates: vec![
+                    Update {
+                        member: "a".into(),
+                        state: State::Alive,
+                        incarnation: 1,
+                    },
+                    Update {
+                        member: "b".into(),
+                        state: State::Dead,
+                        incarnation: 0,
+                    },
+                ],
+            },
+            Command::Nack {
+                name: "hello".into(),
+                index: 3,
+            },
+            Command::Credit {
+                messages: 8,
+                bytes: 1024,
+            },
+            Command::Fragment {
+                id: 7,
+                index: 1,
+                count: 3,
+                data: vec![1, 2, 3],
+            },
+            Command::Offer {
+                name: "hello".into(),
+                meta: file.metadata().clone(),
+                layout: vec!["a".into(), "b".into(), "a".into()],
+            },
+            Command::Delete {
+                name: "hello".into(),
+                stamp,
+            },
+            Command::Create {
+                name: "blocks".into(),
+                meta: blocks.metadata().clone().with_encryption(Encryption {
+                    key_id: 9,
+                    nonce: [5; 24],
+                    recipients: vec![WrappedKey {
+                        recipient: [1; 32],
+                        ephemeral: [2; 32],
+                        sealed: vec![3; 48].into(),
+                    }],
+                }),
+            },
+            Command::Create {
+                name: "tagged".into(),
+                meta: file
+                    .metadata()
+                    .clone()
+                    .with_created_at(1_000)
+                    .with_content_type("text/plain")
+                    .with_attribute("owner", "alice")
+                    .with_attribute("", ""),
+            },
+        ];
+        #[cfg(feature = "signing")]
+        let commands = {
+            use erasure_node::sign::SigningKey;
+
+            let signed = file
+                .clone()
+                .signed(&SigningKey::from_bytes(&[5; 32]))
+                .unwrap();
+            let mut commands = commands;
+            commands.push(Command::Create {
+                name: "signed".into(),
+                meta: signed.metadata().clone(),
+            });
+            commands
+        };
+
+        for command in commands {
+            let frame = codec::encode(&command).unwrap();
+            assert_eq!(frame.len(), command.size());
+            assert_eq!(codec::decode(frame), Some(command));
+        }
+    }
+
+    #[test]
+    fn zero_copy() {
+        let file = File::encode("hello world!").unwrap();
+        let command = Command::Replicate {
+            name: "hello".into(),
+            stamp: Stamp::default(),
+            shard: file.shards().get(0).unwrap(),
+            checksum: 0,
+        };
+
+        let frame = codec::encode(&command).unwrap();
+        let range = frame.as_ptr_range();
+        let Some(Command::Replicate { shard, .. }) = codec::decode(frame) else {
+            panic!("expected replicate");
+        };
+
+        assert!(range.contains(&shard.data().as_ptr()));
+    }
+
+    #[test]
+    fn bytes() {
+        let file = File::encode("hello world!").unwrap();
+        let command = Command::Meta {
+            name: "hello".into(),
+            meta: file.metadata().clone(),
+            bitmap: file.shards().bitmap(),
+        };
+
+        let frame = command.to_bytes().unwrap();
+        assert_eq!(frame, codec::encode(&command).unwrap());
+        assert_eq!(Command::from_bytes(frame.clone()), Ok(command));
+        assert_eq!(
+            Command::from_bytes(frame.slice(..frame.len() - 1)),
+            Err(Error::Malformed)
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let file = File::encode("hello world!").unwrap();
+        let commands = vec![
+            Command::Create {
+                name: "hello".into(),
+                meta: file.metadata().clone(),
+            },
+            Command::Replicate {
+                name: "hello".into(),
+                stamp: Stamp::new(42, "7"),
+                checksum: 0,
+                shard: file.shards().get(1).unwrap(),
+            },
+            Command::Reject {
+                name: "hello".into(),
+                reason: Rejection::Storage,
+            },
+            Command::Ack {
+                seq: 2,
+                updates: vec![Update {
+                    member: "a".into(),
+                    state: State::Alive,
+                    incarnation: 1,
+                }],
+            },
+        ];
+
+        for command in commands {
+            let json = serde_json::to_string(&command).unwrap();
+            assert_eq!(serde_json::from_str::<Command>(&json).unwrap(), command);
+        }
+    }
+
+    #[test]
+    fn split() {
+        let first = codec::encode(&Command::Announce).unwrap();
+        let second = codec::encode(&Command::Request {
+            name: "hello".into(),
+            bitmap: Vec::new(),
+        })
+        .unwrap();
+
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&first);
+        buf.extend_from_slice(&second[..3]);
+
+        assert_eq!(codec::split(&mut buf), Some(first));
+        assert_eq!(codec::split(&mut buf), None);
+
+        buf.extend_from_slice(&second[3..]);
+        let frame = codec::split(&mut buf).unwrap();
+        assert_eq!(codec::decode(frame.clone()), codec::decode(second));
+        assert!(buf.is_empty());
+
+        assert!(codec::decode(frame.slice(..frame.len() - 1)).is_none());
+    }
+
+    #[test]
+    fn version() {
+        let file = File::encode("hello world!").unwrap();
+        let command = Command::Create {
+            name: "hello".into(),
+            meta: file.metadata().clone(),
+        };
+        let frame = codec::encode(&command).unwrap();
+
+        // Header, version, tag and the name come before the metadata's own length and version.
+        let (meta_len, meta_version) = (4 + 1 + 1 + 4 + 5, 4 + 1 + 1 + 4 + 5 + 4);
+        assert_eq!(frame[4], codec::VERSION);
+        assert_eq!(frame[meta_version], codec::VERSION);
+
+        let rewrite = |version: u8, meta_extra: &[u8], extra: &[u8]| {
+            let mut frame = frame.to_vec();
+            frame[4] = version;
+            frame[meta_version] = version;
+            frame.extend_from_slice(meta_extra);
+            frame.extend_from_slice(extra);
+            let grow = |at: usize, by: usize, frame: &mut Vec<u8>| {
+                let len = u32::from_be_bytes(frame[at..at + 4].try_into().unwrap());
+                frame[at..at + 4].copy_from_slice(&(len + by as u32).to_be_bytes());
+            };
+            grow(meta_len, meta_extra.len(), &mut frame);
+            grow(0, meta_extra.len() + extra.len(), &mut frame);
+            codec::decode(frame.into())
+        };
+
+        // Fields appended by a newer writer are skipped; from the same version they are garbage.
+        let newer = codec::VERSION + 1;
+        assert_eq!(rewrite(newer, &[1, 2, 3], &[4, 5]), Some(command.clone()));
+        assert_eq!(rewrite(newer, &[], &[]), Some(command.clone()));
+        assert_eq!(rewrite(codec::VERSION, &[], &[]), Some(command));
+        assert!(rewrite(codec::VERSION, &[1, 2, 3], &[]).is_none());
+        assert!(rewrite(codec::VERSION, &[], &[4, 5]).is_none());
+        assert!(rewrite(0, &[], &[]).is_none());
+    }
+
+    // Offset of the metadata's data shard count in a `Create` frame for `name`.
+    fn data_shards_at(name: &str) -> usize {
+        4 + 1 + 1 + 4 + name.len() + 4 + 1 + 8 + 4 + 4
+    }
+
+    #[test]
+    fn layout() {
+        let file = File::encode("hello world!").unwrap();
+        let command = Command::Create {
+            name: "hello".into(),
+            meta: file.metadata().clone(),
+        };
+        let frame = codec::encode(&command).unwrap();
+        let at = data_shards_at("hello");
+        assert_eq!(
+            &frame[at..at + 8],
+            [
+                file.metadata().data_shards(),
+                file.metadata().parity_shards()
+            ]
+            .map(|count| count as u32)
+            .map(u32::to_be_bytes)
+            .concat()
+        );
+
+        let patch = |at: usize, value: &[u8]| {
+            let mut frame = frame.to_vec();
+            frame[at..at + value.len()].copy_from_slice(value);
+            codec::decode(frame.into())
+        };
+        let count = |count: usize| (count as u32).to_be_bytes();
+        let (data_shards, parity_shards) = (
+            file.metadata().data_shards(),
+            file.metadata().parity_shards(),
+        );
+        assert_eq!(patch(at, &count(data_shards)), Some(command));
+        assert!(patch(at, &count(0x7fffffff)).is_none());
+        assert!(patch(at + 4, &count(0x7fffffff)).is_none());
+        assert!(patch(at, &count(0)).is_none());
+        // One more parity shard no longer matches the checksums.
+        assert!(patch(at + 4, &count(parity_shards + 1)).is_none());
+        // No shard size, and more content than the data shards hold.
+        assert!(patch(at - 8, &count(0)).is_none());
+        assert!(patch(at - 16, &u64::MAX.to_be_bytes()).is_none());
+    }
+
+    #[test]
+    fn fuzz() {
+        let file = File::encode("hello world!".repeat(10)).unwrap();
+        let config = EncodeConfig::new(2, 1).with_block_size(64);
+        let blocks = File::encode_with("hello world!".repeat(10), config).unwrap();
+        let frames = [
+            Command::Create {
+                name: "hello".into(),
+                meta: file.metadata().clone(),
+            },
+            Command::Manifest {
+                files: vec![("blocks".into(), blocks.metadata().clone())],
+            },
+            Command::Meta {
+                name: "hello".into(),
+                meta: blocks.metadata().clone(),
+                bitmap: blocks.shards().bitmap(),
+            },
+            Command::Drop {
+                name: "hello".into(),
+                indices: vec![1, 2],
+            },
+        ]
+        .map(|command| codec::encode(&command).unwrap().to_vec());
+
+        let mut state = 0x2545f4914f6cdd1du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as usize
+        };
+        for round in 0..20_000 {
+            let mut frame = frames[round % frames.len()].clone();
+            for _ in 0..1 + next() % 4 {
+                let at = 4 + next() % (frame.len() - 4);
+                match next() % 3 {
+                    0 => frame[at] = next() as u8,
+                    1 => {
+                        let end = (at + 4).min(frame.len());
+                        let value = [0x7f, 0xff, 0xff, 0xff, 0x00][next() % 5];
+                        frame[at..end].fill(value);
+                    }
+                    _ => frame[at] ^= 1 << (next() % 8),
+                }
+            }
+            if next() % 8 == 0 {
+                frame.truncate(4 + next() % (frame.len() - 4));
+                let len = frame.len() as u32 - 4;
+                frame[..4].copy_from_slice(&len.to_be_bytes());
+            }
+
+            let metas = match codec::decode(frame.into()) {
+                Some(Command::Create { meta, .. } | Command::Meta { meta, .. }) => vec![meta],
+                Some(Command::Manifest { files }) => {
+                    files.into_iter().map(|(_, meta)| meta).collect()
+                }
+                _ => Vec::new(),
+            };
+            for meta in metas {
+                assert!(meta.shard_count() <= erasure_node::file::MAX_SHARDS);
+                let file = File::empty(meta);
+                assert!(file.decode().is_err() || file.metadata().is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn oversized() {
+        let command = Command::Drop {
+            name: "hello".into(),
+            indices: vec![usize::MAX],
+        };
+        assert_eq!(codec::encode(&command), None);
+        assert_eq!(command.to_bytes(), Err(Error::TooLarge));
+
+        // The scratch buffer is left clean for the next frame.
+        let command = Command::Announce;
+        assert_eq!(
+            codec::decode(codec::encode(&command).unwrap()),
+            Some(command)
+        );
+    }
+}
+
+#[cfg(feature = "proto")]
+mod proto {
+    use std::collections::HashSet;
+
+    use erasure_node::{
+        file::{EncodeConfig, Encryption, File, Stamp, VectorClock, WrappedKey},
+        membership::{State, Update},
+        network::Command,
+        proto,
+        quota::Rejection,
+    };
+    use prost::Message;
+    use protobuf::{
+        MessageDyn,
+        reflect::{ReflectFieldRef, ReflectValueRef},
+    };
+
+    fn commands() -> Vec<Command> {
+        let stamp = Stamp::new(42, "7");
+        let file = File::encode("hello world!".repeat(10))
+            .unwrap()
+            .with_stamp(stamp);
+        let meta = file.metadata().clone();
+        let config = EncodeConfig::new(2, 1).with_block_size(64);
+        let blocks = File::encode_with("hello world!".repeat(10), config).unwrap();
+
+        vec![
+            Command::Create {
+                name: "hello".into(),
+                meta: meta.clone(),
+            },
+            Command::Replicate {
+                name: "hello".into(),
+                stamp,
+                shard: file.shards().get(2).unwrap(),
+                checksum: 7,
+            },
+            Command::Request {
+                name: "hello".into(),
+                bitmap: vec![0b11],
+            },
+            Command::Fetch {
+                name: "hello".into(),
+                index: 2,
+            },
+            Command::Cancel {
+                name: "hello".into(),
+            },
+            Command::Announce,
+            Command::Manifest {
+                files: vec![("hello".into(), meta)],
+            },
+            Command::Leave,
+            Command::Challenge {
+                name: "hello".into(),
+                index: 1,
+                nonce: 42,
+            },
+            Command::Proof {
+                name: "hello".into(),
+                index: 1,
+                nonce: 42,
+                proof: Some(7),
+            },
+            Command::Proof {
+                name: "hello".into(),
+                index: 1,
+                nonce: 42,
+                proof: None,
+            },
+            Command::Reject {
+                name: "hello".into(),
+                reason: Rejection::Bandwidth,
+            },
+            Command::Drop {
+                name: "hello".into(),
+                indices: vec![1, 2],
+            },
+            Command::RequestMeta {
+                name: "hello".into(),
+            },
+            Command::Meta {
+                name: "hello".into(),
+                meta: file.metadata().clone(),
+                bitmap: vec![0b101],
+            },
+            Command::Ingest {
+                name: "hello".into(),
+                content: "hello world!".into(),
+            },
+            Command::Ping {
+                seq: 1,
+                updates: vec![],
+            },
+            Command::PingReq {
+                seq: 2,
+                target: "peer".into(),
+                updates: vec![Update {
+                    member: "peer".into(),
+                    state: State::Suspect,
+                    incarnation: 3,
+                }],
+            },
+            Command::Ack {
+                seq: 2,
+                updates: vec![
+                    Update {
+                        member: "a".into(),
+                        state: State::Alive,
+                        incarnation: 1,
+                    },
+                    Update {
+                        member: "b".into(),
+                        state: State::Dead,
+                        incarnation: 0,
+                    },
+                ],
+            },
+            Command::Nack {
+                name: "hello".into(),
+                index: 3,
+            },
+            Command::Credit {
+                messages: 8,
+                bytes: 1024,
+            },
+            Command::Fragment {
+                id: 7,
+                index: 1,
+                count: 3,
+                data: vec![1, 2, 3],
+            },
+            Command::Offer {
+                name: "hello".into(),
+                meta: file.metadata().clone(),
+                layout: vec!["a".into(), "b".into(), "a".into()],
+            },
+            Command::Delete {
+                name: "hello".into(),
+                stamp,
+            },
+            Command::Create {
+                name: "blocks".into(),
+                meta: blocks.metadata().clone().with_encryption(Encryption {
+                    key_id: 9,
+                    nonce: [5; 24],
+                    recipients: vec![WrappedKey {
+                        recipient: [1; 32],
+                        ephemeral: [2; 32],
+                        sealed: vec![3; 48].into(),
+                    }],
+                }),
+            },
+            Command::Create {
+                name: "tagged".into(),
+                meta: file
+                    .metadata()
+                    .clone()
+                    .with_clock(VectorClock::from_iter([(7, 2)]))
+                    .with_created_at(1_000)
+                    .with_content_type("text/plain")
+                    .with_attribute("owner", "alice")
+                    .with_attribute("", ""),
+            },
+        ]
+    }
+
+    #[test]
+    fn roundtrip() {
+        for command in commands() {
+            assert_eq!(
+                proto::decode(&proto::encode(&command).unwrap()),
+                Some(command)
+            );
+        }
+    }
+
+    // proto/erasure.proto is what other implementations build against, so every command
+    // proto.rs encodes must parse against it without unknown fields, and every message
+    // it declares must be in use.
+    #[test]
+    fn schema() {
+        use protobuf::reflect::FileDescriptor;
+
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/proto");
+        let parsed = protobuf_parse::Parser::new()
+            .pure()
+            .include(dir)
+            .input(format!("{dir}/erasure.proto"))
+            .parse_and_typecheck()
+            .unwrap();
+        let files = FileDescriptor::new_dynamic_fds(parsed.file_descriptors, &[]).unwrap();
+        let schema = files[0]
+            .message_by_package_relative_name("Command")
+            .unwrap();
+
+        #[allow(unused_mut)]
+        let mut commands = commands();
+        #[cfg(feature = "signing")]
+        commands.push(Command::Create {
+            name: "signed".into(),
+            meta: File::encode("hello world!")
+                .unwrap()
+                .signed(&erasure_node::sign::SigningKey::from_bytes(&[5; 32]))
+                .unwrap()
+                .metadata()
+                .clone(),
+        });
+
+        let mut used = HashSet::new();
+        for command in commands {
+            let message = schema
+                .parse_from_bytes(&proto::encode(&command).unwrap())
+                .unwrap();
+            known(&*message, &mut used);
+            let bytes = message.write_to_bytes_dyn().unwrap();
+            assert_eq!(proto::decode(&bytes), Some(command));
+        }
+
+        let declared = files[0]
+            .messages()
+            .map(|message| message.full_name().to_string())
+            .collect::<HashSet<_>>();
+        assert_eq!(used, declared);
+    }
+
+    fn known(message: &dyn MessageDyn, used: &mut HashSet<String>) {
+        let descriptor = message.descriptor_dyn();
+        assert!(
+            message.unknown_fields_dyn().iter().next().is_none(),
+            "{} carries fields the schema does not declare",
+            descriptor.full_name()
+        );
+        used.insert(descriptor.full_name().to_string());
+
+        for field in descriptor.fields() {
+            let values = match field.get_reflect(message) {
+                ReflectFieldRef::Optional(value) => value.value().into_iter().collect(),
+                ReflectFieldRef::Repeated(values) => values.into_iter().collect(),
+                ReflectFieldRef::Map(_) => Vec::new(),
+            };
+            for value in values {
+                if let ReflectValueRef::Message(nested) = value {
+                    known(&*nested, used);
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "signing")]
+    #[test]
+    fn signed() {
+        use erasure_node::sign::SigningKey;
+
+        let file = File::encode("hello world!")
+            .unwrap()
+            .signed(&SigningKey::from_bytes(&[5; 32]))
+            .unwrap();
+        let command = Command::Create {
+            name: "hello".into(),
+            meta: file.metadata().clone(),
+        };
+        let Some(Command::Create { meta, .. }) = proto::decode(&proto::encode(&command).unwrap())
+        else {
+            panic!("roundtrip failed");
+        };
+        assert_eq!(&meta, file.metadata());
+        assert!(meta.verify_signature());
+    }
+
+    #[test]
+    fn version() {
+        let mut command = proto::to_proto(&Command::Announce).unwrap();
+        command.version += 1;
+        assert_eq!(
+            proto::decode(&command.encode_to_vec()),
+            Some(Command::Announce)
+        );
+        command.version = 0;
+        assert!(proto::decode(&command.encode_to_vec()).is_none());
+    }
+
+    #[test]
+    fn oversized() {
+        let index = u32::MAX as usize + 1;
+        let fetch = Command::Fetch {
+            name: "hello".into(),
+            index,
+        };
+        assert_eq!(proto::encode(&fetch), None);
+
+        let drop = Command::Drop {
+            name: "hello".into(),
+            indices: vec![0, index],
+        };
+        assert_eq!(proto::encode(&drop), None);
+    }
+}
+
+mod pool {
+    use erasure_node::pool;
+
+    #[test]
+    fn reuse() {
+        let before = pool::stats();
+        let buf = pool::take(4099);
+        pool::give(buf);
+        let buf = pool::take(4099);
+        assert!(buf.iter().all(|byte| *byte == 0));
+        assert_eq!(buf.len(), 4099);
+
+        // Other tests share the size class, so the first take may itself be a reuse.
+        let after = pool::stats();
+        assert!(after.allocations + after.reuses >= before.allocations + before.reuses + 2);
+        assert!(after.reuses > before.reuses);
+    }
+
+    #[test]
+    fn classes() {
+        // Any size in the same power-of-two class gets the same buffer back.
+        let buf = pool::take(600_000);
+        let ptr = buf.as_ptr();
+        pool::give(buf);
+        let buf = pool::take(1_000_000);
+        assert_eq!(buf.as_ptr(), ptr);
+        assert_eq!(buf.len(), 1_000_000);
+        assert!(buf.iter().all(|byte| *byte == 0));
+        pool::give(buf);
+    }
+
+    #[test]
+    fn bounded() {
+        let before = pool::stats();
+        let bufs = (0..20).map(|_| pool::take(3 << 20)).collect::<Vec<_>>();
+        for buf in bufs {
+            pool::give(buf);
+        }
+
+        let after = pool::stats();
+        assert!(after.bytes <= 64 << 20);
+        assert!(after.pooled < before.pooled + 20);
+    }
+}
+
+#[cfg(feature = "tcp")]
+mod tcp {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use erasure_node::{
+        config::NodeConfig,
+        metrics::{self, MetricsSink},
+        network::{Command, Network},
+        node::Node,
+        tcp::{TcpConfig, TcpNetwork},
+    };
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    async fn bind(peers: Vec<String>) -> TcpNetwork {
+        TcpNetwork::bind("127.0.0.1:0", peers).await.unwrap()
+    }
+
+    async fn recv(network: &TcpNetwork) -> (String, Command) {
+        tokio::time::timeout(Duration::from_secs(5), network.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn cluster() {
+        runtime().block_on(async {
+            let mut networks = Vec::new();
+            for _ in 0..3 {
+                networks.push(bind(Vec::new()).await);
+            }
+            let peers = networks.iter().map(|n| n.id()).collect::<Vec<_>>();
+            let nodes = networks
+                .into_iter()
+                .map(|network| {
+                    network.set_peers(peers.clone());
+                    let node = Arc::new(Node::new(network, NodeConfig::default()));
+                    let runner = Arc::clone(&node);
+                    tokio::spawn(async move { runner.run().await });
+                    node
+                })
+                .collect::<Vec<_>>();
+
+            let content = "hello world!".repeat(100);
+            nodes[0]
+                .upload("hello".to_string(), content.clone())
+                .await
+                .unwrap();
+            // A download that lacks shards requests them and fails; a later one decodes.
+            let mut res = nodes[2].download_string("hello".to_string()).await;
+            for _ in 0..50 {
+                if res.is_ok() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                res = nodes[2].download_string("hello".to_string()).await;
+            }
+            assert_eq!(res, Ok(content));
+
+            // Every peer got a connection of its own, reused for everything sent after.
+            let stats = nodes[0].network().stats();
+            assert_eq!((stats.dials, stats.connections), (2, 2));
+            assert_eq!(stats.dropped, 0);
+        });
+    }
+
+    #[test]
+    fn reconnect() {
+        runtime().block_on(async {
+            let b = bind(Vec::new()).await;
+            let addr = b.local_addr();
+            let a = bind(vec![b.id()]).await;
+
+            a.send(b.id(), Command::Announce).await;
+            assert_eq!(recv(&b).await, (a.id(), Command::Announce));
+
+            // The pooled connection notices the peer going away and the next send redials.
+            drop(b);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let b = TcpNetwork::bind(addr, Vec::new()).await.unwrap();
+            a.send(b.id(), Command::Leave).await;
+            assert_eq!(recv(&b).await, (a.id(), Command::Leave));
+
+            let stats = a.stats();
+            assert_eq!((stats.dials, stats.reconnects, stats.broken), (2, 1, 1));
+            assert_eq!((stats.connections, stats.dropped), (1, 0));
+        });
+    }
+
+    #[test]
+    fn backoff() {
+        runtime().block_on(async {
+            let closed = bind(Vec::new()).await;
+            let peer = closed.id();
+            drop(closed);
+
+            let config = TcpConfig {
+                backoff: Duration::from_millis(50),
+                ..TcpConfig::default()
+            };
+            let a = TcpNetwork::bind_with("127.0.0.1:0", vec![peer.clone()], config)
+                .await
+                .unwrap();
+
+            // A failed dial holds off the next one until the backoff is over.
+            a.send(peer.clone(), Command::Announce).await;
+            a.send(peer.clone(), Command::Announce).await;
+            let stats = a.stats();
+            assert_eq!((stats.dials, stats.dial_failures, stats.dropped), (1, 1, 2));
+
+            tokio::time::sleep(Duration::from_millis(60)).await;
+            a.send(peer.clone(), Command::Announce).await;
+            a.send(peer, Command::Announce).await;
+            let stats = a.stats();
+            assert_eq!((stats.dials, stats.dial_failures, stats.dropped), (2, 2, 4));
+        });
+    }
+
+    #[derive(Default)]
+    struct Sink {
+        counters: Mutex<HashMap<&'static str, u64>>,
+        gauges: Mutex<HashMap<&'static str, f64>>,
+    }
+
+    impl MetricsSink for Sink {
+        fn counter(&self, name: &'static str, value: u64) {
+            *self.counters.lock().unwrap().entry(name).or_default() += value;
+        }
+
+        fn gauge(&self, name: &'static str, value: f64) {
+            self.gauges.lock().unwrap().insert(name, value);
+        }
+
+        fn histogram(&self, _name: &'static str, _value: f64) {}
+    }
+
+    #[test]
+    fn heartbeat() {
+        runtime().block_on(async {
+            let config = TcpConfig {
+                heartbeat: Duration::from_millis(20),
+                ..TcpConfig::default()
+            };
+            let b = bind(Vec::new()).await;
+            let a = TcpNetwork::bind_with("127.0.0.1:0", vec![b.id()], config)
+                .await
+                .unwrap();
+            a.send(b.id(), Command::Announce).await;
+            assert_eq!(recv(&b).await, (a.id(), Command::Announce));
+
+            // Heartbeats keep the idle connection checked without reaching the node.
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            assert!(a.stats().heartbeats > 0);
+            a.send(b.id(), Command::Leave).await;
+            assert_eq!(recv(&b).await, (a.id(), Command::Leave));
+
+            let sink = Sink::default();
+            a.report(&sink);
+            a.report(&sink);
+            let counters = sink.counters.lock().unwrap();
+            assert_eq!(counters[metrics::TCP_DIALS], 1);
+            assert_eq!(
+                counters[metrics::TCP_HEARTBEATS] as usize,
+                a.stats().heartbeats
+            );
+            assert_eq!(sink.gauges.lock().unwrap()[metrics::TCP_CONNECTIONS], 1.0);
+            assert_eq!(b.stats().inbound, 1);
+        });
     }
 }
@@ -0,0 +1,47 @@
+use std::{collections::BTreeMap, io, path::Path};
+
+use erasure_node::{config::NodeConfig, quota::Quota, tcp::TcpConfig};
+use serde::Deserialize;
+
+/// Everything `erasure-noded` reads from its config file, a JSON object whose fields all
+/// have defaults.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// Address the node listens on for peers and clients.
+    pub listen: String,
+    /// Every node of the cluster by the id it advertises; this node's own entry is skipped.
+    pub peers: Vec<String>,
+    /// Where `/metrics` is served in the Prometheus text format; not served when None.
+    pub metrics: Option<String>,
+    /// Quotas of single tenants, in place of `node.quota`.
+    pub quotas: BTreeMap<String, Quota>,
+    pub tcp: TcpConfig,
+    /// The node's own settings. `node.repair_interval` also paces the daemon's audit and
+    /// repair pass, and `node.store` is where shards are kept across restarts.
+    pub node: NodeConfig,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            listen: "127.0.0.1:7000".to_string(),
+            peers: Vec::new(),
+            metrics: None,
+            quotas: BTreeMap::new(),
+            tcp: TcpConfig::default(),
+            node: NodeConfig::default(),
+        }
+    }
+}
+
+impl DaemonConfig {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let json = std::fs::read_to_string(path)?;
+        Self::parse(&json)
+    }
+
+    pub fn parse(json: &str) -> io::Result<Self> {
+        serde_json::from_str(json).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
@@ -0,0 +1,165 @@
+pub mod config;
+pub mod prometheus;
+pub mod reload;
+pub mod repair;
+
+use std::{io, net::SocketAddr, sync::Arc, time::Duration};
+
+use erasure_node::{node::Node, store::StoreConfig, tcp::TcpNetwork};
+use tokio::{net::TcpListener, sync::watch, task::JoinHandle};
+use tracing::{debug, info, warn};
+
+use crate::{config::DaemonConfig, prometheus::Prometheus};
+
+/// A running node: its network, store, repair loop and metrics endpoint.
+pub struct Daemon {
+    node: Arc<Node<TcpNetwork>>,
+    metrics: Arc<Prometheus>,
+    metrics_addr: Option<SocketAddr>,
+    repair_interval: watch::Sender<Option<Duration>>,
+    // What `reload` cannot change, to warn when a new config does.
+    listen: String,
+    metrics_listen: Option<String>,
+    store: StoreConfig,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Daemon {
+    /// Binds the listeners and opens the store, then runs the node, its repair loop and the
+    /// metrics endpoint on the current tokio runtime until the daemon is dropped.
+    pub async fn start(config: DaemonConfig) -> io::Result<Self> {
+        let network =
+            TcpNetwork::bind_with(config.listen.as_str(), config.peers, config.tcp).await?;
+        let metrics = Arc::new(Prometheus::default());
+        let (repair_interval, interval) = watch::channel(config.node.repair_interval);
+        let store = config.node.store.clone();
+        let node = Node::open(network, config.node)?.with_metrics(Arc::clone(&metrics));
+        for (tenant, quota) in config.quotas {
+            node.set_quota(tenant, quota);
+        }
+        let node = Arc::new(node);
+
+        let mut tasks = Vec::new();
+        let runner = Arc::clone(&node);
+        tasks.push(tokio::spawn(async move { runner.run().await }));
+
+        let joiner = Arc::clone(&node);
+        tasks.push(tokio::spawn(async move { joiner.join().await }));
+
+        tasks.push(tokio::spawn(repair(Arc::clone(&node), interval)));
+        tasks.push(tokio::spawn(resume(Arc::clone(&node))));
+
+        let mut metrics_addr = None;
+        if let Some(addr) = &config.metrics {
+            let listener = TcpListener::bind(addr.as_str()).await?;
+            metrics_addr = Some(listener.local_addr()?);
+            let sink = Arc::clone(&metrics);
+            tasks.push(tokio::spawn(async move {
+                if let Err(err) = prometheus::serve(listener, sink).await {
+                    warn!(%err, "metrics endpoint failed");
+                }
+            }));
+        }
+
+        Ok(Self {
+            node,
+            metrics,
+            metrics_addr,
+            repair_interval,
+            listen: config.listen,
+            metrics_listen: config.metrics,
+            store,
+            tasks,
+        })
+    }
+
+    /// Applies the tunable part of `config` to the running node: the peer list and their
+    /// regions, the default and per-tenant quotas (rate limits included) and the repair
+    /// interval. Files, usage and peer health are kept. The listen and metrics addresses and the store only change on
+    /// restart, and so do the other `tcp` and `node` settings.
+    pub fn reload(&self, config: DaemonConfig) {
+        if config.listen != self.listen
+            || config.metrics != self.metrics_listen
+            || config.node.store != self.store
+        {
+            warn!("listen, metrics and node.store changes take a restart");
+        }
+
+        info!(
+            peers = config.peers.len(),
+            quotas = config.quotas.len(),
+            "reloading"
+        );
+        self.node.network().set_peers(config.peers);
+        self.node.network().set_regions(config.tcp.regions);
+        self.node
+            .set_quotas(config.node.quota, config.quotas.into_iter().collect());
+        self.node.set_repair_interval(config.node.repair_interval);
+        self.repair_interval
+            .send_replace(config.node.repair_interval);
+    }
+
+    pub fn node(&self) -> &Arc<Node<TcpNetwork>> {
+        &self.node
+    }
+
+    pub fn metrics(&self) -> &Prometheus {
+        &self.metrics
+    }
+
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics_addr
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+// Attempts per interrupted download, one request window apart.
+const RESUME_ATTEMPTS: usize = 100;
+
+// Finishes the downloads a restart interrupted, from the shards the store kept.
+async fn resume(node: Arc<Node<TcpNetwork>>) {
+    for name in node.downloads() {
+        let mut resumed = false;
+        for _ in 0..RESUME_ATTEMPTS {
+            if node.download(name.clone()).await.is_ok() {
+                resumed = true;
+                break;
+            }
+            tokio::time::sleep(node.config().request_window).await;
+        }
+        match resumed {
+            true => info!(file = name, "resumed download"),
+            false => warn!(file = name, "interrupted download did not finish"),
+        }
+    }
+}
+
+// Paced by the latest reloaded interval; a change restarts the wait.
+async fn repair(node: Arc<Node<TcpNetwork>>, mut interval: watch::Receiver<Option<Duration>>) {
+    loop {
+        let current = *interval.borrow_and_update();
+        let Some(current) = current else {
+            if interval.changed().await.is_err() {
+                return;
+            }
+            continue;
+        };
+
+        tokio::select! {
+            () = tokio::time::sleep(current) => {
+                let pass = repair::pass(&node).await;
+                debug!(?pass, "repair pass");
+            }
+            changed = interval.changed() => if changed.is_err() {
+                return;
+            },
+        }
+    }
+}
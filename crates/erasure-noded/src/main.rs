@@ -0,0 +1,74 @@
+use std::process::ExitCode;
+
+use erasure_node::network::Network;
+use erasure_noded::{Daemon, config::DaemonConfig, reload::ConfigWatch};
+use tokio::signal::unix::{SignalKind, signal};
+use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
+
+const USAGE: &str = "usage: erasure-noded <config.json>";
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()))
+        .init();
+
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let [path] = args.as_slice() else {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    };
+
+    let config = match DaemonConfig::load(path) {
+        Ok(config) => config,
+        Err(err) => {
+            error!(path, %err, "cannot read config");
+            return ExitCode::from(2);
+        }
+    };
+
+    let daemon = match Daemon::start(config).await {
+        Ok(daemon) => daemon,
+        Err(err) => {
+            error!(%err, "cannot start node");
+            return ExitCode::FAILURE;
+        }
+    };
+    info!(id = daemon.node().network().id(), "node running");
+
+    // SIGHUP, or writing the config file, reloads it; a config that no longer parses is
+    // logged and the running settings stay.
+    let mut watch = match ConfigWatch::new(path) {
+        Ok(watch) => Some(watch),
+        Err(err) => {
+            warn!(path, %err, "cannot watch config, reloading on SIGHUP only");
+            None
+        }
+    };
+    let mut hangup = signal(SignalKind::hangup()).expect("signal handler");
+    let mut terminate = signal(SignalKind::terminate()).expect("signal handler");
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            _ = terminate.recv() => break,
+            _ = hangup.recv() => {}
+            () = changed(&mut watch) => {}
+        }
+
+        match DaemonConfig::load(path) {
+            Ok(config) => daemon.reload(config),
+            Err(err) => error!(path, %err, "cannot reload config"),
+        }
+    }
+    info!("shutting down");
+
+    ExitCode::SUCCESS
+}
+
+async fn changed(watch: &mut Option<ConfigWatch>) {
+    match watch {
+        Some(watch) => watch.changed().await,
+        None => std::future::pending().await,
+    }
+}
@@ -0,0 +1,82 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+};
+
+use axum::{Router, extract::State, routing::get};
+use erasure_node::metrics::MetricsSink;
+use tokio::net::TcpListener;
+use tracing::info;
+
+#[derive(Default)]
+struct Values {
+    counters: BTreeMap<&'static str, u64>,
+    gauges: BTreeMap<&'static str, f64>,
+    // Observation count and sum; rendered as a summary without quantiles.
+    histograms: BTreeMap<&'static str, (u64, f64)>,
+}
+
+/// A `MetricsSink` that keeps the latest value of everything a node reports and renders it
+/// in the Prometheus text format.
+#[derive(Default)]
+pub struct Prometheus {
+    values: Mutex<Values>,
+}
+
+impl Prometheus {
+    pub fn render(&self) -> String {
+        let values = self.values.lock().unwrap();
+        let mut out = String::new();
+        for (name, value) in &values.counters {
+            let _ = writeln!(out, "# TYPE {name} counter\n{name} {value}");
+        }
+        for (name, value) in &values.gauges {
+            let _ = writeln!(out, "# TYPE {name} gauge\n{name} {value}");
+        }
+        for (name, (count, sum)) in &values.histograms {
+            let _ = writeln!(
+                out,
+                "# TYPE {name} summary\n{name}_sum {sum}\n{name}_count {count}"
+            );
+        }
+        out
+    }
+}
+
+impl MetricsSink for Prometheus {
+    fn counter(&self, name: &'static str, value: u64) {
+        *self
+            .values
+            .lock()
+            .unwrap()
+            .counters
+            .entry(name)
+            .or_default() += value;
+    }
+
+    fn gauge(&self, name: &'static str, value: f64) {
+        self.values.lock().unwrap().gauges.insert(name, value);
+    }
+
+    fn histogram(&self, name: &'static str, value: f64) {
+        let mut values = self.values.lock().unwrap();
+        let (count, sum) = values.histograms.entry(name).or_default();
+        *count += 1;
+        *sum += value;
+    }
+}
+
+/// Serves `GET /metrics` on `listener` until the task is dropped.
+pub async fn serve(listener: TcpListener, sink: Arc<Prometheus>) -> std::io::Result<()> {
+    let app = Router::new()
+        .route("/metrics", get(metrics))
+        .with_state(sink);
+
+    info!(addr = %listener.local_addr()?, "serving metrics");
+    axum::serve(listener, app).await
+}
+
+async fn metrics(State(sink): State<Arc<Prometheus>>) -> String {
+    sink.render()
+}
@@ -0,0 +1,49 @@
+use std::path::{Path, PathBuf};
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+
+/// Watches the daemon's config file through inotify (or the platform's equivalent).
+///
+/// The parent directory is watched rather than the file, so editors that save by writing a
+/// new file and renaming it over the old one are noticed too.
+pub struct ConfigWatch {
+    _watcher: RecommendedWatcher,
+    changes: mpsc::UnboundedReceiver<()>,
+}
+
+impl ConfigWatch {
+    pub fn new(path: impl AsRef<Path>) -> notify::Result<Self> {
+        let path = std::path::absolute(path.as_ref())?;
+        let dir = path
+            .parent()
+            .map_or_else(|| PathBuf::from("/"), Path::to_path_buf);
+
+        let (tx, changes) = mpsc::unbounded_channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let Ok(event) = event else {
+                    return;
+                };
+                let written = matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_));
+                if written && event.paths.contains(&path) {
+                    let _ = tx.send(());
+                }
+            })?;
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+
+        Ok(Self {
+            _watcher: watcher,
+            changes,
+        })
+    }
+
+    /// Waits for the file to be written, folding in any further writes already queued so a
+    /// save reloads once.
+    pub async fn changed(&mut self) {
+        if self.changes.recv().await.is_none() {
+            std::future::pending::<()>().await;
+        }
+        while self.changes.try_recv().is_ok() {}
+    }
+}
@@ -0,0 +1,44 @@
+use std::collections::BTreeSet;
+
+use erasure_node::{network::Network, node::Node};
+use tracing::{info, warn};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RepairPass {
+    pub audited: usize,
+    pub repaired: usize,
+    pub failed: usize,
+}
+
+/// One round of the daemon's repair loop over the files this node placed: those with a
+/// shard on a peer the node suspects are rebuilt and placed again, and the rest have
+/// their holders challenged, so a holder that lost its shard or went away is suspected
+/// by the next round.
+pub async fn pass<N: Network>(node: &Node<N>) -> RepairPass {
+    let suspects = node.suspects();
+    let (mut placed, mut degraded) = (BTreeSet::new(), BTreeSet::new());
+    for (name, _, peer) in node.placements() {
+        if suspects.contains(&peer) {
+            degraded.insert(name.clone());
+        }
+        placed.insert(name);
+    }
+
+    let mut pass = RepairPass::default();
+    for name in &degraded {
+        if node.repair(name).await {
+            info!(file = name, "repaired");
+            pass.repaired += 1;
+        } else {
+            warn!(file = name, "repair failed");
+            pass.failed += 1;
+        }
+    }
+
+    for name in placed.difference(&degraded) {
+        node.audit(name).await;
+        pass.audited += 1;
+    }
+
+    pass
+}
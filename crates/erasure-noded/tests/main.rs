@@ -0,0 +1,295 @@
+mod daemon {
+    use std::time::Duration;
+
+    use erasure_node::{
+        config::Replication,
+        metrics::MetricsSink,
+        network::Network,
+        quota::Quota,
+        store::{Directory, ShardStore, StoreConfig},
+    };
+    use erasure_noded::{
+        Daemon, config::DaemonConfig, prometheus::Prometheus, reload::ConfigWatch, repair,
+    };
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    fn config(metrics: bool) -> DaemonConfig {
+        DaemonConfig {
+            listen: "127.0.0.1:0".to_string(),
+            metrics: metrics.then(|| "127.0.0.1:0".to_string()),
+            ..DaemonConfig::default()
+        }
+    }
+
+    #[test]
+    fn parse() {
+        let config = DaemonConfig::parse(
+            r#"{
+                "listen": "0.0.0.0:7100",
+                "peers": ["10.0.0.1:7100", "10.0.0.2:7100"],
+                "metrics": "127.0.0.1:9100",
+                "quotas": {"backups": {"storage": 1048576, "ingress": 65536}},
+                "tcp": {"advertise": "10.0.0.1:7100"},
+                "node": {"store": {"directory": "/var/lib/erasure"}, "replication": "pull"}
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.listen, "0.0.0.0:7100");
+        assert_eq!(config.peers.len(), 2);
+        assert_eq!(config.quotas["backups"].ingress, Some(65536));
+        assert_eq!(config.tcp.advertise.as_deref(), Some("10.0.0.1:7100"));
+        assert_eq!(config.tcp.heartbeat, Duration::from_secs(5));
+        assert_eq!(
+            config.node.store,
+            StoreConfig::Directory("/var/lib/erasure".into())
+        );
+        assert_eq!(config.node.replication, Replication::Pull);
+
+        assert_eq!(DaemonConfig::parse("{}").unwrap().listen, "127.0.0.1:7000");
+        assert!(DaemonConfig::parse(r#"{"listen": 7000}"#).is_err());
+        assert!(DaemonConfig::parse(r#"{"peer": []}"#).is_err());
+    }
+
+    #[test]
+    fn render() {
+        let sink = Prometheus::default();
+        sink.counter("uploads", 2);
+        sink.counter("uploads", 3);
+        sink.gauge("pending", 1.5);
+        sink.histogram("latency", 0.25);
+        sink.histogram("latency", 0.75);
+
+        assert_eq!(
+            sink.render(),
+            "# TYPE uploads counter\nuploads 5\n\
+             # TYPE pending gauge\npending 1.5\n\
+             # TYPE latency summary\nlatency_sum 1\nlatency_count 2\n"
+        );
+    }
+
+    async fn get(addr: std::net::SocketAddr, path: &str) -> String {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let request =
+            format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[test]
+    fn cluster() {
+        runtime().block_on(async {
+            let dir = std::env::temp_dir().join(format!("erasure-noded-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+
+            let mut first = config(true);
+            first.node.store = StoreConfig::Directory(dir.clone());
+            let daemons = [
+                Daemon::start(first).await.unwrap(),
+                Daemon::start(config(false)).await.unwrap(),
+                Daemon::start(config(false)).await.unwrap(),
+            ];
+            let peers = daemons
+                .iter()
+                .map(|daemon| daemon.node().network().id())
+                .collect::<Vec<_>>();
+            for daemon in &daemons {
+                daemon.node().network().set_peers(peers.clone());
+            }
+
+            let content = "hello world!".repeat(100);
+            daemons[0]
+                .node()
+                .upload("hello".to_string(), content.clone())
+                .await
+                .unwrap();
+
+            let mut res = daemons[2].node().download_string("hello".to_string()).await;
+            for _ in 0..50 {
+                if res.is_ok() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                res = daemons[2].node().download_string("hello".to_string()).await;
+            }
+            assert_eq!(res, Ok(content));
+
+            // The uploader audits the holders of what it placed, and they all answer.
+            let pass = repair::pass(daemons[0].node()).await;
+            assert_eq!((pass.audited, pass.repaired, pass.failed), (1, 0, 0));
+
+            let response = get(daemons[0].metrics_addr().unwrap(), "/metrics").await;
+            assert!(response.starts_with("HTTP/1.1 200"));
+            assert!(response.contains("erasure_node_uploads 1\n"));
+            assert!(response.contains("# TYPE erasure_node_tcp_connections gauge"));
+            assert!(daemons[1].metrics_addr().is_none());
+
+            // The store keeps what the node holds across a restart.
+            let held = daemons[0].node().shards("hello");
+            drop(daemons);
+            let mut reopened = config(false);
+            reopened.node.store = StoreConfig::Directory(dir.clone());
+            let daemon = Daemon::start(reopened).await.unwrap();
+            assert_eq!(daemon.node().shards("hello"), held);
+
+            let _ = std::fs::remove_dir_all(&dir);
+        });
+    }
+
+    #[test]
+    fn resume() {
+        runtime().block_on(async {
+            let dir = std::env::temp_dir().join(format!("erasure-resume-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+
+            let holder = Daemon::start(config(false)).await.unwrap();
+            let content = "hello world!".repeat(100);
+            holder
+                .node()
+                .upload("hello".to_string(), content)
+                .await
+                .unwrap();
+
+            // A store left behind by a node that stopped one shard short of decoding.
+            let (_, meta, shards) = holder.node().snapshot().into_iter().next().unwrap();
+            let arrived = &shards[..meta.data_shards() - 1];
+            let indices = arrived
+                .iter()
+                .map(|shard| shard.index())
+                .collect::<Vec<_>>();
+            {
+                let store = Directory::open(&dir).unwrap();
+                store.put("hello", &meta, arrived).unwrap();
+                store.put_download("hello", &indices).unwrap();
+            }
+
+            let mut restarted = config(false);
+            restarted.peers = vec![holder.node().network().id()];
+            restarted.node.store = StoreConfig::Directory(dir.clone());
+            let daemon = Daemon::start(restarted).await.unwrap();
+            for _ in 0..100 {
+                if daemon.node().downloads().is_empty() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            assert!(daemon.node().downloads().is_empty());
+            assert!(daemon.node().shards("hello").len() >= meta.data_shards());
+            assert!(
+                Directory::open(&dir)
+                    .unwrap()
+                    .downloads()
+                    .unwrap()
+                    .is_empty()
+            );
+
+            drop(daemon);
+            let _ = std::fs::remove_dir_all(&dir);
+        });
+    }
+
+    #[test]
+    fn reload() {
+        runtime().block_on(async {
+            let (first, second) = (
+                Daemon::start(config(false)).await.unwrap(),
+                Daemon::start(config(false)).await.unwrap(),
+            );
+            let (a, b) = (first.node().network().id(), second.node().network().id());
+
+            let content = "hello world!".repeat(100);
+            first
+                .node()
+                .upload("tenant/a".to_string(), content)
+                .await
+                .unwrap();
+            assert_eq!(first.node().unplaced(), vec!["tenant/a".to_string()]);
+            assert!(first.node().network().discover().await.is_empty());
+
+            // The second node caps the tenant and both learn of each other, with the first
+            // retrying its placement every few milliseconds from now on.
+            let quota = Quota {
+                storage: Some(640),
+                ..Quota::default()
+            };
+            let mut reloaded = config(false);
+            reloaded.peers = vec![a.clone(), b.clone()];
+            reloaded.quotas.insert("tenant".to_string(), quota);
+            second.reload(reloaded.clone());
+            reloaded.quotas.clear();
+            reloaded.node.repair_interval = Some(Duration::from_millis(5));
+            reloaded.tcp.regions.insert(b.clone(), "east".to_string());
+            first.reload(reloaded);
+
+            assert_eq!(first.node().network().discover().await, vec![b.clone()]);
+            assert_eq!(first.node().network().region(&b).as_deref(), Some("east"));
+            assert_eq!(
+                first.node().repair_interval(),
+                Some(Duration::from_millis(5))
+            );
+            for _ in 0..100 {
+                if first.node().unplaced().is_empty() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            assert!(first.node().unplaced().is_empty());
+            let held = first.node().stats().tenants["tenant"].storage;
+
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let usage = second.node().stats().tenants["tenant"].clone();
+            assert_eq!(usage.ingress, 640);
+            assert!(usage.rejections_sent > 0);
+
+            // Reloading again keeps the node's state: the tenant's usage and what it holds.
+            let mut unchanged = config(false);
+            unchanged.peers = vec![a, b];
+            second.reload(unchanged.clone());
+            first.reload(unchanged);
+            assert_eq!(first.node().repair_interval(), None);
+            assert_eq!(second.node().stats().tenants["tenant"], usage);
+            assert_eq!(first.node().stats().tenants["tenant"].storage, held);
+        });
+    }
+
+    #[test]
+    fn watch() {
+        runtime().block_on(async {
+            let dir =
+                std::env::temp_dir().join(format!("erasure-noded-watch-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let path = dir.join("node.json");
+            std::fs::write(&path, "{}").unwrap();
+
+            let mut watch = ConfigWatch::new(&path).unwrap();
+            let timeout = Duration::from_secs(5);
+
+            std::fs::write(dir.join("other.json"), "{}").unwrap();
+            std::fs::write(&path, r#"{"peers": ["127.0.0.1:7001"]}"#).unwrap();
+            tokio::time::timeout(timeout, watch.changed())
+                .await
+                .unwrap();
+            assert_eq!(DaemonConfig::load(&path).unwrap().peers.len(), 1);
+
+            // Saved through a rename, as most editors do.
+            std::fs::write(dir.join("node.json.tmp"), "{}").unwrap();
+            std::fs::rename(dir.join("node.json.tmp"), &path).unwrap();
+            tokio::time::timeout(timeout, watch.changed())
+                .await
+                .unwrap();
+            assert!(DaemonConfig::load(&path).unwrap().peers.is_empty());
+
+            let _ = std::fs::remove_dir_all(&dir);
+        });
+    }
+}
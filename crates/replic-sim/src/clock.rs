@@ -0,0 +1,163 @@
+use std::{
+    cell::{Cell, RefCell},
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+use futures::{
+    executor::{LocalPool, LocalSpawner},
+    task::LocalSpawnExt,
+};
+
+// A task parked until its timestamp comes up. Only the time and sequence number order
+// events, so those scheduled for the same instant fire in the order they were scheduled.
+struct Event(Waker);
+
+impl PartialEq for Event {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Event {}
+
+impl PartialOrd for Event {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Event {
+    fn cmp(&self, _: &Self) -> Ordering {
+        Ordering::Equal
+    }
+}
+
+// Discrete-event scheduler: every task runs until it blocks, then the clock jumps to the
+// earliest pending event and wakes it. Time never passes while a task can make progress.
+struct Scheduler {
+    now: Cell<Instant>,
+    seq: Cell<u64>,
+    queue: RefCell<BinaryHeap<Reverse<(Instant, u64, Event)>>>,
+    spawner: LocalSpawner,
+}
+
+thread_local! {
+    static SCHEDULER: RefCell<Option<Rc<Scheduler>>> = const { RefCell::new(None) };
+}
+
+fn scheduler() -> Option<Rc<Scheduler>> {
+    SCHEDULER.with_borrow(Clone::clone)
+}
+
+/// Runs the simulation on the scheduler's virtual clock, on the current thread.
+pub fn run<F: Future + 'static>(fut: F) -> F::Output {
+    let mut pool = LocalPool::new();
+    let scheduler = Rc::new(Scheduler {
+        now: Cell::new(Instant::now()),
+        seq: Cell::new(0),
+        queue: RefCell::new(BinaryHeap::new()),
+        spawner: pool.spawner(),
+    });
+    SCHEDULER.set(Some(Rc::clone(&scheduler)));
+
+    let out = Rc::new(RefCell::new(None));
+    let slot = Rc::clone(&out);
+    scheduler
+        .spawner
+        .spawn_local(async move { *slot.borrow_mut() = Some(fut.await) })
+        .expect("pool alive");
+
+    loop {
+        pool.run_until_stalled();
+        if let Some(out) = out.take() {
+            SCHEDULER.set(None);
+            return out;
+        }
+
+        let mut queue = scheduler.queue.borrow_mut();
+        let Some(Reverse((at, ..))) = queue.peek() else {
+            panic!("simulation stalled: no task can run and no event is pending");
+        };
+        let now = scheduler.now.get().max(*at);
+        scheduler.now.set(now);
+        while let Some(Reverse((at, ..))) = queue.peek()
+            && *at <= now
+        {
+            let Reverse((_, _, Event(waker))) = queue.pop().unwrap();
+            waker.wake();
+        }
+    }
+}
+
+/// Virtual time under `run`, the wall clock otherwise.
+pub fn now() -> Instant {
+    match scheduler() {
+        Some(scheduler) => scheduler.now.get(),
+        None => Instant::now(),
+    }
+}
+
+pub fn elapsed(since: Instant) -> Duration {
+    now().saturating_duration_since(since)
+}
+
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(now() + duration)
+}
+
+pub fn sleep_until(at: Instant) -> Sleep {
+    match scheduler() {
+        Some(_) => Sleep::Virtual { at, waker: None },
+        None => Sleep::Real(Box::pin(tokio::time::sleep_until(at.into()))),
+    }
+}
+
+pub fn spawn(fut: impl Future<Output = ()> + Send + 'static) {
+    match scheduler() {
+        Some(scheduler) => scheduler.spawner.spawn_local(fut).expect("pool alive"),
+        None => drop(tokio::spawn(fut)),
+    }
+}
+
+pub enum Sleep {
+    Virtual {
+        at: Instant,
+        // The waker last queued, so repeated polls do not queue the same wakeup twice.
+        waker: Option<Waker>,
+    },
+    // Outside `run` the wall clock runs and tokio's timer keeps it.
+    Real(Pin<Box<tokio::time::Sleep>>),
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let (at, waker) = match &mut *self {
+            Sleep::Virtual { at, waker } => (*at, waker),
+            Sleep::Real(sleep) => return sleep.as_mut().poll(cx),
+        };
+        let scheduler = scheduler().expect("virtual sleep outside the scheduler");
+        if scheduler.now.get() >= at {
+            return Poll::Ready(());
+        }
+
+        if waker
+            .as_ref()
+            .is_none_or(|waker| !waker.will_wake(cx.waker()))
+        {
+            let seq = scheduler.seq.get();
+            scheduler.seq.set(seq + 1);
+            let event = Event(cx.waker().clone());
+            scheduler.queue.borrow_mut().push(Reverse((at, seq, event)));
+            *waker = Some(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
@@ -0,0 +1,190 @@
+use std::{env, process::Command};
+
+use tracing::{info, warn};
+
+struct Phase {
+    placed: u64,
+    uploader_bytes: u64,
+    total_bytes: u64,
+    p50_ms: u64,
+    p99_ms: u64,
+    downloads: u64,
+    unrecoverable: u64,
+}
+
+fn field(line: &str, key: &str) -> Option<u64> {
+    line.split_whitespace()
+        .find_map(|part| part.strip_prefix(key)?.strip_prefix('=')?.parse().ok())
+}
+
+impl Phase {
+    // The upload phase line, plus the download outcomes from the final totals.
+    fn parse(upload: &str, complete: &str) -> Option<Self> {
+        Some(Self {
+            placed: field(upload, "placed")?,
+            uploader_bytes: field(upload, "uploader_bytes")?,
+            total_bytes: field(upload, "total_bytes")?,
+            p50_ms: field(upload, "p50_ms")?,
+            p99_ms: field(upload, "p99_ms")?,
+            downloads: field(complete, "downloads")?,
+            unrecoverable: field(complete, "unrecoverable")?,
+        })
+    }
+}
+
+fn run(seed: u64, var: &str, mode: &str) -> Option<Phase> {
+    let exe = env::current_exe().unwrap();
+    let output = Command::new(&exe)
+        .env_remove("SIM_COMPARE")
+        .env("SIM_SEED", seed.to_string())
+        .env(var, mode)
+        .env("RUST_LOG", "replic_sim=info")
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let line = |message| stdout.lines().find(|line| line.contains(message));
+    let phase = line("upload phase")
+        .zip(line("simulation complete"))
+        .and_then(|(upload, complete)| Phase::parse(upload, complete));
+    if phase.is_none() {
+        warn!(seed, mode, "run did not report an upload phase");
+    }
+
+    phase
+}
+
+fn compare(seed: u64, var: &str, modes: &[&str], message: &str) {
+    let mut phases = Vec::new();
+    for mode in modes {
+        let Some(phase) = run(seed, var, mode) else {
+            return;
+        };
+
+        info!(
+            seed,
+            mode,
+            placed = phase.placed,
+            uploader_bytes = phase.uploader_bytes,
+            total_bytes = phase.total_bytes,
+            p50_ms = phase.p50_ms,
+            p99_ms = phase.p99_ms,
+            downloads = phase.downloads,
+            unrecoverable = phase.unrecoverable,
+            "upload architecture"
+        );
+        phases.push(phase);
+    }
+
+    let base = &phases[0];
+    let ratio = |other: u64, base: u64| other as f64 / base.max(1) as f64;
+    for (mode, other) in modes.iter().zip(&phases).skip(1) {
+        info!(
+            seed,
+            mode,
+            uploader_bytes = ratio(other.uploader_bytes, base.uploader_bytes),
+            total_bytes = ratio(other.total_bytes, base.total_bytes),
+            p50 = ratio(other.p50_ms, base.p50_ms),
+            p99 = ratio(other.p99_ms, base.p99_ms),
+            "{message}"
+        );
+    }
+}
+
+pub fn encoding(seed: u64) {
+    compare(
+        seed,
+        "SIM_UPLOAD",
+        &["client", "node"],
+        "node-side relative to client-side encoding",
+    );
+}
+
+pub fn replication(seed: u64) {
+    compare(
+        seed,
+        "SIM_REPLICATION",
+        &["push", "pull"],
+        "pull relative to push replication",
+    );
+}
+
+pub fn codec(seed: u64) {
+    let modes = [
+        "rs",
+        "xor",
+        "replication",
+        #[cfg(feature = "raptorq")]
+        "raptorq",
+    ];
+    compare(seed, "SIM_CODEC", &modes, "codec relative to reed-solomon");
+}
+
+pub fn compression(seed: u64) {
+    let modes = [
+        "none",
+        #[cfg(feature = "lz4")]
+        "lz4",
+        #[cfg(feature = "zstd")]
+        "zstd",
+    ];
+    compare(
+        seed,
+        "SIM_COMPRESSION",
+        &modes,
+        "compression relative to uncompressed shards",
+    );
+}
+
+// Cross-region bytes of the download rounds with and without telling nodes their regions.
+pub fn locality(seed: u64, regions: usize) {
+    let mut traffic = Vec::new();
+    for mode in ["off", "on"] {
+        let exe = env::current_exe().unwrap();
+        let output = Command::new(&exe)
+            .env_remove("SIM_COMPARE")
+            .env("SIM_SEED", seed.to_string())
+            .env("SIM_REGIONS", regions.to_string())
+            .env("SIM_LOCALITY", mode)
+            .env("RUST_LOG", "replic_sim=info")
+            .env("NO_COLOR", "1")
+            .output()
+            .unwrap();
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let line = |message| stdout.lines().find(|line| line.contains(message));
+        let Some((download, complete)) = line("download traffic").zip(line("simulation complete"))
+        else {
+            warn!(seed, mode, "run did not report its download traffic");
+            return;
+        };
+        let (Some(cross), Some(same), Some(downloads)) = (
+            field(download, "cross_region_bytes"),
+            field(download, "same_region_bytes"),
+            field(complete, "downloads"),
+        ) else {
+            warn!(seed, mode, "run did not report its download traffic");
+            return;
+        };
+
+        info!(
+            seed,
+            mode,
+            same_region_bytes = same,
+            cross_region_bytes = cross,
+            downloads,
+            "locality"
+        );
+        traffic.push(cross);
+    }
+
+    let (off, on) = (traffic[0], traffic[1]);
+    info!(
+        seed,
+        regions,
+        saved_bytes = off as i64 - on as i64,
+        cross_region_bytes = on as f64 / off.max(1) as f64,
+        "cross-region savings of locality-aware sourcing"
+    );
+}
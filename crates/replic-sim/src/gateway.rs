@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::StatusCode,
+    routing::get,
+};
+use bytes::Bytes;
+use serde::Serialize;
+use tracing::info;
+
+use crate::network::SimNode;
+
+#[derive(Serialize)]
+struct Stats {
+    failure_rate: f64,
+    audits_passed: usize,
+    audits_failed: usize,
+    tenants: HashMap<String, TenantStats>,
+}
+
+#[derive(Serialize)]
+struct TenantStats {
+    storage: usize,
+    ingress: usize,
+    egress: usize,
+    rejections_sent: usize,
+    rejections_received: usize,
+}
+
+pub async fn serve(addr: String, node: SimNode) {
+    let app = Router::new()
+        .route("/files/{*name}", get(download).put(upload).delete(delete))
+        .route("/stats", get(stats))
+        .with_state(node);
+
+    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+    info!(addr, "gateway listening");
+    axum::serve(listener, app).await.unwrap();
+}
+
+async fn upload(
+    State(node): State<SimNode>,
+    Path(name): Path<String>,
+    content: Bytes,
+) -> StatusCode {
+    node.upload(name, content).await;
+    StatusCode::CREATED
+}
+
+async fn download(
+    State(node): State<SimNode>,
+    Path(name): Path<String>,
+) -> Result<Bytes, StatusCode> {
+    node.download(name).await.map_err(|err| {
+        if err.is_corrupt() {
+            StatusCode::INTERNAL_SERVER_ERROR
+        } else {
+            StatusCode::NOT_FOUND
+        }
+    })
+}
+
+async fn delete(State(node): State<SimNode>, Path(name): Path<String>) -> StatusCode {
+    if node.delete(&name).await {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+async fn stats(State(node): State<SimNode>) -> Json<Stats> {
+    let stats = node.stats();
+
+    let tenants = stats
+        .tenants
+        .into_iter()
+        .map(|(tenant, usage)| {
+            let usage = TenantStats {
+                storage: usage.storage,
+                ingress: usage.ingress,
+                egress: usage.egress,
+                rejections_sent: usage.rejections_sent,
+                rejections_received: usage.rejections_received,
+            };
+            (tenant, usage)
+        })
+        .collect();
+
+    Json(Stats {
+        failure_rate: stats.failure_rate,
+        audits_passed: stats.audits_passed,
+        audits_failed: stats.audits_failed,
+        tenants,
+    })
+}
@@ -1,14 +1,32 @@
+mod clock;
+mod compare;
+#[cfg(feature = "gateway")]
+mod gateway;
 mod network;
+mod rng;
+mod snapshot;
+mod soak;
+mod sweep;
 
-use std::collections::HashSet;
+use std::{collections::HashSet, time::Duration};
 
-use network::{SimNetworkManager, SimNode};
+use erasure_node::{
+    compress::Compression,
+    config::Replication,
+    file::{CodecId, EncodeConfig},
+    flow::{FlowConfig, FlowStats},
+    membership::MembershipConfig,
+    node::ImportStats,
+};
+
+use network::{DropMatrix, Jitter, Latency, SimNetworkManager, SimNode};
 use rand::{
     Rng,
     distr::{Alphabetic, Alphanumeric, Uniform},
-    seq::{IndexedRandom, index},
+    seq::{IndexedRandom, IteratorRandom},
 };
-use tracing::info;
+use snapshot::Snapshot;
+use tracing::{info, warn};
 
 struct File {
     name: String,
@@ -17,13 +35,13 @@ struct File {
 
 impl File {
     pub fn generate(size: usize) -> Self {
-        let name = rand::rng()
+        let name = rng::rng()
             .sample_iter(&Alphabetic)
             .take(16)
             .map(char::from)
             .collect();
 
-        let content = rand::rng()
+        let content = rng::rng()
             .sample_iter(&Alphanumeric)
             .take(size)
             .map(char::from)
@@ -41,53 +59,310 @@ impl File {
     }
 }
 
+struct NodeClass {
+    name: &'static str,
+    count: usize,
+
+    min_latency: usize,
+    max_latency: usize,
+
+    min_throughput: usize,
+    max_throughput: usize,
+
+    capacity: Option<usize>,
+    failure_probability: f64,
+
+    storage_cost_per_gb: f64,
+    egress_cost_per_gb: f64,
+}
+
 struct Config {
-    nodes: usize,
+    classes: Vec<NodeClass>,
 
     file_count: usize,
     file_min_size: usize,
     file_max_size: usize,
+    conflicts: usize,
 
-    network_min_latency: usize,
-    network_max_latency: usize,
+    network_jitter: Jitter,
+    network_spike_probability: f64,
+    network_spike_latency: usize,
+    network_corruption_probability: f64,
+    network_metadata_loss_probability: f64,
+    network_link_drop: DropMatrix,
 
-    network_min_throughput: usize,
-    network_max_throughput: usize,
+    adaptive_redundancy: bool,
+    weighted_placement: bool,
+    node_encoding: bool,
+    replication: Replication,
+    encoding: Option<EncodeConfig>,
+    compression: Option<Compression>,
+    membership: Option<MembershipConfig>,
+    flow: Option<FlowConfig>,
+    mtu: Option<usize>,
+    regions: usize,
+    region_latency: usize,
+    locality: bool,
 
     rounds: usize,
     timeout: usize,
     downloads: usize,
-    disable: usize,
+    joins: usize,
+    decommissions: usize,
+
+    maintenance_interval: usize,
+    maintenance_downtime: usize,
+
+    mass_failure: f64,
+    recovery_timeout: usize,
+
+    soak: usize,
+    soak_interval: usize,
+
+    single_threaded: bool,
+    virtual_time: bool,
+    step: bool,
+    snapshot: Option<String>,
+    resume: Option<String>,
+    export: Option<String>,
+    import: Option<String>,
+    events: Option<String>,
+    heatmap: bool,
+    seed: u64,
+    strict: bool,
 }
 
 impl Config {
-    pub async fn spawn_nodes(&self) -> Vec<SimNode> {
-        let mut nodes = Vec::with_capacity(self.nodes);
+    pub fn standard() -> Self {
+        Self {
+            classes: vec![
+                NodeClass {
+                    name: "fast",
+                    count: 4,
+                    min_latency: 5,
+                    max_latency: 15,
+                    min_throughput: 5000,
+                    max_throughput: 10000,
+                    capacity: None,
+                    failure_probability: 0.25,
+                    storage_cost_per_gb: 0.023,
+                    egress_cost_per_gb: 0.09,
+                },
+                NodeClass {
+                    name: "slow",
+                    count: 8,
+                    min_latency: 15,
+                    max_latency: 40,
+                    min_throughput: 100,
+                    max_throughput: 2000,
+                    capacity: Some(16384),
+                    failure_probability: 0.6,
+                    storage_cost_per_gb: 0.004,
+                    egress_cost_per_gb: 0.02,
+                },
+            ],
+
+            file_count: 32,
+            file_min_size: 256,
+            file_max_size: 1024,
+            conflicts: 0,
+
+            network_jitter: Jitter::Exponential(2),
+            network_spike_probability: 0.001,
+            network_spike_latency: 100,
+            network_corruption_probability: 0.0,
+            network_metadata_loss_probability: 0.0,
+            network_link_drop: DropMatrix::default(),
+
+            adaptive_redundancy: false,
+            weighted_placement: true,
+            node_encoding: false,
+            replication: Replication::Push,
+            encoding: None,
+            compression: None,
+            membership: None,
+            flow: None,
+            mtu: None,
+            regions: 0,
+            region_latency: 40,
+            locality: true,
+
+            rounds: 4,
+            timeout: 8000,
+            downloads: 8,
+            joins: 1,
+            decommissions: 1,
+
+            maintenance_interval: 200,
+            maintenance_downtime: 200,
+
+            mass_failure: 0.25,
+            recovery_timeout: 10000,
+
+            soak: 0,
+            soak_interval: 600_000,
 
-        let latency_distribution =
-            Uniform::new(self.network_min_latency, self.network_max_latency).unwrap();
+            single_threaded: true,
+            virtual_time: true,
+            step: false,
+            snapshot: None,
+            resume: None,
+            export: None,
+            import: None,
+            events: None,
+            heatmap: false,
+            seed: 0,
+            strict: false,
+        }
+    }
+
+    pub fn scale() -> Self {
+        Self {
+            classes: vec![
+                NodeClass {
+                    name: "fast",
+                    count: 1000,
+                    min_latency: 5,
+                    max_latency: 15,
+                    min_throughput: 5000,
+                    max_throughput: 10000,
+                    capacity: None,
+                    failure_probability: 0.1,
+                    storage_cost_per_gb: 0.023,
+                    egress_cost_per_gb: 0.09,
+                },
+                NodeClass {
+                    name: "slow",
+                    count: 4000,
+                    min_latency: 15,
+                    max_latency: 40,
+                    min_throughput: 1000,
+                    max_throughput: 5000,
+                    capacity: None,
+                    failure_probability: 0.2,
+                    storage_cost_per_gb: 0.004,
+                    egress_cost_per_gb: 0.02,
+                },
+            ],
+
+            file_count: 16,
+            file_min_size: 256,
+            file_max_size: 1024,
+            conflicts: 0,
+
+            network_jitter: Jitter::Exponential(2),
+            network_spike_probability: 0.001,
+            network_spike_latency: 100,
+            network_corruption_probability: 0.0,
+            network_metadata_loss_probability: 0.0,
+            network_link_drop: DropMatrix::default(),
+
+            adaptive_redundancy: false,
+            weighted_placement: true,
+            node_encoding: false,
+            replication: Replication::Push,
+            encoding: None,
+            compression: None,
+            membership: None,
+            flow: None,
+            mtu: None,
+            regions: 0,
+            region_latency: 40,
+            locality: true,
+
+            rounds: 1,
+            timeout: 4000,
+            downloads: 16,
+            joins: 0,
+            decommissions: 0,
+
+            maintenance_interval: 0,
+            maintenance_downtime: 0,
+
+            mass_failure: 0.25,
+            recovery_timeout: 30000,
+
+            soak: 0,
+            soak_interval: 600_000,
+
+            single_threaded: true,
+            virtual_time: true,
+            step: false,
+            snapshot: None,
+            resume: None,
+            export: None,
+            import: None,
+            events: None,
+            heatmap: false,
+            seed: 0,
+            strict: false,
+        }
+    }
+
+    pub async fn spawn_node<'a>(&self, class: &'a NodeClass) -> (SimNode, &'a NodeClass) {
+        let latency_distribution = Uniform::new(class.min_latency, class.max_latency).unwrap();
 
         let throughtput_distribution =
-            Uniform::new(self.network_min_throughput, self.network_max_throughput).unwrap();
+            Uniform::new(class.min_throughput, class.max_throughput).unwrap();
+
+        let latency = rng::rng().sample(latency_distribution);
+        let throuput = rng::rng().sample(throughtput_distribution);
+        let node = self.spawn_node_with(class, latency, throuput).await;
+
+        (node, class)
+    }
+
+    pub async fn spawn_node_with(
+        &self,
+        class: &NodeClass,
+        latency: usize,
+        throughput: usize,
+    ) -> SimNode {
+        let latency = Latency {
+            base: latency,
+            jitter: self.network_jitter,
+            spike_probability: self.network_spike_probability,
+            spike: self.network_spike_latency,
+        };
+        let node = SimNode::spawn(latency, throughput, self.adaptive_redundancy).await;
+        node.set_class(class.name);
+        if self.regions > 0 {
+            node.set_region(&format!("region-{}", node.id() % self.regions));
+        }
 
-        for _ in 0..self.nodes {
-            let latency = rand::rng().sample(latency_distribution);
-            let throuput = rand::rng().sample(throughtput_distribution);
-            nodes.push(SimNode::spawn(latency, throuput).await);
+        if let Some(capacity) = class.capacity {
+            node.set_capacity(capacity);
         }
 
-        info!(count = nodes.len(), "spawned nodes");
+        node
+    }
+
+    pub async fn spawn_nodes(&self) -> Vec<(SimNode, &NodeClass)> {
+        let mut nodes = Vec::new();
+
+        for class in &self.classes {
+            for _ in 0..class.count {
+                nodes.push(self.spawn_node(class).await);
+            }
+            info!(class = class.name, count = class.count, "spawned nodes");
+        }
 
         nodes
     }
 
+    pub fn random_class(&self) -> &NodeClass {
+        self.classes
+            .choose_weighted(&mut rng::rng(), |class| class.count)
+            .unwrap()
+    }
+
     pub fn generate_files(&self) -> Vec<File> {
         let mut files = Vec::with_capacity(self.file_count);
 
         let distribution = Uniform::new(self.file_min_size, self.file_max_size).unwrap();
 
         for _ in 0..self.file_count {
-            let size = rand::rng().sample(distribution);
+            let size = rng::rng().sample(distribution);
             files.push(File::generate(size));
         }
 
@@ -97,89 +372,1130 @@ impl Config {
     }
 }
 
-#[tokio::main]
-async fn main() {
+fn held<'a>(nodes: impl Iterator<Item = &'a SimNode>, name: &str) -> HashSet<usize> {
+    nodes.flat_map(|node| node.shards(name)).collect()
+}
+
+fn degraded<'a>(nodes: &[(SimNode, &NodeClass)], files: &'a [File]) -> Vec<&'a File> {
+    files
+        .iter()
+        .filter(|file| {
+            let Some(meta) = nodes.iter().find_map(|(node, _)| node.metadata(&file.name)) else {
+                return true;
+            };
+
+            held(nodes.iter().map(|(node, _)| node), &file.name).len() < meta.shard_count()
+        })
+        .collect()
+}
+
+fn placed(nodes: &[(SimNode, &NodeClass)], name: &str, encoder: usize) -> bool {
+    let Some(meta) = nodes.iter().find_map(|(node, _)| node.metadata(name)) else {
+        return false;
+    };
+
+    let holders = nodes
+        .iter()
+        .map(|(node, _)| node)
+        .filter(|node| node.id() != encoder);
+    held(holders, name).len() >= meta.data_shards()
+}
+
+fn check(
+    round: usize,
+    nodes: &[(SimNode, &NodeClass)],
+    live: &[&SimNode],
+    files: &[File],
+) -> usize {
+    let failed = nodes.len() - live.len();
+
+    let mut violations = 0;
+    for file in files {
+        let Some(meta) = nodes.iter().find_map(|(node, _)| node.metadata(&file.name)) else {
+            warn!(round, file = file.name, "invariant violated: metadata lost");
+            violations += 1;
+            continue;
+        };
+
+        let versions = live
+            .iter()
+            .filter_map(|node| node.metadata(&file.name))
+            .map(|meta| meta.stamp())
+            .collect::<HashSet<_>>();
+        if versions.len() > 1 {
+            warn!(
+                round,
+                file = file.name,
+                versions = versions.len(),
+                "invariant violated: divergent file versions"
+            );
+            violations += 1;
+        }
+
+        let tolerated = meta.parity_shards() / meta.shard_count().div_ceil(nodes.len());
+        let held = held(live.iter().copied(), &file.name).len();
+        if failed <= tolerated && held < meta.data_shards() {
+            warn!(
+                round,
+                file = file.name,
+                failed,
+                tolerated,
+                held,
+                needed = meta.data_shards(),
+                "invariant violated: file unrecoverable within parity failures"
+            );
+            violations += 1;
+        }
+    }
+
+    violations
+}
+
+async fn converge(nodes: &[(SimNode, &NodeClass)]) {
+    let start = clock::now();
+    while clock::elapsed(start) < Duration::from_secs(60) {
+        let converged = nodes.iter().all(|(node, _)| {
+            node.alive()
+                .is_none_or(|alive| alive.len() + 1 == nodes.len())
+        });
+        if converged {
+            info!(elapsed =? clock::elapsed(start), "membership converged");
+            return;
+        }
+
+        clock::sleep(Duration::from_millis(100)).await;
+    }
+
+    warn!(elapsed =? clock::elapsed(start), "membership did not converge");
+}
+
+fn membership(round: usize, live: &[&SimNode]) {
+    let live_ids = live.iter().map(|node| node.id()).collect::<HashSet<_>>();
+
+    let (mut stale, mut missing) = (0, 0);
+    for node in live {
+        let Some(alive) = node.alive() else {
+            continue;
+        };
+
+        stale += alive.iter().filter(|id| !live_ids.contains(id)).count();
+        missing += live_ids
+            .iter()
+            .filter(|id| **id != node.id() && !alive.contains(id))
+            .count();
+    }
+
+    info!(round, stale, missing, "membership");
+}
+
+enum Step {
+    Next,
+    Continue,
+    Quit,
+}
+
+fn dump(round: usize, nodes: &[(SimNode, &NodeClass)], files: &[File]) {
+    info!(
+        round,
+        nodes = nodes.len(),
+        files = files.len(),
+        "cluster state"
+    );
+
+    for (node, class) in nodes {
+        let held = files
+            .iter()
+            .enumerate()
+            .filter_map(|(index, file)| {
+                let shards = node.shards(&file.name);
+                (!shards.is_empty()).then(|| format!("{index}:{shards:?}"))
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let stats = node.stats();
+        info!(
+            node = node.id(),
+            class = class.name,
+            enabled = node.is_enabled(),
+            pending = node.pending(),
+            failure_rate = stats.failure_rate,
+            held,
+            "node state"
+        );
+    }
+
+    for (index, file) in files.iter().enumerate() {
+        let shards = held(nodes.iter().map(|(node, _)| node), &file.name);
+        let meta = nodes.iter().find_map(|(node, _)| node.metadata(&file.name));
+        info!(
+            file = index,
+            name = file.name,
+            shards = shards.len(),
+            needed = meta.map(|meta| meta.data_shards()),
+            "file state"
+        );
+    }
+}
+
+fn step(round: usize) -> Step {
+    eprint!("round {round} done: [enter] next round, [c] continue, [q] quit > ");
+
+    let mut line = String::new();
+    if std::io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+        return Step::Continue;
+    }
+
+    match line.trim() {
+        "c" => Step::Continue,
+        "q" => Step::Quit,
+        _ => Step::Next,
+    }
+}
+
+async fn upload(config: &Config, nodes: &[(SimNode, &NodeClass)], files: &[File]) {
+    let deadline = clock::now() + Duration::from_millis(config.timeout as u64);
+    let bytes = SimNetworkManager::stats().bytes_sent;
+
+    let (mut pending, mut uploader_bytes) = (Vec::new(), 0);
+    for (index, file) in files.iter().enumerate() {
+        let (node, _) = nodes.choose(&mut rng::rng()).unwrap();
+        let (sent, start) = (node.bytes_sent(), clock::now());
+
+        let encoder = if index < config.conflicts {
+            let (rival, _) = nodes
+                .iter()
+                .filter(|(other, _)| other.id() != node.id())
+                .choose(&mut rng::rng())
+                .unwrap();
+            let content = File::generate(file.content.len()).content;
+            futures::join!(
+                node.upload(file.name(), file.content()),
+                rival.upload(file.name(), content)
+            );
+            node.id()
+        } else if config.node_encoding {
+            let (ingest, _) = nodes
+                .iter()
+                .filter(|(other, _)| other.id() != node.id())
+                .choose(&mut rng::rng())
+                .unwrap();
+            node.delegate(ingest, file.name(), file.content()).await;
+            ingest.id()
+        } else {
+            node.upload(file.name(), file.content()).await;
+            node.id()
+        };
+
+        uploader_bytes += node.bytes_sent() - sent;
+        pending.push((file.name(), encoder, start));
+    }
+
+    let mut latencies = Vec::new();
+    while !pending.is_empty() && clock::now() < deadline {
+        clock::sleep(Duration::from_millis(1)).await;
+        pending.retain(|(name, encoder, start)| {
+            let done = placed(nodes, name, *encoder);
+            if done {
+                latencies.push(clock::elapsed(*start));
+            }
+            !done
+        });
+    }
+    latencies.sort();
+
+    let percentile = |percentile: usize| match latencies.len() {
+        0 => 0,
+        len => latencies[(len - 1) * percentile / 100].as_millis(),
+    };
+
+    info!(
+        mode = if config.node_encoding {
+            "node"
+        } else {
+            "client"
+        },
+        replication = ?config.replication,
+        files = files.len(),
+        placed = latencies.len(),
+        uploader_bytes,
+        total_bytes = SimNetworkManager::stats().bytes_sent - bytes,
+        p50_ms = percentile(50),
+        p99_ms = percentile(99),
+        "upload phase"
+    );
+
+    clock::sleep_until(deadline).await;
+}
+
+async fn maintenance(config: &Config, nodes: &[(SimNode, &NodeClass)], files: &[File]) -> usize {
+    let downtime = Duration::from_millis(config.maintenance_downtime as u64);
+    let interval = Duration::from_millis(config.maintenance_interval as u64);
+
+    let (mut violations, mut margin) = (0, usize::MAX);
+    for (index, (node, _)) in nodes.iter().enumerate() {
+        node.disable().await;
+        clock::sleep(downtime).await;
+
+        for file in files {
+            let live = nodes
+                .iter()
+                .enumerate()
+                .filter(|(other, _)| *other != index)
+                .map(|(_, (node, _))| node);
+
+            let Some(meta) = nodes.iter().find_map(|(node, _)| node.metadata(&file.name)) else {
+                violations += 1;
+                continue;
+            };
+
+            match held(live, &file.name).len().checked_sub(meta.data_shards()) {
+                Some(spare) => margin = margin.min(spare),
+                None => {
+                    warn!(file = file.name, "file unrecoverable during maintenance");
+                    violations += 1;
+                }
+            }
+        }
+
+        node.enable().await;
+        clock::sleep(interval).await;
+    }
+
+    info!(
+        nodes = nodes.len(),
+        violations, margin, "rolling maintenance"
+    );
+
+    violations
+}
+
+fn overhead(nodes: &[(SimNode, &NodeClass)], files: &[File]) -> usize {
+    const REPLICATION: f64 = 3.0;
+
+    let (mut raw, mut encoded, mut distinct) = (0, 0, 0);
+    let mut replicated = false;
+    for file in files {
+        raw += file.content.len();
+        let Some(meta) = nodes.iter().find_map(|(node, _)| node.metadata(&file.name)) else {
+            continue;
+        };
+        replicated |= meta.codec() == CodecId::Replication;
+        encoded += meta.shard_count() * meta.shard_size();
+        distinct += held(nodes.iter().map(|(node, _)| node), &file.name).len() * meta.shard_size();
+    }
+    let stored = nodes
+        .iter()
+        .flat_map(|(node, _)| node.stats().tenants.into_values())
+        .map(|usage| usage.storage)
+        .sum::<usize>();
+
+    let factor = |bytes: usize| bytes as f64 / raw.max(1) as f64;
+    info!(
+        theoretical = factor(encoded),
+        placed = factor(distinct),
+        actual = factor(stored),
+        copies = stored - distinct,
+        replication = REPLICATION,
+        saved = 1.0 - factor(distinct) / REPLICATION,
+        "storage overhead"
+    );
+
+    // Replicated files are the baseline itself, so only the coding parameters bound them.
+    let ceiling = if replicated {
+        factor(encoded)
+    } else {
+        factor(encoded).min(REPLICATION)
+    };
+    if factor(distinct) > ceiling {
+        warn!(
+            placed = factor(distinct),
+            theoretical = factor(encoded),
+            replication = REPLICATION,
+            "invariant violated: placed shards cost more than the coding parameters or replication"
+        );
+        return 1;
+    }
+
+    0
+}
+
+fn bar(value: usize, max: usize) -> String {
+    const WIDTH: usize = 20;
+    let filled = (value * WIDTH).div_ceil(max.max(1));
+    format!("{}{}", "#".repeat(filled), ".".repeat(WIDTH - filled))
+}
+
+fn imbalance(values: &[usize]) -> f64 {
+    let max = values.iter().max().copied().unwrap_or(0);
+    let mean = values.iter().sum::<usize>() as f64 / values.len().max(1) as f64;
+    if mean > 0.0 { max as f64 / mean } else { 0.0 }
+}
+
+fn heatmap(nodes: &[(SimNode, &NodeClass)], files: &[File]) {
+    let mut rows = nodes
+        .iter()
+        .map(|(node, class)| {
+            let shards = files
+                .iter()
+                .map(|file| node.shards(&file.name).len())
+                .sum::<usize>();
+            let stats = node.stats();
+            let stored = stats
+                .tenants
+                .values()
+                .map(|usage| usage.storage)
+                .sum::<usize>();
+            let busy = node.nic_busy();
+            (
+                node.id(),
+                class.name,
+                shards,
+                stored,
+                stats.bytes_served,
+                busy,
+            )
+        })
+        .collect::<Vec<_>>();
+    rows.sort_by_key(|(id, ..)| *id);
+
+    let shards = rows.iter().map(|row| row.2).collect::<Vec<_>>();
+    let served = rows.iter().map(|row| row.4).collect::<Vec<_>>();
+    let max_shards = shards.iter().max().copied().unwrap_or(0);
+    let max_served = served.iter().max().copied().unwrap_or(0);
+
+    for (id, class, held, stored, bytes, busy) in &rows {
+        info!(
+            node = id,
+            class,
+            shards = held,
+            stored,
+            served = bytes,
+            busy =? busy,
+            held = bar(*held, max_shards),
+            load = bar(*bytes, max_served),
+            "heatmap"
+        );
+    }
+
+    info!(
+        nodes = rows.len(),
+        shards_imbalance = imbalance(&shards),
+        served_imbalance = imbalance(&served),
+        "placement imbalance"
+    );
+}
+
+fn export(dir: &str, nodes: &[(SimNode, &NodeClass)]) {
+    if let Err(err) = std::fs::create_dir_all(dir) {
+        warn!(dir, %err, "failed to create export directory");
+        return;
+    }
+
+    let (mut exported, mut bytes) = (0, 0);
+    for (node, _) in nodes {
+        let path = std::path::Path::new(dir).join(format!("node-{}.archive", node.id()));
+        match node.export(&path) {
+            Ok(()) => {
+                exported += 1;
+                bytes += std::fs::metadata(&path).map_or(0, |meta| meta.len());
+            }
+            Err(err) => warn!(node = node.id(), %err, "failed to export node"),
+        }
+    }
+
+    info!(dir, nodes = exported, bytes, "exported archives");
+}
+
+async fn import(dir: &str, nodes: &[(SimNode, &NodeClass)]) {
+    let mut archives = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "archive"))
+            .collect::<Vec<_>>(),
+        Err(err) => {
+            warn!(dir, %err, "failed to read import directory");
+            return;
+        }
+    };
+    archives.sort();
+
+    let mut total = ImportStats::default();
+    for (path, (node, _)) in archives.iter().zip(nodes.iter().cycle()) {
+        match node.import(path).await {
+            Ok(stats) => {
+                total.files += stats.files;
+                total.shards += stats.shards;
+                total.duplicates += stats.duplicates;
+                total.corrupt += stats.corrupt;
+                total.rejected += stats.rejected;
+            }
+            Err(err) => {
+                warn!(node = node.id(), path = %path.display(), %err, "failed to import archive")
+            }
+        }
+    }
+
+    info!(
+        dir,
+        archives = archives.len(),
+        files = total.files,
+        shards = total.shards,
+        duplicates = total.duplicates,
+        corrupt = total.corrupt,
+        rejected = total.rejected,
+        "imported archives"
+    );
+}
+
+async fn recover(config: &Config, nodes: &mut Vec<(SimNode, &NodeClass)>, files: &[File]) {
+    let count = (nodes.len() as f64 * config.mass_failure).round() as usize;
+    for _ in 0..count {
+        let index = rng::rng().random_range(0..nodes.len());
+        nodes.swap_remove(index).0.disable().await;
+    }
+    info!(count, "killed nodes");
+
+    let start = clock::now();
+    let bytes = SimNetworkManager::stats().bytes_sent;
+    let timeout = Duration::from_millis(config.recovery_timeout as u64);
+
+    let degraded_files = degraded(nodes, files);
+    info!(count = degraded_files.len(), "degraded files");
+
+    let repairs = degraded_files.iter().map(|file| {
+        let (node, _) = nodes.choose(&mut rng::rng()).unwrap();
+        node.repair(file.name())
+    });
+    futures::future::join_all(repairs).await;
+
+    while !degraded(nodes, files).is_empty() && clock::elapsed(start) < timeout {
+        clock::sleep(Duration::from_millis(50)).await;
+    }
+
+    let unrecovered = degraded(nodes, files).len();
+    info!(
+        recovered = unrecovered == 0,
+        unrecovered,
+        mttr =? clock::elapsed(start),
+        bytes = SimNetworkManager::stats().bytes_sent - bytes,
+        "recovery"
+    );
+}
+
+fn main() {
     tracing_subscriber::fmt()
         .with_env_filter(tracing_subscriber::filter::EnvFilter::from_default_env())
         .init();
 
-    let config = Config {
-        nodes: 12,
+    let mut config = match std::env::var("SIM_MODE").as_deref() {
+        Ok("scale") => Config::scale(),
+        _ => Config::standard(),
+    };
+    config.step = std::env::var("SIM_STEP").is_ok();
+    config.snapshot = std::env::var("SIM_SNAPSHOT").ok();
+    config.resume = std::env::var("SIM_RESUME").ok();
+    config.export = std::env::var("SIM_EXPORT").ok();
+    config.import = std::env::var("SIM_IMPORT").ok();
+    config.events = std::env::var("SIM_EVENTS").ok();
+    config.strict = std::env::var("SIM_STRICT").is_ok();
+    config.heatmap = std::env::var("SIM_HEATMAP").is_ok();
+    if std::env::var("SIM_PLACEMENT").as_deref() == Ok("uniform") {
+        config.weighted_placement = false;
+    }
+    if std::env::var("SIM_UPLOAD").as_deref() == Ok("node") {
+        config.node_encoding = true;
+    }
+    if std::env::var("SIM_REPLICATION").as_deref() == Ok("pull") {
+        config.replication = Replication::Pull;
+    }
+    match std::env::var("SIM_CODEC").as_deref() {
+        Ok("xor") => config.encoding = Some(EncodeConfig::new(4, 1).with_codec(CodecId::Xor)),
+        Ok("replication") => {
+            config.encoding = Some(EncodeConfig::new(1, 2).with_codec(CodecId::Replication))
+        }
+        #[cfg(feature = "raptorq")]
+        Ok("raptorq") => {
+            config.encoding = Some(EncodeConfig::new(4, 4).with_codec(CodecId::RaptorQ))
+        }
+        _ => {}
+    }
+    match std::env::var("SIM_COMPRESSION").as_deref() {
+        #[cfg(feature = "lz4")]
+        Ok("lz4") => config.compression = Some(Compression::Lz4),
+        #[cfg(feature = "zstd")]
+        Ok("zstd") => config.compression = Some(Compression::Zstd),
+        Ok("none") | Err(_) => {}
+        Ok(other) => warn!(
+            compression = other,
+            "compression not built in, running without"
+        ),
+    }
+    if let Some(conflicts) = std::env::var("SIM_CONFLICTS")
+        .ok()
+        .and_then(|conflicts| conflicts.parse().ok())
+    {
+        config.conflicts = conflicts;
+    }
+    if let Some(hours) = std::env::var("SIM_SOAK")
+        .ok()
+        .and_then(|hours| hours.parse::<f64>().ok())
+    {
+        config.soak = (hours * 3_600_000.0) as usize;
+    }
+    if let Some(minutes) = std::env::var("SIM_SOAK_INTERVAL")
+        .ok()
+        .and_then(|minutes| minutes.parse::<f64>().ok())
+    {
+        config.soak_interval = (minutes * 60_000.0) as usize;
+    }
+    if let Some(corruption) = std::env::var("SIM_CORRUPTION")
+        .ok()
+        .and_then(|corruption| corruption.parse().ok())
+    {
+        config.network_corruption_probability = corruption;
+    }
+    if let Some(loss) = std::env::var("SIM_METADATA_LOSS")
+        .ok()
+        .and_then(|loss| loss.parse().ok())
+    {
+        config.network_metadata_loss_probability = loss;
+    }
+    if let Ok(spec) = std::env::var("SIM_LINK_DROP") {
+        match DropMatrix::parse(&spec) {
+            Some(matrix) => config.network_link_drop = matrix,
+            None => warn!(spec, "ignoring malformed SIM_LINK_DROP"),
+        }
+    }
+    if let Some(mtu) = std::env::var("SIM_MTU")
+        .ok()
+        .and_then(|mtu| mtu.parse().ok())
+    {
+        config.mtu = Some(mtu);
+    }
+    if let Some(regions) = std::env::var("SIM_REGIONS")
+        .ok()
+        .and_then(|regions| regions.parse().ok())
+    {
+        config.regions = regions;
+    }
+    if std::env::var("SIM_LOCALITY").as_deref() == Ok("off") {
+        config.locality = false;
+    }
+    if std::env::var("SIM_FLOW").is_ok() {
+        config.flow = Some(FlowConfig::default());
+    }
+    if std::env::var("SIM_MEMBERSHIP").as_deref() == Ok("swim") {
+        let mut membership = MembershipConfig::default();
+        if let Some(suspicion) = std::env::var("SIM_SUSPICION")
+            .ok()
+            .and_then(|suspicion| suspicion.parse().ok())
+        {
+            membership.suspicion_timeout = Duration::from_millis(suspicion);
+        }
+        config.membership = Some(membership);
+    }
+
+    let seed = std::env::var("SIM_SEED")
+        .ok()
+        .and_then(|seed| seed.parse().ok());
+    config.seed = seed.unwrap_or_else(rand::random);
 
-        file_count: 32,
-        file_min_size: 256,
-        file_max_size: 1024,
+    if let Some(count) = std::env::var("SIM_SWEEP")
+        .ok()
+        .and_then(|count| count.parse().ok())
+    {
+        sweep::run(seed.unwrap_or(0), count);
+        return;
+    }
+
+    match std::env::var("SIM_COMPARE").as_deref() {
+        Ok("encoding") => return compare::encoding(seed.unwrap_or(0)),
+        Ok("replication") => return compare::replication(seed.unwrap_or(0)),
+        Ok("codec") => return compare::codec(seed.unwrap_or(0)),
+        Ok("compression") => return compare::compression(seed.unwrap_or(0)),
+        Ok("locality") => return compare::locality(seed.unwrap_or(0), config.regions.max(2)),
+        _ => {}
+    }
+
+    #[cfg(feature = "gateway")]
+    {
+        config.virtual_time = false;
+    }
 
-        network_min_latency: 10,
-        network_max_latency: 30,
+    let strict = config.strict;
+    let violations = if config.virtual_time {
+        assert!(config.single_threaded, "virtual time needs a single thread");
+        clock::run(simulate(config))
+    } else {
+        let mut runtime = match config.single_threaded {
+            true => tokio::runtime::Builder::new_current_thread(),
+            false => tokio::runtime::Builder::new_multi_thread(),
+        };
+        runtime
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(simulate(config))
+    };
+
+    if strict && violations > 0 {
+        std::process::exit(1);
+    }
+}
 
-        network_min_throughput: 100,
-        network_max_throughput: 10000,
+async fn simulate(config: Config) -> usize {
+    info!(
+        seed = config.seed,
+        weighted_placement = config.weighted_placement,
+        node_encoding = config.node_encoding,
+        replication = ?config.replication,
+        codec = ?config.encoding.and_then(|encoding| encoding.codec),
+        compression = ?config.compression,
+        conflicts = config.conflicts,
+        swim = config.membership.is_some(),
+        flow = config.flow.is_some(),
+        mtu = config.mtu,
+        regions = config.regions,
+        locality = config.locality,
+        soak =? Duration::from_millis(config.soak as u64),
+        "starting simulation"
+    );
+    rng::seed(config.seed);
+    SimNetworkManager::set_weighted_placement(config.weighted_placement);
+    SimNetworkManager::set_replication(config.replication);
+    if let Some(encoding) = config.encoding {
+        SimNetworkManager::set_encoding(encoding);
+    }
+    if let Some(compression) = config.compression {
+        SimNetworkManager::set_compression(compression);
+    }
+    SimNetworkManager::set_corruption(config.network_corruption_probability);
+    SimNetworkManager::set_metadata_loss(config.network_metadata_loss_probability);
+    SimNetworkManager::set_link_drop(config.network_link_drop.clone());
+    if let Some(membership) = &config.membership {
+        SimNetworkManager::set_membership(membership.clone());
+    }
+    if let Some(flow) = &config.flow {
+        SimNetworkManager::set_flow(flow.clone());
+    }
+    if let Some(mtu) = config.mtu {
+        SimNetworkManager::set_mtu(mtu);
+    }
+    SimNetworkManager::set_region_latency(config.region_latency);
+    SimNetworkManager::set_locality(config.locality);
+    if let Some(path) = &config.events
+        && let Err(err) = SimNetworkManager::set_event_log(path)
+    {
+        warn!(path, %err, "failed to create event log");
+    }
 
-        rounds: 4,
-        timeout: 8000,
-        downloads: 8,
-        disable: 6,
+    let (mut nodes, mut departed, files, start) = match config.resume.as_deref() {
+        Some(path) => {
+            let snapshot = Snapshot::load(path).expect("malformed snapshot");
+            let round = snapshot.round + 1;
+            let (nodes, departed, files) = snapshot
+                .restore(&config)
+                .await
+                .expect("snapshot does not match config");
+            info!(path, round, "resumed from snapshot");
+            (nodes, departed, files, round)
+        }
+        None => (
+            config.spawn_nodes().await,
+            Vec::new(),
+            config.generate_files(),
+            0,
+        ),
     };
 
-    info!("starting simulation");
+    #[cfg(feature = "gateway")]
+    {
+        let addr = std::env::var("GATEWAY_ADDR").unwrap_or_else(|_| "127.0.0.1:8080".into());
+        let (node, _) = config.spawn_node(config.random_class()).await;
+        clock::spawn(gateway::serve(addr, node));
+    }
 
-    let nodes = config.spawn_nodes().await;
-    let files = config.generate_files();
+    if config.membership.is_some() {
+        converge(&nodes).await;
+    }
 
-    for file in &files {
-        nodes
-            .choose(&mut rand::rng())
-            .unwrap()
-            .upload(file.name(), file.content())
-            .await;
+    if start == 0 {
+        if let Some(dir) = &config.import {
+            import(dir, &nodes).await;
+        }
+        upload(&config, &nodes, &files).await;
     }
 
-    tokio::time::sleep(std::time::Duration::from_millis(config.timeout as u64)).await;
+    let (mut stepping, mut violations) = (config.step, 0);
+    let (mut same_region, mut cross_region) = (0, 0);
+    for round in start..config.rounds {
+        clock::sleep(std::time::Duration::from_millis(config.timeout as u64)).await;
 
-    for round in 0..config.rounds {
-        tokio::time::sleep(std::time::Duration::from_millis(config.timeout as u64)).await;
+        for _ in 0..config.joins {
+            let (node, class) = config.spawn_node(config.random_class()).await;
+            node.join().await;
+            nodes.push((node, class));
+        }
+        info!(round, count = config.joins, "joined nodes");
 
-        let sample = index::sample(&mut rand::rng(), nodes.len(), config.disable)
-            .into_iter()
-            .collect::<HashSet<_>>();
-        info!(round, nodes =? sample, "disabling nodes");
+        for _ in 0..config.decommissions {
+            let index = rng::rng().random_range(0..nodes.len());
+            let (node, class) = nodes.swap_remove(index);
+            node.decommission().await;
+            departed.push((node, class));
+        }
+        info!(round, count = config.decommissions, "decommissioned nodes");
 
         let (mut enabled, mut disabled) = (Vec::new(), Vec::new());
-        for (index, node) in nodes.iter().enumerate() {
-            if sample.contains(&index) {
+        for (node, class) in &nodes {
+            if rng::rng().random_bool(class.failure_probability) {
                 node.disable().await;
                 disabled.push(node);
             } else {
                 enabled.push(node);
             }
         }
+        info!(round, count = disabled.len(), "disabled nodes");
 
         info!(round, "starting");
 
+        let before = SimNetworkManager::stats();
         let mut downloads = Vec::new();
         for _ in 0..config.downloads {
-            let file = files.choose(&mut rand::rng()).unwrap();
-            let node = enabled.choose(&mut rand::rng()).unwrap();
+            let file = files.choose(&mut rng::rng()).unwrap();
+            let node = enabled.choose(&mut rng::rng()).unwrap();
             downloads.push(node.download(file.name()));
         }
         futures::future::join_all(downloads).await;
+        let after = SimNetworkManager::stats();
+        same_region += after.same_region_bytes - before.same_region_bytes;
+        cross_region += after.cross_region_bytes - before.cross_region_bytes;
 
         info!(round, "done");
 
+        violations += check(round, &nodes, &enabled, &files);
+        if config.membership.is_some() {
+            membership(round, &enabled);
+        }
+
+        for node in &enabled {
+            for file in &files {
+                node.audit(&file.name).await;
+            }
+            node.backfill().await;
+        }
+
+        if stepping {
+            dump(round, &nodes, &files);
+            match step(round) {
+                Step::Next => {}
+                Step::Continue => stepping = false,
+                Step::Quit => break,
+            }
+        }
+
         for node in disabled {
             node.enable().await;
         }
+
+        if let Some(path) = &config.snapshot {
+            match Snapshot::capture(round, &nodes, &departed, &files).save(path) {
+                Ok(()) => info!(round, path, "saved snapshot"),
+                Err(err) => warn!(round, path, %err, "failed to save snapshot"),
+            }
+        }
+    }
+
+    if config.regions > 0 {
+        info!(
+            regions = config.regions,
+            locality = config.locality,
+            same_region_bytes = same_region,
+            cross_region_bytes = cross_region,
+            cross_region_share = cross_region as f64 / (same_region + cross_region).max(1) as f64,
+            "download traffic"
+        );
+    }
+
+    if config.soak > 0 {
+        violations += soak::run(&config, &mut nodes, &mut departed, &files).await;
+    }
+
+    clock::sleep(std::time::Duration::from_secs(1)).await;
+
+    if config.maintenance_downtime > 0 {
+        violations += maintenance(&config, &nodes, &files).await;
+    }
+
+    if config.mass_failure > 0.0 {
+        recover(&config, &mut nodes, &files).await;
     }
 
-    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    let mut total_cost = 0.0;
+    for class in &config.classes {
+        let members = nodes
+            .iter()
+            .filter(|(_, c)| c.name == class.name)
+            .map(|(node, _)| node)
+            .collect::<Vec<_>>();
+        let stats = members.iter().map(|node| node.stats()).collect::<Vec<_>>();
+
+        let stored = stats
+            .iter()
+            .flat_map(|stats| stats.tenants.values())
+            .map(|usage| usage.storage)
+            .sum::<usize>();
+
+        let rejections = stats
+            .iter()
+            .flat_map(|stats| stats.tenants.values())
+            .map(|usage| usage.rejections_sent)
+            .sum::<usize>();
+
+        let egress = members.iter().map(|node| node.bytes_sent()).sum::<u64>();
+
+        let cost = stored as f64 / 1e9 * class.storage_cost_per_gb
+            + egress as f64 / 1e9 * class.egress_cost_per_gb;
+        total_cost += cost;
+
+        info!(
+            class = class.name,
+            nodes = stats.len(),
+            stored,
+            egress,
+            rejections,
+            cost,
+            "node class"
+        );
+    }
+
+    let (audits_passed, audits_failed) = nodes
+        .iter()
+        .map(|(node, _)| node.stats())
+        .fold((0, 0), |(passed, failed), stats| {
+            (passed + stats.audits_passed, failed + stats.audits_failed)
+        });
+    info!(
+        passed = audits_passed,
+        failed = audits_failed,
+        "storage audits"
+    );
+
+    let (cache_hits, cache_misses) = nodes
+        .iter()
+        .map(|(node, _)| node.stats())
+        .fold((0, 0), |(hits, misses), stats| {
+            (hits + stats.cache_hits, misses + stats.cache_misses)
+        });
+    info!(
+        hits = cache_hits,
+        misses = cache_misses,
+        hit_rate = cache_hits as f64 / (cache_hits + cache_misses).max(1) as f64,
+        "shard cache"
+    );
+
+    let (reused, saved) = nodes.iter().map(|(node, _)| node.stats()).fold(
+        (0, Duration::ZERO),
+        |(reused, saved), stats| {
+            (
+                reused + stats.encodes_reused,
+                saved + stats.encode_time_saved,
+            )
+        },
+    );
+    info!(reused, saved =? saved, "encodings");
+
+    let (backfilled, pending, abandoned) = nodes.iter().map(|(node, _)| node.stats()).fold(
+        (0, 0, 0),
+        |(backfilled, pending, abandoned), stats| {
+            (
+                backfilled + stats.backfilled,
+                pending + stats.backfill_pending,
+                abandoned + stats.backfill_abandoned,
+            )
+        },
+    );
+    info!(backfilled, pending, abandoned, "backfill");
+
+    let (deduplicated, coalesced, negative) = nodes.iter().map(|(node, _)| node.stats()).fold(
+        (0, 0, 0),
+        |(deduplicated, coalesced, negative), stats| {
+            (
+                deduplicated + stats.requests_deduplicated,
+                coalesced + stats.shards_coalesced,
+                negative + stats.negative_hits,
+            )
+        },
+    );
+    info!(deduplicated, coalesced, negative, "request dedup");
+
+    let downloads = nodes
+        .iter()
+        .map(|(node, _)| node.stats().downloads_coalesced)
+        .sum::<usize>();
+    info!(coalesced = downloads, "download coalescing");
+
+    let (nacks, retransmits, exhausted) = nodes.iter().map(|(node, _)| node.stats()).fold(
+        (0, 0, 0),
+        |(nacks, retransmits, exhausted), stats| {
+            (
+                nacks + stats.nacks_sent,
+                retransmits + stats.retransmits,
+                exhausted + stats.nacks_exhausted,
+            )
+        },
+    );
+    info!(
+        corrupted = SimNetworkManager::stats().shards_corrupted,
+        nacks, retransmits, exhausted, "wire corruption"
+    );
+
+    info!(
+        dropped = SimNetworkManager::stats().links_dropped,
+        "link drops"
+    );
+
+    let (adopted, orphaned) = nodes
+        .iter()
+        .chain(&departed)
+        .map(|(node, _)| node.stats())
+        .fold((0, 0), |(adopted, orphaned), stats| {
+            (
+                adopted + stats.orphans_adopted,
+                orphaned + stats.orphans_dropped,
+            )
+        });
+    info!(
+        dropped = SimNetworkManager::stats().metadata_dropped,
+        adopted, orphaned, "metadata loss"
+    );
+
+    let network = SimNetworkManager::stats();
+    let flow = nodes
+        .iter()
+        .chain(&departed)
+        .filter_map(|(node, _)| node.flow())
+        .fold(FlowStats::default(), |total, stats| FlowStats {
+            queued: total.queued + stats.queued,
+            peak_queued: total.peak_queued.max(stats.peak_queued),
+            stalls: total.stalls + stats.stalls,
+            timeouts: total.timeouts + stats.timeouts,
+            blocked: total.blocked + stats.blocked,
+        });
+    info!(
+        overflowed = network.frames_overflowed,
+        peak_queue = network.peak_queue,
+        stalls = flow.stalls,
+        peak_queued = flow.peak_queued,
+        timeouts = flow.timeouts,
+        blocked = flow.blocked,
+        "flow control"
+    );
+
+    info!(
+        fragmented = network.frames_fragmented,
+        fragments = network.fragments_sent,
+        reassembled = network.frames_reassembled,
+        lost = network.frames_fragmented - network.frames_reassembled,
+        "fragmentation"
+    );
+
+    let busiest = nodes
+        .iter()
+        .chain(&departed)
+        .map(|(node, _)| node.nic_busy())
+        .max()
+        .unwrap_or_default();
+    info!(
+        wait_p50 =? network.nic_wait_p50,
+        wait_p99 =? network.nic_wait_p99,
+        busiest =? busiest,
+        "nic"
+    );
+
+    let (decodes, cached) = nodes
+        .iter()
+        .map(|(node, _)| node.stats())
+        .fold((0, 0), |(decodes, cached), stats| {
+            (decodes + stats.decodes, cached + stats.decodes_cached)
+        });
+    info!(decodes, cached, "decoded content");
+
+    let (conflicts, unresolved, stale) = nodes
+        .iter()
+        .chain(&departed)
+        .map(|(node, _)| node.stats())
+        .fold((0, 0, 0), |(conflicts, unresolved, stale), stats| {
+            (
+                conflicts + stats.conflicts,
+                unresolved + stats.unresolved,
+                stale + stats.stale_shards,
+            )
+        });
+    info!(
+        uploads = config.conflicts,
+        conflicts, unresolved, stale, "write conflicts"
+    );
+
+    let pool = erasure_node::pool::stats();
+    info!(
+        allocations = pool.allocations,
+        reuses = pool.reuses,
+        pooled = pool.pooled,
+        bytes = pool.bytes,
+        evictions = pool.evictions,
+        "shard buffers"
+    );
 
     let stats = SimNetworkManager::stats();
     info!(
         downloads = stats.successfull_downloads,
         failures = stats.failed_downloads,
+        unrecoverable = stats.unrecoverable_downloads,
+        corrupt = stats.corrupt_downloads,
+        pending = stats.pending_downloads,
         messages = stats.messages_sent,
         bytes = stats.bytes_sent,
+        cancels = stats.cancels_sent,
+        latency_p50 =? stats.latency_p50,
+        latency_p99 =? stats.latency_p99,
+        upload_p50 =? stats.upload_p50,
+        upload_p99 =? stats.upload_p99,
         "simulation complete"
     );
+    info!(
+        received = stats.messages_received,
+        stored = stats.shards_stored,
+        rejected = stats.shards_rejected,
+        stored_bytes = stats.stored_bytes,
+        "node metrics"
+    );
+
+    let strategy = if config.adaptive_redundancy {
+        "adaptive"
+    } else {
+        "fixed"
+    };
+    let durability = stats.successfull_downloads as f64
+        / (stats.successfull_downloads + stats.failed_downloads).max(1) as f64;
+    info!(strategy, durability, cost = total_cost, "cost");
+    violations += overhead(&nodes, &files);
+    info!(violations, "invariants");
+
+    if config.heatmap {
+        heatmap(&nodes, &files);
+    }
+
+    if let Some(dir) = &config.export {
+        export(dir, &nodes);
+    }
+
+    #[cfg(feature = "gateway")]
+    {
+        info!("gateway still serving, press ctrl-c to exit");
+        tokio::signal::ctrl_c().await.unwrap();
+    }
+
+    violations
 }
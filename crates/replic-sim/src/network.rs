@@ -1,249 +1,1190 @@
 use std::{
     collections::{HashMap, HashSet},
+    fs::OpenOptions,
     sync::{
-        Arc,
-        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+        atomic::{AtomicBool, AtomicU64, Ordering},
     },
+    time::{Duration, Instant},
 };
 
+use bytes::Bytes;
 use erasure_node::{
+    codec,
+    compress::Compression,
+    config::{NodeConfig, Replication},
+    events::EventLog,
+    file::{DecodeError, EncodeConfig, Metadata, Shard, ShardError},
+    flow::{FlowConfig, FlowControl, FlowStats, Full},
+    fragment::{FragmentConfig, Fragments},
+    membership::{Membership, MembershipConfig},
+    metrics::{self, MetricsSink},
     network::{Command, Network},
-    node::Node,
+    node::{Conflict, ImportStats, Node, NodeStats},
+    observer::NodeObserver,
+    quota::Quota,
+    redundancy::AdaptiveRedundancy,
 };
 use lazy_static::lazy_static;
+use rand::{Rng, rngs::StdRng};
 use tokio::sync::{
     Mutex,
-    mpsc::{Receiver, Sender, channel},
+    mpsc::{Receiver, Sender, channel, error::TrySendError},
 };
-use tracing::{debug, error, info};
+use tracing::{Instrument, debug, error, info, info_span, trace, warn};
+
+use crate::clock;
 
 lazy_static! {
     static ref MANAGER: SimNetworkManager = SimNetworkManager::new();
 }
 
 pub struct SimNetworkManager {
-    inner: Mutex<SimNetworkManagerInner>,
-    stats: SimNetworkStatsCounter,
+    senders: RwLock<Vec<Sender<(usize, Bytes)>>>,
+    disabled: RwLock<HashSet<usize>>,
+    throughputs: RwLock<Vec<usize>>,
+    weighted: AtomicBool,
+    events: RwLock<Option<(String, Instant)>>,
+    membership: RwLock<Option<MembershipConfig>>,
+    flow: RwLock<Option<FlowConfig>>,
+    replication: RwLock<Replication>,
+    encoding: RwLock<Option<EncodeConfig>>,
+    compression: RwLock<Option<Compression>>,
+    mtu: RwLock<Option<usize>>,
+    corruption: RwLock<f64>,
+    metadata_loss: RwLock<f64>,
+    link_drop: RwLock<DropMatrix>,
+    classes: RwLock<HashMap<usize, String>>,
+    regions: RwLock<HashMap<usize, String>>,
+    region_latency: RwLock<usize>,
+    locality: AtomicBool,
+    peak_queue: AtomicU64,
+    metrics: Metrics,
 }
 
 impl SimNetworkManager {
     fn new() -> Self {
         Self {
-            inner: Mutex::new(SimNetworkManagerInner {
-                id: 0,
-                senders: HashMap::new(),
-                disabled: HashSet::new(),
-            }),
-            stats: SimNetworkStatsCounter::new(),
+            senders: RwLock::new(Vec::new()),
+            disabled: RwLock::new(HashSet::new()),
+            throughputs: RwLock::new(Vec::new()),
+            weighted: AtomicBool::new(true),
+            events: RwLock::new(None),
+            membership: RwLock::new(None),
+            flow: RwLock::new(None),
+            replication: RwLock::new(Replication::Push),
+            encoding: RwLock::new(None),
+            compression: RwLock::new(None),
+            mtu: RwLock::new(None),
+            corruption: RwLock::new(0.0),
+            metadata_loss: RwLock::new(0.0),
+            link_drop: RwLock::new(DropMatrix::default()),
+            classes: RwLock::new(HashMap::new()),
+            regions: RwLock::new(HashMap::new()),
+            region_latency: RwLock::new(0),
+            locality: AtomicBool::new(true),
+            peak_queue: AtomicU64::new(0),
+            metrics: Metrics::default(),
         }
     }
 
     pub fn stats() -> SimNetworkStats {
-        MANAGER.stats.get()
+        MANAGER.metrics.get()
+    }
+
+    pub fn count() -> usize {
+        MANAGER.senders.read().unwrap().len()
+    }
+
+    pub fn set_weighted_placement(weighted: bool) {
+        MANAGER.weighted.store(weighted, Ordering::Relaxed);
+    }
+
+    pub fn set_event_log(path: &str) -> std::io::Result<()> {
+        std::fs::File::create(path)?;
+        *MANAGER.events.write().unwrap() = Some((path.to_string(), clock::now()));
+        Ok(())
+    }
+
+    pub fn set_flow(config: FlowConfig) {
+        *MANAGER.flow.write().unwrap() = Some(config);
+    }
+
+    pub fn set_replication(replication: Replication) {
+        *MANAGER.replication.write().unwrap() = replication;
+    }
+
+    pub fn set_encoding(encoding: EncodeConfig) {
+        *MANAGER.encoding.write().unwrap() = Some(encoding);
+    }
+
+    pub fn set_compression(compression: Compression) {
+        *MANAGER.compression.write().unwrap() = Some(compression);
+    }
+
+    pub fn set_mtu(mtu: usize) {
+        *MANAGER.mtu.write().unwrap() = Some(mtu);
+    }
+
+    pub fn set_corruption(probability: f64) {
+        *MANAGER.corruption.write().unwrap() = probability;
+    }
+
+    pub fn set_metadata_loss(probability: f64) {
+        *MANAGER.metadata_loss.write().unwrap() = probability;
+    }
+
+    pub fn set_link_drop(matrix: DropMatrix) {
+        *MANAGER.link_drop.write().unwrap() = matrix;
+    }
+
+    pub fn set_membership(config: MembershipConfig) {
+        *MANAGER.membership.write().unwrap() = Some(config);
+    }
+
+    /// Extra one-way latency, in milliseconds, of messages between nodes in different regions.
+    pub fn set_region_latency(latency: usize) {
+        *MANAGER.region_latency.write().unwrap() = latency;
+    }
+
+    /// Whether nodes are told the regions they and their peers are in; when not, they pick
+    /// download sources by latency alone, while the traffic is still accounted per region.
+    pub fn set_locality(locality: bool) {
+        MANAGER.locality.store(locality, Ordering::Relaxed);
+    }
+
+    // None while either node has no region.
+    fn cross_region(&self, from: usize, to: usize) -> Option<bool> {
+        let regions = self.regions.read().unwrap();
+        Some(regions.get(&from)? != regions.get(&to)?)
+    }
+
+    fn event_log(&self, id: usize) -> Option<EventLog<std::fs::File>> {
+        let (path, epoch) = self.events.read().unwrap().clone()?;
+        match OpenOptions::new().append(true).open(&path) {
+            Ok(file) => {
+                Some(EventLog::new(id.to_string(), file).with_clock(move || clock::elapsed(epoch)))
+            }
+            Err(err) => {
+                warn!(id, path, %err, "failed to open event log");
+                None
+            }
+        }
     }
 
-    async fn spawn(&self, latency: usize, throughput: usize) -> SimNode {
-        let mut inner = self.inner.lock().await;
-        let id = inner.id;
-        inner.id += 1;
+    fn throughput(&self, id: usize) -> Option<usize> {
+        if !self.weighted.load(Ordering::Relaxed) {
+            return None;
+        }
 
+        self.throughputs.read().unwrap().get(id).copied()
+    }
+
+    fn spawn(&self, latency: Latency, throughput: usize, adaptive: bool) -> SimNode {
         let (sender, receiver) = channel(256);
-        inner.senders.insert(id, sender);
+
+        let mut senders = self.senders.write().unwrap();
+        let id = senders.len();
+        senders.push(sender);
+        self.throughputs.write().unwrap().push(throughput);
+        drop(senders);
+
+        let membership = self.membership.read().unwrap().clone().map(|config| {
+            let seeds = self.peers(id).into_iter().map(|peer| format!("{peer}"));
+            let now = clock::now();
+            std::sync::Mutex::new(Membership::new(format!("{id}"), seeds, config, now))
+        });
+        let flow = self
+            .flow
+            .read()
+            .unwrap()
+            .clone()
+            .map(|config| std::sync::Mutex::new(FlowControl::new(config)));
+        let fragments = self
+            .mtu
+            .read()
+            .unwrap()
+            .map(|_| std::sync::Mutex::new(Fragments::new(FragmentConfig::default())));
+
         let net = SimNetwork {
             id,
             receiver: Mutex::new(receiver),
             latency,
+            rng: std::sync::Mutex::new(crate::rng::fork()),
             throughput,
+            bytes_sent: AtomicU64::new(0),
+            busy: std::sync::Mutex::new(clock::now()),
+            busy_time: AtomicU64::new(0),
+            membership,
+            flow,
+            fragments,
         };
 
         debug!(id, "spawned node");
-        SimNode::new(net)
+        SimNode::new(net, adaptive)
     }
 
-    async fn disable(&self, id: usize) {
-        self.inner.lock().await.disabled.insert(id);
+    fn disable(&self, id: usize) {
+        self.disabled.write().unwrap().insert(id);
         debug!(id, "disabled");
     }
 
-    async fn enable(&self, id: usize) {
-        self.inner.lock().await.disabled.remove(&id);
+    fn enable(&self, id: usize) {
+        self.disabled.write().unwrap().remove(&id);
         debug!(id, "enabled");
     }
 
-    async fn peers(&self, id: usize) -> Vec<usize> {
-        let inner = self.inner.lock().await;
-        (0..inner.id)
-            .filter(|i| *i != id && !inner.disabled.contains(i))
+    fn is_enabled(&self, id: usize) -> bool {
+        !self.disabled.read().unwrap().contains(&id)
+    }
+
+    fn peers(&self, id: usize) -> Vec<usize> {
+        let count = self.senders.read().unwrap().len();
+        let disabled = self.disabled.read().unwrap();
+        (0..count)
+            .filter(|i| *i != id && !disabled.contains(i))
             .collect()
     }
 
-    async fn forward(&self, from: usize, to: usize, cmd: Command) {
-        self.inner
-            .lock()
-            .await
-            .senders
-            .get_mut(&to)
-            .unwrap()
-            .send((from, cmd))
-            .await
-            .unwrap();
+    fn sender(&self, to: usize) -> Sender<(usize, Bytes)> {
+        self.senders.read().unwrap()[to].clone()
     }
-}
 
-struct SimNetworkManagerInner {
-    id: usize,
-    senders: HashMap<usize, Sender<(usize, Command)>>,
-    disabled: HashSet<usize>,
+    fn try_forward(&self, from: usize, to: usize, frame: Bytes) -> Option<Bytes> {
+        let sender = self.sender(to);
+        match sender.try_send((from, frame)) {
+            Ok(()) => {
+                let depth = sender.max_capacity() - sender.capacity();
+                self.peak_queue.fetch_max(depth as u64, Ordering::Relaxed);
+                None
+            }
+            Err(TrySendError::Full((_, frame))) => {
+                self.metrics.counter(FRAMES_OVERFLOWED, 1);
+                Some(frame)
+            }
+            Err(TrySendError::Closed(_)) => {
+                debug!(from, to, "dropped message to departed node");
+                None
+            }
+        }
+    }
+
+    async fn forward(&self, from: usize, to: usize, frame: Bytes) {
+        if self.sender(to).send((from, frame)).await.is_err() {
+            debug!(from, to, "dropped message to departed node");
+        }
+    }
 }
 
-pub struct SimNetworkStatsCounter {
-    successfull_downloads: AtomicU64,
-    failed_downloads: AtomicU64,
-    messages_sent: AtomicU64,
-    bytes_sent: AtomicU64,
+const DOWNLOADS_SUCCEEDED: &str = "sim_downloads_succeeded";
+const DOWNLOADS_FAILED: &str = "sim_downloads_failed";
+const DOWNLOADS_UNRECOVERABLE: &str = "sim_downloads_unrecoverable";
+const DOWNLOADS_CORRUPT: &str = "sim_downloads_corrupt";
+const DOWNLOADS_PENDING: &str = "sim_downloads_pending";
+const MESSAGES_SENT: &str = "sim_messages_sent";
+const BYTES_SENT: &str = "sim_bytes_sent";
+const CANCELS_SENT: &str = "sim_cancels_sent";
+const SHARDS_CORRUPTED: &str = "sim_shards_corrupted";
+const METADATA_DROPPED: &str = "sim_metadata_dropped";
+const LINKS_DROPPED: &str = "sim_links_dropped";
+const FRAMES_OVERFLOWED: &str = "sim_frames_overflowed";
+const FLOW_RETRY: Duration = Duration::from_millis(50);
+const FRAMES_FRAGMENTED: &str = "sim_frames_fragmented";
+const FRAGMENTS_SENT: &str = "sim_fragments_sent";
+const FRAMES_REASSEMBLED: &str = "sim_frames_reassembled";
+const DOWNLOAD_LATENCY: &str = "sim_download_latency_seconds";
+const UPLOAD_LATENCY: &str = "sim_upload_latency_seconds";
+const NIC_WAIT: &str = "sim_nic_wait_seconds";
+const SAME_REGION_BYTES: &str = "sim_same_region_bytes";
+const CROSS_REGION_BYTES: &str = "sim_cross_region_bytes";
+
+#[derive(Default)]
+pub struct Metrics {
+    counters: std::sync::Mutex<HashMap<&'static str, u64>>,
+    gauges: std::sync::Mutex<HashMap<(&'static str, usize), f64>>,
+    histograms: std::sync::Mutex<HashMap<&'static str, Vec<f64>>>,
 }
 
 pub struct SimNetworkStats {
     pub successfull_downloads: u64,
     pub failed_downloads: u64,
+    pub unrecoverable_downloads: u64,
+    pub corrupt_downloads: u64,
+    pub pending_downloads: u64,
     pub messages_sent: u64,
     pub bytes_sent: u64,
+    pub same_region_bytes: u64,
+    pub cross_region_bytes: u64,
+    pub cancels_sent: u64,
+    pub latency_p50: Duration,
+    pub latency_p99: Duration,
+    pub upload_p50: Duration,
+    pub upload_p99: Duration,
+    pub nic_wait_p50: Duration,
+    pub nic_wait_p99: Duration,
+    pub messages_received: u64,
+    pub shards_stored: u64,
+    pub shards_rejected: u64,
+    pub stored_bytes: u64,
+    pub shards_corrupted: u64,
+    pub metadata_dropped: u64,
+    pub links_dropped: u64,
+    pub frames_overflowed: u64,
+    pub frames_fragmented: u64,
+    pub fragments_sent: u64,
+    pub frames_reassembled: u64,
+    pub peak_queue: u64,
 }
 
-impl SimNetworkStatsCounter {
-    fn new() -> Self {
-        Self {
-            successfull_downloads: AtomicU64::new(0),
-            failed_downloads: AtomicU64::new(0),
-            messages_sent: AtomicU64::new(0),
-            bytes_sent: AtomicU64::new(0),
-        }
+impl Metrics {
+    fn counter(&self, name: &'static str, value: u64) {
+        *self.counters.lock().unwrap().entry(name).or_default() += value;
     }
 
-    fn increment_successfull_downloads(&self) {
-        self.successfull_downloads.fetch_add(1, Ordering::Relaxed);
+    fn gauge(&self, name: &'static str, node: usize, value: f64) {
+        self.gauges.lock().unwrap().insert((name, node), value);
     }
 
-    fn increment_failed_downloads(&self) {
-        self.failed_downloads.fetch_add(1, Ordering::Relaxed);
+    fn histogram(&self, name: &'static str, value: f64) {
+        self.histograms
+            .lock()
+            .unwrap()
+            .entry(name)
+            .or_default()
+            .push(value);
     }
 
-    fn increment_messages_sent(&self) {
-        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+    fn download_failed(&self, err: &DecodeError, exhausted: bool) {
+        self.counter(DOWNLOADS_FAILED, 1);
+
+        let counter = if err.is_corrupt() {
+            DOWNLOADS_CORRUPT
+        } else if exhausted {
+            DOWNLOADS_UNRECOVERABLE
+        } else {
+            DOWNLOADS_PENDING
+        };
+        self.counter(counter, 1);
     }
 
-    fn increment_bytes_sent(&self, val: u64) {
-        self.bytes_sent.fetch_add(val, Ordering::Relaxed);
+    fn count(&self, name: &'static str) -> u64 {
+        self.counters
+            .lock()
+            .unwrap()
+            .get(name)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn total(&self, name: &'static str) -> f64 {
+        self.gauges
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|((gauge, _), _)| *gauge == name)
+            .map(|(_, value)| value)
+            .sum()
+    }
+
+    fn percentile(&self, name: &'static str, percentile: usize) -> Duration {
+        let mut values = self
+            .histograms
+            .lock()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .unwrap_or_default();
+        values.sort_by(f64::total_cmp);
+
+        match values.len() {
+            0 => Duration::ZERO,
+            len => Duration::from_secs_f64(values[(len - 1) * percentile / 100]),
+        }
     }
 
     fn get(&self) -> SimNetworkStats {
         SimNetworkStats {
-            successfull_downloads: self.successfull_downloads.load(Ordering::Relaxed),
-            failed_downloads: self.failed_downloads.load(Ordering::Relaxed),
-            messages_sent: self.messages_sent.load(Ordering::Relaxed),
-            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            successfull_downloads: self.count(DOWNLOADS_SUCCEEDED),
+            failed_downloads: self.count(DOWNLOADS_FAILED),
+            unrecoverable_downloads: self.count(DOWNLOADS_UNRECOVERABLE),
+            corrupt_downloads: self.count(DOWNLOADS_CORRUPT),
+            pending_downloads: self.count(DOWNLOADS_PENDING),
+            messages_sent: self.count(MESSAGES_SENT),
+            bytes_sent: self.count(BYTES_SENT),
+            same_region_bytes: self.count(SAME_REGION_BYTES),
+            cross_region_bytes: self.count(CROSS_REGION_BYTES),
+            cancels_sent: self.count(CANCELS_SENT),
+            latency_p50: self.percentile(DOWNLOAD_LATENCY, 50),
+            latency_p99: self.percentile(DOWNLOAD_LATENCY, 99),
+            upload_p50: self.percentile(UPLOAD_LATENCY, 50),
+            upload_p99: self.percentile(UPLOAD_LATENCY, 99),
+            nic_wait_p50: self.percentile(NIC_WAIT, 50),
+            nic_wait_p99: self.percentile(NIC_WAIT, 99),
+            messages_received: self.count(metrics::MESSAGES_RECEIVED),
+            shards_stored: self.count(metrics::SHARDS_STORED),
+            shards_rejected: self.count(metrics::SHARDS_REJECTED),
+            stored_bytes: self.total(metrics::STORED_BYTES) as u64,
+            shards_corrupted: self.count(SHARDS_CORRUPTED),
+            metadata_dropped: self.count(METADATA_DROPPED),
+            links_dropped: self.count(LINKS_DROPPED),
+            frames_overflowed: self.count(FRAMES_OVERFLOWED),
+            frames_fragmented: self.count(FRAMES_FRAGMENTED),
+            fragments_sent: self.count(FRAGMENTS_SENT),
+            frames_reassembled: self.count(FRAMES_REASSEMBLED),
+            peak_queue: MANAGER.peak_queue.load(Ordering::Relaxed),
         }
     }
 }
 
+struct SimMetrics {
+    node: usize,
+}
+
+impl MetricsSink for SimMetrics {
+    fn counter(&self, name: &'static str, value: u64) {
+        MANAGER.metrics.counter(name, value);
+    }
+
+    fn gauge(&self, name: &'static str, value: f64) {
+        MANAGER.metrics.gauge(name, self.node, value);
+    }
+
+    fn histogram(&self, name: &'static str, value: f64) {
+        MANAGER.metrics.histogram(name, value);
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug)]
+pub enum Jitter {
+    None,
+    Uniform(usize),
+    Exponential(usize),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DropMatrix {
+    links: HashMap<(String, String), f64>,
+}
+
+impl DropMatrix {
+    pub fn parse(spec: &str) -> Option<Self> {
+        let links = spec
+            .split(',')
+            .filter(|link| !link.trim().is_empty())
+            .map(|link| {
+                let (pair, probability) = link.split_once('=')?;
+                let (from, to) = pair.split_once('>')?;
+                let probability = probability.trim().parse::<f64>().ok()?;
+                let key = (from.trim().to_string(), to.trim().to_string());
+                (0.0..=1.0)
+                    .contains(&probability)
+                    .then_some((key, probability))
+            })
+            .collect::<Option<HashMap<_, _>>>()?;
+
+        Some(Self { links })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+
+    pub fn probability(&self, from: &str, to: &str) -> f64 {
+        [(from, to), (from, "*"), ("*", to), ("*", "*")]
+            .into_iter()
+            .find_map(|(from, to)| self.links.get(&(from.to_string(), to.to_string())).copied())
+            .unwrap_or(0.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Latency {
+    pub base: usize,
+    pub jitter: Jitter,
+    pub spike_probability: f64,
+    pub spike: usize,
+}
+
+impl Latency {
+    fn sample(&self, rng: &mut impl Rng) -> Duration {
+        let jitter = match self.jitter {
+            Jitter::None => 0.0,
+            Jitter::Uniform(max) => rng.random_range(0.0..=max as f64),
+            Jitter::Exponential(mean) => -(mean as f64) * (1.0 - rng.random::<f64>()).ln(),
+        };
+
+        let spike = if rng.random_bool(self.spike_probability) {
+            self.spike
+        } else {
+            0
+        };
+
+        Duration::from_secs_f64((self.base as f64 + jitter + spike as f64) / 1000.0)
+    }
+}
+
 pub struct SimNetwork {
     id: usize,
-    receiver: Mutex<Receiver<(usize, Command)>>,
-    latency: usize,
+    receiver: Mutex<Receiver<(usize, Bytes)>>,
+    latency: Latency,
+    rng: std::sync::Mutex<StdRng>,
     throughput: usize,
+    bytes_sent: AtomicU64,
+    busy: std::sync::Mutex<Instant>,
+    busy_time: AtomicU64,
+    membership: Option<std::sync::Mutex<Membership>>,
+    flow: Option<std::sync::Mutex<FlowControl>>,
+    fragments: Option<std::sync::Mutex<Fragments>>,
+}
+
+impl SimNetwork {
+    async fn tick(&self) {
+        let Some(membership) = &self.membership else {
+            return;
+        };
+
+        let out = membership.lock().unwrap().tick(self.now());
+        for (peer, cmd) in out {
+            self.send(peer, cmd).await;
+        }
+    }
+
+    fn alive(&self) -> Option<Vec<usize>> {
+        let membership = self.membership.as_ref()?.lock().unwrap();
+        let alive = membership.alive();
+        Some(alive.iter().filter_map(|peer| peer.parse().ok()).collect())
+    }
+
+    fn corrupt(&self, cmd: Command) -> Command {
+        let probability = *MANAGER.corruption.read().unwrap();
+        if probability <= 0.0 {
+            return cmd;
+        }
+
+        let Command::Replicate {
+            name,
+            stamp,
+            shard,
+            checksum,
+        } = cmd
+        else {
+            return cmd;
+        };
+
+        let mut rng = self.rng.lock().unwrap();
+        if shard.size() == 0 || !rng.random_bool(probability.min(1.0)) {
+            return Command::Replicate {
+                name,
+                stamp,
+                shard,
+                checksum,
+            };
+        }
+
+        let mut data = shard.data().to_vec();
+        let offset = rng.random_range(0..data.len());
+        data[offset] ^= 1 << rng.random_range(0..8);
+        MANAGER.metrics.counter(SHARDS_CORRUPTED, 1);
+
+        Command::Replicate {
+            name,
+            stamp,
+            shard: Shard::new(shard.index(), data),
+            checksum,
+        }
+    }
+
+    fn lose(&self, cmd: &Command) -> bool {
+        let probability = *MANAGER.metadata_loss.read().unwrap();
+        let metadata = matches!(
+            cmd,
+            Command::Create { .. } | Command::Manifest { .. } | Command::Offer { .. }
+        );
+        if probability <= 0.0 || !metadata {
+            return false;
+        }
+
+        self.rng.lock().unwrap().random_bool(probability.min(1.0))
+    }
+
+    fn link_drop(&self, to: usize) -> bool {
+        let matrix = MANAGER.link_drop.read().unwrap();
+        if matrix.is_empty() {
+            return false;
+        }
+
+        let classes = MANAGER.classes.read().unwrap();
+        let class = |id| classes.get(&id).map_or("", String::as_str);
+        let probability = matrix.probability(class(self.id), class(to));
+        probability > 0.0 && self.rng.lock().unwrap().random_bool(probability)
+    }
+
+    async fn transmit(&self, peer: String, cmd: Command) {
+        let id = peer.parse().unwrap();
+        if self.lose(&cmd) {
+            MANAGER.metrics.counter(METADATA_DROPPED, 1);
+            return;
+        }
+
+        let cmd = self.corrupt(cmd);
+        if let Command::Cancel { .. } = cmd {
+            MANAGER.metrics.counter(CANCELS_SENT, 1);
+        }
+
+        let out = match (&self.fragments, self.mtu()) {
+            (Some(fragments), Some(mtu)) => fragments.lock().unwrap().split(cmd, mtu),
+            _ => vec![cmd],
+        };
+        if out.len() > 1 {
+            MANAGER.metrics.counter(FRAMES_FRAGMENTED, 1);
+            MANAGER.metrics.counter(FRAGMENTS_SENT, out.len() as u64);
+        }
+
+        for cmd in out {
+            let Some(frame) = codec::encode(&cmd) else {
+                warn!(size = cmd.size(), "command too large to frame");
+                continue;
+            };
+            MANAGER.metrics.counter(MESSAGES_SENT, 1);
+            MANAGER.metrics.counter(BYTES_SENT, frame.len() as u64);
+            match MANAGER.cross_region(self.id, id) {
+                Some(true) => MANAGER
+                    .metrics
+                    .counter(CROSS_REGION_BYTES, frame.len() as u64),
+                Some(false) => MANAGER
+                    .metrics
+                    .counter(SAME_REGION_BYTES, frame.len() as u64),
+                None => {}
+            }
+            self.bytes_sent
+                .fetch_add(frame.len() as u64, Ordering::Relaxed);
+
+            let transmission = Duration::from_micros((frame.len() * 1000 / self.throughput) as u64);
+            let now = clock::now();
+            let done = {
+                let mut busy = self.busy.lock().unwrap();
+                let start = (*busy).max(now);
+                *busy = start + transmission;
+                *busy
+            };
+            self.busy_time
+                .fetch_add(transmission.as_micros() as u64, Ordering::Relaxed);
+            MANAGER
+                .metrics
+                .histogram(NIC_WAIT, (done - now - transmission).as_secs_f64());
+
+            if done > now {
+                clock::sleep_until(done).await;
+            }
+
+            if self.link_drop(id) {
+                MANAGER.metrics.counter(LINKS_DROPPED, 1);
+                continue;
+            }
+
+            if let Some(frame) = MANAGER.try_forward(self.id, id, frame) {
+                clock::spawn(MANAGER.forward(self.id, id, frame));
+            }
+        }
+    }
+}
+
+/// Failed nodes stop answering the failure detector, which is what makes them
+/// look failed; the rest of their traffic is treated as in oracle mode.
+fn is_probe(cmd: &Command) -> bool {
+    matches!(
+        cmd,
+        Command::Ping { .. } | Command::PingReq { .. } | Command::Ack { .. }
+    )
 }
 
 impl Network for SimNetwork {
+    fn id(&self) -> String {
+        format!("{}", self.id)
+    }
+
+    fn now(&self) -> Instant {
+        clock::now()
+    }
+
+    fn throughput(&self, peer: &str) -> Option<usize> {
+        MANAGER.throughput(peer.parse().ok()?)
+    }
+
+    fn region(&self, peer: &str) -> Option<String> {
+        if !MANAGER.locality.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        let id = peer.parse().ok()?;
+        MANAGER.regions.read().unwrap().get(&id).cloned()
+    }
+
+    fn mtu(&self) -> Option<usize> {
+        *MANAGER.mtu.read().unwrap()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        let Some(membership) = &self.membership else {
+            return clock::sleep(duration).await;
+        };
+
+        // The node's maintenance timer also drives the failure detector.
+        let until = self.now() + duration;
+        loop {
+            self.tick().await;
+
+            let now = self.now();
+            if now >= until {
+                break;
+            }
+
+            let deadline = membership.lock().unwrap().deadline().min(until);
+            clock::sleep(deadline.saturating_duration_since(now)).await;
+        }
+    }
+
     async fn discover(&self) -> Vec<String> {
+        if let Some(membership) = &self.membership {
+            return membership.lock().unwrap().alive();
+        }
+
         MANAGER
             .peers(self.id)
-            .await
             .into_iter()
             .map(|id| format!("{id}"))
             .collect()
     }
 
     async fn send(&self, peer: String, cmd: Command) {
-        let id = peer.parse().unwrap();
-        debug!(from = self.id, to = id, ?cmd, "sending");
-        MANAGER.stats.increment_messages_sent();
-        MANAGER.stats.increment_bytes_sent(cmd.size() as u64);
-        tokio::spawn(MANAGER.forward(self.id, id, cmd));
+        if self.membership.is_some() && is_probe(&cmd) && !MANAGER.is_enabled(self.id) {
+            trace!(from = self.id, to = peer, "dropped probe from failed node");
+            return;
+        }
+
+        let Some(flow) = &self.flow else {
+            return self.transmit(peer, cmd).await;
+        };
+
+        // A full queue holds the sender back until credit or a window timeout drains it.
+        let mut cmd = cmd;
+        let out = loop {
+            let res = flow.lock().unwrap().send(&peer, cmd, self.now());
+            match res {
+                Ok(out) => break out,
+                Err(Full(back)) => cmd = *back,
+            }
+
+            clock::sleep(FLOW_RETRY).await;
+            let out = flow.lock().unwrap().expire(self.now());
+            for (peer, cmd) in out {
+                self.transmit(peer, cmd).await;
+            }
+        };
+
+        for (peer, cmd) in out {
+            self.transmit(peer, cmd).await;
+        }
     }
 
     async fn recv(&self) -> Option<(String, Command)> {
-        let res = self.receiver.lock().await.recv().await?;
+        loop {
+            let (from, frame) = self.receiver.lock().await.recv().await?;
+            let cmd = codec::decode(frame).expect("malformed frame");
 
-        tokio::time::sleep(std::time::Duration::from_millis(
-            (self.latency + res.1.size() / self.throughput) as u64,
-        ))
-        .await;
+            let mut latency = self.latency.sample(&mut *self.rng.lock().unwrap());
+            if MANAGER.cross_region(from, self.id) == Some(true) {
+                let extra = *MANAGER.region_latency.read().unwrap();
+                latency += Duration::from_millis(extra as u64);
+            }
+            clock::sleep(latency).await;
 
-        debug!(from = res.0, to = self.id, cmd =? res.1, "received");
-        Some((format!("{}", res.0), res.1))
+            if self.membership.is_some() && is_probe(&cmd) && !MANAGER.is_enabled(self.id) {
+                trace!(from, to = self.id, "dropped probe to failed node");
+                continue;
+            }
+
+            let from = format!("{from}");
+            let cmd = match &self.fragments {
+                Some(fragments) if matches!(cmd, Command::Fragment { .. }) => {
+                    let cmd = fragments.lock().unwrap().reassemble(&from, cmd, self.now());
+                    let Some(cmd) = cmd else {
+                        continue;
+                    };
+                    MANAGER.metrics.counter(FRAMES_REASSEMBLED, 1);
+                    cmd
+                }
+                _ => cmd,
+            };
+            if let Some(flow) = &self.flow {
+                if let Command::Credit { messages, bytes } = cmd {
+                    let out = flow
+                        .lock()
+                        .unwrap()
+                        .credit(&from, messages, bytes, self.now());
+                    for (peer, cmd) in out {
+                        self.transmit(peer, cmd).await;
+                    }
+                    continue;
+                }
+
+                let credit = flow.lock().unwrap().received(&from, &cmd);
+                if let Some(credit) = credit {
+                    self.transmit(from.clone(), credit).await;
+                }
+            }
+
+            let Some(membership) = &self.membership else {
+                return Some((from, cmd));
+            };
+
+            let out = membership.lock().unwrap().handle(&from, &cmd, self.now());
+            let Some(out) = out else {
+                return Some((from, cmd));
+            };
+
+            for (peer, cmd) in out {
+                self.send(peer, cmd).await;
+            }
+        }
     }
 }
 
+struct SimObserver {
+    id: usize,
+    events: Option<EventLog<std::fs::File>>,
+}
+
+impl NodeObserver for SimObserver {
+    fn on_command(&self, peer: &str, command: &Command) {
+        debug!(from = peer, to = self.id, ?command, "received");
+        if let Some(events) = &self.events {
+            events.on_command(peer, command);
+        }
+    }
+
+    fn on_send(&self, peer: &str, command: &Command) {
+        debug!(from = self.id, to = peer, ?command, "sending");
+        if let Some(events) = &self.events {
+            events.on_send(peer, command);
+        }
+    }
+
+    fn on_shard_stored(&self, name: &str, index: usize) {
+        trace!(node = self.id, file = name, index, "shard stored");
+        if let Some(events) = &self.events {
+            events.on_shard_stored(name, index);
+        }
+    }
+
+    fn on_decode(&self, name: &str, result: &Result<Bytes, DecodeError>) {
+        if let Err(err) = result {
+            trace!(node = self.id, file = name, %err, "decode failed");
+        }
+        if let Some(events) = &self.events {
+            events.on_decode(name, result);
+        }
+    }
+
+    fn on_conflict(&self, conflict: &Conflict) {
+        debug!(
+            node = self.id,
+            file = conflict.name,
+            kept = ?conflict.kept,
+            discarded = ?conflict.discarded,
+            "write conflict"
+        );
+        if let Some(events) = &self.events {
+            events.on_conflict(conflict);
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct SimNode {
     inner: Arc<Node<SimNetwork>>,
 }
 
 impl SimNode {
-    pub async fn spawn(latency: usize, throughput: usize) -> Self {
-        MANAGER.spawn(latency, throughput).await
+    pub async fn spawn(latency: Latency, throughput: usize, adaptive: bool) -> Self {
+        MANAGER.spawn(latency, throughput, adaptive)
     }
 
     pub async fn disable(&self) {
-        MANAGER.disable(self.inner.network().id).await
+        MANAGER.disable(self.inner.network().id)
     }
 
     pub async fn enable(&self) {
-        MANAGER.enable(self.inner.network().id).await
+        MANAGER.enable(self.inner.network().id)
+    }
+
+    pub fn id(&self) -> usize {
+        self.inner.network().id
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        MANAGER.is_enabled(self.id())
+    }
+
+    pub fn latency(&self) -> Latency {
+        self.inner.network().latency
+    }
+
+    pub fn throughput(&self) -> usize {
+        self.inner.network().throughput
+    }
+
+    pub fn alive(&self) -> Option<Vec<usize>> {
+        self.inner.network().alive()
     }
 
-    fn new(network: SimNetwork) -> Self {
-        let inner = Arc::new(Node::new(network));
+    pub fn pending(&self) -> usize {
+        let sender = MANAGER.sender(self.id());
+        sender.max_capacity() - sender.capacity()
+    }
+
+    fn new(network: SimNetwork, adaptive: bool) -> Self {
+        let id = network.id;
+        let events = MANAGER.event_log(id);
+        let replication = *MANAGER.replication.read().unwrap();
+        let mut config = NodeConfig::default().with_replication(replication);
+        if adaptive {
+            config = config.with_adaptive_redundancy(AdaptiveRedundancy::default());
+        }
+        if let Some(encoding) = *MANAGER.encoding.read().unwrap() {
+            config = config.with_encoding(encoding);
+        }
+        if let Some(compression) = *MANAGER.compression.read().unwrap() {
+            config = config.with_compression(compression);
+        }
+        let node = Node::new(network, config)
+            .with_observer(SimObserver { id, events })
+            .with_metrics(SimMetrics { node: id });
+
+        let inner = Arc::new(node);
         let inner_clone = Arc::clone(&inner);
-        tokio::spawn(async move {
+        clock::spawn(async move {
             inner_clone.run().await;
         });
+
         Self { inner }
     }
 
-    pub async fn upload(&self, name: String, content: String) {
+    pub async fn join(&self) {
         let id = self.inner.network().id;
-        info!(to = id, file = name, "uploading");
-        self.inner.upload(name, content).await;
+        info!(node = id, "joining");
+        self.inner.join().await;
     }
 
-    pub async fn download(&self, name: String) -> Option<String> {
+    pub async fn decommission(&self) {
         let id = self.inner.network().id;
-        info!(from = id, file = name, "downloading");
-        let res = self._download(name.clone()).await;
+        info!(node = id, "decommissioning");
+        self.inner.decommission().await;
+        MANAGER.disable(id);
+    }
 
-        if res.is_some() {
-            info!(from = id, file = name, "download successfull");
-            MANAGER.stats.increment_successfull_downloads();
-        } else {
-            error!(from = id, file = name, "download failed");
-            MANAGER.stats.increment_failed_downloads();
+    pub fn set_class(&self, class: &str) {
+        MANAGER
+            .classes
+            .write()
+            .unwrap()
+            .insert(self.id(), class.to_string());
+    }
+
+    pub fn set_region(&self, region: &str) {
+        MANAGER
+            .regions
+            .write()
+            .unwrap()
+            .insert(self.id(), region.to_string());
+    }
+
+    pub fn set_capacity(&self, capacity: usize) {
+        let quota = Quota {
+            storage: Some(capacity),
+            ingress: None,
+            egress: None,
+        };
+        self.inner.set_quota(String::new(), quota);
+    }
+
+    pub fn stats(&self) -> NodeStats {
+        self.inner.stats()
+    }
+
+    pub fn flow(&self) -> Option<FlowStats> {
+        let flow = self.inner.network().flow.as_ref()?;
+        Some(flow.lock().unwrap().stats())
+    }
+
+    pub fn nic_busy(&self) -> Duration {
+        Duration::from_micros(self.inner.network().busy_time.load(Ordering::Relaxed))
+    }
+
+    pub fn bytes_sent(&self) -> u64 {
+        self.inner.network().bytes_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn metadata(&self, name: &str) -> Option<Metadata> {
+        self.inner.metadata(name)
+    }
+
+    pub fn shards(&self, name: &str) -> Vec<usize> {
+        self.inner.shards(name)
+    }
+
+    pub fn snapshot(&self) -> Vec<(String, Metadata, Vec<Shard>)> {
+        self.inner.snapshot()
+    }
+
+    pub fn restore(
+        &self,
+        name: String,
+        meta: Metadata,
+        shards: Vec<Shard>,
+    ) -> Result<(), ShardError> {
+        self.inner.restore(name, meta, shards)
+    }
+
+    pub fn export(&self, path: &std::path::Path) -> std::io::Result<()> {
+        self.inner.export(path)
+    }
+
+    pub async fn import(&self, path: &std::path::Path) -> std::io::Result<ImportStats> {
+        self.inner.import(path).await
+    }
+
+    pub fn downloads(&self) -> Vec<String> {
+        self.inner.downloads()
+    }
+
+    pub async fn resume(&self, name: String) {
+        let id = self.inner.network().id;
+        let span = info_span!("resume", node = id, file = name);
+        async {
+            info!(have = ?self.shards(&name), "resuming download");
+            let _ = self.inner.download(name).await;
         }
+        .instrument(span)
+        .await
+    }
 
-        res
+    pub fn placements(&self) -> Vec<(String, usize, String)> {
+        self.inner.placements()
+    }
+
+    pub fn placed(&self, name: String, index: usize, peer: String) {
+        self.inner.placed(name, index, peer);
     }
 
-    async fn _download(&self, name: String) -> Option<String> {
-        if let Some(res) = self.inner.download(name.clone()).await {
-            return Some(res);
+    pub async fn repair(&self, name: String) -> bool {
+        let id = self.inner.network().id;
+        let span = info_span!("repair", node = id, file = name);
+        async {
+            info!("repairing");
+            self._download(name.clone()).await.is_ok() && self.inner.repair(&name).await
         }
+        .instrument(span)
+        .await
+    }
 
-        for _ in 0..1000 {
-            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
-            if let Some(res) = self.inner.try_download(&name).await {
-                return Some(res);
+    pub async fn audit(&self, name: &str) {
+        self.inner.audit(name).await;
+    }
+
+    pub async fn backfill(&self) -> usize {
+        self.inner.backfill().await
+    }
+
+    pub async fn upload(&self, name: String, content: impl Into<Bytes>) {
+        let id = self.inner.network().id;
+        let span = info_span!("upload", node = id, file = name);
+        async {
+            info!("uploading");
+            let start = clock::now();
+            match self.inner.upload(name, content).await {
+                Ok(Some(warning)) => warn!(?warning, "durability warning"),
+                Ok(None) => {}
+                Err(err) => error!(%err, "upload failed"),
             }
+            MANAGER
+                .metrics
+                .histogram(UPLOAD_LATENCY, clock::elapsed(start).as_secs_f64());
         }
+        .instrument(span)
+        .await
+    }
+
+    pub async fn delegate(&self, to: &SimNode, name: String, content: impl Into<Bytes>) {
+        let id = self.inner.network().id;
+        let span = info_span!("delegate", node = id, to = to.id(), file = name);
+        async {
+            info!("delegating upload");
+            self.inner
+                .delegate(to.id().to_string(), name, content)
+                .await;
+        }
+        .instrument(span)
+        .await
+    }
 
-        None
+    #[cfg(feature = "gateway")]
+    pub async fn delete(&self, name: &str) -> bool {
+        self.inner.delete(name).await
+    }
+
+    pub async fn download(&self, name: String) -> Result<Bytes, DecodeError> {
+        let id = self.inner.network().id;
+        let span = info_span!("download", node = id, file = name);
+        async {
+            info!("downloading");
+            let start = clock::now();
+            let res = self._download(name.clone()).await;
+
+            match &res {
+                Ok(_) => {
+                    info!("download successfull");
+                    MANAGER.metrics.counter(DOWNLOADS_SUCCEEDED, 1);
+                    MANAGER
+                        .metrics
+                        .histogram(DOWNLOAD_LATENCY, clock::elapsed(start).as_secs_f64());
+                }
+                Err(err) => {
+                    let exhausted = self.inner.failed(&name);
+                    error!(%err, exhausted, "download failed");
+                    MANAGER.metrics.download_failed(err, exhausted);
+                }
+            }
+
+            res
+        }
+        .instrument(span)
+        .await
+    }
+
+    async fn _download(&self, name: String) -> Result<Bytes, DecodeError> {
+        let mut res = self.inner.download(name.clone()).await;
+        if res.is_ok() {
+            return res;
+        }
+
+        for attempt in 1..=1000 {
+            clock::sleep(Duration::from_millis(5)).await;
+            if attempt % 100 == 0 {
+                let _ = self.inner.download(name.clone()).await;
+            }
+
+            res = self.inner.try_download(&name).await;
+            if res.is_ok() {
+                return res;
+            }
+
+            if self.inner.failed(&name) {
+                break;
+            }
+        }
+
+        res
     }
 }
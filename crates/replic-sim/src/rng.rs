@@ -0,0 +1,35 @@
+use std::cell::RefCell;
+
+use rand::{RngCore, SeedableRng, rngs::StdRng};
+
+thread_local! {
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_os_rng());
+}
+
+pub struct SimRng;
+
+pub fn rng() -> SimRng {
+    SimRng
+}
+
+pub fn seed(seed: u64) {
+    RNG.set(StdRng::seed_from_u64(seed));
+}
+
+pub fn fork() -> StdRng {
+    StdRng::seed_from_u64(rng().next_u64())
+}
+
+impl RngCore for SimRng {
+    fn next_u32(&mut self) -> u32 {
+        RNG.with_borrow_mut(|rng| rng.next_u32())
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        RNG.with_borrow_mut(|rng| rng.next_u64())
+    }
+
+    fn fill_bytes(&mut self, dst: &mut [u8]) {
+        RNG.with_borrow_mut(|rng| rng.fill_bytes(dst))
+    }
+}
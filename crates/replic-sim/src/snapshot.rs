@@ -0,0 +1,276 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use erasure_node::{codec, network::Command};
+
+use crate::{
+    Config, File, NodeClass,
+    network::{SimNetworkManager, SimNode},
+};
+
+const MAGIC: &[u8] = b"SIMSNAP2";
+
+struct NodeEntry {
+    id: usize,
+    enabled: bool,
+    class: String,
+    latency: usize,
+    throughput: usize,
+    frames: Vec<Bytes>,
+    placements: Vec<(String, usize, String)>,
+    downloads: Vec<String>,
+}
+
+pub struct Snapshot {
+    pub round: usize,
+    spawned: usize,
+    nodes: Vec<NodeEntry>,
+    files: Vec<(String, String)>,
+}
+
+impl Snapshot {
+    pub fn capture(
+        round: usize,
+        nodes: &[(SimNode, &NodeClass)],
+        departed: &[(SimNode, &NodeClass)],
+        files: &[File],
+    ) -> Self {
+        let nodes = nodes
+            .iter()
+            .chain(departed)
+            .map(|(node, class)| {
+                let frames = node
+                    .snapshot()
+                    .into_iter()
+                    .flat_map(|(name, meta, shards)| {
+                        let stamp = meta.stamp();
+                        let create = Command::Create {
+                            name: name.clone(),
+                            meta,
+                        };
+                        let replicas = shards.into_iter().map(move |shard| Command::Replicate {
+                            name: name.clone(),
+                            stamp,
+                            checksum: shard.crc(),
+                            shard,
+                        });
+                        std::iter::once(create).chain(replicas)
+                    })
+                    .map(|cmd| codec::encode(&cmd).expect("simulated files fit a frame"))
+                    .collect();
+
+                NodeEntry {
+                    id: node.id(),
+                    enabled: node.is_enabled(),
+                    class: class.name.to_string(),
+                    latency: node.latency().base,
+                    throughput: node.throughput(),
+                    frames,
+                    placements: node.placements(),
+                    downloads: node.downloads(),
+                }
+            })
+            .collect();
+
+        let files = files
+            .iter()
+            .map(|file| (file.name(), file.content()))
+            .collect();
+
+        Self {
+            round,
+            spawned: SimNetworkManager::count(),
+            nodes,
+            files,
+        }
+    }
+
+    pub async fn restore(
+        mut self,
+        config: &Config,
+    ) -> Option<(
+        Vec<(SimNode, &NodeClass)>,
+        Vec<(SimNode, &NodeClass)>,
+        Vec<File>,
+    )> {
+        self.nodes.sort_by_key(|entry| entry.id);
+
+        let (mut nodes, mut departed, mut resumes) = (Vec::new(), Vec::new(), Vec::new());
+        for entry in self.nodes {
+            let class = config
+                .classes
+                .iter()
+                .find(|class| class.name == entry.class)?;
+
+            if SimNetworkManager::count() > entry.id {
+                return None;
+            }
+            pad(config, entry.id).await;
+
+            let node = config
+                .spawn_node_with(class, entry.latency, entry.throughput)
+                .await;
+
+            for frame in entry.frames {
+                match codec::decode(frame)? {
+                    Command::Create { name, meta } => node.restore(name, meta, Vec::new()).ok()?,
+                    Command::Replicate {
+                        name,
+                        stamp,
+                        shard,
+                        checksum,
+                    } => {
+                        if shard.crc() != checksum {
+                            return None;
+                        }
+                        let meta = node.metadata(&name).filter(|meta| meta.stamp() == stamp)?;
+                        node.restore(name, meta, vec![shard]).ok()?;
+                    }
+                    _ => return None,
+                }
+            }
+
+            for (name, index, peer) in entry.placements {
+                node.placed(name, index, peer);
+            }
+
+            if entry.enabled {
+                resumes.extend(entry.downloads.into_iter().map(|name| (nodes.len(), name)));
+                nodes.push((node, class));
+            } else {
+                node.disable().await;
+                departed.push((node, class));
+            }
+        }
+
+        pad(config, self.spawned).await;
+
+        for (index, name) in resumes {
+            nodes[index].0.resume(name).await;
+        }
+
+        let files = self
+            .files
+            .into_iter()
+            .map(|(name, content)| File { name, content })
+            .collect();
+
+        Some((nodes, departed, files))
+    }
+
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        let mut buf = BytesMut::new();
+        buf.put_slice(MAGIC);
+        buf.put_u32(self.round as u32);
+        buf.put_u32(self.spawned as u32);
+
+        buf.put_u32(self.nodes.len() as u32);
+        for node in &self.nodes {
+            buf.put_u32(node.id as u32);
+            buf.put_u8(node.enabled as u8);
+            put_str(&mut buf, &node.class);
+            buf.put_u32(node.latency as u32);
+            buf.put_u32(node.throughput as u32);
+            buf.put_u32(node.frames.len() as u32);
+            for frame in &node.frames {
+                buf.put_slice(frame);
+            }
+            buf.put_u32(node.placements.len() as u32);
+            for (name, index, peer) in &node.placements {
+                put_str(&mut buf, name);
+                buf.put_u32(*index as u32);
+                put_str(&mut buf, peer);
+            }
+            buf.put_u32(node.downloads.len() as u32);
+            for name in &node.downloads {
+                put_str(&mut buf, name);
+            }
+        }
+
+        buf.put_u32(self.files.len() as u32);
+        for (name, content) in &self.files {
+            put_str(&mut buf, name);
+            put_str(&mut buf, content);
+        }
+
+        std::fs::write(path, buf)
+    }
+
+    pub fn load(path: &str) -> Option<Self> {
+        let mut buf = BytesMut::from(std::fs::read(path).ok()?.as_slice());
+        if !buf.starts_with(MAGIC) {
+            return None;
+        }
+        buf.advance(MAGIC.len());
+
+        let round = buf.try_get_u32().ok()? as usize;
+        let spawned = buf.try_get_u32().ok()? as usize;
+
+        let count = buf.try_get_u32().ok()?;
+        let nodes = (0..count)
+            .map(|_| {
+                let id = buf.try_get_u32().ok()? as usize;
+                let enabled = buf.try_get_u8().ok()? != 0;
+                let class = get_str(&mut buf)?;
+                let latency = buf.try_get_u32().ok()? as usize;
+                let throughput = buf.try_get_u32().ok()? as usize;
+                let frames = buf.try_get_u32().ok()?;
+                let frames = (0..frames)
+                    .map(|_| codec::split(&mut buf))
+                    .collect::<Option<_>>()?;
+                let placements = buf.try_get_u32().ok()?;
+                let placements = (0..placements)
+                    .map(|_| {
+                        let name = get_str(&mut buf)?;
+                        let index = buf.try_get_u32().ok()? as usize;
+                        Some((name, index, get_str(&mut buf)?))
+                    })
+                    .collect::<Option<_>>()?;
+                let downloads = buf.try_get_u32().ok()?;
+                let downloads = (0..downloads)
+                    .map(|_| get_str(&mut buf))
+                    .collect::<Option<_>>()?;
+                Some(NodeEntry {
+                    id,
+                    enabled,
+                    class,
+                    latency,
+                    throughput,
+                    frames,
+                    placements,
+                    downloads,
+                })
+            })
+            .collect::<Option<_>>()?;
+
+        let count = buf.try_get_u32().ok()?;
+        let files = (0..count)
+            .map(|_| Some((get_str(&mut buf)?, get_str(&mut buf)?)))
+            .collect::<Option<_>>()?;
+
+        buf.is_empty().then_some(Self {
+            round,
+            spawned,
+            nodes,
+            files,
+        })
+    }
+}
+
+async fn pad(config: &Config, count: usize) {
+    while SimNetworkManager::count() < count {
+        let (node, _) = config.spawn_node(config.random_class()).await;
+        node.disable().await;
+    }
+}
+
+fn put_str(buf: &mut BytesMut, value: &str) {
+    buf.put_u32(value.len() as u32);
+    buf.put_slice(value.as_bytes());
+}
+
+fn get_str(buf: &mut BytesMut) -> Option<String> {
+    let len = buf.try_get_u32().ok()? as usize;
+    if buf.len() < len {
+        return None;
+    }
+    String::from_utf8(buf.split_to(len).to_vec()).ok()
+}
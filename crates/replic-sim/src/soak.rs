@@ -0,0 +1,169 @@
+use std::time::Duration;
+
+use rand::{Rng, seq::IndexedRandom};
+use tracing::{info, warn};
+
+use crate::{
+    Config, File, NodeClass, check, clock, network::SimNetworkManager, network::SimNode, rng,
+};
+
+const LEAK_CHECKS: usize = 3;
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Health {
+    queued: usize,
+    downloads: usize,
+    requests: usize,
+    nacks: usize,
+    orphans: usize,
+    cancellations: usize,
+}
+
+impl Health {
+    fn measure(nodes: &[(SimNode, &NodeClass)]) -> Self {
+        nodes.iter().fold(Self::default(), |total, (node, _)| {
+            let stats = node.stats();
+            Self {
+                queued: total.queued + node.pending(),
+                downloads: total.downloads + stats.downloads_active,
+                requests: total.requests + stats.requests_queued,
+                nacks: total.nacks + stats.nacks_pending,
+                orphans: total.orphans + stats.orphans_held,
+                cancellations: total.cancellations + stats.cancellations,
+            }
+        })
+    }
+
+    fn grew(&self, previous: &Self) -> Vec<&'static str> {
+        [
+            ("queued", self.queued > previous.queued),
+            ("downloads", self.downloads > previous.downloads),
+            ("requests", self.requests > previous.requests),
+            ("nacks", self.nacks > previous.nacks),
+            ("orphans", self.orphans > previous.orphans),
+            ("cancellations", self.cancellations > previous.cancellations),
+        ]
+        .into_iter()
+        .filter_map(|(gauge, grew)| grew.then_some(gauge))
+        .collect()
+    }
+}
+
+fn resident() -> Option<usize> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages = statm.split_whitespace().nth(1)?.parse::<usize>().ok()?;
+    Some(pages * 4096)
+}
+
+pub async fn run<'a>(
+    config: &'a Config,
+    nodes: &mut Vec<(SimNode, &'a NodeClass)>,
+    departed: &mut Vec<(SimNode, &'a NodeClass)>,
+    files: &[File],
+) -> usize {
+    let start = clock::now();
+    let tick = Duration::from_millis(config.timeout as u64);
+    let duration = Duration::from_millis(config.soak as u64);
+    let interval = Duration::from_millis(config.soak_interval as u64);
+
+    let (mut violations, mut round) = (0, 0);
+    let (mut last_check, mut history) = (start, Vec::<Health>::new());
+    while clock::elapsed(start) < duration {
+        let (mut enabled, mut disabled) = (Vec::new(), Vec::new());
+        for (node, class) in nodes.iter() {
+            if rng::rng().random_bool(class.failure_probability) {
+                node.disable().await;
+                disabled.push(node);
+            } else {
+                enabled.push(node);
+            }
+        }
+
+        let mut downloads = Vec::new();
+        for _ in 0..config.downloads {
+            let file = files.choose(&mut rng::rng()).unwrap();
+            let node = enabled.choose(&mut rng::rng()).unwrap();
+            downloads.push(node.download(file.name()));
+        }
+        futures::future::join_all(downloads).await;
+
+        for node in &enabled {
+            node.backfill().await;
+        }
+
+        let due = clock::elapsed(last_check) >= interval;
+        if due {
+            violations += check(round, nodes, &enabled, files);
+        }
+
+        for node in disabled {
+            node.enable().await;
+        }
+        clock::sleep(tick).await;
+
+        if due {
+            last_check = clock::now();
+            for _ in 0..config.joins {
+                let (node, class) = config.spawn_node(config.random_class()).await;
+                node.join().await;
+                nodes.push((node, class));
+            }
+            for _ in 0..config.decommissions {
+                let index = rng::rng().random_range(0..nodes.len());
+                let (node, class) = nodes.swap_remove(index);
+                node.decommission().await;
+                departed.push((node, class));
+            }
+
+            let health = Health::measure(nodes);
+            let pool = erasure_node::pool::stats();
+            info!(
+                round,
+                elapsed =? clock::elapsed(start),
+                nodes = nodes.len(),
+                violations,
+                queued = health.queued,
+                peak_queue = SimNetworkManager::stats().peak_queue,
+                downloads = health.downloads,
+                requests = health.requests,
+                nacks = health.nacks,
+                orphans = health.orphans,
+                cancellations = health.cancellations,
+                pooled = pool.pooled,
+                resident = resident(),
+                "soak health"
+            );
+
+            history.push(health);
+            if history.len() > LEAK_CHECKS {
+                let growing = history
+                    .windows(2)
+                    .rev()
+                    .take(LEAK_CHECKS)
+                    .map(|pair| pair[1].grew(&pair[0]))
+                    .reduce(|common, grew| {
+                        common
+                            .into_iter()
+                            .filter(|gauge| grew.contains(gauge))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                if !growing.is_empty() {
+                    warn!(round, ?growing, "soak gauges grew across health checks");
+                }
+            }
+        }
+
+        round += 1;
+    }
+
+    info!(
+        rounds = round,
+        elapsed =? clock::elapsed(start),
+        checks = history.len(),
+        violations,
+        "soak finished"
+    );
+
+    violations
+}
@@ -0,0 +1,52 @@
+use std::{env, fs, process::Command};
+
+use tracing::{debug, info, warn};
+
+pub fn run(first: u64, count: u64) {
+    let exe = env::current_exe().unwrap();
+
+    for seed in first..first + count {
+        let status = Command::new(&exe)
+            .env_remove("SIM_SWEEP")
+            .env("SIM_SEED", seed.to_string())
+            .env("SIM_STRICT", "1")
+            .env("RUST_LOG", "off")
+            .status()
+            .unwrap();
+
+        if status.success() {
+            debug!(seed, "seed passed");
+            continue;
+        }
+
+        warn!(seed, "seed failed, collecting trace");
+        let output = Command::new(&exe)
+            .env_remove("SIM_SWEEP")
+            .env("SIM_SEED", seed.to_string())
+            .env("SIM_STRICT", "1")
+            .env("RUST_LOG", "replic_sim=debug")
+            .env("NO_COLOR", "1")
+            .output()
+            .unwrap();
+
+        let trace = env::temp_dir().join(format!("replic-sim-{seed}.trace"));
+        fs::write(&trace, &output.stdout).unwrap();
+
+        for line in String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| line.contains("invariant violated"))
+        {
+            println!("{line}");
+        }
+
+        info!(
+            seed,
+            trace = %trace.display(),
+            reproduced = !output.status.success(),
+            "found failing seed"
+        );
+        return;
+    }
+
+    info!(first, count, "no failing seed found");
+}